@@ -136,3 +136,104 @@ impl<C: Color> ImageMut for RawPixBuf<C> {
         buffer[y * width + x] = color;
     }
 }
+
+/// An image buffer storing one palette index per pixel.
+///
+/// Indices must always refer to a valid palette entry.
+#[derive(Clone, Debug)]
+pub struct PalettedBuf<C> {
+    width: usize,
+    height: usize,
+    palette: Vec<C>,
+    indices: Box<[u8]>,
+}
+
+impl<C> PalettedBuf<C> {
+    /// Create a new buffer, with every pixel set to the first
+    /// palette entry. The palette must not be empty.
+    pub fn new(width: usize, height: usize, palette: Vec<C>) -> Self {
+        assert!(!palette.is_empty(), "Empty palette");
+        let indices = vec![0; width * height].into_boxed_slice();
+        PalettedBuf {
+            width,
+            height,
+            palette,
+            indices,
+        }
+    }
+
+    /// Create a buffer from existing indices, failing if their count
+    /// does not match the dimensions, or if any index is out of bounds.
+    pub fn from_indices(
+        width: usize,
+        height: usize,
+        palette: Vec<C>,
+        indices: Vec<u8>,
+    ) -> Result<Self, Vec<u8>> {
+        let valid = indices.len() == width * height
+            && indices.iter().all(|&i| (i as usize) < palette.len());
+        if !valid {
+            return Err(indices);
+        }
+        Ok(PalettedBuf {
+            width,
+            height,
+            palette,
+            indices: indices.into_boxed_slice(),
+        })
+    }
+
+    #[inline]
+    pub fn palette(&self) -> &[C] {
+        &self.palette
+    }
+
+    #[inline]
+    pub fn palette_mut(&mut self) -> &mut [C] {
+        &mut self.palette
+    }
+
+    #[inline]
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    #[inline]
+    pub fn index_get(&self, x: usize, y: usize) -> u8 {
+        self.indices[y * self.width + x]
+    }
+
+    /// Set the palette index of a pixel. Panics if the index
+    /// is out of the palette's bounds.
+    #[inline]
+    pub fn index_set(&mut self, x: usize, y: usize, index: u8) {
+        assert!(
+            (index as usize) < self.palette.len(),
+            "Palette index out of bounds"
+        );
+        self.indices[y * self.width + x] = index;
+    }
+
+    #[inline]
+    pub fn into_parts(self) -> (Vec<C>, Vec<u8>) {
+        (self.palette, self.indices.into_vec())
+    }
+}
+
+impl<C> Dimensions for PalettedBuf<C> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl<C: Copy + Color> Image for PalettedBuf<C> {
+    type Pixel = C;
+
+    fn color_get(&self, x: usize, y: usize) -> C {
+        self.palette[self.index_get(x, y) as usize]
+    }
+}
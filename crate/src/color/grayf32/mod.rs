@@ -0,0 +1,40 @@
+use super::convert::ConvertFrom;
+use super::{Color, Zero};
+
+/// A floating-point gray color, with a native-endian channel.
+///
+/// Like [`RgbF32`](super::RgbF32), values outside of `0.0..=1.0`
+/// are kept, and clamped when converting to other colors.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct GrayF32 {
+    pub y: f32,
+}
+
+impl Zero for GrayF32 {
+    const ZERO: Self = GrayF32 { y: 0.0 };
+}
+
+impl Color for GrayF32 {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        // NaN clamps to zero
+        let y = (self.y.clamp(0.0, 1.0) * 65535.0 + 0.5) as u32;
+
+        let r = y;
+        let g = y;
+        let b = y;
+        let a = 0xffff;
+
+        (r, g, b, a)
+    }
+}
+
+impl<C: Color> ConvertFrom<C> for GrayF32 {
+    fn convert_from(c: C) -> GrayF32 {
+        let (r, g, b, _) = c.as_rgba();
+        let y = (19595 * r + 38470 * g + 7471 * b + 0x8000) >> 16;
+        GrayF32 {
+            y: y as f32 / 65535.0,
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::io;
+
+use super::convert::ConvertInto;
+use super::{Color, Nrgba64Ne};
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// Bradford adapted matrix, converting D50 `XYZ` values
+/// into linear sRGB.
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_141, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// An image buffer, tagged with the ICC profile embedded
+/// in its serialized form, if any.
+#[derive(Clone, Debug)]
+pub struct WithIcc<B> {
+    pub image: B,
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl<B> WithIcc<B> {
+    #[inline]
+    pub fn new(image: B, icc_profile: Option<Vec<u8>>) -> Self {
+        Self { image, icc_profile }
+    }
+
+    /// Parse the embedded ICC profile, if one is present.
+    pub fn parse_profile(&self) -> Option<io::Result<IccProfile>> {
+        self.icc_profile.as_deref().map(IccProfile::parse)
+    }
+}
+
+impl<B: Dimensions> Dimensions for WithIcc<B> {
+    #[inline]
+    fn width(&self) -> usize {
+        self.image.width()
+    }
+
+    #[inline]
+    fn height(&self) -> usize {
+        self.image.height()
+    }
+}
+
+impl<B: Image> Image for WithIcc<B> {
+    type Pixel = B::Pixel;
+
+    #[inline]
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        self.image.color_get(x, y)
+    }
+}
+
+impl<B: ImageMut> ImageMut for WithIcc<B> {
+    type Pixel = B::Pixel;
+
+    #[inline]
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<Self::Pixel, ColorSpecialized> + Color,
+    {
+        self.image.color_set(x, y, color)
+    }
+}
+
+/// A tone reproduction curve, mapping device values
+/// into linear light.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    /// The identity function.
+    Linear,
+    /// A simple power function.
+    Gamma(f32),
+    /// A sampled curve, linearly interpolated.
+    Table(Vec<u16>),
+    /// An ICC parametric curve, of the given function type
+    /// (0 through 4), and its parameters.
+    Parametric(u16, [f32; 7]),
+}
+
+impl Curve {
+    /// Evaluate the curve, with `x` in the range `[0, 1]`.
+    pub fn eval(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => x,
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(table) => {
+                let last = table.len() - 1;
+                let pos = x * last as f32;
+                let i = (pos as usize).min(last);
+                let j = (i + 1).min(last);
+                let frac = pos - i as f32;
+                let a = table[i] as f32;
+                let b = table[j] as f32;
+                (a + (b - a) * frac) / 65535.0
+            }
+            Curve::Parametric(kind, p) => {
+                let [g, a, b, c, d, e, f] = *p;
+                match kind {
+                    0 => x.powf(g),
+                    1 => {
+                        if x >= -b / a {
+                            (a * x + b).powf(g)
+                        } else {
+                            0.0
+                        }
+                    }
+                    2 => {
+                        if x >= -b / a {
+                            (a * x + b).powf(g) + c
+                        } else {
+                            c
+                        }
+                    }
+                    3 => {
+                        if x >= d {
+                            (a * x + b).powf(g)
+                        } else {
+                            c * x
+                        }
+                    }
+                    _ => {
+                        if x >= d {
+                            (a * x + b).powf(g) + e
+                        } else {
+                            c * x + f
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A parsed matrix/TRC ICC profile (either version 2 or 4),
+/// describing an RGB color space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    /// The profile's major version.
+    pub version: u8,
+    /// Matrix converting linear device RGB into D50 `XYZ`,
+    /// i.e. the profile connection space.
+    pub to_xyz: [[f32; 3]; 3],
+    /// The red, green and blue tone reproduction curves.
+    pub trc: [Curve; 3],
+}
+
+impl IccProfile {
+    /// Parse a matrix/TRC RGB profile. Other kinds of profiles,
+    /// such as LUT based or CMYK ones, are rejected.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 132 || &data[36..40] != b"acsp" {
+            return Err(invalid("Invalid ICC profile header"));
+        }
+        if &data[16..20] != b"RGB " {
+            return Err(invalid("Unsupported ICC profile color space"));
+        }
+        let version = data[8];
+        let tag_count = be_u32(data, 128)? as usize;
+
+        let mut r_xyz = None;
+        let mut g_xyz = None;
+        let mut b_xyz = None;
+        let mut r_trc = None;
+        let mut g_trc = None;
+        let mut b_trc = None;
+
+        for i in 0..tag_count {
+            let entry = 132 + i * 12;
+            let sig = data
+                .get(entry..entry + 4)
+                .ok_or_else(|| invalid("Truncated ICC tag table"))?;
+            let offset = be_u32(data, entry + 4)? as usize;
+            let size = be_u32(data, entry + 8)? as usize;
+            let tag = offset
+                .checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or_else(|| invalid("ICC tag out of bounds"))?;
+            match sig {
+                b"rXYZ" => r_xyz = Some(parse_xyz(tag)?),
+                b"gXYZ" => g_xyz = Some(parse_xyz(tag)?),
+                b"bXYZ" => b_xyz = Some(parse_xyz(tag)?),
+                b"rTRC" => r_trc = Some(parse_curve(tag)?),
+                b"gTRC" => g_trc = Some(parse_curve(tag)?),
+                b"bTRC" => b_trc = Some(parse_curve(tag)?),
+                _ => (),
+            }
+        }
+
+        let missing = || invalid("ICC profile is not a matrix/TRC profile");
+        let r = r_xyz.ok_or_else(missing)?;
+        let g = g_xyz.ok_or_else(missing)?;
+        let b = b_xyz.ok_or_else(missing)?;
+        let to_xyz = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        let trc = [
+            r_trc.ok_or_else(missing)?,
+            g_trc.ok_or_else(missing)?,
+            b_trc.ok_or_else(missing)?,
+        ];
+
+        Ok(Self {
+            version,
+            to_xyz,
+            trc,
+        })
+    }
+
+    /// Convert a non-premultiplied device RGB triple, with components
+    /// in the range `[0, 1]`, into gamma encoded sRGB.
+    pub fn rgb_to_srgb(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let lin = [
+            self.trc[0].eval(rgb[0]),
+            self.trc[1].eval(rgb[1]),
+            self.trc[2].eval(rgb[2]),
+        ];
+        let xyz = mat_mul(&self.to_xyz, lin);
+        let out = mat_mul(&XYZ_D50_TO_SRGB, xyz);
+        [
+            srgb_encode(out[0]),
+            srgb_encode(out[1]),
+            srgb_encode(out[2]),
+        ]
+    }
+
+    /// Convert the pixels of an image, interpreted in this profile's
+    /// color space, into sRGB, in place.
+    pub fn convert_to_srgb<B>(&self, buf: &mut B)
+    where
+        B: Image + ImageMut + Dimensions,
+        Nrgba64Ne: ConvertInto<<B as ImageMut>::Pixel>,
+    {
+        let (width, height) = buf.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                // colors carry straight alpha, which is left as is
+                let (r, g, b, a) = buf.color_get(x, y).as_rgba();
+                let rgb = [r as f32 / 65535.0, g as f32 / 65535.0, b as f32 / 65535.0];
+                let [r, g, b] = self.rgb_to_srgb(rgb);
+                let c = Nrgba64Ne::ne(to_u16(r), to_u16(g), to_u16(b), a as u16);
+                buf.color_set_generic(x, y, c);
+            }
+        }
+    }
+}
+
+#[inline]
+fn to_u16(x: f32) -> u16 {
+    (x.clamp(0.0, 1.0) * 65535.0 + 0.5) as u16
+}
+
+#[inline]
+fn srgb_encode(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[inline]
+fn mat_mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+fn be_u32(data: &[u8], at: usize) -> io::Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("Truncated ICC profile"))
+}
+
+fn be_u16(data: &[u8], at: usize) -> io::Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("Truncated ICC profile"))
+}
+
+#[inline]
+fn s15_fixed16(data: &[u8], at: usize) -> io::Result<f32> {
+    be_u32(data, at).map(|x| x as i32 as f32 / 65536.0)
+}
+
+fn parse_xyz(tag: &[u8]) -> io::Result<[f32; 3]> {
+    if tag.get(..4) != Some(&b"XYZ "[..]) {
+        return Err(invalid("Invalid ICC XYZ tag"));
+    }
+    Ok([
+        s15_fixed16(tag, 8)?,
+        s15_fixed16(tag, 12)?,
+        s15_fixed16(tag, 16)?,
+    ])
+}
+
+fn parse_curve(tag: &[u8]) -> io::Result<Curve> {
+    match tag.get(..4) {
+        Some(b"curv") => {
+            let count = be_u32(tag, 8)? as usize;
+            match count {
+                0 => Ok(Curve::Linear),
+                1 => Ok(Curve::Gamma(be_u16(tag, 12)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| be_u16(tag, 12 + i * 2))
+                    .collect::<io::Result<_>>()
+                    .map(Curve::Table),
+            }
+        }
+        Some(b"para") => {
+            let kind = be_u16(tag, 8)?;
+            let nparams = match kind {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(invalid("Unknown ICC parametric curve type")),
+            };
+            let mut params = [0.0; 7];
+            for (i, p) in params.iter_mut().enumerate().take(nparams) {
+                *p = s15_fixed16(tag, 12 + i * 4)?;
+            }
+            Ok(Curve::Parametric(kind, params))
+        }
+        _ => Err(invalid("Invalid ICC curve tag")),
+    }
+}
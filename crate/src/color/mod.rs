@@ -1,20 +1,27 @@
 pub mod convert;
+pub mod icc;
 
 mod cmyk;
 mod gray;
 mod gray16;
+mod grayf32;
 mod nrgba;
 mod nrgba64;
 mod rgb;
 mod rgb48;
+mod rgbaf32;
+mod rgbf32;
 
 pub use cmyk::*;
 pub use gray::*;
 pub use gray16::*;
+pub use grayf32::*;
 pub use nrgba::*;
 pub use nrgba64::*;
 pub use rgb::*;
 pub use rgb48::*;
+pub use rgbaf32::*;
+pub use rgbf32::*;
 
 /// A color which contains a zero value.
 pub trait Zero: Color + Copy {
@@ -0,0 +1,60 @@
+use super::convert::ConvertFrom;
+use super::{Color, Zero};
+
+/// A floating-point RGBA color, with native-endian channels.
+///
+/// Unlike [`Nrgba`](super::Nrgba), the color channels are
+/// premultiplied by alpha. Values outside of `0.0..=1.0`
+/// are kept, and clamped when converting to other colors.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct RgbaF32 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Zero for RgbaF32 {
+    const ZERO: Self = RgbaF32 {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 0.0,
+    };
+}
+
+#[inline]
+fn to_u16(c: f32) -> u32 {
+    // NaN clamps to zero
+    (c.clamp(0.0, 1.0) * 65535.0 + 0.5) as u32
+}
+
+impl Color for RgbaF32 {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        // other colors carry straight alpha
+        if self.a.is_nan() || self.a <= 0.0 {
+            return (0, 0, 0, 0);
+        }
+        let alpha = self.a.min(1.0);
+        let r = to_u16(self.r / alpha);
+        let g = to_u16(self.g / alpha);
+        let b = to_u16(self.b / alpha);
+        let a = to_u16(alpha);
+
+        (r, g, b, a)
+    }
+}
+
+impl<C: Color> ConvertFrom<C> for RgbaF32 {
+    fn convert_from(c: C) -> RgbaF32 {
+        let (r, g, b, a) = c.as_rgba();
+        let a = (a & 0xffff) as f32 / 65535.0;
+        RgbaF32 {
+            r: (r & 0xffff) as f32 / 65535.0 * a,
+            g: (g & 0xffff) as f32 / 65535.0 * a,
+            b: (b & 0xffff) as f32 / 65535.0 * a,
+            a,
+        }
+    }
+}
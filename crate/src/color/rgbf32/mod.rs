@@ -0,0 +1,51 @@
+use super::convert::ConvertFrom;
+use super::{Color, Zero};
+
+/// A floating-point RGB color, with native-endian channels.
+///
+/// Channels are nominally in the range `0.0..=1.0`, but may hold
+/// larger values, like the radiance of high dynamic range images.
+/// Those are clamped when converting to other colors.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct RgbF32 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Zero for RgbF32 {
+    const ZERO: Self = RgbF32 {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+    };
+}
+
+#[inline]
+fn to_u16(c: f32) -> u32 {
+    // NaN clamps to zero
+    (c.clamp(0.0, 1.0) * 65535.0 + 0.5) as u32
+}
+
+impl Color for RgbF32 {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        let r = to_u16(self.r);
+        let g = to_u16(self.g);
+        let b = to_u16(self.b);
+        let a = 0xffff;
+
+        (r, g, b, a)
+    }
+}
+
+impl<C: Color> ConvertFrom<C> for RgbF32 {
+    fn convert_from(c: C) -> RgbF32 {
+        let (r, g, b, _) = c.as_rgba();
+        RgbF32 {
+            r: (r & 0xffff) as f32 / 65535.0,
+            g: (g & 0xffff) as f32 / 65535.0,
+            b: (b & 0xffff) as f32 / 65535.0,
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::io;
+use std::time::Duration;
+
+use crate::buffer::{AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Nrgba, Zero};
+use crate::image::{self, Dimensions, Image};
+
+/// What to do with the area covered by a frame, before
+/// rendering the next one.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Disposal {
+    /// Leave the frame in place.
+    #[default]
+    None,
+    /// Clear the frame's area to transparent.
+    Background,
+    /// Restore the canvas to its state before the frame was drawn.
+    Previous,
+}
+
+/// How many times an animation is played.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum LoopCount {
+    /// Play the animation the given number of times.
+    Finite(u32),
+    /// Loop forever.
+    Infinite,
+}
+
+impl Default for LoopCount {
+    fn default() -> Self {
+        LoopCount::Finite(1)
+    }
+}
+
+/// A single frame of an animation.
+///
+/// The frame's buffer is drawn over the canvas at the given offset,
+/// blending with its contents according to the alpha channel.
+#[derive(Clone, Debug)]
+pub struct Frame<B> {
+    pub buffer: B,
+    /// Display time of the frame.
+    pub delay: Duration,
+    /// Horizontal offset of the frame, within the canvas.
+    pub left: usize,
+    /// Vertical offset of the frame, within the canvas.
+    pub top: usize,
+    pub disposal: Disposal,
+}
+
+impl<B> Frame<B> {
+    #[inline]
+    pub fn new(buffer: B, delay: Duration) -> Self {
+        Self {
+            buffer,
+            delay,
+            left: 0,
+            top: 0,
+            disposal: Disposal::None,
+        }
+    }
+
+    /// Transform the buffer of this frame, keeping its metadata.
+    #[inline]
+    pub fn map<C, F>(self, f: F) -> Frame<C>
+    where
+        F: FnOnce(B) -> C,
+    {
+        Frame {
+            buffer: f(self.buffer),
+            delay: self.delay,
+            left: self.left,
+            top: self.top,
+            disposal: self.disposal,
+        }
+    }
+}
+
+/// A sequence of frames, drawn over a canvas with the
+/// dimensions of the implementing type.
+pub trait Frames: Dimensions + Iterator<Item = io::Result<Frame<Self::Buffer>>> {
+    type Buffer;
+
+    fn loop_count(&self) -> LoopCount;
+
+    /// Render each frame into a full canvas, honoring frame
+    /// offsets, transparency and disposal methods. Fails when
+    /// the canvas is larger than `image::MAX_PIXELS`.
+    #[inline]
+    fn composite(self) -> io::Result<Composite<Self>>
+    where
+        Self: Sized,
+        Self::Buffer: Image + Dimensions,
+    {
+        Composite::new(self)
+    }
+}
+
+/// An animation, with all of its frames kept in memory.
+#[derive(Clone, Debug)]
+pub struct Animation<B> {
+    pub width: usize,
+    pub height: usize,
+    pub loop_count: LoopCount,
+    pub frames: Vec<Frame<B>>,
+}
+
+impl<B> Animation<B> {
+    /// Collect a sequence of frames.
+    pub fn from_frames<F>(frames: F) -> io::Result<Self>
+    where
+        F: Frames<Buffer = B>,
+    {
+        let (width, height) = frames.dimensions();
+        let loop_count = frames.loop_count();
+        Ok(Self {
+            width,
+            height,
+            loop_count,
+            frames: frames.collect::<io::Result<_>>()?,
+        })
+    }
+
+    #[inline]
+    pub fn into_frames(self) -> AnimationFrames<B> {
+        AnimationFrames {
+            width: self.width,
+            height: self.height,
+            loop_count: self.loop_count,
+            frames: self.frames.into_iter(),
+        }
+    }
+}
+
+impl<B> Dimensions for Animation<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// The frames of an `Animation`.
+pub struct AnimationFrames<B> {
+    width: usize,
+    height: usize,
+    loop_count: LoopCount,
+    frames: std::vec::IntoIter<Frame<B>>,
+}
+
+impl<B> Iterator for AnimationFrames<B> {
+    type Item = io::Result<Frame<B>>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames.next().map(Ok)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl<B> Dimensions for AnimationFrames<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl<B> Frames for AnimationFrames<B> {
+    type Buffer = B;
+
+    #[inline]
+    fn loop_count(&self) -> LoopCount {
+        self.loop_count
+    }
+}
+
+/// Frames rendered into full canvases, with `Background` disposal,
+/// as returned by `Frames::composite`.
+pub struct Composite<F> {
+    inner: F,
+    canvas: RawPixBuf<Nrgba>,
+    previous: Option<RawPixBuf<Nrgba>>,
+    dispose: Option<(Disposal, Region)>,
+}
+
+#[derive(Copy, Clone)]
+struct Region {
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+}
+
+impl<F> Composite<F>
+where
+    F: Frames,
+    F::Buffer: Image + Dimensions,
+{
+    fn new(inner: F) -> io::Result<Self> {
+        let (width, height) = inner.dimensions();
+        if image::pixel_count(width, height).is_none() {
+            let k = io::ErrorKind::InvalidData;
+            return Err(io::Error::new(k, "Animation canvas too large"));
+        }
+        let canvas = RawPixBuf::new(width, height);
+        Ok(Self {
+            inner,
+            canvas,
+            previous: None,
+            dispose: None,
+        })
+    }
+
+    fn draw(&mut self, frame: Frame<F::Buffer>) -> Frame<RawPixBuf<Nrgba>> {
+        match self.dispose.take() {
+            Some((Disposal::Background, region)) => {
+                let width = self.canvas.width();
+                let canvas = self.canvas.as_typed_mut();
+                for y in region.top..region.bottom {
+                    for c in canvas[y * width + region.left..y * width + region.right].iter_mut() {
+                        *c = Nrgba::ZERO;
+                    }
+                }
+            }
+            Some((Disposal::Previous, _)) => {
+                if let Some(previous) = self.previous.take() {
+                    self.canvas = previous;
+                }
+            }
+            _ => (),
+        }
+
+        if frame.disposal == Disposal::Previous {
+            self.previous = Some(self.canvas.clone());
+        }
+
+        let (width, height) = self.canvas.dimensions();
+        let region = Region {
+            left: frame.left.min(width),
+            top: frame.top.min(height),
+            right: (frame.left + frame.buffer.width()).min(width),
+            bottom: (frame.top + frame.buffer.height()).min(height),
+        };
+
+        let canvas = self.canvas.as_typed_mut();
+        for y in region.top..region.bottom {
+            for x in region.left..region.right {
+                let src: Nrgba = frame
+                    .buffer
+                    .color_get(x - frame.left, y - frame.top)
+                    .convert_into();
+                let dst = &mut canvas[y * width + x];
+                *dst = blend_over(*dst, src);
+            }
+        }
+        self.dispose = Some((frame.disposal, region));
+
+        Frame {
+            buffer: self.canvas.clone(),
+            delay: frame.delay,
+            left: 0,
+            top: 0,
+            disposal: Disposal::Background,
+        }
+    }
+}
+
+/// Composite two non-premultiplied colors with the alpha over operator.
+fn blend_over(dst: Nrgba, src: Nrgba) -> Nrgba {
+    match src.a {
+        0xff => src,
+        0 => dst,
+        _ => {
+            let sa = src.a as u32;
+            let da = dst.a as u32 * (0xff - sa) / 0xff;
+            let a = sa + da;
+            let mix = |s: u8, d: u8| ((s as u32 * sa + d as u32 * da) / a) as u8;
+            Nrgba {
+                r: mix(src.r, dst.r),
+                g: mix(src.g, dst.g),
+                b: mix(src.b, dst.b),
+                a: a as u8,
+            }
+        }
+    }
+}
+
+impl<F> Iterator for Composite<F>
+where
+    F: Frames,
+    F::Buffer: Image + Dimensions,
+{
+    type Item = io::Result<Frame<RawPixBuf<Nrgba>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|frame| frame.map(|frame| self.draw(frame)))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<F: Dimensions> Dimensions for Composite<F> {
+    fn width(&self) -> usize {
+        self.inner.width()
+    }
+
+    fn height(&self) -> usize {
+        self.inner.height()
+    }
+}
+
+impl<F> Frames for Composite<F>
+where
+    F: Frames,
+    F::Buffer: Image + Dimensions,
+{
+    type Buffer = RawPixBuf<Nrgba>;
+
+    #[inline]
+    fn loop_count(&self) -> LoopCount {
+        self.inner.loop_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageMut;
+
+    const RED: Nrgba = Nrgba {
+        r: 0xff,
+        g: 0,
+        b: 0,
+        a: 0xff,
+    };
+    const GREEN: Nrgba = Nrgba {
+        r: 0,
+        g: 0xff,
+        b: 0,
+        a: 0xff,
+    };
+
+    fn frame(
+        w: usize,
+        h: usize,
+        c: Nrgba,
+        (left, top): (usize, usize),
+        disposal: Disposal,
+    ) -> Frame<RawPixBuf<Nrgba>> {
+        let mut buffer = RawPixBuf::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                buffer.pixel_set(x, y, c);
+            }
+        }
+        Frame {
+            buffer,
+            delay: Duration::from_millis(10),
+            left,
+            top,
+            disposal,
+        }
+    }
+
+    fn composite(frames: Vec<Frame<RawPixBuf<Nrgba>>>) -> Vec<RawPixBuf<Nrgba>> {
+        let animation = Animation {
+            width: 3,
+            height: 3,
+            loop_count: LoopCount::default(),
+            frames,
+        };
+        animation
+            .into_frames()
+            .composite()
+            .unwrap()
+            .map(|frame| frame.unwrap().buffer)
+            .collect()
+    }
+
+    #[test]
+    fn frames_are_drawn_at_their_offsets() {
+        let canvases = composite(vec![
+            frame(1, 1, RED, (0, 0), Disposal::None),
+            frame(2, 1, GREEN, (1, 2), Disposal::None),
+        ]);
+        assert_eq!(canvases[0].color_get(0, 0), RED);
+        assert_eq!(canvases[0].color_get(1, 1), Nrgba::ZERO);
+        assert_eq!(canvases[1].color_get(0, 0), RED);
+        assert_eq!(canvases[1].color_get(1, 2), GREEN);
+        assert_eq!(canvases[1].color_get(2, 2), GREEN);
+        assert_eq!(canvases[1].color_get(0, 2), Nrgba::ZERO);
+    }
+
+    #[test]
+    fn frames_are_disposed_to_the_background() {
+        let canvases = composite(vec![
+            frame(3, 3, RED, (0, 0), Disposal::None),
+            frame(2, 2, GREEN, (1, 1), Disposal::Background),
+            frame(1, 1, GREEN, (0, 0), Disposal::None),
+        ]);
+        assert_eq!(canvases[1].color_get(2, 2), GREEN);
+        assert_eq!(canvases[2].color_get(0, 0), GREEN);
+        assert_eq!(canvases[2].color_get(1, 1), Nrgba::ZERO);
+        assert_eq!(canvases[2].color_get(2, 2), Nrgba::ZERO);
+        assert_eq!(canvases[2].color_get(2, 0), RED);
+    }
+
+    #[test]
+    fn frames_are_disposed_to_the_previous_canvas() {
+        let canvases = composite(vec![
+            frame(3, 3, RED, (0, 0), Disposal::None),
+            frame(2, 2, GREEN, (1, 1), Disposal::Previous),
+            frame(1, 1, GREEN, (0, 0), Disposal::None),
+        ]);
+        assert_eq!(canvases[1].color_get(1, 1), GREEN);
+        assert_eq!(canvases[2].color_get(0, 0), GREEN);
+        assert_eq!(canvases[2].color_get(1, 1), RED);
+        assert_eq!(canvases[2].color_get(2, 2), RED);
+    }
+
+    #[test]
+    fn huge_canvases_are_rejected() {
+        let animation: Animation<RawPixBuf<Nrgba>> = Animation {
+            width: 1 << 20,
+            height: 1 << 20,
+            loop_count: LoopCount::default(),
+            frames: Vec::new(),
+        };
+        let e = animation.into_frames().composite().err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,22 @@
+//! Standard base64, with padding, as used by the
+//! terminal graphics protocols.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        let digits = [18, 12, 6, 0].map(|shift| ALPHABET[(n >> shift) as usize & 0x3f]);
+        // one byte yields two digits, two bytes yield three
+        let len = chunk.len() + 1;
+        out.extend_from_slice(&digits[..len]);
+        out.resize(out.len() + 4 - len, b'=');
+    }
+    out
+}
@@ -0,0 +1,99 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Gray, Nrgba, Rgb};
+use crate::image::{Dimensions, Image, ImageMut};
+
+#[derive(Clone, Debug)]
+pub enum BmpBuf {
+    Gray(RawPixBuf<Gray>),
+    Nrgba(RawPixBuf<Nrgba>),
+    Rgb(RawPixBuf<Rgb>),
+}
+
+impl Dimensions for BmpBuf {
+    fn width(&self) -> usize {
+        match self {
+            BmpBuf::Gray(buf) => buf.width(),
+            BmpBuf::Nrgba(buf) => buf.width(),
+            BmpBuf::Rgb(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            BmpBuf::Gray(buf) => buf.height(),
+            BmpBuf::Nrgba(buf) => buf.height(),
+            BmpBuf::Rgb(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for BmpBuf {
+    type Pixel = BmpPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            BmpBuf::Gray(buf) => BmpPix::Gray(buf.color_get(x, y)),
+            BmpBuf::Nrgba(buf) => BmpPix::Nrgba(buf.color_get(x, y)),
+            BmpBuf::Rgb(buf) => BmpPix::Rgb(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for BmpBuf {
+    type Pixel = BmpPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<BmpPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<BmpPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (BmpBuf::Gray(buf), BmpPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (BmpBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (BmpBuf::Nrgba(buf), BmpPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (BmpBuf::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (BmpBuf::Rgb(buf), BmpPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (BmpBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum BmpPix {
+    Gray(Gray),
+    Nrgba(Nrgba),
+    Rgb(Rgb),
+}
+
+impl Color for BmpPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            BmpPix::Gray(c) => c.as_rgba(),
+            BmpPix::Nrgba(c) => c.as_rgba(),
+            BmpPix::Rgb(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for BmpBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            BmpBuf::Gray(buf) => buf.as_ref(),
+            BmpBuf::Nrgba(buf) => buf.as_ref(),
+            BmpBuf::Rgb(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for BmpBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            BmpBuf::Gray(buf) => buf.as_mut(),
+            BmpBuf::Nrgba(buf) => buf.as_mut(),
+            BmpBuf::Rgb(buf) => buf.as_mut(),
+        }
+    }
+}
@@ -0,0 +1,741 @@
+mod bmp_buf;
+
+pub use bmp_buf::*;
+
+use std::io::{self, Read, Write};
+
+use crate::buffer::{AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Gray, Nrgba, Rgb};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+pub struct Bmp;
+
+impl_format! {
+    name: Bmp,
+    id: "feim:bmp",
+    magic: b"BM",
+}
+
+impl DecodeOptions for Bmp {
+    type Options = ();
+}
+
+impl EncodeOptions for Bmp {
+    type Options = ();
+}
+
+#[derive(Debug)]
+struct Header {
+    width: usize,
+    height: usize,
+    top_down: bool,
+    bpp: u16,
+    compression: u32,
+    colors_used: usize,
+    masks: [u32; 4],
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Channel {
+    shift: u32,
+    max: u32,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Option<Self> {
+        if mask == 0 {
+            return None;
+        }
+        let shift = mask.trailing_zeros();
+        let bits = (mask >> shift).trailing_ones();
+        // only the 16 most significant bits of wider
+        // channels are kept
+        let extra = bits.saturating_sub(16);
+        Some(Channel {
+            shift: shift + extra,
+            max: (1 << (bits - extra)) - 1,
+        })
+    }
+
+    #[inline]
+    fn extract(self, pixel: u32) -> u8 {
+        let value = ((pixel >> self.shift) & self.max) as u64;
+        let max = self.max as u64;
+        ((value * 0xff + max / 2) / max) as u8
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+#[inline]
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+#[inline]
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn read_header<R: Read>(r: &mut R) -> io::Result<(Header, Vec<Rgb>, usize)> {
+    let mut file_header = [0; FILE_HEADER_SIZE];
+    r.read_exact(&mut file_header)?;
+    if !Bmp.is_valid_magic(&file_header) {
+        return Err(invalid("Invalid bmp magic."));
+    }
+    let data_offset = le_u32(&file_header, 10) as usize;
+
+    let (header, palette, consumed) = read_info(r)?;
+    Ok((
+        header,
+        palette,
+        data_offset.saturating_sub(FILE_HEADER_SIZE + consumed),
+    ))
+}
+
+// reads the dib header, along with the bit masks and palette
+// that may follow it, returning the number of bytes consumed
+fn read_info<R: Read>(r: &mut R) -> io::Result<(Header, Vec<Rgb>, usize)> {
+    let mut size = [0; 4];
+    r.read_exact(&mut size)?;
+    let header_size = u32::from_le_bytes(size) as usize;
+    if !(CORE_HEADER_SIZE..=1024).contains(&header_size) {
+        return Err(invalid("Invalid bmp header size"));
+    }
+    let mut dib = vec![0; header_size];
+    dib[..4].copy_from_slice(&size);
+    r.read_exact(&mut dib[4..])?;
+    let mut consumed = header_size;
+
+    let mut header = if header_size == CORE_HEADER_SIZE {
+        Header {
+            width: le_u16(&dib, 4) as usize,
+            height: le_u16(&dib, 6) as usize,
+            top_down: false,
+            bpp: le_u16(&dib, 10),
+            compression: BI_RGB,
+            colors_used: 0,
+            masks: [0; 4],
+        }
+    } else if header_size >= INFO_HEADER_SIZE {
+        let width = le_u32(&dib, 4) as i32;
+        let height = le_u32(&dib, 8) as i32;
+        if width < 0 {
+            return Err(invalid("Invalid bmp width"));
+        }
+        let mut masks = [0; 4];
+        if header_size >= INFO_HEADER_SIZE + 12 {
+            masks[0] = le_u32(&dib, 40);
+            masks[1] = le_u32(&dib, 44);
+            masks[2] = le_u32(&dib, 48);
+        }
+        if header_size >= INFO_HEADER_SIZE + 16 {
+            masks[3] = le_u32(&dib, 52);
+        }
+        Header {
+            width: width as usize,
+            height: height.unsigned_abs() as usize,
+            top_down: height < 0,
+            bpp: le_u16(&dib, 14),
+            compression: le_u32(&dib, 16),
+            colors_used: le_u32(&dib, 32) as usize,
+            masks,
+        }
+    } else {
+        return Err(invalid("Unsupported bmp header"));
+    };
+
+    // plain info headers store their bit masks right
+    // after the header, rather than inside of it
+    if header_size == INFO_HEADER_SIZE {
+        let count = match header.compression {
+            BI_BITFIELDS => 3,
+            BI_ALPHABITFIELDS => 4,
+            _ => 0,
+        };
+        let mut masks = [0; 16];
+        r.read_exact(&mut masks[..count * 4])?;
+        for i in 0..count {
+            header.masks[i] = le_u32(&masks, i * 4);
+        }
+        consumed += count * 4;
+    }
+
+    if header.bpp <= 8 {
+        let entry_size = if header_size == CORE_HEADER_SIZE {
+            3
+        } else {
+            4
+        };
+        let count = match header.colors_used {
+            0 => 1 << header.bpp,
+            n => n.min(256),
+        };
+        let mut raw = vec![0; count * entry_size];
+        r.read_exact(&mut raw)?;
+        consumed += raw.len();
+        let palette = raw
+            .chunks_exact(entry_size)
+            .map(|c| Rgb {
+                r: c[2],
+                g: c[1],
+                b: c[0],
+            })
+            .collect();
+        return Ok((header, palette, consumed));
+    }
+
+    Ok((header, Vec::new(), consumed))
+}
+
+impl Decode<BmpBuf> for Bmp {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<BmpBuf> {
+        let (header, palette, gap) = read_header(&mut r)?;
+        io::copy(&mut (&mut r).take(gap as u64), &mut io::sink())?;
+        decode_pixels(&mut r, &header, &palette)
+    }
+}
+
+fn decode_pixels<R: Read>(r: &mut R, header: &Header, palette: &[Rgb]) -> io::Result<BmpBuf> {
+    let Header { width, height, .. } = *header;
+    if image::pixel_count(width, height).is_none() {
+        return Err(invalid("Bmp image dimensions too large"));
+    }
+
+    match (header.bpp, header.compression) {
+        (1 | 4 | 8, BI_RGB) => {
+            let indices = read_indexed_rows(r, header)?;
+            Ok(map_palette(width, height, palette, &indices))
+        }
+        (8, BI_RLE8) | (4, BI_RLE4) => {
+            let indices = read_rle(r, header)?;
+            Ok(map_palette(width, height, palette, &indices))
+        }
+        (24, BI_RGB) => read_rgb24_rows(r, header),
+        (16 | 32, BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS) => read_masked_rows(r, header),
+        _ => Err(invalid("Unsupported bmp pixel format")),
+    }
+}
+
+impl Probe for Bmp {
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let (header, _, _) = read_header(&mut r)?;
+        header_info(&header)
+    }
+}
+
+fn header_info(header: &Header) -> io::Result<ImageInfo> {
+    let Header { width, height, .. } = *header;
+    match (header.bpp, header.compression) {
+        (1 | 4 | 8, BI_RGB | BI_RLE8 | BI_RLE4) => Ok(ImageInfo::new(
+            width,
+            height,
+            ColorType::Indexed,
+            header.bpp as u8,
+        )),
+        (24, BI_RGB) => Ok(ImageInfo::new(width, height, ColorType::Rgb, 8)),
+        (16 | 32, BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS) => {
+            let masks = channel_masks(header);
+            let bits = masks[..3].iter().map(|m| m.count_ones()).max().unwrap_or(0);
+            let color_type = if masks[3] != 0 {
+                ColorType::Rgba
+            } else {
+                ColorType::Rgb
+            };
+            Ok(ImageInfo::new(width, height, color_type, bits as u8))
+        }
+        _ => Err(invalid("Unsupported bmp pixel format")),
+    }
+}
+
+/// Read the header of the bitmap of an icon or cursor. Every
+/// icon may be transparent, through its transparency mask.
+#[cfg(feature = "fmt-ico")]
+pub(crate) fn probe_icon<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+    let (mut header, _, _) = read_info(&mut r)?;
+    header.height /= 2;
+    if header.bpp == 32 && header.compression == BI_RGB {
+        header.masks[3] = 0xff000000;
+    }
+    let mut info = header_info(&header)?;
+    info.has_alpha = true;
+    Ok(info)
+}
+
+/// Decode the bitmap of an icon or cursor, which has no file
+/// header, and is followed by a transparency mask. Returns the
+/// image along with its bits per pixel.
+#[cfg(feature = "fmt-ico")]
+pub(crate) fn decode_icon<R: Read>(mut r: R) -> io::Result<(BmpBuf, u16)> {
+    let (mut header, palette, _) = read_info(&mut r)?;
+
+    // the height covers both the image and the mask,
+    // and 32 bit images always carry an alpha channel
+    header.height /= 2;
+    if header.bpp == 32 && header.compression == BI_RGB {
+        header.masks[3] = 0xff000000;
+    }
+    let image = decode_pixels(&mut r, &header, &palette)?;
+    if let BmpBuf::Nrgba(_) = image {
+        return Ok((image, header.bpp));
+    }
+
+    // some encoders leave out the mask altogether
+    let Header { width, height, .. } = header;
+    let mut mask = vec![0; row_stride(width, 1) * height];
+    if r.read_exact(&mut mask).is_err() {
+        return Ok((image, header.bpp));
+    }
+    let stride = row_stride(width, 1);
+    let transparent = |x: usize, y: usize| {
+        let row = &mask[dest_row(&header, y) * stride..];
+        row[x / 8] & (0x80 >> (x % 8)) != 0
+    };
+    if !(0..height).any(|y| (0..width).any(|x| transparent(x, y))) {
+        return Ok((image, header.bpp));
+    }
+
+    let mut buf: RawPixBuf<Nrgba> = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut c: Nrgba = image.color_get(x, y).convert_into();
+            if transparent(x, y) {
+                c.a = 0;
+            }
+            buf.pixel_set(x, y, c);
+        }
+    }
+    Ok((BmpBuf::Nrgba(buf), header.bpp))
+}
+
+// maps a row in the file, in bottom-up or top-down order,
+// onto its row in the decoded image
+#[inline]
+fn dest_row(header: &Header, y: usize) -> usize {
+    if header.top_down {
+        y
+    } else {
+        header.height - 1 - y
+    }
+}
+
+#[inline]
+fn row_stride(width: usize, bpp: u16) -> usize {
+    (width * bpp as usize).div_ceil(32) * 4
+}
+
+fn read_indexed_rows<R: Read>(r: &mut R, header: &Header) -> io::Result<Vec<u8>> {
+    let Header { width, height, .. } = *header;
+    let bpp = header.bpp as usize;
+    let per_byte = 8 / bpp;
+    let mask = ((1u16 << bpp) - 1) as u8;
+
+    let mut row = vec![0; row_stride(width, header.bpp)];
+    let mut indices = vec![0; width * height];
+    for y in 0..height {
+        r.read_exact(&mut row)?;
+        let dest = &mut indices[dest_row(header, y) * width..][..width];
+        for (x, index) in dest.iter_mut().enumerate() {
+            let byte = row[x / per_byte];
+            let shift = 8 - bpp * (x % per_byte + 1);
+            *index = (byte >> shift) & mask;
+        }
+    }
+    Ok(indices)
+}
+
+fn read_rle<R: Read>(r: &mut R, header: &Header) -> io::Result<Vec<u8>> {
+    let Header { width, height, .. } = *header;
+    let rle4 = header.compression == BI_RLE4;
+
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let mut data = data.into_iter();
+    let mut next = || data.next().ok_or_else(|| invalid("Truncated bmp RLE data"));
+
+    let mut indices = vec![0; width * height];
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut put = |x: usize, y: usize, index: u8| {
+        if x < width && y < height {
+            let y = dest_row(header, y);
+            indices[y * width + x] = index;
+        }
+    };
+
+    loop {
+        let count = next()?;
+        let value = next()?;
+        if count > 0 {
+            for i in 0..count as usize {
+                let index = if !rle4 {
+                    value
+                } else if i % 2 == 0 {
+                    value >> 4
+                } else {
+                    value & 0xf
+                };
+                put(x, y, index);
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            1 => break,
+            2 => {
+                x += next()? as usize;
+                y += next()? as usize;
+            }
+            n => {
+                let n = n as usize;
+                let bytes = if rle4 { n.div_ceil(2) } else { n };
+                let mut byte = 0;
+                for i in 0..n {
+                    let index = if !rle4 {
+                        next()?
+                    } else if i % 2 == 0 {
+                        byte = next()?;
+                        byte >> 4
+                    } else {
+                        byte & 0xf
+                    };
+                    put(x, y, index);
+                    x += 1;
+                }
+                // absolute runs are padded to a word boundary
+                if bytes % 2 == 1 {
+                    next()?;
+                }
+            }
+        }
+        if y >= height {
+            break;
+        }
+    }
+
+    Ok(indices)
+}
+
+fn map_palette(width: usize, height: usize, palette: &[Rgb], indices: &[u8]) -> BmpBuf {
+    let black = Rgb { r: 0, g: 0, b: 0 };
+    let lookup = |i: u8| palette.get(i as usize).copied().unwrap_or(black);
+
+    if palette.iter().all(|c| c.r == c.g && c.g == c.b) {
+        let mut buf: RawPixBuf<Gray> = RawPixBuf::new(width, height);
+        for (pix, &i) in buf.as_typed_mut().iter_mut().zip(indices) {
+            *pix = Gray { y: lookup(i).r };
+        }
+        BmpBuf::Gray(buf)
+    } else {
+        let mut buf: RawPixBuf<Rgb> = RawPixBuf::new(width, height);
+        for (pix, &i) in buf.as_typed_mut().iter_mut().zip(indices) {
+            *pix = lookup(i);
+        }
+        BmpBuf::Rgb(buf)
+    }
+}
+
+fn read_rgb24_rows<R: Read>(r: &mut R, header: &Header) -> io::Result<BmpBuf> {
+    let Header { width, height, .. } = *header;
+    let mut row = vec![0; row_stride(width, 24)];
+    let mut buf: RawPixBuf<Rgb> = RawPixBuf::new(width, height);
+    let pixels = buf.as_typed_mut();
+    for y in 0..height {
+        r.read_exact(&mut row)?;
+        let dest = &mut pixels[dest_row(header, y) * width..][..width];
+        for (pix, bgr) in dest.iter_mut().zip(row.chunks_exact(3)) {
+            *pix = Rgb {
+                r: bgr[2],
+                g: bgr[1],
+                b: bgr[0],
+            };
+        }
+    }
+    Ok(BmpBuf::Rgb(buf))
+}
+
+// the bit masks of the channels of 16 and 32 bit images
+fn channel_masks(header: &Header) -> [u32; 4] {
+    match (header.compression, header.bpp) {
+        (BI_BITFIELDS | BI_ALPHABITFIELDS, _) => header.masks,
+        (_, 16) => [0x7c00, 0x03e0, 0x001f, 0],
+        // NOTE: v4 and v5 headers may carry an alpha mask,
+        // even when the image is not using bit fields
+        (_, _) => [0xff0000, 0xff00, 0xff, header.masks[3]],
+    }
+}
+
+fn read_masked_rows<R: Read>(r: &mut R, header: &Header) -> io::Result<BmpBuf> {
+    let Header { width, height, .. } = *header;
+
+    let masks = channel_masks(header);
+    let invalid_mask = || invalid("Invalid bmp color mask");
+    let r_chan = Channel::new(masks[0]).ok_or_else(invalid_mask)?;
+    let g_chan = Channel::new(masks[1]).ok_or_else(invalid_mask)?;
+    let b_chan = Channel::new(masks[2]).ok_or_else(invalid_mask)?;
+    let a_chan = Channel::new(masks[3]);
+
+    let bytes = header.bpp as usize / 8;
+    let mut row = vec![0; row_stride(width, header.bpp)];
+    let mut buf: RawPixBuf<Nrgba> = RawPixBuf::new(width, height);
+    let pixels = buf.as_typed_mut();
+    for y in 0..height {
+        r.read_exact(&mut row)?;
+        let dest = &mut pixels[dest_row(header, y) * width..][..width];
+        for (pix, raw) in dest.iter_mut().zip(row.chunks_exact(bytes)) {
+            let p = match raw {
+                [a, b] => u16::from_le_bytes([*a, *b]) as u32,
+                _ => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            };
+            *pix = Nrgba {
+                r: r_chan.extract(p),
+                g: g_chan.extract(p),
+                b: b_chan.extract(p),
+                a: a_chan.map(|a| a.extract(p)).unwrap_or(0xff),
+            };
+        }
+    }
+
+    // a fully transparent image most likely means the
+    // alpha channel is garbage, so drop it
+    match a_chan {
+        Some(_) if pixels.iter().any(|p| p.a != 0) => Ok(BmpBuf::Nrgba(buf)),
+        _ => {
+            let mut rgb: RawPixBuf<Rgb> = RawPixBuf::new(width, height);
+            for (dst, src) in rgb.as_typed_mut().iter_mut().zip(buf.into_pixels()) {
+                *dst = Rgb {
+                    r: src.r,
+                    g: src.g,
+                    b: src.b,
+                };
+            }
+            Ok(BmpBuf::Rgb(rgb))
+        }
+    }
+}
+
+fn write_headers<W: Write>(
+    w: &mut W,
+    (width, height): (usize, usize),
+    bpp: u16,
+    palette_size: usize,
+) -> io::Result<()> {
+    let has_alpha = bpp == 32;
+    let header_size = if has_alpha {
+        V4_HEADER_SIZE
+    } else {
+        INFO_HEADER_SIZE
+    };
+    let data_offset = FILE_HEADER_SIZE + header_size + palette_size * 4;
+    let image_size = row_stride(width, bpp) * height;
+    let file_size = data_offset + image_size;
+
+    w.write_all(b"BM")?;
+    w.write_all(&(file_size as u32).to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(&(data_offset as u32).to_le_bytes())?;
+
+    w.write_all(&(header_size as u32).to_le_bytes())?;
+    w.write_all(&(width as i32).to_le_bytes())?;
+    w.write_all(&(height as i32).to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&bpp.to_le_bytes())?;
+    let compression = if has_alpha { BI_BITFIELDS } else { BI_RGB };
+    w.write_all(&compression.to_le_bytes())?;
+    w.write_all(&(image_size as u32).to_le_bytes())?;
+    // 72 dpi, in pixels per meter
+    w.write_all(&2835u32.to_le_bytes())?;
+    w.write_all(&2835u32.to_le_bytes())?;
+    w.write_all(&(palette_size as u32).to_le_bytes())?;
+    w.write_all(&0u32.to_le_bytes())?;
+
+    if has_alpha {
+        for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+            w.write_all(&mask.to_le_bytes())?;
+        }
+        // LCS_sRGB, with zeroed out endpoints and gamma
+        w.write_all(b"BGRs")?;
+        w.write_all(&[0; 48])?;
+    }
+
+    Ok(())
+}
+
+fn write_rows<W, F>(
+    w: &mut W,
+    (width, height): (usize, usize),
+    bpp: u16,
+    mut f: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(usize, usize, &mut Vec<u8>),
+{
+    let stride = row_stride(width, bpp);
+    let mut row = Vec::with_capacity(stride);
+    for y in (0..height).rev() {
+        row.clear();
+        for x in 0..width {
+            f(x, y, &mut row);
+        }
+        row.resize(stride, 0);
+        w.write_all(&row)?;
+    }
+    Ok(())
+}
+
+impl Encode<RawPixBuf<Gray>> for Bmp {
+    fn encode<W: Write>(mut w: W, _opts: (), buf: &RawPixBuf<Gray>) -> io::Result<()> {
+        let dims = buf.dimensions();
+        write_headers(&mut w, dims, 8, 256)?;
+        for i in 0..=255u8 {
+            w.write_all(&[i, i, i, 0])?;
+        }
+        write_rows(&mut w, dims, 8, |x, y, row| row.push(buf.color_get(x, y).y))
+    }
+}
+
+impl Encode<RawPixBuf<Rgb>> for Bmp {
+    fn encode<W: Write>(mut w: W, _opts: (), buf: &RawPixBuf<Rgb>) -> io::Result<()> {
+        let dims = buf.dimensions();
+        write_headers(&mut w, dims, 24, 0)?;
+        write_rows(&mut w, dims, 24, |x, y, row| {
+            let Rgb { r, g, b } = buf.color_get(x, y);
+            row.extend_from_slice(&[b, g, r]);
+        })
+    }
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Bmp {
+    fn encode<W: Write>(mut w: W, _opts: (), buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        let dims = buf.dimensions();
+        write_headers(&mut w, dims, 32, 0)?;
+        write_rows(&mut w, dims, 32, |x, y, row| {
+            let Nrgba { r, g, b, a } = buf.color_get(x, y);
+            row.extend_from_slice(&[b, g, r, a]);
+        })
+    }
+}
+
+impl Encode<BmpBuf> for Bmp {
+    fn encode<W: Write>(w: W, opts: (), buf: &BmpBuf) -> io::Result<()> {
+        match buf {
+            BmpBuf::Gray(buf) => Bmp::encode_specialized(w, opts, buf),
+            BmpBuf::Nrgba(buf) => Bmp::encode_specialized(w, opts, buf),
+            BmpBuf::Rgb(buf) => Bmp::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Bmp {
+    fn encode<W: Write>(w: W, opts: (), buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Bmp::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 + 11) as u8).collect()
+    }
+
+    // a 1x1, 32 bit image with the given bit masks
+    fn bitfields(masks: [u32; 3], pixel: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&70u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&66u32.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&1i32.to_le_bytes());
+        data.extend_from_slice(&1i32.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&BI_BITFIELDS.to_le_bytes());
+        data.extend_from_slice(&[0; 20]);
+        for mask in masks {
+            data.extend_from_slice(&mask.to_le_bytes());
+        }
+        data.extend_from_slice(&pixel.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let (w, h) = (5, 3);
+        let mut nrgba = pattern(w * h * 4);
+        for a in nrgba.iter_mut().skip(3).step_by(4) {
+            *a |= 1;
+        }
+        let bufs = [
+            BmpBuf::Gray(RawPixBuf::from_vec(w, h, pattern(w * h)).unwrap()),
+            BmpBuf::Rgb(RawPixBuf::from_vec(w, h, pattern(w * h * 3)).unwrap()),
+            BmpBuf::Nrgba(RawPixBuf::from_vec(w, h, nrgba).unwrap()),
+        ];
+        for buf in &bufs {
+            let mut out = Vec::new();
+            Bmp::encode_specialized(&mut out, (), buf).unwrap();
+            let decoded = Bmp::decode(&out[..], ()).unwrap();
+            assert_eq!(decoded.dimensions(), (w, h));
+            let same = match (buf, &decoded) {
+                (BmpBuf::Gray(a), BmpBuf::Gray(b)) => a.as_ref() == b.as_ref(),
+                (BmpBuf::Rgb(a), BmpBuf::Rgb(b)) => a.as_ref() == b.as_ref(),
+                (BmpBuf::Nrgba(a), BmpBuf::Nrgba(b)) => a.as_ref() == b.as_ref(),
+                _ => false,
+            };
+            assert!(same, "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn wide_masks_do_not_overflow() {
+        let data = bitfields([0xffffffff, 0x01ffffff, 0xff], 0x01ff_ff80);
+        assert_eq!(data.len(), 70);
+        match Bmp::decode(&data[..], ()).unwrap() {
+            BmpBuf::Rgb(buf) => assert_eq!(buf.as_ref(), &[0x02, 0xff, 0x80]),
+            other => panic!("unexpected buffer {:?}", other),
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let mut data = bitfields([0xff0000, 0xff00, 0xff], 0);
+        data[18..22].copy_from_slice(&0x7fffffffi32.to_le_bytes());
+        data[22..26].copy_from_slice(&(-0x7fffffffi32).to_le_bytes());
+        assert!(Bmp::decode(&data[..], ()).is_err());
+        data[18..22].copy_from_slice(&100_000i32.to_le_bytes());
+        data[22..26].copy_from_slice(&100_000i32.to_le_bytes());
+        assert!(Bmp::decode(&data[..], ()).is_err());
+    }
+}
@@ -0,0 +1,478 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{
+    Cmyk, Color, Gray, Gray16Be, Gray16Le, GrayF32, Nrgba, Nrgba64Be, Nrgba64Le, Rgb, Rgb48Be,
+    Rgb48Le, RgbF32, RgbaF32,
+};
+use crate::image::{Dimensions, Image, ImageMut};
+
+#[cfg(feature = "fmt-bmp")]
+use crate::image::bmp::BmpBuf;
+#[cfg(feature = "fmt-exr")]
+use crate::image::exr::{ExrBuf, ExrEncodeOptions};
+#[cfg(feature = "fmt-fits")]
+use crate::image::fits::{FitsBuf, FitsEncodeOptions};
+#[cfg(feature = "fmt-gif")]
+use crate::image::gif::GifEncodeOptions;
+#[cfg(feature = "fmt-hdr")]
+use crate::image::hdr::HdrEncodeOptions;
+#[cfg(feature = "fmt-ico")]
+use crate::image::ico::IcoBuf;
+#[cfg(feature = "fmt-jpeg")]
+use crate::image::jpeg::{JpegBuf, JpegEncodeOptions};
+#[cfg(feature = "fmt-png")]
+use crate::image::png::{PngBuf, PngEncodeOptions};
+#[cfg(feature = "fmt-pnm")]
+use crate::image::pnm::{PnmBuf, PnmEncodeOptions};
+#[cfg(feature = "fmt-qoi")]
+use crate::image::qoi::{QoiBuf, QoiEncodeOptions};
+#[cfg(feature = "fmt-tga")]
+use crate::image::tga::{TgaBuf, TgaEncodeOptions};
+#[cfg(feature = "fmt-tiff")]
+use crate::image::tiff::{TiffBuf, TiffEncodeOptions};
+#[cfg(feature = "fmt-webp")]
+use crate::image::webp::{NrgbaWebpBuf, RgbWebpBuf, WebpEncodeOptions};
+#[cfg(feature = "fmt-xpm")]
+use crate::image::xpm::{XbmEncodeOptions, XpmEncodeOptions};
+#[cfg(feature = "fmt-y4m")]
+use crate::image::y4m::Y4mEncodeOptions;
+#[cfg(feature = "fmt-webp")]
+use either::Either;
+
+/// An image decoded from any of the built-in formats, without
+/// knowing the format, or the pixel type, at compile time.
+#[derive(Clone, Debug)]
+pub enum DynImage {
+    Gray(RawPixBuf<Gray>),
+    Gray16Be(RawPixBuf<Gray16Be>),
+    Gray16Le(RawPixBuf<Gray16Le>),
+    GrayF32(RawPixBuf<GrayF32>),
+    Rgb(RawPixBuf<Rgb>),
+    Rgb48Be(RawPixBuf<Rgb48Be>),
+    Rgb48Le(RawPixBuf<Rgb48Le>),
+    RgbF32(RawPixBuf<RgbF32>),
+    Nrgba(RawPixBuf<Nrgba>),
+    Nrgba64Be(RawPixBuf<Nrgba64Be>),
+    Nrgba64Le(RawPixBuf<Nrgba64Le>),
+    RgbaF32(RawPixBuf<RgbaF32>),
+    Cmyk(RawPixBuf<Cmyk>),
+}
+
+impl Dimensions for DynImage {
+    fn width(&self) -> usize {
+        match self {
+            DynImage::Gray(buf) => buf.width(),
+            DynImage::Gray16Be(buf) => buf.width(),
+            DynImage::Gray16Le(buf) => buf.width(),
+            DynImage::GrayF32(buf) => buf.width(),
+            DynImage::Rgb(buf) => buf.width(),
+            DynImage::Rgb48Be(buf) => buf.width(),
+            DynImage::Rgb48Le(buf) => buf.width(),
+            DynImage::RgbF32(buf) => buf.width(),
+            DynImage::Nrgba(buf) => buf.width(),
+            DynImage::Nrgba64Be(buf) => buf.width(),
+            DynImage::Nrgba64Le(buf) => buf.width(),
+            DynImage::RgbaF32(buf) => buf.width(),
+            DynImage::Cmyk(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            DynImage::Gray(buf) => buf.height(),
+            DynImage::Gray16Be(buf) => buf.height(),
+            DynImage::Gray16Le(buf) => buf.height(),
+            DynImage::GrayF32(buf) => buf.height(),
+            DynImage::Rgb(buf) => buf.height(),
+            DynImage::Rgb48Be(buf) => buf.height(),
+            DynImage::Rgb48Le(buf) => buf.height(),
+            DynImage::RgbF32(buf) => buf.height(),
+            DynImage::Nrgba(buf) => buf.height(),
+            DynImage::Nrgba64Be(buf) => buf.height(),
+            DynImage::Nrgba64Le(buf) => buf.height(),
+            DynImage::RgbaF32(buf) => buf.height(),
+            DynImage::Cmyk(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for DynImage {
+    type Pixel = DynPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            DynImage::Gray(buf) => DynPix::Gray(buf.color_get(x, y)),
+            DynImage::Gray16Be(buf) => DynPix::Gray16Be(buf.color_get(x, y)),
+            DynImage::Gray16Le(buf) => DynPix::Gray16Le(buf.color_get(x, y)),
+            DynImage::GrayF32(buf) => DynPix::GrayF32(buf.color_get(x, y)),
+            DynImage::Rgb(buf) => DynPix::Rgb(buf.color_get(x, y)),
+            DynImage::Rgb48Be(buf) => DynPix::Rgb48Be(buf.color_get(x, y)),
+            DynImage::Rgb48Le(buf) => DynPix::Rgb48Le(buf.color_get(x, y)),
+            DynImage::RgbF32(buf) => DynPix::RgbF32(buf.color_get(x, y)),
+            DynImage::Nrgba(buf) => DynPix::Nrgba(buf.color_get(x, y)),
+            DynImage::Nrgba64Be(buf) => DynPix::Nrgba64Be(buf.color_get(x, y)),
+            DynImage::Nrgba64Le(buf) => DynPix::Nrgba64Le(buf.color_get(x, y)),
+            DynImage::RgbaF32(buf) => DynPix::RgbaF32(buf.color_get(x, y)),
+            DynImage::Cmyk(buf) => DynPix::Cmyk(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for DynImage {
+    type Pixel = DynPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<DynPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<DynPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (DynImage::Gray(buf), DynPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Gray16Be(buf), DynPix::Gray16Be(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Gray16Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Gray16Le(buf), DynPix::Gray16Le(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Gray16Le(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::GrayF32(buf), DynPix::GrayF32(c)) => buf.pixel_set(x, y, c),
+            (DynImage::GrayF32(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Rgb(buf), DynPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Rgb48Be(buf), DynPix::Rgb48Be(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Rgb48Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Rgb48Le(buf), DynPix::Rgb48Le(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Rgb48Le(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::RgbF32(buf), DynPix::RgbF32(c)) => buf.pixel_set(x, y, c),
+            (DynImage::RgbF32(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Nrgba(buf), DynPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Nrgba64Be(buf), DynPix::Nrgba64Be(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Nrgba64Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Nrgba64Le(buf), DynPix::Nrgba64Le(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Nrgba64Le(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::RgbaF32(buf), DynPix::RgbaF32(c)) => buf.pixel_set(x, y, c),
+            (DynImage::RgbaF32(buf), c) => buf.color_set_generic(x, y, c),
+
+            (DynImage::Cmyk(buf), DynPix::Cmyk(c)) => buf.pixel_set(x, y, c),
+            (DynImage::Cmyk(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum DynPix {
+    Gray(Gray),
+    Gray16Be(Gray16Be),
+    Gray16Le(Gray16Le),
+    GrayF32(GrayF32),
+    Rgb(Rgb),
+    Rgb48Be(Rgb48Be),
+    Rgb48Le(Rgb48Le),
+    RgbF32(RgbF32),
+    Nrgba(Nrgba),
+    Nrgba64Be(Nrgba64Be),
+    Nrgba64Le(Nrgba64Le),
+    RgbaF32(RgbaF32),
+    Cmyk(Cmyk),
+}
+
+impl Color for DynPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            DynPix::Gray(c) => c.as_rgba(),
+            DynPix::Gray16Be(c) => c.as_rgba(),
+            DynPix::Gray16Le(c) => c.as_rgba(),
+            DynPix::GrayF32(c) => c.as_rgba(),
+            DynPix::Rgb(c) => c.as_rgba(),
+            DynPix::Rgb48Be(c) => c.as_rgba(),
+            DynPix::Rgb48Le(c) => c.as_rgba(),
+            DynPix::RgbF32(c) => c.as_rgba(),
+            DynPix::Nrgba(c) => c.as_rgba(),
+            DynPix::Nrgba64Be(c) => c.as_rgba(),
+            DynPix::Nrgba64Le(c) => c.as_rgba(),
+            DynPix::RgbaF32(c) => c.as_rgba(),
+            DynPix::Cmyk(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for DynImage {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            DynImage::Gray(buf) => buf.as_ref(),
+            DynImage::Gray16Be(buf) => buf.as_ref(),
+            DynImage::Gray16Le(buf) => buf.as_ref(),
+            DynImage::GrayF32(buf) => buf.as_ref(),
+            DynImage::Rgb(buf) => buf.as_ref(),
+            DynImage::Rgb48Be(buf) => buf.as_ref(),
+            DynImage::Rgb48Le(buf) => buf.as_ref(),
+            DynImage::RgbF32(buf) => buf.as_ref(),
+            DynImage::Nrgba(buf) => buf.as_ref(),
+            DynImage::Nrgba64Be(buf) => buf.as_ref(),
+            DynImage::Nrgba64Le(buf) => buf.as_ref(),
+            DynImage::RgbaF32(buf) => buf.as_ref(),
+            DynImage::Cmyk(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for DynImage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            DynImage::Gray(buf) => buf.as_mut(),
+            DynImage::Gray16Be(buf) => buf.as_mut(),
+            DynImage::Gray16Le(buf) => buf.as_mut(),
+            DynImage::GrayF32(buf) => buf.as_mut(),
+            DynImage::Rgb(buf) => buf.as_mut(),
+            DynImage::Rgb48Be(buf) => buf.as_mut(),
+            DynImage::Rgb48Le(buf) => buf.as_mut(),
+            DynImage::RgbF32(buf) => buf.as_mut(),
+            DynImage::Nrgba(buf) => buf.as_mut(),
+            DynImage::Nrgba64Be(buf) => buf.as_mut(),
+            DynImage::Nrgba64Le(buf) => buf.as_mut(),
+            DynImage::RgbaF32(buf) => buf.as_mut(),
+            DynImage::Cmyk(buf) => buf.as_mut(),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+macro_rules! impl_from_raw {
+    ($($pixel:ident),*) => {
+        $(
+            impl From<RawPixBuf<$pixel>> for DynImage {
+                #[inline]
+                fn from(buf: RawPixBuf<$pixel>) -> Self {
+                    DynImage::$pixel(buf)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_raw!(
+    Gray, Gray16Be, Gray16Le, GrayF32, Rgb, Rgb48Be, Rgb48Le, RgbF32, Nrgba, Nrgba64Be, Nrgba64Le,
+    RgbaF32, Cmyk
+);
+
+#[cfg(feature = "fmt-jpeg")]
+impl From<JpegBuf> for DynImage {
+    fn from(buf: JpegBuf) -> Self {
+        match buf {
+            JpegBuf::Gray(buf) => DynImage::Gray(buf),
+            JpegBuf::Gray16(buf) => DynImage::Gray16Be(buf),
+            JpegBuf::Rgb(buf) => DynImage::Rgb(buf),
+            JpegBuf::Cmyk(buf) => DynImage::Cmyk(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-png")]
+impl From<PngBuf> for DynImage {
+    fn from(buf: PngBuf) -> Self {
+        match buf {
+            PngBuf::Gray(buf) => DynImage::Gray(buf),
+            PngBuf::Gray16(buf) => DynImage::Gray16Be(buf),
+            PngBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            PngBuf::Nrgba64(buf) => DynImage::Nrgba64Be(buf),
+            PngBuf::Rgb(buf) => DynImage::Rgb(buf),
+            PngBuf::Rgb48(buf) => DynImage::Rgb48Be(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-pnm")]
+impl From<PnmBuf> for DynImage {
+    fn from(buf: PnmBuf) -> Self {
+        match buf {
+            PnmBuf::Gray(buf) => DynImage::Gray(buf),
+            PnmBuf::Gray16(buf) => DynImage::Gray16Be(buf),
+            PnmBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            PnmBuf::Nrgba64(buf) => DynImage::Nrgba64Be(buf),
+            PnmBuf::Rgb(buf) => DynImage::Rgb(buf),
+            PnmBuf::Rgb48(buf) => DynImage::Rgb48Be(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-bmp")]
+impl From<BmpBuf> for DynImage {
+    fn from(buf: BmpBuf) -> Self {
+        match buf {
+            BmpBuf::Gray(buf) => DynImage::Gray(buf),
+            BmpBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            BmpBuf::Rgb(buf) => DynImage::Rgb(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-qoi")]
+impl From<QoiBuf> for DynImage {
+    fn from(buf: QoiBuf) -> Self {
+        match buf {
+            QoiBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            QoiBuf::Rgb(buf) => DynImage::Rgb(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-tga")]
+impl From<TgaBuf> for DynImage {
+    fn from(buf: TgaBuf) -> Self {
+        match buf {
+            TgaBuf::Gray(buf) => DynImage::Gray(buf),
+            TgaBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            TgaBuf::Rgb(buf) => DynImage::Rgb(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-tiff")]
+impl From<TiffBuf> for DynImage {
+    fn from(buf: TiffBuf) -> Self {
+        match buf {
+            TiffBuf::Gray(buf) => DynImage::Gray(buf),
+            TiffBuf::Gray16Be(buf) => DynImage::Gray16Be(buf),
+            TiffBuf::Gray16Le(buf) => DynImage::Gray16Le(buf),
+            TiffBuf::Rgb(buf) => DynImage::Rgb(buf),
+            TiffBuf::Rgb48Be(buf) => DynImage::Rgb48Be(buf),
+            TiffBuf::Rgb48Le(buf) => DynImage::Rgb48Le(buf),
+            TiffBuf::Nrgba(buf) => DynImage::Nrgba(buf),
+            TiffBuf::Nrgba64Be(buf) => DynImage::Nrgba64Be(buf),
+            TiffBuf::Nrgba64Le(buf) => DynImage::Nrgba64Le(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-exr")]
+impl From<ExrBuf> for DynImage {
+    fn from(buf: ExrBuf) -> Self {
+        match buf {
+            ExrBuf::Gray(buf) => DynImage::GrayF32(buf),
+            ExrBuf::Rgb(buf) => DynImage::RgbF32(buf),
+            ExrBuf::Rgba(buf) => DynImage::RgbaF32(buf),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-fits")]
+impl From<FitsBuf> for DynImage {
+    fn from(buf: FitsBuf) -> Self {
+        match buf {
+            FitsBuf::Gray(buf) => DynImage::Gray(buf),
+            FitsBuf::Gray16(buf) => DynImage::Gray16Be(buf),
+            FitsBuf::GrayF32(buf) => DynImage::GrayF32(buf),
+            FitsBuf::Rgb(buf) => DynImage::Rgb(buf),
+            FitsBuf::Rgb48(buf) => DynImage::Rgb48Be(buf),
+            FitsBuf::RgbF32(buf) => DynImage::RgbF32(buf),
+        }
+    }
+}
+#[cfg(feature = "fmt-ico")]
+impl From<IcoBuf> for DynImage {
+    fn from(buf: IcoBuf) -> Self {
+        match buf {
+            IcoBuf::Png(buf) => buf.into(),
+            IcoBuf::Bmp(buf) => buf.into(),
+        }
+    }
+}
+
+#[cfg(feature = "fmt-webp")]
+impl From<Either<RgbWebpBuf, NrgbaWebpBuf>> for DynImage {
+    fn from(buf: Either<RgbWebpBuf, NrgbaWebpBuf>) -> Self {
+        match &buf {
+            Either::Left(buf) => DynImage::Rgb(buf.into()),
+            Either::Right(buf) => DynImage::Nrgba(buf.into()),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+/// The encoding options of each of the built-in formats, used
+/// when the format is picked at runtime.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(not(feature = "fmt-webp"), derive(Default))]
+pub struct FormatOptions {
+    #[cfg(feature = "fmt-jpeg")]
+    pub jpeg: JpegEncodeOptions,
+    #[cfg(feature = "fmt-png")]
+    pub png: PngEncodeOptions,
+    #[cfg(feature = "fmt-pnm")]
+    pub pnm: PnmEncodeOptions,
+    #[cfg(feature = "fmt-qoi")]
+    pub qoi: QoiEncodeOptions,
+    #[cfg(feature = "fmt-tga")]
+    pub tga: TgaEncodeOptions,
+    #[cfg(feature = "fmt-gif")]
+    pub gif: GifEncodeOptions,
+    #[cfg(feature = "fmt-tiff")]
+    pub tiff: TiffEncodeOptions,
+    #[cfg(feature = "fmt-ico")]
+    pub ico: PngEncodeOptions,
+    #[cfg(feature = "fmt-hdr")]
+    pub hdr: HdrEncodeOptions,
+    #[cfg(feature = "fmt-exr")]
+    pub exr: ExrEncodeOptions,
+    #[cfg(feature = "fmt-webp")]
+    pub webp: WebpEncodeOptions,
+    #[cfg(feature = "fmt-xpm")]
+    pub xbm: XbmEncodeOptions,
+    #[cfg(feature = "fmt-xpm")]
+    pub xpm: XpmEncodeOptions,
+    #[cfg(feature = "fmt-y4m")]
+    pub y4m: Y4mEncodeOptions,
+    #[cfg(feature = "fmt-fits")]
+    pub fits: FitsEncodeOptions,
+}
+
+// the webp options have no `Default` of their own
+#[cfg(feature = "fmt-webp")]
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "fmt-jpeg")]
+            jpeg: Default::default(),
+            #[cfg(feature = "fmt-png")]
+            png: Default::default(),
+            #[cfg(feature = "fmt-pnm")]
+            pnm: Default::default(),
+            #[cfg(feature = "fmt-qoi")]
+            qoi: Default::default(),
+            #[cfg(feature = "fmt-tga")]
+            tga: Default::default(),
+            #[cfg(feature = "fmt-gif")]
+            gif: Default::default(),
+            #[cfg(feature = "fmt-tiff")]
+            tiff: Default::default(),
+            #[cfg(feature = "fmt-ico")]
+            ico: Default::default(),
+            #[cfg(feature = "fmt-hdr")]
+            hdr: Default::default(),
+            #[cfg(feature = "fmt-exr")]
+            exr: Default::default(),
+            #[cfg(feature = "fmt-webp")]
+            webp: crate::image::webp::default_config(),
+            #[cfg(feature = "fmt-xpm")]
+            xbm: Default::default(),
+            #[cfg(feature = "fmt-xpm")]
+            xpm: Default::default(),
+            #[cfg(feature = "fmt-y4m")]
+            y4m: Default::default(),
+            #[cfg(feature = "fmt-fits")]
+            fits: Default::default(),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, GrayF32, RgbF32, RgbaF32};
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// A decoded exr image. Alpha is premultiplied,
+/// as it is in the file.
+#[derive(Clone, Debug)]
+pub enum ExrBuf {
+    Gray(RawPixBuf<GrayF32>),
+    Rgb(RawPixBuf<RgbF32>),
+    Rgba(RawPixBuf<RgbaF32>),
+}
+
+impl Dimensions for ExrBuf {
+    fn width(&self) -> usize {
+        match self {
+            ExrBuf::Gray(buf) => buf.width(),
+            ExrBuf::Rgb(buf) => buf.width(),
+            ExrBuf::Rgba(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            ExrBuf::Gray(buf) => buf.height(),
+            ExrBuf::Rgb(buf) => buf.height(),
+            ExrBuf::Rgba(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for ExrBuf {
+    type Pixel = ExrPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            ExrBuf::Gray(buf) => ExrPix::Gray(buf.color_get(x, y)),
+            ExrBuf::Rgb(buf) => ExrPix::Rgb(buf.color_get(x, y)),
+            ExrBuf::Rgba(buf) => ExrPix::Rgba(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for ExrBuf {
+    type Pixel = ExrPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<ExrPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<ExrPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (ExrBuf::Gray(buf), ExrPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (ExrBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (ExrBuf::Rgb(buf), ExrPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (ExrBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+
+            (ExrBuf::Rgba(buf), ExrPix::Rgba(c)) => buf.pixel_set(x, y, c),
+            (ExrBuf::Rgba(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ExrPix {
+    Gray(GrayF32),
+    Rgb(RgbF32),
+    Rgba(RgbaF32),
+}
+
+impl Color for ExrPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            ExrPix::Gray(c) => c.as_rgba(),
+            ExrPix::Rgb(c) => c.as_rgba(),
+            ExrPix::Rgba(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for ExrBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            ExrBuf::Gray(buf) => buf.as_ref(),
+            ExrBuf::Rgb(buf) => buf.as_ref(),
+            ExrBuf::Rgba(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for ExrBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            ExrBuf::Gray(buf) => buf.as_mut(),
+            ExrBuf::Rgb(buf) => buf.as_mut(),
+            ExrBuf::Rgba(buf) => buf.as_mut(),
+        }
+    }
+}
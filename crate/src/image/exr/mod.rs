@@ -0,0 +1,850 @@
+mod exr_buf;
+
+pub use exr_buf::*;
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{GrayF32, RgbF32, RgbaF32, Zero};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const VERSION: u32 = 2;
+
+const FLAG_TILED: u32 = 0x200;
+const FLAG_DEEP: u32 = 0x800;
+const FLAG_MULTIPART: u32 = 0x1000;
+
+const PIXEL_UINT: i32 = 0;
+const PIXEL_HALF: i32 = 1;
+const PIXEL_FLOAT: i32 = 2;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_RLE: u8 = 1;
+const COMPRESSION_ZIPS: u8 = 2;
+const COMPRESSION_ZIP: u8 = 3;
+
+const LINE_ORDER_INCREASING_Y: u8 = 0;
+
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 127;
+
+pub struct Exr;
+
+impl_format! {
+    name: Exr,
+    id: "feim:exr",
+    magic: b"\x76\x2f\x31\x01",
+}
+
+/// The compression scheme applied to each block of scanlines.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ExrCompression {
+    None,
+    /// Run-length encoding, one scanline at a time.
+    Rle,
+    /// Deflate, one scanline at a time.
+    Zips,
+    /// Deflate, in blocks of 16 scanlines.
+    Zip,
+}
+
+/// The type of the samples written to the file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ExrPixelType {
+    /// 16 bit floats.
+    Half,
+    /// 32 bit floats.
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ExrEncodeOptions {
+    pub compression: ExrCompression,
+    pub pixel_type: ExrPixelType,
+}
+
+impl Default for ExrEncodeOptions {
+    fn default() -> Self {
+        Self {
+            compression: ExrCompression::Zip,
+            pixel_type: ExrPixelType::Half,
+        }
+    }
+}
+
+impl DecodeOptions for Exr {
+    type Options = ();
+}
+
+impl EncodeOptions for Exr {
+    type Options = ExrEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+#[inline]
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+#[inline]
+fn le_u64(b: &[u8]) -> u64 {
+    u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+}
+
+#[inline]
+fn le_i32(b: &[u8], at: usize) -> i32 {
+    le_u32(b, at) as i32
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let man = (h & 0x3ff) as u32;
+    let bits = match (exp, man) {
+        (0, 0) => sign,
+        (0, _) => {
+            // subnormal halves are normal floats
+            let v = man as f32 * 2f32.powi(-24);
+            return if sign != 0 { -v } else { v };
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (man << 13),
+        _ => sign | ((exp + 127 - 15) << 23) | (man << 13),
+    };
+    f32::from_bits(bits)
+}
+
+// rounds to the nearest half, ties to even
+fn f32_to_half(f: f32) -> u16 {
+    let bits = f.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        let nan = if man != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exp = exp - 127 + 15;
+    if exp >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exp <= 0 {
+        if exp < -10 {
+            return sign;
+        }
+        let man = man | 0x80_0000;
+        let shift = (14 - exp) as u32;
+        let half = (man >> shift) as u16;
+        let rest = man & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rest > halfway || (rest == halfway && half & 1 == 1);
+        return sign | (half + round as u16);
+    }
+
+    // a carry out of the mantissa correctly bumps the exponent
+    let half = sign | ((exp as u16) << 10) | (man >> 13) as u16;
+    let rest = man & 0x1fff;
+    if rest > 0x1000 || (rest == 0x1000 && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    }
+}
+
+struct Channel {
+    name: Vec<u8>,
+    pixel_type: i32,
+}
+
+impl Channel {
+    #[inline]
+    fn sample_size(&self) -> usize {
+        match self.pixel_type {
+            PIXEL_HALF => 2,
+            _ => 4,
+        }
+    }
+
+    #[inline]
+    fn sample(&self, b: &[u8]) -> f32 {
+        match self.pixel_type {
+            PIXEL_HALF => half_to_f32(u16::from_le_bytes([b[0], b[1]])),
+            PIXEL_FLOAT => f32::from_bits(le_u32(b, 0)),
+            _ => le_u32(b, 0) as f32,
+        }
+    }
+}
+
+fn read_str<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<&'a [u8]> {
+    let rest = data
+        .get(*pos..)
+        .ok_or_else(|| invalid("Truncated exr header"))?;
+    let end = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("Truncated exr header"))?;
+    *pos += end + 1;
+    Ok(&rest[..end])
+}
+
+fn parse_channels(value: &[u8]) -> io::Result<Vec<Channel>> {
+    let mut channels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let name = read_str(value, &mut pos)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let fields = value
+            .get(pos..pos + 16)
+            .ok_or_else(|| invalid("Truncated exr channel list"))?;
+        let pixel_type = le_i32(fields, 0);
+        if !(PIXEL_UINT..=PIXEL_FLOAT).contains(&pixel_type) {
+            return Err(invalid("Invalid exr pixel type"));
+        }
+        if le_i32(fields, 8) != 1 || le_i32(fields, 12) != 1 {
+            return Err(invalid("Subsampled exr channels are not supported"));
+        }
+        channels.push(Channel {
+            name: name.to_vec(),
+            pixel_type,
+        });
+        pos += 16;
+    }
+}
+
+fn lines_per_block(compression: u8) -> io::Result<usize> {
+    match compression {
+        COMPRESSION_NONE | COMPRESSION_RLE | COMPRESSION_ZIPS => Ok(1),
+        COMPRESSION_ZIP => Ok(16),
+        _ => Err(invalid("Unsupported exr compression")),
+    }
+}
+
+// splits the bytes into two halves, the even ones first
+// and the odd ones second, then stores differences
+// between neighbours, which compress better
+fn reorder(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = data.iter().step_by(2).copied().collect();
+    out.extend(data.iter().skip(1).step_by(2));
+    let mut previous = out.first().copied().unwrap_or(0);
+    for b in out.iter_mut().skip(1) {
+        let current = *b;
+        *b = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    out
+}
+
+fn unreorder(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    let (even, odd) = data.split_at(data.len().div_ceil(2));
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in even.iter().enumerate() {
+        out.push(b);
+        if let Some(&b) = odd.get(i) {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn rle_decode(mut raw: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    while let Some((&count, rest)) = raw.split_first() {
+        let count = count as i8;
+        if count < 0 {
+            let n = count.unsigned_abs() as usize;
+            let literal = rest
+                .get(..n)
+                .ok_or_else(|| invalid("Truncated exr rle data"))?;
+            out.extend_from_slice(literal);
+            raw = &rest[n..];
+        } else {
+            let &value = rest
+                .first()
+                .ok_or_else(|| invalid("Truncated exr rle data"))?;
+            out.resize(out.len() + count as usize + 1, value);
+            raw = &rest[1..];
+        }
+        if out.len() > len {
+            return Err(invalid("Invalid exr rle data"));
+        }
+    }
+    Ok(out)
+}
+
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let mut end = i + 1;
+        while end < data.len() && data[end] == data[i] && end - i <= MAX_RUN {
+            end += 1;
+        }
+        if end - i >= MIN_RUN {
+            out.push((end - i - 1) as u8);
+            out.push(data[i]);
+            i = end;
+            continue;
+        }
+        // extend the literal until a run starts
+        while end < data.len()
+            && end - i < MAX_RUN
+            && (end + 2 >= data.len() || data[end] != data[end + 1] || data[end] != data[end + 2])
+        {
+            end += 1;
+        }
+        out.push((end - i).wrapping_neg() as u8);
+        out.extend_from_slice(&data[i..end]);
+        i = end;
+    }
+    out
+}
+
+fn decompress(compression: u8, raw: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    // blocks which don't shrink are stored as they are
+    if raw.len() == len {
+        return Ok(raw.to_vec());
+    }
+    let data = match compression {
+        COMPRESSION_RLE => rle_decode(raw, len)?,
+        COMPRESSION_ZIPS | COMPRESSION_ZIP => {
+            miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(raw, len)
+                .map_err(|_| invalid("Invalid exr zip data"))?
+        }
+        _ => return Err(invalid("Invalid exr block size")),
+    };
+    if data.len() != len {
+        return Err(invalid("Invalid exr block size"));
+    }
+    Ok(unreorder(data))
+}
+
+fn compress(compression: ExrCompression, data: Vec<u8>) -> Vec<u8> {
+    let compressed = match compression {
+        ExrCompression::None => return data,
+        ExrCompression::Rle => rle_encode(&reorder(&data)),
+        ExrCompression::Zips | ExrCompression::Zip => {
+            miniz_oxide::deflate::compress_to_vec_zlib(&reorder(&data), 6)
+        }
+    };
+    if compressed.len() < data.len() {
+        compressed
+    } else {
+        data
+    }
+}
+
+struct Header {
+    channels: Vec<Channel>,
+    compression: u8,
+    width: usize,
+    height: usize,
+    y_min: i64,
+}
+
+fn check_version(data: &[u8]) -> io::Result<()> {
+    if data.len() < 8 || !data.starts_with(Exr.magic()) {
+        return Err(invalid("Invalid exr header."));
+    }
+    let version = le_u32(data, 4);
+    if version & 0xff != VERSION {
+        return Err(invalid("Unsupported exr version"));
+    }
+    if version & (FLAG_TILED | FLAG_DEEP | FLAG_MULTIPART) != 0 {
+        return Err(invalid(
+            "Only single part scanline exr images are supported",
+        ));
+    }
+    Ok(())
+}
+
+// parses the header, along with the magic and version
+// before it, up to the offset table
+fn parse_header(data: &[u8], pos: &mut usize) -> io::Result<Header> {
+    check_version(data)?;
+
+    *pos = 8;
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = read_str(data, pos)?;
+        if name.is_empty() {
+            break;
+        }
+        read_str(data, pos)?;
+        let size = data
+            .get(*pos..*pos + 4)
+            .map(|b| le_u32(b, 0) as usize)
+            .ok_or_else(|| invalid("Truncated exr header"))?;
+        *pos += 4;
+        let value = data
+            .get(*pos..pos.saturating_add(size))
+            .ok_or_else(|| invalid("Truncated exr header"))?;
+        *pos += size;
+
+        match (name, value.len()) {
+            (b"channels", _) => channels = Some(parse_channels(value)?),
+            (b"compression", 1) => compression = Some(value[0]),
+            (b"dataWindow", 16) => {
+                let coords = [0, 4, 8, 12].map(|at| le_i32(value, at) as i64);
+                data_window = Some(coords);
+            }
+            _ => (),
+        }
+    }
+    let channels = channels.ok_or_else(|| invalid("Missing exr channels"))?;
+    let compression = compression.ok_or_else(|| invalid("Missing exr compression"))?;
+    let [x_min, y_min, x_max, y_max] =
+        data_window.ok_or_else(|| invalid("Missing exr data window"))?;
+
+    let width = usize::try_from(x_max - x_min + 1).map_err(|_| invalid("Invalid exr width"))?;
+    let height = usize::try_from(y_max - y_min + 1).map_err(|_| invalid("Invalid exr height"))?;
+    Ok(Header {
+        channels,
+        compression,
+        width,
+        height,
+        y_min,
+    })
+}
+
+impl Probe for Exr {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        let mut r = BufReader::new(r);
+        let mut data = vec![0; 8];
+        r.read_exact(&mut data)?;
+        check_version(&data)?;
+
+        // gather the attributes of the header, each made of
+        // a name, a type, and a value prefixed by its size
+        loop {
+            let start = data.len();
+            r.read_until(0, &mut data)?;
+            if data[start..] == [0] {
+                break;
+            }
+            r.read_until(0, &mut data)?;
+            let mut size = [0; 4];
+            r.read_exact(&mut size)?;
+            data.extend_from_slice(&size);
+            let size = u32::from_le_bytes(size) as u64;
+            let read = (&mut r).take(size).read_to_end(&mut data)?;
+            if read as u64 != size {
+                return Err(invalid("Truncated exr header"));
+            }
+        }
+
+        let header = parse_header(&data, &mut 0)?;
+        let has = |name: &[u8]| header.channels.iter().any(|c| c.name == name);
+        let color_type = match (has(b"R") && has(b"G") && has(b"B"), has(b"A")) {
+            (true, false) => ColorType::Rgb,
+            (true, true) => ColorType::Rgba,
+            (false, _) if !has(b"Y") => {
+                return Err(invalid("Exr image has no color channels"));
+            }
+            (false, false) => ColorType::Gray,
+            (false, true) => ColorType::GrayAlpha,
+        };
+        let bit_depth = header
+            .channels
+            .iter()
+            .map(|c| c.sample_size() * 8)
+            .max()
+            .unwrap_or(0);
+        Ok(ImageInfo::new(
+            header.width,
+            header.height,
+            color_type,
+            bit_depth as u8,
+        ))
+    }
+}
+
+impl Decode<ExrBuf> for Exr {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<ExrBuf> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let mut pos = 0;
+        let Header {
+            channels,
+            compression,
+            width,
+            height,
+            y_min,
+        } = parse_header(&data, &mut pos)?;
+        let pixels =
+            image::pixel_count(width, height).ok_or_else(|| invalid("Exr image too large"))?;
+        if pixels == 0 {
+            return Err(invalid("Empty exr image"));
+        }
+
+        // where each channel goes in an rgba pixel, gray
+        // images have their luminance in the red slot
+        let slot_of = |name: &[u8]| match name {
+            b"R" => Some(0),
+            b"G" => Some(1),
+            b"B" => Some(2),
+            b"A" => Some(3),
+            _ => None,
+        };
+        let has = |name: &[u8]| channels.iter().any(|c| c.name == name);
+        let rgb = has(b"R") && has(b"G") && has(b"B");
+        let alpha = has(b"A");
+        if !rgb && !has(b"Y") {
+            return Err(invalid("Exr image has no color channels"));
+        }
+        let slots: Vec<Option<usize>> = channels
+            .iter()
+            .map(|c| match &c.name[..] {
+                b"Y" if !rgb => Some(0),
+                b"R" | b"G" | b"B" if !rgb => None,
+                name => slot_of(name),
+            })
+            .collect();
+
+        let line_bytes = channels
+            .iter()
+            .map(|c| c.sample_size() * width)
+            .sum::<usize>();
+        let block_lines = lines_per_block(compression)?;
+        let blocks = height.div_ceil(block_lines);
+        let offsets = data
+            .get(pos..pos + blocks * 8)
+            .ok_or_else(|| invalid("Truncated exr offset table"))?;
+
+        let mut samples = vec![[0.0, 0.0, 0.0, 1.0]; pixels];
+        for offset in offsets.chunks_exact(8) {
+            let offset = le_u64(offset);
+            let chunk = usize::try_from(offset)
+                .ok()
+                .and_then(|at| data.get(at..at.checked_add(8)?))
+                .ok_or_else(|| invalid("Invalid exr block offset"))?;
+            let start = usize::try_from(le_i32(chunk, 0) as i64 - y_min)
+                .ok()
+                .filter(|&y| y < height)
+                .ok_or_else(|| invalid("Invalid exr block position"))?;
+            let size = le_u32(chunk, 4) as usize;
+            let at = offset as usize + 8;
+            let raw = data
+                .get(at..at.saturating_add(size))
+                .ok_or_else(|| invalid("Truncated exr block"))?;
+
+            let lines = block_lines.min(height - start);
+            let block = decompress(compression, raw, lines * line_bytes)?;
+            for (y, line) in block.chunks_exact(line_bytes).enumerate() {
+                let row = &mut samples[(start + y) * width..(start + y + 1) * width];
+                let mut line = line;
+                for (channel, &slot) in channels.iter().zip(&slots) {
+                    let (values, rest) = line.split_at(channel.sample_size() * width);
+                    line = rest;
+                    let slot = match slot {
+                        Some(slot) => slot,
+                        None => continue,
+                    };
+                    for (pixel, value) in row
+                        .iter_mut()
+                        .zip(values.chunks_exact(channel.sample_size()))
+                    {
+                        pixel[slot] = channel.sample(value);
+                    }
+                }
+            }
+        }
+
+        Ok(match (rgb, alpha) {
+            (true, false) => ExrBuf::Rgb(collect(width, height, &samples, |[r, g, b, _]| RgbF32 {
+                r,
+                g,
+                b,
+            })),
+            (false, false) => {
+                ExrBuf::Gray(collect(width, height, &samples, |[y, ..]| GrayF32 { y }))
+            }
+            (true, true) => ExrBuf::Rgba(collect(width, height, &samples, |[r, g, b, a]| {
+                RgbaF32 { r, g, b, a }
+            })),
+            (false, true) => {
+                ExrBuf::Rgba(collect(width, height, &samples, |[y, _, _, a]| RgbaF32 {
+                    r: y,
+                    g: y,
+                    b: y,
+                    a,
+                }))
+            }
+        })
+    }
+}
+
+fn collect<T, F>(width: usize, height: usize, samples: &[[f32; 4]], f: F) -> RawPixBuf<T>
+where
+    T: Zero,
+    F: Fn([f32; 4]) -> T,
+{
+    let mut buf = RawPixBuf::new(width, height);
+    for (pixel, &sample) in buf.as_typed_mut().iter_mut().zip(samples) {
+        *pixel = f(sample);
+    }
+    buf
+}
+
+fn write_attribute<W: Write>(w: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(kind.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(&(value.len() as u32).to_le_bytes())?;
+    w.write_all(value)
+}
+
+// encodes an image whose pixels yield the samples
+// of the given channels, in alphabetical order
+fn encode_impl<W, F>(
+    mut w: W,
+    opts: ExrEncodeOptions,
+    (width, height): (usize, usize),
+    channels: &[&str],
+    pixel: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(usize) -> [f32; 4],
+{
+    if width == 0 || height == 0 {
+        return Err(invalid("Exr images must not be empty"));
+    }
+    let x_max = i32::try_from(width - 1).map_err(|_| invalid("Exr image too wide"))?;
+    let y_max = i32::try_from(height - 1).map_err(|_| invalid("Exr image too tall"))?;
+
+    let (pixel_type, sample_size) = match opts.pixel_type {
+        ExrPixelType::Half => (PIXEL_HALF, 2),
+        ExrPixelType::Float => (PIXEL_FLOAT, 4),
+    };
+    let (compression, block_lines) = match opts.compression {
+        ExrCompression::None => (COMPRESSION_NONE, 1),
+        ExrCompression::Rle => (COMPRESSION_RLE, 1),
+        ExrCompression::Zips => (COMPRESSION_ZIPS, 1),
+        ExrCompression::Zip => (COMPRESSION_ZIP, 16),
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(Exr.magic());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut list = Vec::new();
+    for name in channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&pixel_type.to_le_bytes());
+        // linear flag and reserved bytes, then the sampling
+        list.extend_from_slice(&[0; 4]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    let window: Vec<u8> = [0, 0, x_max, y_max]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+
+    write_attribute(&mut header, "channels", "chlist", &list)?;
+    write_attribute(&mut header, "compression", "compression", &[compression])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_attribute(
+        &mut header,
+        "lineOrder",
+        "lineOrder",
+        &[LINE_ORDER_INCREASING_Y],
+    )?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    header.push(0);
+
+    let blocks = height.div_ceil(block_lines);
+    let mut offset = (header.len() + blocks * 8) as u64;
+    let mut offsets = Vec::with_capacity(blocks * 8);
+    let mut chunks = Vec::with_capacity(blocks);
+    for start in (0..height).step_by(block_lines) {
+        let lines = block_lines.min(height - start);
+        let mut block = Vec::with_capacity(lines * width * channels.len() * sample_size);
+        for y in start..start + lines {
+            for c in 0..channels.len() {
+                for x in 0..width {
+                    let v = pixel(y * width + x)[c];
+                    match opts.pixel_type {
+                        ExrPixelType::Half => {
+                            block.extend_from_slice(&f32_to_half(v).to_le_bytes())
+                        }
+                        ExrPixelType::Float => block.extend_from_slice(&v.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let block = compress(opts.compression, block);
+        let size = u32::try_from(block.len()).map_err(|_| invalid("Exr image too large"))?;
+
+        offsets.extend_from_slice(&offset.to_le_bytes());
+        offset += 8 + block.len() as u64;
+        chunks.push((start as i32, size, block));
+    }
+
+    w.write_all(&header)?;
+    w.write_all(&offsets)?;
+    for (y, size, block) in chunks {
+        w.write_all(&y.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&block)?;
+    }
+    Ok(())
+}
+
+impl Encode<RawPixBuf<GrayF32>> for Exr {
+    fn encode<W: Write>(w: W, opts: ExrEncodeOptions, buf: &RawPixBuf<GrayF32>) -> io::Result<()> {
+        let pixels = buf.as_typed();
+        encode_impl(w, opts, buf.dimensions(), &["Y"], |i| {
+            [pixels[i].y, 0.0, 0.0, 0.0]
+        })
+    }
+}
+
+impl Encode<RawPixBuf<RgbF32>> for Exr {
+    fn encode<W: Write>(w: W, opts: ExrEncodeOptions, buf: &RawPixBuf<RgbF32>) -> io::Result<()> {
+        let pixels = buf.as_typed();
+        encode_impl(w, opts, buf.dimensions(), &["B", "G", "R"], |i| {
+            let c = pixels[i];
+            [c.b, c.g, c.r, 0.0]
+        })
+    }
+}
+
+impl Encode<RawPixBuf<RgbaF32>> for Exr {
+    fn encode<W: Write>(w: W, opts: ExrEncodeOptions, buf: &RawPixBuf<RgbaF32>) -> io::Result<()> {
+        let pixels = buf.as_typed();
+        encode_impl(w, opts, buf.dimensions(), &["A", "B", "G", "R"], |i| {
+            let c = pixels[i];
+            [c.a, c.b, c.g, c.r]
+        })
+    }
+}
+
+impl Encode<ExrBuf> for Exr {
+    fn encode<W: Write>(w: W, opts: ExrEncodeOptions, buf: &ExrBuf) -> io::Result<()> {
+        match buf {
+            ExrBuf::Gray(buf) => Exr::encode_specialized(w, opts, buf),
+            ExrBuf::Rgb(buf) => Exr::encode_specialized(w, opts, buf),
+            ExrBuf::Rgba(buf) => Exr::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Exr {
+    fn encode<W: Write>(w: W, opts: ExrEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: RgbaF32 = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Exr::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(i: usize) -> f32 {
+        ((i / 3) % 17) as f32 / 4.0
+    }
+
+    fn same(a: &ExrBuf, b: &ExrBuf) -> bool {
+        match (a, b) {
+            (ExrBuf::Gray(a), ExrBuf::Gray(b)) => a.as_typed() == b.as_typed(),
+            (ExrBuf::Rgb(a), ExrBuf::Rgb(b)) => a.as_typed() == b.as_typed(),
+            (ExrBuf::Rgba(a), ExrBuf::Rgba(b)) => a.as_typed() == b.as_typed(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let (w, h) = (20, 19);
+        let mut gray: RawPixBuf<GrayF32> = RawPixBuf::new(w, h);
+        let mut rgb: RawPixBuf<RgbF32> = RawPixBuf::new(w, h);
+        let mut rgba: RawPixBuf<RgbaF32> = RawPixBuf::new(w, h);
+        for i in 0..w * h {
+            let (y, v) = (sample(i), sample(i * 7));
+            gray.as_typed_mut()[i] = GrayF32 { y };
+            rgb.as_typed_mut()[i] = RgbF32 { r: y, g: v, b: 1.0 };
+            rgba.as_typed_mut()[i] = RgbaF32 {
+                r: y,
+                g: v,
+                b: 1.0,
+                a: 0.5,
+            };
+        }
+        let bufs = [ExrBuf::Gray(gray), ExrBuf::Rgb(rgb), ExrBuf::Rgba(rgba)];
+        let compressions = [
+            ExrCompression::None,
+            ExrCompression::Rle,
+            ExrCompression::Zips,
+            ExrCompression::Zip,
+        ];
+        for buf in &bufs {
+            for &compression in &compressions {
+                for &pixel_type in &[ExrPixelType::Half, ExrPixelType::Float] {
+                    let opts = ExrEncodeOptions {
+                        compression,
+                        pixel_type,
+                    };
+                    let mut out = Vec::new();
+                    Exr::encode_specialized(&mut out, opts, buf).unwrap();
+                    let decoded = Exr::decode(&out[..], ()).unwrap();
+                    assert!(same(buf, &decoded), "{:?}", opts);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let buf = ExrBuf::Gray(RawPixBuf::new(1, 1));
+        let mut out = Vec::new();
+        Exr::encode_specialized(&mut out, Default::default(), &buf).unwrap();
+        let name = b"dataWindow\0box2i\0\x10\0\0\0";
+        let at = out.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+        for &(x_max, y_max) in &[(i32::MAX - 1, i32::MAX - 1), (30_000, 30_000)] {
+            out[at + 8..at + 12].copy_from_slice(&x_max.to_le_bytes());
+            out[at + 12..at + 16].copy_from_slice(&y_max.to_le_bytes());
+            assert!(Exr::decode(&out[..], ()).is_err());
+        }
+    }
+}
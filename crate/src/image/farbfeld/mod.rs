@@ -1,11 +1,11 @@
 use std::io::{self, Read, Write};
 
-use super::{Dimensions, Format, Image};
+use super::{ColorType, Dimensions, Format, Image, ImageInfo};
 use crate::buffer::RawPixBuf;
 use crate::color::convert::ConvertInto;
 use crate::color::{BigEndian, NativeEndian, Nrgba64};
 use crate::impl_format;
-use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions};
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, Probe};
 use crate::specialized;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -82,9 +82,7 @@ impl Decode<RawPixBuf<Nrgba64<BigEndian>>> for Farbfeld {
         let mut m: [u8; 16] = [0; 16];
         r.read_exact(&mut m[..])?;
         if opt.check_header && !Farbfeld.is_valid_magic(&m[..]) {
-            let k = std::io::ErrorKind::Other;
-            let e = std::io::Error::new(k, "Invalid farbfeld magic.");
-            return Err(e);
+            return Err(io::Error::other("Invalid farbfeld magic."));
         }
         let width = u32::from_be_bytes([m[8], m[9], m[10], m[11]]) as usize;
         let height = u32::from_be_bytes([m[12], m[13], m[14], m[15]]) as usize;
@@ -93,3 +91,16 @@ impl Decode<RawPixBuf<Nrgba64<BigEndian>>> for Farbfeld {
         Ok(buf)
     }
 }
+
+impl Probe for Farbfeld {
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let mut m: [u8; 16] = [0; 16];
+        r.read_exact(&mut m[..])?;
+        if !Farbfeld.is_valid_magic(&m[..]) {
+            return Err(io::Error::other("Invalid farbfeld magic."));
+        }
+        let width = u32::from_be_bytes([m[8], m[9], m[10], m[11]]) as usize;
+        let height = u32::from_be_bytes([m[12], m[13], m[14], m[15]]) as usize;
+        Ok(ImageInfo::new(width, height, ColorType::Rgba, 16))
+    }
+}
@@ -0,0 +1,134 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Gray, Gray16Be, GrayF32, Rgb, Rgb48Be, RgbF32};
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// A decoded fits image. Float buffers hold the physical
+/// values of the samples, unless they were normalized.
+#[derive(Clone, Debug)]
+pub enum FitsBuf {
+    Gray(RawPixBuf<Gray>),
+    Gray16(RawPixBuf<Gray16Be>),
+    GrayF32(RawPixBuf<GrayF32>),
+    Rgb(RawPixBuf<Rgb>),
+    Rgb48(RawPixBuf<Rgb48Be>),
+    RgbF32(RawPixBuf<RgbF32>),
+}
+
+impl Dimensions for FitsBuf {
+    fn width(&self) -> usize {
+        match self {
+            FitsBuf::Gray(buf) => buf.width(),
+            FitsBuf::Gray16(buf) => buf.width(),
+            FitsBuf::GrayF32(buf) => buf.width(),
+            FitsBuf::Rgb(buf) => buf.width(),
+            FitsBuf::Rgb48(buf) => buf.width(),
+            FitsBuf::RgbF32(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            FitsBuf::Gray(buf) => buf.height(),
+            FitsBuf::Gray16(buf) => buf.height(),
+            FitsBuf::GrayF32(buf) => buf.height(),
+            FitsBuf::Rgb(buf) => buf.height(),
+            FitsBuf::Rgb48(buf) => buf.height(),
+            FitsBuf::RgbF32(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for FitsBuf {
+    type Pixel = FitsPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            FitsBuf::Gray(buf) => FitsPix::Gray(buf.color_get(x, y)),
+            FitsBuf::Gray16(buf) => FitsPix::Gray16(buf.color_get(x, y)),
+            FitsBuf::GrayF32(buf) => FitsPix::GrayF32(buf.color_get(x, y)),
+            FitsBuf::Rgb(buf) => FitsPix::Rgb(buf.color_get(x, y)),
+            FitsBuf::Rgb48(buf) => FitsPix::Rgb48(buf.color_get(x, y)),
+            FitsBuf::RgbF32(buf) => FitsPix::RgbF32(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for FitsBuf {
+    type Pixel = FitsPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<FitsPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<FitsPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (FitsBuf::Gray(buf), FitsPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (FitsBuf::Gray16(buf), FitsPix::Gray16(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::Gray16(buf), c) => buf.color_set_generic(x, y, c),
+
+            (FitsBuf::GrayF32(buf), FitsPix::GrayF32(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::GrayF32(buf), c) => buf.color_set_generic(x, y, c),
+
+            (FitsBuf::Rgb(buf), FitsPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+
+            (FitsBuf::Rgb48(buf), FitsPix::Rgb48(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::Rgb48(buf), c) => buf.color_set_generic(x, y, c),
+
+            (FitsBuf::RgbF32(buf), FitsPix::RgbF32(c)) => buf.pixel_set(x, y, c),
+            (FitsBuf::RgbF32(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum FitsPix {
+    Gray(Gray),
+    Gray16(Gray16Be),
+    GrayF32(GrayF32),
+    Rgb(Rgb),
+    Rgb48(Rgb48Be),
+    RgbF32(RgbF32),
+}
+
+impl Color for FitsPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            FitsPix::Gray(c) => c.as_rgba(),
+            FitsPix::Gray16(c) => c.as_rgba(),
+            FitsPix::GrayF32(c) => c.as_rgba(),
+            FitsPix::Rgb(c) => c.as_rgba(),
+            FitsPix::Rgb48(c) => c.as_rgba(),
+            FitsPix::RgbF32(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for FitsBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            FitsBuf::Gray(buf) => buf.as_ref(),
+            FitsBuf::Gray16(buf) => buf.as_ref(),
+            FitsBuf::GrayF32(buf) => buf.as_ref(),
+            FitsBuf::Rgb(buf) => buf.as_ref(),
+            FitsBuf::Rgb48(buf) => buf.as_ref(),
+            FitsBuf::RgbF32(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for FitsBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            FitsBuf::Gray(buf) => buf.as_mut(),
+            FitsBuf::Gray16(buf) => buf.as_mut(),
+            FitsBuf::GrayF32(buf) => buf.as_mut(),
+            FitsBuf::Rgb(buf) => buf.as_mut(),
+            FitsBuf::Rgb48(buf) => buf.as_mut(),
+            FitsBuf::RgbF32(buf) => buf.as_mut(),
+        }
+    }
+}
@@ -0,0 +1,487 @@
+mod fits_buf;
+
+pub use fits_buf::*;
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::buffer::{AsTyped, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Gray, Gray16Be, GrayF32, Rgb, Rgb48Be, RgbF32};
+use crate::image::{ColorType, Dimensions, Format, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+// headers and data are stored in blocks of this many bytes
+const BLOCK_LEN: usize = 2880;
+
+// each header record is a card of this many characters
+const CARD_LEN: usize = 80;
+
+// the offset turning signed samples into unsigned ones
+const ZERO_16: f64 = 32768.0;
+const ZERO_32: f64 = 2147483648.0;
+
+/// Flexible Image Transport System images. Only the primary
+/// data unit is read, as a 2D image or a cube of three color
+/// planes. Rows are stored from the bottom of the image.
+pub struct Fits;
+
+impl_format! {
+    name: Fits,
+    id: "feim:fits",
+    magic: b"SIMPLE  =                    T",
+}
+
+/// The type of the samples of an image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum FitsBitpix {
+    /// Unsigned 8 bit integers.
+    U8,
+    /// 16 bit integers, offset to be unsigned.
+    I16,
+    /// 32 bit integers, offset to be unsigned.
+    I32,
+    /// 32 bit floats.
+    F32,
+    /// 64 bit floats.
+    F64,
+}
+
+impl FitsBitpix {
+    fn from_value(bitpix: i64) -> io::Result<Self> {
+        match bitpix {
+            8 => Ok(FitsBitpix::U8),
+            16 => Ok(FitsBitpix::I16),
+            32 => Ok(FitsBitpix::I32),
+            -32 => Ok(FitsBitpix::F32),
+            -64 => Ok(FitsBitpix::F64),
+            _ => Err(invalid("Unsupported fits bitpix")),
+        }
+    }
+
+    fn value(self) -> i32 {
+        match self {
+            FitsBitpix::U8 => 8,
+            FitsBitpix::I16 => 16,
+            FitsBitpix::I32 => 32,
+            FitsBitpix::F32 => -32,
+            FitsBitpix::F64 => -64,
+        }
+    }
+
+    #[inline]
+    fn sample_len(self) -> usize {
+        self.value().unsigned_abs() as usize / 8
+    }
+
+    // the largest value of integer samples, after the offset
+    fn max(self) -> Option<f64> {
+        match self {
+            FitsBitpix::U8 => Some(u8::MAX as f64),
+            FitsBitpix::I16 => Some(u16::MAX as f64),
+            FitsBitpix::I32 => Some(u32::MAX as f64),
+            FitsBitpix::F32 | FitsBitpix::F64 => None,
+        }
+    }
+
+    // reads a sample, as stored
+    #[inline]
+    fn read(self, b: &[u8]) -> f64 {
+        match self {
+            FitsBitpix::U8 => b[0] as f64,
+            FitsBitpix::I16 => i16::from_be_bytes([b[0], b[1]]) as f64,
+            FitsBitpix::I32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            FitsBitpix::F32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            FitsBitpix::F64 => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+        }
+    }
+
+    // writes a sample from 0 to 1, scaled to the range of integers
+    #[inline]
+    fn write(self, out: &mut Vec<u8>, v: f64) {
+        let scaled = |max: f64| (v * max).round().clamp(0.0, max);
+        match self {
+            FitsBitpix::U8 => out.push(scaled(u8::MAX as f64) as u8),
+            FitsBitpix::I16 => {
+                let v = (scaled(u16::MAX as f64) - ZERO_16) as i16;
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+            FitsBitpix::I32 => {
+                let v = (scaled(u32::MAX as f64) - ZERO_32) as i32;
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+            FitsBitpix::F32 => out.extend_from_slice(&(v as f32).to_be_bytes()),
+            FitsBitpix::F64 => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FitsDecodeOptions {
+    /// Rescale float samples, so the smallest and largest
+    /// values of the image become 0 and 1.
+    pub normalize: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FitsEncodeOptions {
+    /// The type of the samples written. If none is given, it
+    /// follows the buffer. Samples of integer types span their
+    /// whole range, while floats go from 0 to 1.
+    pub bitpix: Option<FitsBitpix>,
+}
+
+impl DecodeOptions for Fits {
+    type Options = FitsDecodeOptions;
+}
+
+impl EncodeOptions for Fits {
+    type Options = FitsEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    planes: usize,
+    bitpix: FitsBitpix,
+    bzero: f64,
+    bscale: f64,
+}
+
+// the value of a card, without its comment
+fn card_value(card: &[u8]) -> Option<&str> {
+    let value = card.get(10..)?;
+    if &card[8..10] != b"= " {
+        return None;
+    }
+    let value = std::str::from_utf8(value).ok()?;
+    Some(value.split('/').next().unwrap_or_default().trim())
+}
+
+fn parse_int(value: Option<&str>) -> io::Result<i64> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid("Invalid fits integer"))
+}
+
+fn parse_float(value: Option<&str>) -> io::Result<f64> {
+    // exponents may be written the fortran way
+    value
+        .and_then(|v| v.replace(['D', 'd'], "E").parse().ok())
+        .ok_or_else(|| invalid("Invalid fits number"))
+}
+
+fn read_header<R: Read>(r: &mut R) -> io::Result<Header> {
+    let mut bitpix = None;
+    let mut axes = Vec::new();
+    let (mut bzero, mut bscale) = (0.0, 1.0);
+    let mut block = [0; BLOCK_LEN];
+    let mut first = true;
+    loop {
+        r.read_exact(&mut block)?;
+        if first && !Fits.is_valid_magic(&block) {
+            return Err(invalid("Invalid fits header."));
+        }
+        first = false;
+        for card in block.chunks_exact(CARD_LEN) {
+            let key = std::str::from_utf8(&card[..8])
+                .unwrap_or_default()
+                .trim_end();
+            let value = card_value(card);
+            match key {
+                "END" => {
+                    let bitpix = bitpix.ok_or_else(|| invalid("Missing fits bitpix"))?;
+                    return header(bitpix, &axes, bzero, bscale);
+                }
+                "BITPIX" => bitpix = Some(FitsBitpix::from_value(parse_int(value)?)?),
+                "NAXIS" => {
+                    let naxis = parse_int(value)?;
+                    if !(0..=999).contains(&naxis) {
+                        return Err(invalid("Invalid fits axes"));
+                    }
+                    axes = vec![None; naxis as usize];
+                }
+                "BZERO" => bzero = parse_float(value)?,
+                "BSCALE" => bscale = parse_float(value)?,
+                _ => {
+                    let axis = key
+                        .strip_prefix("NAXIS")
+                        .and_then(|n| n.parse::<usize>().ok());
+                    if let Some(slot) = axis.and_then(|n| axes.get_mut(n.wrapping_sub(1))) {
+                        let len = usize::try_from(parse_int(value)?)
+                            .map_err(|_| invalid("Invalid fits axes"))?;
+                        *slot = Some(len);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn header(
+    bitpix: FitsBitpix,
+    axes: &[Option<usize>],
+    bzero: f64,
+    bscale: f64,
+) -> io::Result<Header> {
+    let axes = axes
+        .iter()
+        .copied()
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| invalid("Missing fits axes"))?;
+    // extra axes of a single element add nothing
+    let (width, height, planes) = match axes[..] {
+        [width, height] => (width, height, 1),
+        [width, height, planes, ref rest @ ..] if rest.iter().all(|&n| n == 1) => {
+            (width, height, planes)
+        }
+        _ => return Err(invalid("Unsupported fits axes")),
+    };
+    if planes != 1 && planes != 3 {
+        return Err(invalid("Unsupported fits planes"));
+    }
+    Ok(Header {
+        width,
+        height,
+        planes,
+        bitpix,
+        bzero,
+        bscale,
+    })
+}
+
+impl Probe for Fits {
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let header = read_header(&mut r)?;
+        let color_type = match header.planes {
+            1 => ColorType::Gray,
+            _ => ColorType::Rgb,
+        };
+        let bit_depth = header.bitpix.value().unsigned_abs() as u8;
+        Ok(ImageInfo::new(
+            header.width,
+            header.height,
+            color_type,
+            bit_depth,
+        ))
+    }
+}
+
+// rescales finite samples between 0 and 1
+fn normalize(samples: &mut [f32]) {
+    let finite = samples.iter().copied().filter(|v| v.is_finite());
+    let min = finite.clone().fold(f32::INFINITY, f32::min);
+    let max = finite.fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    if range.is_finite() && range > 0.0 {
+        for v in samples.iter_mut() {
+            *v = (*v - min) / range;
+        }
+    }
+}
+
+impl Decode<FitsBuf> for Fits {
+    fn decode<R: Read>(mut r: R, opts: FitsDecodeOptions) -> io::Result<FitsBuf> {
+        let Header {
+            width,
+            height,
+            planes,
+            bitpix,
+            bzero,
+            bscale,
+        } = read_header(&mut r)?;
+        let plane_len = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid("Fits image too large"))?;
+        let len = plane_len
+            .checked_mul(planes * bitpix.sample_len())
+            .ok_or_else(|| invalid("Fits image too large"))?;
+
+        // read the samples as they come, rather than trusting
+        // the header with a large allocation
+        let mut data = Vec::new();
+        r.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(invalid("Truncated fits data"));
+        }
+
+        // the index of a sample, with rows flipped upright,
+        // and planes interleaved
+        let index = |i: usize| {
+            let (plane, i) = (i / plane_len.max(1), i % plane_len.max(1));
+            let (x, y) = (i % width, i / width);
+            ((height - 1 - y) * width + x) * planes + plane
+        };
+        let samples = data.chunks_exact(bitpix.sample_len());
+        let wrong_size = |_| invalid("Fits raster size mismatch");
+
+        // samples with no scaling fit integer buffers
+        let buf = match (bitpix, bzero, bscale) {
+            (FitsBitpix::U8, z, s) if z == 0.0 && s == 1.0 => {
+                let mut buf = vec![0; len];
+                for (i, s) in samples.enumerate() {
+                    buf[index(i)] = s[0];
+                }
+                match planes {
+                    1 => {
+                        FitsBuf::Gray(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?)
+                    }
+                    _ => FitsBuf::Rgb(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+                }
+            }
+            (FitsBitpix::I16, z, s) if z == ZERO_16 && s == 1.0 => {
+                let mut buf = vec![0; len];
+                for (i, s) in samples.enumerate() {
+                    let j = index(i) * 2;
+                    buf[j..j + 2].copy_from_slice(&[s[0] ^ 0x80, s[1]]);
+                }
+                match planes {
+                    1 => FitsBuf::Gray16(
+                        RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?,
+                    ),
+                    _ => {
+                        FitsBuf::Rgb48(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?)
+                    }
+                }
+            }
+            _ => {
+                let mut values = vec![0f32; plane_len * planes];
+                for (i, s) in samples.enumerate() {
+                    values[index(i)] = (bitpix.read(s) * bscale + bzero) as f32;
+                }
+                if opts.normalize {
+                    normalize(&mut values);
+                }
+                let buf = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+                match planes {
+                    1 => FitsBuf::GrayF32(
+                        RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?,
+                    ),
+                    _ => FitsBuf::RgbF32(
+                        RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?,
+                    ),
+                }
+            }
+        };
+        Ok(buf)
+    }
+}
+
+fn write_card<W: Write>(w: &mut W, key: &str, value: &str) -> io::Result<()> {
+    // fixed format values end at the 30th column
+    write!(w, "{:<8}= {:>20}{:50}", key, value, "")
+}
+
+fn encode_impl<W, F>(
+    mut w: W,
+    opts: FitsEncodeOptions,
+    (width, height): (usize, usize),
+    planes: usize,
+    natural: FitsBitpix,
+    sample: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(usize, usize) -> f64,
+{
+    let bitpix = opts.bitpix.unwrap_or(natural);
+    // samples are scaled between 0 and 1 in between
+    let scale = natural.max().unwrap_or(1.0);
+
+    let mut header = Vec::with_capacity(BLOCK_LEN);
+    write_card(&mut header, "SIMPLE", "T")?;
+    write_card(&mut header, "BITPIX", &bitpix.value().to_string())?;
+    write_card(&mut header, "NAXIS", if planes == 1 { "2" } else { "3" })?;
+    write_card(&mut header, "NAXIS1", &width.to_string())?;
+    write_card(&mut header, "NAXIS2", &height.to_string())?;
+    if planes != 1 {
+        write_card(&mut header, "NAXIS3", &planes.to_string())?;
+    }
+    let zero = match bitpix {
+        FitsBitpix::I16 => Some(ZERO_16),
+        FitsBitpix::I32 => Some(ZERO_32),
+        _ => None,
+    };
+    if let Some(zero) = zero {
+        write_card(&mut header, "BZERO", &zero.to_string())?;
+        write_card(&mut header, "BSCALE", "1")?;
+    }
+    write!(header, "{:80}", "END")?;
+    header.resize(header.len().div_ceil(BLOCK_LEN) * BLOCK_LEN, b' ');
+    w.write_all(&header)?;
+
+    let mut data = Vec::with_capacity(width * height * planes * bitpix.sample_len());
+    for plane in 0..planes {
+        for y in (0..height).rev() {
+            for x in 0..width {
+                bitpix.write(&mut data, sample(y * width + x, plane) / scale);
+            }
+        }
+    }
+    data.resize(data.len().div_ceil(BLOCK_LEN) * BLOCK_LEN, 0);
+    w.write_all(&data)
+}
+
+macro_rules! impl_encode {
+    ($type:ty, $planes:expr, $natural:expr, $sample:expr) => {
+        impl Encode<RawPixBuf<$type>> for Fits {
+            fn encode<W: Write>(
+                w: W,
+                opts: FitsEncodeOptions,
+                buf: &RawPixBuf<$type>,
+            ) -> io::Result<()> {
+                let pixels = buf.as_typed();
+                let sample: fn(&$type, usize) -> f64 = $sample;
+                encode_impl(w, opts, buf.dimensions(), $planes, $natural, |i, plane| {
+                    sample(&pixels[i], plane)
+                })
+            }
+        }
+    };
+}
+
+impl_encode!(Gray, 1, FitsBitpix::U8, |c, _| c.y as f64);
+impl_encode!(Gray16Be, 1, FitsBitpix::I16, |c, _| c.y() as f64);
+impl_encode!(GrayF32, 1, FitsBitpix::F32, |c, _| c.y as f64);
+impl_encode!(Rgb, 3, FitsBitpix::U8, |c, plane| [c.r, c.g, c.b][plane]
+    as f64);
+impl_encode!(Rgb48Be, 3, FitsBitpix::I16, |c, plane| {
+    [c.r(), c.g(), c.b()][plane] as f64
+});
+impl_encode!(RgbF32, 3, FitsBitpix::F32, |c, plane| {
+    [c.r, c.g, c.b][plane] as f64
+});
+
+impl Encode<FitsBuf> for Fits {
+    fn encode<W: Write>(w: W, opts: FitsEncodeOptions, buf: &FitsBuf) -> io::Result<()> {
+        match buf {
+            FitsBuf::Gray(buf) => Fits::encode_specialized(w, opts, buf),
+            FitsBuf::Gray16(buf) => Fits::encode_specialized(w, opts, buf),
+            FitsBuf::GrayF32(buf) => Fits::encode_specialized(w, opts, buf),
+            FitsBuf::Rgb(buf) => Fits::encode_specialized(w, opts, buf),
+            FitsBuf::Rgb48(buf) => Fits::encode_specialized(w, opts, buf),
+            FitsBuf::RgbF32(buf) => Fits::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Fits {
+    fn encode<W: Write>(w: W, opts: FitsEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Rgb48Be = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Fits::encode_specialized(w, opts, &new_buf)
+    }
+}
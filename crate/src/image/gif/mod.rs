@@ -0,0 +1,534 @@
+use std::convert::TryFrom;
+use std::io::{self, BufReader, Read, Write};
+use std::iter;
+use std::time::Duration;
+
+use gif::{ColorOutput, Decoder, DecodingError, DisposalMethod, Encoder, EncodingError, Repeat};
+
+use crate::buffer::{PalettedBuf, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Nrgba, Zero};
+use crate::image::animation::{Composite, Disposal, Frame, Frames, LoopCount};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
+use crate::impl_format;
+use crate::serialize::{
+    Decode, DecodeFrames, DecodeOptions, Encode, EncodeFrames, EncodeOptions, EncodeSpecialized,
+    Probe,
+};
+use crate::specialized;
+
+pub struct Gif;
+
+impl_format! {
+    name: Gif,
+    id: "feim:gif",
+    magic: b"GIF8",
+    signatures: &[Signature::new(b"GIF87a"), Signature::new(b"GIF89a")],
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct GifEncodeOptions {
+    /// Quantization speed of true color frames, from 1 to 30.
+    /// Higher values are faster, at the cost of quality.
+    pub speed: i32,
+}
+
+impl Default for GifEncodeOptions {
+    fn default() -> Self {
+        Self { speed: 10 }
+    }
+}
+
+impl DecodeOptions for Gif {
+    type Options = ();
+}
+
+impl EncodeOptions for Gif {
+    type Options = GifEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+fn decoding_error(e: DecodingError) -> io::Error {
+    match e {
+        DecodingError::Io(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+fn encoding_error(e: EncodingError) -> io::Error {
+    match e {
+        EncodingError::Io(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+fn disposal_from_gif(method: DisposalMethod) -> Disposal {
+    match method {
+        DisposalMethod::Any | DisposalMethod::Keep => Disposal::None,
+        DisposalMethod::Background => Disposal::Background,
+        DisposalMethod::Previous => Disposal::Previous,
+    }
+}
+
+fn disposal_to_gif(disposal: Disposal) -> DisposalMethod {
+    match disposal {
+        Disposal::None => DisposalMethod::Keep,
+        Disposal::Background => DisposalMethod::Background,
+        Disposal::Previous => DisposalMethod::Previous,
+    }
+}
+
+/// The frames of a GIF image, as stored in the file.
+pub struct GifFrames<R: Read> {
+    decoder: Decoder<R>,
+    global_palette: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: Read> GifFrames<R> {
+    fn new(r: R) -> io::Result<Self> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let decoder = options.read_info(r).map_err(decoding_error)?;
+        // frames are drawn over a canvas of the logical screen size
+        let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+        if image::pixel_count(width, height).is_none() {
+            let k = io::ErrorKind::InvalidData;
+            return Err(io::Error::new(k, "Gif image too large"));
+        }
+        let global_palette = decoder.global_palette().map(Vec::from);
+        Ok(Self {
+            decoder,
+            global_palette,
+            done: false,
+        })
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<Frame<PalettedBuf<Nrgba>>>> {
+        let frame = match self.decoder.read_next_frame().map_err(decoding_error)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        let rgb = frame
+            .palette
+            .as_deref()
+            .or(self.global_palette.as_deref())
+            .ok_or_else(|| invalid("Gif frame has no palette"))?;
+        let mut palette: Vec<_> = rgb
+            .chunks_exact(3)
+            .map(|c| Nrgba {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+                a: 0xff,
+            })
+            .collect();
+        // out of range indices are rendered as transparent
+        // black by most decoders
+        let len = frame.buffer.iter().max().map_or(0, |&i| i as usize + 1);
+        if palette.len() < len {
+            palette.resize(len, Nrgba::ZERO);
+        }
+        if let Some(c) = frame.transparent.and_then(|i| palette.get_mut(i as usize)) {
+            c.a = 0;
+        }
+
+        let buffer = PalettedBuf::from_indices(
+            frame.width as usize,
+            frame.height as usize,
+            palette,
+            frame.buffer.to_vec(),
+        )
+        .map_err(|_| invalid("Invalid gif frame data"))?;
+
+        Ok(Some(Frame {
+            buffer,
+            delay: Duration::from_millis(frame.delay as u64 * 10),
+            left: frame.left as usize,
+            top: frame.top as usize,
+            disposal: disposal_from_gif(frame.dispose),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for GifFrames<R> {
+    type Item = io::Result<Frame<PalettedBuf<Nrgba>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.done = !matches!(frame, Some(Ok(_)));
+        frame
+    }
+}
+
+impl<R: Read> Dimensions for GifFrames<R> {
+    fn width(&self) -> usize {
+        self.decoder.width() as usize
+    }
+
+    fn height(&self) -> usize {
+        self.decoder.height() as usize
+    }
+}
+
+impl<R: Read> Frames for GifFrames<R> {
+    type Buffer = PalettedBuf<Nrgba>;
+
+    fn loop_count(&self) -> LoopCount {
+        // the looping extension holds the number of
+        // repetitions, after the first play
+        match self.decoder.repeat() {
+            Repeat::Finite(n) => LoopCount::Finite(n as u32 + 1),
+            Repeat::Infinite => LoopCount::Infinite,
+        }
+    }
+}
+
+impl DecodeFrames<PalettedBuf<Nrgba>> for Gif {
+    type Frames<R: Read> = GifFrames<R>;
+
+    #[inline]
+    fn decode_frames<R: Read>(r: R, _opt: ()) -> io::Result<GifFrames<R>> {
+        GifFrames::new(r)
+    }
+}
+
+impl DecodeFrames<RawPixBuf<Nrgba>> for Gif {
+    type Frames<R: Read> = Composite<GifFrames<R>>;
+
+    #[inline]
+    fn decode_frames<R: Read>(r: R, _opt: ()) -> io::Result<Composite<GifFrames<R>>> {
+        GifFrames::new(r)?.composite()
+    }
+}
+
+impl Decode<RawPixBuf<Nrgba>> for Gif {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<RawPixBuf<Nrgba>> {
+        GifFrames::new(r)?
+            .composite()?
+            .next()
+            .unwrap_or_else(|| Err(invalid("Gif image has no frames")))
+            .map(|frame| frame.buffer)
+    }
+}
+
+// reads a data sub-block, which is empty at the end of a block
+fn read_sub_block<'a, R: Read>(r: &mut R, buf: &'a mut [u8; 255]) -> io::Result<&'a [u8]> {
+    let mut size = [0];
+    r.read_exact(&mut size)?;
+    let data = &mut buf[..size[0] as usize];
+    r.read_exact(data)?;
+    Ok(data)
+}
+
+fn skip_sub_blocks<R: Read>(r: &mut R) -> io::Result<()> {
+    let mut buf = [0; 255];
+    while !read_sub_block(r, &mut buf)?.is_empty() {}
+    Ok(())
+}
+
+// the number of entries of a color table is stored as a
+// power of two, in the lower bits of the block flags
+fn skip_color_table<R: Read>(r: &mut R, flags: u8) -> io::Result<()> {
+    let len = 3 << ((flags & 0x07) + 1);
+    io::copy(&mut r.take(len), &mut io::sink())?;
+    Ok(())
+}
+
+impl Probe for Gif {
+    /// Walks over every block of the image to count its frames,
+    /// without decompressing them.
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        let mut r = BufReader::new(r);
+
+        let mut header = [0; 13];
+        r.read_exact(&mut header)?;
+        if !Gif.is_valid_magic(&header) {
+            return Err(invalid("Invalid gif magic."));
+        }
+        let width = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let height = u16::from_le_bytes([header[8], header[9]]) as usize;
+
+        let mut global_depth = None;
+        if header[10] & 0x80 != 0 {
+            skip_color_table(&mut r, header[10])?;
+            global_depth = Some((header[10] & 0x07) + 1);
+        }
+
+        let mut info = ImageInfo::new(width, height, ColorType::Indexed, 8);
+        let mut frames = 0;
+        let mut buf = [0; 255];
+        loop {
+            let mut block = [0];
+            // some encoders leave out the trailer
+            if r.read(&mut block)? == 0 {
+                break;
+            }
+            match block[0] {
+                // image descriptor
+                0x2c => {
+                    let mut descriptor = [0; 9];
+                    r.read_exact(&mut descriptor)?;
+                    let flags = descriptor[8];
+                    // the first frame decides the depth, through
+                    // its own palette or the global one
+                    let depth = if flags & 0x80 != 0 {
+                        skip_color_table(&mut r, flags)?;
+                        Some((flags & 0x07) + 1)
+                    } else {
+                        global_depth
+                    };
+                    if frames == 0 {
+                        info.bit_depth = depth.unwrap_or(8);
+                    }
+                    info.interlaced |= flags & 0x40 != 0;
+                    frames += 1;
+
+                    // skip the lzw code size, and then the image data
+                    r.read_exact(&mut [0])?;
+                    skip_sub_blocks(&mut r)?;
+                }
+                // extension
+                0x21 => {
+                    let mut label = [0];
+                    r.read_exact(&mut label)?;
+                    let data = read_sub_block(&mut r, &mut buf)?;
+                    // the transparency flag of a graphic control extension
+                    if label[0] == 0xf9 && data.first().is_some_and(|flags| flags & 0x01 != 0) {
+                        info.has_alpha = true;
+                    }
+                    if !data.is_empty() {
+                        skip_sub_blocks(&mut r)?;
+                    }
+                }
+                // trailer
+                0x3b => break,
+                _ => return Err(invalid("Invalid gif block")),
+            }
+        }
+
+        info.frame_count = Some(frames);
+        Ok(info)
+    }
+}
+
+fn encode_impl<W, B, I, F>(
+    w: W,
+    (width, height): (usize, usize),
+    loop_count: LoopCount,
+    frames: I,
+    mut make_frame: F,
+) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = io::Result<Frame<B>>>,
+    F: FnMut(&B) -> io::Result<gif::Frame<'static>>,
+{
+    let width = u16::try_from(width).map_err(|_| invalid("Gif image too wide"))?;
+    let height = u16::try_from(height).map_err(|_| invalid("Gif image too tall"))?;
+
+    let mut encoder = Encoder::new(w, width, height, &[]).map_err(encoding_error)?;
+    match loop_count {
+        // the absence of the looping extension means the
+        // animation is only played once
+        LoopCount::Finite(0) | LoopCount::Finite(1) => (),
+        LoopCount::Finite(n) => {
+            let n = (n - 1).min(u16::MAX as u32) as u16;
+            encoder
+                .set_repeat(Repeat::Finite(n))
+                .map_err(encoding_error)?
+        }
+        LoopCount::Infinite => encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(encoding_error)?,
+    }
+
+    for frame in frames {
+        let frame = frame?;
+        let mut out = make_frame(&frame.buffer)?;
+        out.left = u16::try_from(frame.left).map_err(|_| invalid("Gif frame out of bounds"))?;
+        out.top = u16::try_from(frame.top).map_err(|_| invalid("Gif frame out of bounds"))?;
+        out.delay = (frame.delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+        out.dispose = disposal_to_gif(frame.disposal);
+        encoder.write_frame(&out).map_err(encoding_error)?;
+    }
+
+    Ok(())
+}
+
+fn frame_dimensions<D: Dimensions>(buf: &D) -> io::Result<(u16, u16)> {
+    let width = u16::try_from(buf.width()).map_err(|_| invalid("Gif frame too wide"))?;
+    let height = u16::try_from(buf.height()).map_err(|_| invalid("Gif frame too tall"))?;
+    Ok((width, height))
+}
+
+fn paletted_frame(buf: &PalettedBuf<Nrgba>) -> io::Result<gif::Frame<'static>> {
+    let (width, height) = frame_dimensions(buf)?;
+    // only the entries up to the last one in use are written
+    let used = buf.indices().iter().max().map_or(1, |&i| i as usize + 1);
+    let palette = &buf.palette()[..used.min(buf.palette().len())];
+    if palette.len() > 256 {
+        return Err(invalid("Gif palettes have at most 256 colors"));
+    }
+    // gif frames have a single transparent index, which every
+    // transparent entry is mapped to
+    let transparent = palette.iter().position(|c| c.a == 0).map(|i| i as u8);
+    let indices: Vec<_> = match transparent {
+        Some(t) => buf
+            .indices()
+            .iter()
+            .map(|&i| if palette[i as usize].a == 0 { t } else { i })
+            .collect(),
+        None => buf.indices().to_vec(),
+    };
+    let rgb: Vec<_> = palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    Ok(gif::Frame::from_palette_pixels(
+        width,
+        height,
+        indices,
+        rgb,
+        transparent,
+    ))
+}
+
+fn rgba_frame(buf: &RawPixBuf<Nrgba>, opts: GifEncodeOptions) -> io::Result<gif::Frame<'static>> {
+    let (width, height) = frame_dimensions(buf)?;
+    let speed = opts.speed.clamp(1, 30);
+    let mut pixels = buf.as_ref().to_vec();
+    Ok(gif::Frame::from_rgba_speed(
+        width,
+        height,
+        &mut pixels,
+        speed,
+    ))
+}
+
+fn to_nrgba<I: Image + Dimensions>(buf: &I) -> RawPixBuf<Nrgba> {
+    let (width, height) = buf.dimensions();
+    let mut new_buf = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let c = buf.color_get(x, y);
+            let c: Nrgba = c.convert_into();
+            new_buf.pixel_set(x, y, c);
+        }
+    }
+    new_buf
+}
+
+impl EncodeFrames<PalettedBuf<Nrgba>> for Gif {
+    fn encode_frames<W, F>(w: W, _opts: GifEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = PalettedBuf<Nrgba>>,
+    {
+        let dims = frames.dimensions();
+        let loop_count = frames.loop_count();
+        encode_impl(w, dims, loop_count, frames, paletted_frame)
+    }
+}
+
+impl EncodeFrames<RawPixBuf<Nrgba>> for Gif {
+    fn encode_frames<W, F>(w: W, opts: GifEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = RawPixBuf<Nrgba>>,
+    {
+        let dims = frames.dimensions();
+        let loop_count = frames.loop_count();
+        encode_impl(w, dims, loop_count, frames, |buf| rgba_frame(buf, opts))
+    }
+}
+
+impl<I: Image + Dimensions> EncodeFrames<I, specialized::No> for Gif {
+    fn encode_frames<W, F>(w: W, opts: GifEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = I>,
+    {
+        let dims = frames.dimensions();
+        let loop_count = frames.loop_count();
+        let frames = frames.map(|frame| frame.map(|frame| frame.map(|buf| to_nrgba(&buf))));
+        encode_impl(w, dims, loop_count, frames, |buf| rgba_frame(buf, opts))
+    }
+}
+
+impl Encode<PalettedBuf<Nrgba>> for Gif {
+    fn encode<W: Write>(w: W, _opts: GifEncodeOptions, buf: &PalettedBuf<Nrgba>) -> io::Result<()> {
+        let frames = iter::once(Ok(Frame::new(buf, Duration::ZERO)));
+        encode_impl(w, buf.dimensions(), LoopCount::default(), frames, |buf| {
+            paletted_frame(buf)
+        })
+    }
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Gif {
+    fn encode<W: Write>(w: W, opts: GifEncodeOptions, buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        let frames = iter::once(Ok(Frame::new(buf, Duration::ZERO)));
+        encode_impl(w, buf.dimensions(), LoopCount::default(), frames, |buf| {
+            rgba_frame(buf, opts)
+        })
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Gif {
+    fn encode<W: Write>(w: W, opts: GifEncodeOptions, buf: &I) -> io::Result<()> {
+        Gif::encode_specialized(w, opts, &to_nrgba(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_paletted(data: &[u8]) -> PalettedBuf<Nrgba> {
+        let mut frames = GifFrames::new(data).unwrap();
+        frames.next().unwrap().unwrap().buffer
+    }
+
+    #[test]
+    fn paletted_round_trip() {
+        let rgb = |r, g, b| Nrgba { r, g, b, a: 0xff };
+        for &alpha in &[false, true] {
+            let mut palette = vec![rgb(10, 20, 30), rgb(40, 50, 60), rgb(70, 80, 90)];
+            if alpha {
+                palette[1].a = 0;
+            }
+            let indices = (0..20).map(|i| (i % 3) as u8).collect();
+            let buf = PalettedBuf::from_indices(5, 4, palette.clone(), indices).unwrap();
+
+            let mut out = Vec::new();
+            Gif::encode_specialized(&mut out, Default::default(), &buf).unwrap();
+            let decoded = decode_paletted(&out);
+            assert_eq!(decoded.indices(), buf.indices());
+            assert_eq!(&decoded.palette()[..3], &palette[..]);
+            let transparent = decoded.palette().iter().filter(|c| c.a == 0).count();
+            assert_eq!(transparent, alpha as usize);
+
+            // re-encoding keeps the palette, rather than growing
+            // it with transparent padding
+            let mut again = Vec::new();
+            Gif::encode_specialized(&mut again, Default::default(), &decoded).unwrap();
+            assert_eq!(again, out);
+        }
+    }
+
+    #[test]
+    fn huge_screens_are_rejected() {
+        let mut data = Vec::from(&b"GIF89a"[..]);
+        data.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+        // a single 1x1 frame, with a two entry color table
+        data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0x80]);
+        data.extend_from_slice(&[0, 0, 0, 0xff, 0xff, 0xff]);
+        data.extend_from_slice(&[0x02, 0x02, 0x44, 0x01, 0x00, 0x3b]);
+        let e = Gif::decode(&data[..], ()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
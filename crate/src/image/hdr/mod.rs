@@ -0,0 +1,407 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{RgbF32, Zero};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const FORMAT_RGBE: &[u8] = b"32-bit_rle_rgbe";
+
+// scanlines outside this range of widths can't be run-length encoded
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+
+// the shortest run worth encoding as such, rather than as literals
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+pub struct Hdr;
+
+impl_format! {
+    name: Hdr,
+    id: "feim:hdr",
+    magic: b"#?RADIANCE",
+    signatures: &[Signature::new(b"#?RADIANCE"), Signature::new(b"#?RGBE")],
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct HdrEncodeOptions {
+    /// Compress scanlines with run-length encoding.
+    pub rle: bool,
+}
+
+impl Default for HdrEncodeOptions {
+    fn default() -> Self {
+        HdrEncodeOptions { rle: true }
+    }
+}
+
+impl DecodeOptions for Hdr {
+    type Options = ();
+}
+
+impl EncodeOptions for Hdr {
+    type Options = HdrEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// converts a pixel with a shared exponent into floats,
+// centering each mantissa in its interval like radiance does
+#[inline]
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> RgbF32 {
+    if e == 0 {
+        return RgbF32::ZERO;
+    }
+    let f = 2f32.powi(e as i32 - (128 + 8));
+    RgbF32 {
+        r: (r as f32 + 0.5) * f,
+        g: (g as f32 + 0.5) * f,
+        b: (b as f32 + 0.5) * f,
+    }
+}
+
+#[inline]
+fn to_rgbe(c: RgbF32) -> [u8; 4] {
+    // negative and NaN channels are stored as zero
+    let clean = |v: f32| if v > 0.0 { v } else { 0.0 };
+    let (r, g, b) = (clean(c.r), clean(c.g), clean(c.b));
+    let max = r.max(g).max(b);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    if !max.is_finite() || max >= 2f32.powi(127) {
+        return [0xff; 4];
+    }
+
+    // split the largest channel into a mantissa in [0.5, 1)
+    // and an exponent, the equivalent of frexp
+    let bits = max.to_bits();
+    let exp = ((bits >> 23) & 0xff) as i32 - 126;
+    let mantissa = f32::from_bits((bits & 0x807f_ffff) | (126 << 23));
+
+    let scale = mantissa * 255.9999 / max;
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (exp + 128) as u8,
+    ]
+}
+
+impl Probe for Hdr {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        // gather the lines of the header, which are followed
+        // by an empty line and the resolution string
+        let mut r = BufReader::new(r);
+        let mut data = Vec::new();
+        let mut empty = false;
+        loop {
+            let start = data.len();
+            if r.read_until(b'\n', &mut data)? == 0 || empty {
+                break;
+            }
+            empty = &data[start..] == b"\n";
+        }
+        let ((width, height), _) = parse_header(&data, &mut 0)?;
+        // samples are floats, sharing an exponent between them
+        Ok(ImageInfo::new(width, height, ColorType::Rgb, 32))
+    }
+}
+
+fn read_line<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<&'a [u8]> {
+    let rest = &data[*pos..];
+    let end = rest
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid("Truncated hdr header"))?;
+    *pos += end + 1;
+    Ok(&rest[..end])
+}
+
+// parses a resolution string such as "-Y 480 +X 640",
+// returning the dimensions and whether rows and columns
+// are stored in reverse
+fn parse_resolution(line: &[u8]) -> io::Result<((usize, usize), (bool, bool))> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("Invalid hdr resolution"))?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (y_axis, height, x_axis, width) = match fields[..] {
+        [y_axis, height, x_axis, width] => (y_axis, height, x_axis, width),
+        _ => return Err(invalid("Invalid hdr resolution")),
+    };
+    let flip_y = match y_axis {
+        "-Y" => false,
+        "+Y" => true,
+        _ => return Err(invalid("Unsupported hdr orientation")),
+    };
+    let flip_x = match x_axis {
+        "+X" => false,
+        "-X" => true,
+        _ => return Err(invalid("Unsupported hdr orientation")),
+    };
+    let height = height.parse().map_err(|_| invalid("Invalid hdr height"))?;
+    let width = width.parse().map_err(|_| invalid("Invalid hdr width"))?;
+    Ok(((width, height), (flip_x, flip_y)))
+}
+
+#[inline]
+fn next_byte(data: &[u8], pos: &mut usize) -> io::Result<u8> {
+    let b = *data
+        .get(*pos)
+        .ok_or_else(|| invalid("Truncated hdr pixel data"))?;
+    *pos += 1;
+    Ok(b)
+}
+
+fn read_scanline(data: &[u8], pos: &mut usize, line: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = line.len();
+    // run-length encoded scanlines start with two 2s and their width
+    let encoded_width = match data.get(*pos..*pos + 4) {
+        Some(&[2, 2, hi, lo]) if hi & 0x80 == 0 => ((hi as usize) << 8) | lo as usize,
+        _ => 0,
+    };
+    if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) || encoded_width == 0 {
+        return read_flat_scanline(data, pos, line);
+    }
+    if encoded_width != width {
+        return Err(invalid("Hdr scanline width mismatch"));
+    }
+    *pos += 4;
+
+    // each channel is run-length encoded separately
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = next_byte(data, pos)? as usize;
+            if count > 128 {
+                let run = count - 128;
+                let value = next_byte(data, pos)?;
+                if x + run > width {
+                    return Err(invalid("Hdr run overflows scanline"));
+                }
+                for pixel in &mut line[x..x + run] {
+                    pixel[channel] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("Invalid hdr run length"));
+                }
+                let literal = data
+                    .get(*pos..*pos + count)
+                    .ok_or_else(|| invalid("Truncated hdr pixel data"))?;
+                for (pixel, &value) in line[x..x + count].iter_mut().zip(literal) {
+                    pixel[channel] = value;
+                }
+                *pos += count;
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+// reads flat pixels, which may contain the repeat
+// markers of the original run-length encoding
+fn read_flat_scanline(data: &[u8], pos: &mut usize, line: &mut [[u8; 4]]) -> io::Result<()> {
+    let mut x = 0;
+    let mut shift = 0;
+    while x < line.len() {
+        let pixel = data
+            .get(*pos..*pos + 4)
+            .ok_or_else(|| invalid("Truncated hdr pixel data"))?;
+        *pos += 4;
+        if let [1, 1, 1, count] = *pixel {
+            if x == 0 || shift > 16 {
+                return Err(invalid("Invalid hdr repeat"));
+            }
+            let repeat = (count as usize) << shift;
+            if x + repeat > line.len() {
+                return Err(invalid("Hdr run overflows scanline"));
+            }
+            let previous = line[x - 1];
+            line[x..x + repeat].fill(previous);
+            x += repeat;
+            shift += 8;
+        } else {
+            line[x] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(())
+}
+
+// parses the header, up to and including the resolution string
+fn parse_header(data: &[u8], pos: &mut usize) -> io::Result<((usize, usize), (bool, bool))> {
+    if !Hdr.is_valid_magic(data) {
+        return Err(invalid("Invalid hdr header."));
+    }
+
+    // header variables end with an empty line
+    read_line(data, pos)?;
+    loop {
+        let line = read_line(data, pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix(b"FORMAT=") {
+            if !format.starts_with(FORMAT_RGBE) {
+                return Err(invalid("Unsupported hdr pixel format"));
+            }
+        }
+    }
+    parse_resolution(read_line(data, pos)?)
+}
+
+impl Decode<RawPixBuf<RgbF32>> for Hdr {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<RawPixBuf<RgbF32>> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let mut pos = 0;
+        let ((width, height), (flip_x, flip_y)) = parse_header(&data, &mut pos)?;
+        if image::pixel_count(width, height).is_none() {
+            return Err(invalid("Hdr image too large"));
+        }
+
+        let mut buf = RawPixBuf::new(width, height);
+        let mut line = vec![[0; 4]; width];
+        for y in 0..height {
+            read_scanline(&data, &mut pos, &mut line)?;
+            let row_y = if flip_y { height - 1 - y } else { y };
+            let row = &mut buf.as_typed_mut()[row_y * width..(row_y + 1) * width];
+            for (x, &pixel) in line.iter().enumerate() {
+                let col = if flip_x { width - 1 - x } else { x };
+                row[col] = from_rgbe(pixel);
+            }
+        }
+        Ok(buf)
+    }
+}
+
+fn write_rle_channel<W: Write>(w: &mut W, values: &[u8]) -> io::Result<()> {
+    let run_len = |i: usize| {
+        values[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&v| v == values[i])
+            .count()
+    };
+    let mut i = 0;
+    while i < values.len() {
+        let run = run_len(i);
+        if run >= MIN_RUN {
+            w.write_all(&[(128 + run) as u8, values[i]])?;
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < values.len() && i - start < MAX_LITERAL && run_len(i) < MIN_RUN {
+            i += 1;
+        }
+        w.write_all(&[(i - start) as u8])?;
+        w.write_all(&values[start..i])?;
+    }
+    Ok(())
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: HdrEncodeOptions,
+    (width, height): (usize, usize),
+    pixels: &[RgbF32],
+) -> io::Result<()> {
+    w.write_all(b"#?RADIANCE\n")?;
+    w.write_all(b"FORMAT=")?;
+    w.write_all(FORMAT_RGBE)?;
+    w.write_all(b"\n\n")?;
+    writeln!(w, "-Y {} +X {}", height, width)?;
+
+    let rle = opts.rle && (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+    let mut channel = vec![0; width];
+    for row in pixels.chunks_exact(width.max(1)).take(height) {
+        let line: Vec<[u8; 4]> = row.iter().map(|&c| to_rgbe(c)).collect();
+        if !rle {
+            for pixel in &line {
+                w.write_all(pixel)?;
+            }
+            continue;
+        }
+        w.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for c in 0..4 {
+            for (value, pixel) in channel.iter_mut().zip(&line) {
+                *value = pixel[c];
+            }
+            write_rle_channel(&mut w, &channel)?;
+        }
+    }
+    Ok(())
+}
+
+impl Encode<RawPixBuf<RgbF32>> for Hdr {
+    fn encode<W: Write>(w: W, opts: HdrEncodeOptions, buf: &RawPixBuf<RgbF32>) -> io::Result<()> {
+        encode_impl(w, opts, buf.dimensions(), buf.as_typed())
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Hdr {
+    fn encode<W: Write>(w: W, opts: HdrEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: RgbF32 = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Hdr::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let (w, h) = (40, 3);
+        let mut buf: RawPixBuf<RgbF32> = RawPixBuf::new(w, h);
+        for (i, pix) in buf.as_typed_mut().iter_mut().enumerate() {
+            let v = (i / 4) as f32 / 8.0;
+            *pix = RgbF32 {
+                r: v,
+                g: 0.5,
+                b: 1.0 / (v + 1.0),
+            };
+        }
+        for &rle in &[false, true] {
+            let mut out = Vec::new();
+            Hdr::encode_specialized(&mut out, HdrEncodeOptions { rle }, &buf).unwrap();
+            let decoded: RawPixBuf<RgbF32> = Hdr::decode(&out[..], ()).unwrap();
+            // channels share an exponent, so their error depends
+            // on the brightest one
+            for (a, b) in buf.as_typed().iter().zip(decoded.as_typed()) {
+                let max = a.r.max(a.g).max(a.b);
+                for (p, q) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                    assert!((p - q).abs() <= max / 128.0, "{:?} {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2000000000 +X 2000000000\n";
+        assert!(<Hdr as Decode<RawPixBuf<RgbF32>>>::decode(&data[..], ()).is_err());
+        let data = b"#?RADIANCE\n\n-Y 30000 +X 30000\n";
+        assert!(<Hdr as Decode<RawPixBuf<RgbF32>>>::decode(&data[..], ()).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::color::convert::ConvertInto;
+use crate::color::Color;
+use crate::image::bmp::{BmpBuf, BmpPix};
+use crate::image::png::{PngBuf, PngPix};
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// The image of an icon entry, stored either as a png or as a bitmap.
+#[derive(Clone, Debug)]
+pub enum IcoBuf {
+    Png(PngBuf),
+    Bmp(BmpBuf),
+}
+
+impl Dimensions for IcoBuf {
+    fn width(&self) -> usize {
+        match self {
+            IcoBuf::Png(buf) => buf.width(),
+            IcoBuf::Bmp(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            IcoBuf::Png(buf) => buf.height(),
+            IcoBuf::Bmp(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for IcoBuf {
+    type Pixel = IcoPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            IcoBuf::Png(buf) => IcoPix::Png(buf.color_get(x, y)),
+            IcoBuf::Bmp(buf) => IcoPix::Bmp(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for IcoBuf {
+    type Pixel = IcoPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<IcoPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<IcoPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (IcoBuf::Png(buf), IcoPix::Png(c)) => buf.pixel_set(x, y, c),
+            (IcoBuf::Png(buf), c) => buf.pixel_set(x, y, PngPix::Nrgba64(c.convert_into())),
+
+            (IcoBuf::Bmp(buf), IcoPix::Bmp(c)) => buf.pixel_set(x, y, c),
+            (IcoBuf::Bmp(buf), c) => buf.pixel_set(x, y, BmpPix::Nrgba(c.convert_into())),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum IcoPix {
+    Png(PngPix),
+    Bmp(BmpPix),
+}
+
+impl Color for IcoPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            IcoPix::Png(c) => c.as_rgba(),
+            IcoPix::Bmp(c) => c.as_rgba(),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+mod ico_buf;
+
+pub use ico_buf::*;
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba;
+use crate::image::bmp::{self, BmpBuf};
+use crate::image::png::{Png, PngBuf, PngEncodeOptions};
+use crate::image::{Confidence, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const HEADER_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 16;
+
+const TYPE_ICON: u16 = 1;
+const TYPE_CURSOR: u16 = 2;
+
+pub struct Ico;
+
+impl_format! {
+    name: Ico,
+    id: "feim:ico",
+    magic: b"\0\0\x01\0",
+    signatures: SIGNATURES,
+    detect: detect,
+}
+
+const SIGNATURES: &[Signature] = &[Signature::new(b"\0\0\x01\0"), Signature::new(b"\0\0\x02\0")];
+
+// a file without any images is more likely the
+// header of a tga image
+fn detect(data: &[u8]) -> Confidence {
+    let confidence = SIGNATURES
+        .iter()
+        .map(|signature| signature.detect(data))
+        .max()
+        .unwrap_or(Confidence::No);
+    if confidence < Confidence::High {
+        return confidence;
+    }
+    match data.get(4..6) {
+        None => Confidence::Incomplete,
+        Some([0, 0]) => Confidence::No,
+        Some(_) => Confidence::High,
+    }
+}
+
+/// Whether a file holds icons or cursors.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum IcoKind {
+    #[default]
+    Icon,
+    Cursor,
+}
+
+/// A single image of an icon or cursor file.
+#[derive(Clone, Debug)]
+pub struct IcoEntry {
+    pub image: IcoBuf,
+    /// Bits per pixel of the image.
+    pub bit_depth: u16,
+    /// The hotspot of a cursor, relative to the top left
+    /// corner of the image.
+    pub hotspot: Option<(u16, u16)>,
+}
+
+impl Dimensions for IcoEntry {
+    fn width(&self) -> usize {
+        self.image.width()
+    }
+
+    fn height(&self) -> usize {
+        self.image.height()
+    }
+}
+
+/// All the entries of an icon or cursor file, usually
+/// the same image at different sizes.
+#[derive(Clone, Debug, Default)]
+pub struct IcoFile {
+    pub kind: IcoKind,
+    pub entries: Vec<IcoEntry>,
+}
+
+impl IcoFile {
+    /// Returns the largest entry of the file, preferring
+    /// the one with the highest bit depth among equals.
+    pub fn largest(&self) -> Option<&IcoEntry> {
+        self.entries.iter().max_by_key(|e| e.sort_key())
+    }
+}
+
+impl IcoEntry {
+    #[inline]
+    fn sort_key(&self) -> (usize, u16) {
+        (self.width() * self.height(), self.bit_depth)
+    }
+}
+
+impl DecodeOptions for Ico {
+    type Options = ();
+}
+
+impl EncodeOptions for Ico {
+    type Options = PngEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+#[inline]
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+#[inline]
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn png_depth(buf: &PngBuf) -> u16 {
+    match buf {
+        PngBuf::Gray(_) => 8,
+        PngBuf::Gray16(_) => 16,
+        PngBuf::Rgb(_) => 24,
+        PngBuf::Nrgba(_) => 32,
+        PngBuf::Rgb48(_) => 48,
+        PngBuf::Nrgba64(_) => 64,
+    }
+}
+
+impl Decode<IcoFile> for Ico {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<IcoFile> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        if data.len() < HEADER_SIZE || !Ico.is_valid_magic(&data) {
+            return Err(invalid("Invalid ico header."));
+        }
+        let kind = match le_u16(&data, 2) {
+            TYPE_CURSOR => IcoKind::Cursor,
+            _ => IcoKind::Icon,
+        };
+        let count = le_u16(&data, 4) as usize;
+        let directory = data
+            .get(HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE)
+            .ok_or_else(|| invalid("Truncated ico directory"))?;
+
+        let mut entries = Vec::with_capacity(count);
+        for entry in directory.chunks_exact(ENTRY_SIZE) {
+            // the planes and bit count fields of icons
+            // hold the hotspot of cursors instead
+            let (x, y) = (le_u16(entry, 4), le_u16(entry, 6));
+            let size = le_u32(entry, 8) as usize;
+            let offset = le_u32(entry, 12) as usize;
+            let payload = data
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| invalid("Truncated ico image data"))?;
+
+            let (image, depth) = if Png.is_valid_magic(payload) {
+                let image: PngBuf = Png::decode(payload, ())?;
+                let depth = png_depth(&image);
+                (IcoBuf::Png(image), depth)
+            } else {
+                let (image, depth) = bmp::decode_icon(payload)?;
+                (IcoBuf::Bmp(image), depth)
+            };
+
+            entries.push(match kind {
+                IcoKind::Icon => IcoEntry {
+                    image,
+                    bit_depth: if y != 0 { y } else { depth },
+                    hotspot: None,
+                },
+                IcoKind::Cursor => IcoEntry {
+                    image,
+                    bit_depth: depth,
+                    hotspot: Some((x, y)),
+                },
+            });
+        }
+
+        Ok(IcoFile { kind, entries })
+    }
+}
+
+impl Decode<IcoBuf> for Ico {
+    fn decode<R: Read>(r: R, opt: ()) -> io::Result<IcoBuf> {
+        let file: IcoFile = Ico::decode(r, opt)?;
+        let largest = file
+            .entries
+            .into_iter()
+            .max_by_key(IcoEntry::sort_key)
+            .ok_or_else(|| invalid("Ico file contains no images"))?;
+        Ok(largest.image)
+    }
+}
+
+impl Probe for Ico {
+    /// Describes the largest image of the file, the one decoded
+    /// into an `IcoBuf`, while the frame count is the number of
+    /// images in the file.
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let mut header = [0; HEADER_SIZE];
+        r.read_exact(&mut header)?;
+        if !Ico.is_valid_magic(&header) {
+            return Err(invalid("Invalid ico header."));
+        }
+        let cursor = le_u16(&header, 2) == TYPE_CURSOR;
+        let count = le_u16(&header, 4) as usize;
+        let mut directory = vec![0; count * ENTRY_SIZE];
+        r.read_exact(&mut directory)?;
+
+        // a size of zero stands for 256 pixels
+        let size = |b: u8| if b == 0 { 256 } else { b as usize };
+        let largest = directory
+            .chunks_exact(ENTRY_SIZE)
+            .max_by_key(|e| {
+                let depth = if cursor { 0 } else { le_u16(e, 6) };
+                (size(e[0]) * size(e[1]), depth)
+            })
+            .ok_or_else(|| invalid("Ico file contains no images"))?;
+
+        // skip ahead to the header of the image
+        let offset = le_u32(largest, 12) as usize;
+        let gap = offset
+            .checked_sub(HEADER_SIZE + directory.len())
+            .ok_or_else(|| invalid("Invalid ico image offset"))?;
+        io::copy(&mut (&mut r).take(gap as u64), &mut io::sink())?;
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        let payload = (&magic[..]).chain(r);
+        let mut info = if Png.is_valid_magic(&magic) {
+            Png::probe(payload)?
+        } else {
+            bmp::probe_icon(payload)?
+        };
+        info.frame_count = Some(count);
+        Ok(info)
+    }
+}
+
+fn encode_payload(opts: PngEncodeOptions, image: &IcoBuf) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    match image {
+        IcoBuf::Png(buf) => Png::encode_specialized(&mut payload, opts, buf)?,
+        IcoBuf::Bmp(BmpBuf::Gray(buf)) => Png::encode_specialized(&mut payload, opts, buf)?,
+        IcoBuf::Bmp(BmpBuf::Nrgba(buf)) => Png::encode_specialized(&mut payload, opts, buf)?,
+        IcoBuf::Bmp(BmpBuf::Rgb(buf)) => Png::encode_specialized(&mut payload, opts, buf)?,
+    }
+    Ok(payload)
+}
+
+// an entry to be encoded, with its image, bit depth and hotspot
+type EntryRef<'a> = (&'a IcoBuf, u16, Option<(u16, u16)>);
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: PngEncodeOptions,
+    kind: IcoKind,
+    entries: &[EntryRef<'_>],
+) -> io::Result<()> {
+    let count = u16::try_from(entries.len()).map_err(|_| invalid("Too many ico images"))?;
+
+    let mut directory = Vec::with_capacity(HEADER_SIZE + entries.len() * ENTRY_SIZE);
+    directory.extend_from_slice(&[0, 0]);
+    directory.extend_from_slice(&match kind {
+        IcoKind::Icon => TYPE_ICON.to_le_bytes(),
+        IcoKind::Cursor => TYPE_CURSOR.to_le_bytes(),
+    });
+    directory.extend_from_slice(&count.to_le_bytes());
+
+    let mut offset = HEADER_SIZE + entries.len() * ENTRY_SIZE;
+    let mut payloads = Vec::with_capacity(entries.len());
+    for &(image, bit_depth, hotspot) in entries {
+        let (width, height) = image.dimensions();
+        if !(1..=256).contains(&width) || !(1..=256).contains(&height) {
+            return Err(invalid(
+                "Ico images must be between 1 and 256 pixels wide and tall",
+            ));
+        }
+        let payload = encode_payload(opts, image)?;
+        let (x, y) = match kind {
+            IcoKind::Icon => (1, bit_depth),
+            IcoKind::Cursor => hotspot.unwrap_or((0, 0)),
+        };
+
+        // a size of 256 is stored as 0
+        directory.push(width as u8);
+        directory.push(height as u8);
+        // no palette, and a reserved byte
+        directory.extend_from_slice(&[0, 0]);
+        directory.extend_from_slice(&x.to_le_bytes());
+        directory.extend_from_slice(&y.to_le_bytes());
+        directory.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        let at = u32::try_from(offset).map_err(|_| invalid("Ico file too large"))?;
+        directory.extend_from_slice(&at.to_le_bytes());
+
+        offset += payload.len();
+        payloads.push(payload);
+    }
+
+    w.write_all(&directory)?;
+    for payload in payloads {
+        w.write_all(&payload)?;
+    }
+    Ok(())
+}
+
+impl Ico {
+    /// Encode a multi-resolution icon, with one entry per image.
+    pub fn encode_images<'a, W, I, B>(w: W, opts: PngEncodeOptions, images: I) -> io::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = &'a B>,
+        B: Image + Dimensions + 'a,
+    {
+        let images: Vec<_> = images
+            .into_iter()
+            .map(|image| IcoBuf::Bmp(BmpBuf::Nrgba(to_nrgba(image))))
+            .collect();
+        let entries: Vec<_> = images.iter().map(|image| (image, 32, None)).collect();
+        encode_impl(w, opts, IcoKind::Icon, &entries)
+    }
+}
+
+fn to_nrgba<I: Image + Dimensions>(buf: &I) -> RawPixBuf<Nrgba> {
+    let (width, height) = buf.dimensions();
+    let mut new_buf = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let c = buf.color_get(x, y);
+            let c: Nrgba = c.convert_into();
+            new_buf.pixel_set(x, y, c);
+        }
+    }
+    new_buf
+}
+
+impl Encode<IcoFile> for Ico {
+    fn encode<W: Write>(w: W, opts: PngEncodeOptions, file: &IcoFile) -> io::Result<()> {
+        let entries: Vec<_> = file
+            .entries
+            .iter()
+            .map(|e| (&e.image, e.bit_depth, e.hotspot))
+            .collect();
+        encode_impl(w, opts, file.kind, &entries)
+    }
+}
+
+impl Encode<IcoBuf> for Ico {
+    fn encode<W: Write>(w: W, opts: PngEncodeOptions, buf: &IcoBuf) -> io::Result<()> {
+        let bit_depth = match buf {
+            IcoBuf::Png(buf) => png_depth(buf),
+            IcoBuf::Bmp(BmpBuf::Gray(_)) => 8,
+            IcoBuf::Bmp(BmpBuf::Rgb(_)) => 24,
+            IcoBuf::Bmp(BmpBuf::Nrgba(_)) => 32,
+        };
+        encode_impl(w, opts, IcoKind::Icon, &[(buf, bit_depth, None)])
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Ico {
+    fn encode<W: Write>(w: W, opts: PngEncodeOptions, buf: &I) -> io::Result<()> {
+        Ico::encode_images(w, opts, std::iter::once(buf))
+    }
+}
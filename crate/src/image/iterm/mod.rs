@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba;
+use crate::image::base64;
+use crate::image::png::Png;
+use crate::image::{Dimensions, Image, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Encode, EncodeOptions, EncodeSpecialized};
+use crate::specialized;
+
+/// Inline images with the iTerm2 escape sequence, also
+/// supported by terminals such as wezterm or mintty.
+/// Only encoding is supported.
+pub struct Iterm;
+
+impl_format! {
+    name: Iterm,
+    id: "feim:iterm",
+    magic: b"\x1b]1337;File=",
+}
+
+/// The size an image is displayed at.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum ItermSize {
+    /// The size of the image itself.
+    #[default]
+    Auto,
+    /// A number of character cells.
+    Cells(u32),
+    /// A number of pixels.
+    Pixels(u32),
+    /// A percentage of the terminal's width or height.
+    Percent(u32),
+}
+
+impl fmt::Display for ItermSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItermSize::Auto => write!(f, "auto"),
+            ItermSize::Cells(n) => write!(f, "{}", n),
+            ItermSize::Pixels(n) => write!(f, "{}px", n),
+            ItermSize::Percent(n) => write!(f, "{}%", n),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ItermEncodeOptions {
+    pub width: ItermSize,
+    pub height: ItermSize,
+    /// Keep the aspect ratio of the image, when both
+    /// its width and height are given.
+    pub preserve_aspect_ratio: bool,
+}
+
+impl Default for ItermEncodeOptions {
+    fn default() -> Self {
+        Self {
+            width: ItermSize::Auto,
+            height: ItermSize::Auto,
+            preserve_aspect_ratio: true,
+        }
+    }
+}
+
+impl EncodeOptions for Iterm {
+    type Options = ItermEncodeOptions;
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: ItermEncodeOptions,
+    buf: &RawPixBuf<Nrgba>,
+) -> io::Result<()> {
+    let mut png = Vec::new();
+    Png::encode_specialized(&mut png, Default::default(), buf)?;
+
+    write!(
+        w,
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio={}:",
+        png.len(),
+        opts.width,
+        opts.height,
+        u8::from(opts.preserve_aspect_ratio),
+    )?;
+    w.write_all(&base64::encode(&png))?;
+    w.write_all(b"\x07")
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Iterm {
+    fn encode<W: Write>(w: W, opts: ItermEncodeOptions, buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        encode_impl(w, opts, buf)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Iterm {
+    fn encode<W: Write>(w: W, opts: ItermEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        encode_impl(w, opts, &new_buf)
+    }
+}
@@ -4,16 +4,17 @@ pub use jpeg_buf::*;
 
 use std::io::{self, Read, Write};
 
-use jpeg_decoder::{Decoder, Error, PixelFormat};
+use jpeg_decoder::{CodingProcess, Decoder, Error, PixelFormat};
 use jpeg_encoder::{ColorType, Encoder, EncodingError};
 
-use crate::buffer::RawPixBuf;
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
 use crate::color::convert::ConvertInto;
+use crate::color::icc::WithIcc;
 use crate::color::{Cmyk, Gray, Nrgba, Rgb};
-use crate::image::{Dimensions, Image, ImageMut};
+use crate::image::{self, Dimensions, Image, ImageInfo, ImageMut};
 use crate::impl_format;
 use crate::serialize::{
-    Decode, DecodeOptions, Encode, EncodeGeneric, EncodeOptions, EncodeSpecialized,
+    Decode, DecodeOptions, Encode, EncodeGeneric, EncodeOptions, EncodeSpecialized, Probe,
 };
 use crate::specialized;
 
@@ -30,25 +31,64 @@ impl DecodeOptions for Jpeg {
 }
 
 impl Decode<JpegBuf> for Jpeg {
+    #[inline]
     fn decode<R: Read>(r: R, _opt: ()) -> io::Result<JpegBuf> {
+        decode_impl(r).map(|buf| buf.image)
+    }
+}
+
+impl Decode<WithIcc<JpegBuf>> for Jpeg {
+    #[inline]
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<WithIcc<JpegBuf>> {
+        decode_impl(r)
+    }
+}
+
+fn decode_impl<R: Read>(r: R) -> io::Result<WithIcc<JpegBuf>> {
+    let mut d = Decoder::new(r);
+    d.read_info().map_err(|e| match e {
+        Error::Io(e) => e,
+        e => io::Error::other(e),
+    })?;
+    let buf = d.decode().map_err(|e| match e {
+        Error::Io(e) => e,
+        e => io::Error::other(e),
+    })?;
+    let info = d.info().unwrap();
+    let w = info.width as usize;
+    let h = info.height as usize;
+    let image = match info.pixel_format {
+        PixelFormat::L8 => JpegBuf::Gray(RawPixBuf::from_vec(w, h, buf).unwrap()),
+        PixelFormat::L16 => JpegBuf::Gray16(RawPixBuf::from_vec(w, h, buf).unwrap()),
+        PixelFormat::RGB24 => JpegBuf::Rgb(RawPixBuf::from_vec(w, h, buf).unwrap()),
+        PixelFormat::CMYK32 => JpegBuf::Cmyk(RawPixBuf::from_vec(w, h, buf).unwrap()),
+    };
+    Ok(WithIcc::new(image, d.icc_profile()))
+}
+
+impl Probe for Jpeg {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        // reading the info stops at the first scan
         let mut d = Decoder::new(r);
         d.read_info().map_err(|e| match e {
             Error::Io(e) => e,
-            e => io::Error::new(io::ErrorKind::Other, e),
-        })?;
-        let buf = d.decode().map_err(|e| match e {
-            Error::Io(e) => e,
-            e => io::Error::new(io::ErrorKind::Other, e),
+            e => io::Error::other(e),
         })?;
         let info = d.info().unwrap();
-        let w = info.width as usize;
-        let h = info.height as usize;
-        Ok(match info.pixel_format {
-            PixelFormat::L8 => JpegBuf::Gray(RawPixBuf::from_vec(w, h, buf).unwrap()),
-            PixelFormat::L16 => JpegBuf::Gray16(RawPixBuf::from_vec(w, h, buf).unwrap()),
-            PixelFormat::RGB24 => JpegBuf::Rgb(RawPixBuf::from_vec(w, h, buf).unwrap()),
-            PixelFormat::CMYK32 => JpegBuf::Cmyk(RawPixBuf::from_vec(w, h, buf).unwrap()),
-        })
+        let (color_type, bit_depth) = match info.pixel_format {
+            PixelFormat::L8 => (image::ColorType::Gray, 8),
+            PixelFormat::L16 => (image::ColorType::Gray, 16),
+            PixelFormat::RGB24 => (image::ColorType::Rgb, 8),
+            PixelFormat::CMYK32 => (image::ColorType::Cmyk, 8),
+        };
+        let mut probed = ImageInfo::new(
+            info.width as usize,
+            info.height as usize,
+            color_type,
+            bit_depth,
+        );
+        probed.interlaced = info.coding_process == CodingProcess::DctProgressive;
+        Ok(probed)
     }
 }
 
@@ -67,29 +107,56 @@ impl JpegEncodeOptions {
     }
 }
 
+impl Default for JpegEncodeOptions {
+    fn default() -> Self {
+        Self { quality: 85 }
+    }
+}
+
 impl EncodeOptions for Jpeg {
     type Options = JpegEncodeOptions;
 }
 
+fn encode_impl<W: Write>(
+    w: W,
+    JpegEncodeOptions { quality }: JpegEncodeOptions,
+    (width, height): (usize, usize),
+    color: ColorType,
+    icc_profile: Option<&[u8]>,
+    data: &[u8],
+) -> io::Result<()> {
+    let width = (width & 0xffff) as u16;
+    let height = (height & 0xffff) as u16;
+    let map_err = |e| match e {
+        EncodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    };
+    let mut encoder = Encoder::new(w, quality);
+    if let Some(icc_profile) = icc_profile {
+        encoder.add_icc_profile(icc_profile).map_err(map_err)?;
+    }
+    encoder.encode(data, width, height, color).map_err(map_err)
+}
+
 macro_rules! impl_encode {
     ($type:ty, $color:expr) => {
         impl Encode<$type> for Jpeg {
+            fn encode<W: Write>(w: W, opts: JpegEncodeOptions, buf: &$type) -> io::Result<()> {
+                let dims = buf.dimensions();
+                encode_impl(w, opts, dims, $color, None, buf.as_ref())
+            }
+        }
+
+        impl Encode<WithIcc<$type>> for Jpeg {
             fn encode<W: Write>(
                 w: W,
-                JpegEncodeOptions { quality }: JpegEncodeOptions,
-                buf: &$type,
+                opts: JpegEncodeOptions,
+                buf: &WithIcc<$type>,
             ) -> io::Result<()> {
-                let width = (buf.width() & 0xffff) as u16;
-                let height = (buf.height() & 0xffff) as u16;
-                let encoder = Encoder::new(w, quality);
-                let color = $color;
-                let buf = buf.as_ref();
-                encoder
-                    .encode(buf, width, height, color)
-                    .map_err(|e| match e {
-                        EncodingError::IoError(e) => e,
-                        other => io::Error::new(io::ErrorKind::Other, other),
-                    })
+                let dims = buf.dimensions();
+                let icc_profile = buf.icc_profile.as_deref();
+                let data = buf.image.as_ref();
+                encode_impl(w, opts, dims, $color, icc_profile, data)
             }
         }
     };
@@ -116,6 +183,32 @@ impl Encode<JpegBuf> for Jpeg {
     }
 }
 
+impl Encode<WithIcc<JpegBuf>> for Jpeg {
+    fn encode<W: Write>(w: W, opts: JpegEncodeOptions, buf: &WithIcc<JpegBuf>) -> io::Result<()> {
+        let dims = buf.dimensions();
+        let icc_profile = buf.icc_profile.as_deref();
+        match &buf.image {
+            JpegBuf::Gray16(buf) => {
+                let mut new_buf: RawPixBuf<Gray> = RawPixBuf::new_from_dims(buf);
+                for (pix, new_pix) in buf.as_typed().iter().zip(new_buf.as_typed_mut()) {
+                    *new_pix = pix.convert_into();
+                }
+                let data = new_buf.as_ref();
+                encode_impl(w, opts, dims, ColorType::Luma, icc_profile, data)
+            }
+            JpegBuf::Gray(buf) => {
+                encode_impl(w, opts, dims, ColorType::Luma, icc_profile, buf.as_ref())
+            }
+            JpegBuf::Rgb(buf) => {
+                encode_impl(w, opts, dims, ColorType::Rgb, icc_profile, buf.as_ref())
+            }
+            JpegBuf::Cmyk(buf) => {
+                encode_impl(w, opts, dims, ColorType::Cmyk, icc_profile, buf.as_ref())
+            }
+        }
+    }
+}
+
 impl<I: Image + Dimensions> Encode<I, specialized::No> for Jpeg {
     fn encode<W: Write>(w: W, opts: JpegEncodeOptions, buf: &I) -> io::Result<()> {
         let (width, height) = buf.dimensions();
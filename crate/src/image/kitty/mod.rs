@@ -0,0 +1,160 @@
+use std::io::{self, Write};
+
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba;
+use crate::image::base64;
+use crate::image::png::Png;
+use crate::image::{Dimensions, Image, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Encode, EncodeOptions, EncodeSpecialized};
+use crate::specialized;
+
+// the protocol limits each escape sequence to this many bytes of payload
+const CHUNK_SIZE: usize = 4096;
+
+/// Inline images with the kitty terminal graphics protocol,
+/// also supported by terminals such as wezterm or konsole.
+/// Only encoding is supported.
+pub struct Kitty;
+
+impl_format! {
+    name: Kitty,
+    id: "feim:kitty",
+    magic: b"\x1b_G",
+}
+
+/// How the pixels of an image are transmitted to the terminal.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum KittyPayload {
+    /// A png file, which is usually smaller.
+    #[default]
+    Png,
+    /// Raw 8 bit RGBA pixels.
+    Rgba,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct KittyEncodeOptions {
+    pub payload: KittyPayload,
+    /// Scale the image to this many terminal columns.
+    pub columns: Option<u32>,
+    /// Scale the image to this many terminal rows.
+    pub rows: Option<u32>,
+    /// An id to refer to the image by, in later commands.
+    pub image_id: Option<u32>,
+    /// An id to refer to this placement of the image by.
+    pub placement_id: Option<u32>,
+    /// The stacking order of the image, with negative values
+    /// drawing it under text.
+    pub z_index: Option<i32>,
+    /// Move the cursor past the image, once it is displayed.
+    pub move_cursor: bool,
+    /// Suppress the responses of the terminal, which would
+    /// otherwise show up as input.
+    pub quiet: bool,
+}
+
+impl Default for KittyEncodeOptions {
+    fn default() -> Self {
+        Self {
+            payload: KittyPayload::Png,
+            columns: None,
+            rows: None,
+            image_id: None,
+            placement_id: None,
+            z_index: None,
+            move_cursor: true,
+            quiet: true,
+        }
+    }
+}
+
+impl EncodeOptions for Kitty {
+    type Options = KittyEncodeOptions;
+}
+
+fn control_data(opts: &KittyEncodeOptions, (width, height): (usize, usize)) -> Vec<String> {
+    // transmit and display the image at once
+    let mut keys = vec!["a=T".to_string()];
+    match opts.payload {
+        KittyPayload::Png => keys.push("f=100".to_string()),
+        KittyPayload::Rgba => {
+            keys.push("f=32".to_string());
+            keys.push(format!("s={}", width));
+            keys.push(format!("v={}", height));
+        }
+    }
+    let optional = [
+        ("c", opts.columns.map(i64::from)),
+        ("r", opts.rows.map(i64::from)),
+        ("i", opts.image_id.map(i64::from)),
+        ("p", opts.placement_id.map(i64::from)),
+        ("z", opts.z_index.map(i64::from)),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            keys.push(format!("{}={}", key, value));
+        }
+    }
+    if !opts.move_cursor {
+        keys.push("C=1".to_string());
+    }
+    if opts.quiet {
+        keys.push("q=2".to_string());
+    }
+    keys
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: KittyEncodeOptions,
+    buf: &RawPixBuf<Nrgba>,
+) -> io::Result<()> {
+    let data = match opts.payload {
+        KittyPayload::Png => {
+            let mut png = Vec::new();
+            Png::encode_specialized(&mut png, Default::default(), buf)?;
+            base64::encode(&png)
+        }
+        KittyPayload::Rgba => base64::encode(buf.as_ref()),
+    };
+
+    // the control data goes in the first chunk, and
+    // every chunk but the last one flags that more follow
+    let mut chunks = data.chunks(CHUNK_SIZE).peekable();
+    let mut keys = control_data(&opts, buf.dimensions());
+    loop {
+        let chunk = chunks.next().unwrap_or_default();
+        let more = chunks.peek().is_some();
+        keys.push(format!("m={}", u8::from(more)));
+        write!(w, "\x1b_G{};", keys.join(","))?;
+        w.write_all(chunk)?;
+        w.write_all(b"\x1b\\")?;
+        if !more {
+            return Ok(());
+        }
+        keys.clear();
+    }
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Kitty {
+    fn encode<W: Write>(w: W, opts: KittyEncodeOptions, buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        encode_impl(w, opts, buf)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Kitty {
+    fn encode<W: Write>(w: W, opts: KittyEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        encode_impl(w, opts, &new_buf)
+    }
+}
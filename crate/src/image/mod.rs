@@ -1,3 +1,5 @@
+pub mod animation;
+
 #[cfg(feature = "fmt-farbfeld")]
 pub mod farbfeld;
 
@@ -7,16 +9,76 @@ pub mod jpeg;
 #[cfg(feature = "fmt-png")]
 pub mod png;
 
+#[cfg(feature = "fmt-pnm")]
+pub mod pnm;
+
+#[cfg(feature = "fmt-bmp")]
+pub mod bmp;
+
+#[cfg(feature = "fmt-qoi")]
+pub mod qoi;
+
+#[cfg(feature = "fmt-gif")]
+pub mod gif;
+
+#[cfg(feature = "fmt-tiff")]
+pub mod tiff;
+
+#[cfg(feature = "fmt-ico")]
+pub mod ico;
+
+#[cfg(feature = "fmt-hdr")]
+pub mod hdr;
+
+#[cfg(feature = "fmt-exr")]
+pub mod exr;
+
+#[cfg(feature = "fmt-sixel")]
+pub mod sixel;
+
+#[cfg(feature = "fmt-kitty")]
+pub mod kitty;
+
+#[cfg(feature = "fmt-iterm")]
+pub mod iterm;
+
+#[cfg(any(feature = "fmt-kitty", feature = "fmt-iterm"))]
+mod base64;
+
 #[cfg(feature = "fmt-webp")]
 pub mod webp;
 
+#[cfg(feature = "fmt-tga")]
+pub mod tga;
+
+#[cfg(feature = "fmt-xpm")]
+pub mod xpm;
+
+#[cfg(feature = "fmt-y4m")]
+pub mod y4m;
+
+#[cfg(feature = "fmt-fits")]
+pub mod fits;
+
+#[cfg(feature = "fmt-pdf")]
+pub mod pdf;
+
+mod dyn_image;
+mod registry;
+
+pub use dyn_image::*;
+pub use registry::*;
+
+use std::io::{self, Read, Write};
+
 use crate::color::convert::ConvertInto;
 use crate::color::Color;
+use crate::serialize::Peek;
 use crate::specialized::{self, No};
 
 #[macro_export]
 macro_rules! impl_format {
-    (name: $name:ty, id: $id:expr, magic: $magic:expr $(,)?) => {
+    (name: $name:ty, id: $id:expr, magic: $magic:expr, valid_magic: $valid:expr $(,)?) => {
         impl $crate::image::Format for $name {
             fn id(&self) -> &'static str {
                 $id
@@ -24,8 +86,118 @@ macro_rules! impl_format {
             fn magic(&self) -> &'static [u8] {
                 $magic
             }
+            fn detect(&self, data: &[u8]) -> $crate::image::Confidence {
+                let valid: fn(&[u8]) -> bool = $valid;
+                if valid(data) {
+                    $crate::image::Confidence::High
+                } else {
+                    $crate::image::Confidence::No
+                }
+            }
         }
     };
+    (
+        name: $name:ty,
+        id: $id:expr,
+        magic: $magic:expr
+        $(, signatures: $signatures:expr)?
+        $(, detect: $detect:expr)?
+        $(,)?
+    ) => {
+        impl $crate::image::Format for $name {
+            fn id(&self) -> &'static str {
+                $id
+            }
+            fn magic(&self) -> &'static [u8] {
+                $magic
+            }
+            $(
+                fn signatures(&self) -> &'static [$crate::image::Signature] {
+                    const FORMAT_SIGNATURES: &[$crate::image::Signature] = $signatures;
+                    FORMAT_SIGNATURES
+                }
+            )?
+            $(
+                fn detect(&self, data: &[u8]) -> $crate::image::Confidence {
+                    let detect: fn(&[u8]) -> $crate::image::Confidence = $detect;
+                    detect(data)
+                }
+            )?
+        }
+    };
+}
+
+/// How likely some data is to be the start of a file of
+/// some format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Confidence {
+    /// The data doesn't belong to the format.
+    No,
+    /// More data is needed to tell whether it belongs to the
+    /// format or not.
+    Incomplete,
+    /// The data may belong to the format, which has no magic
+    /// string of its own, or a very short one.
+    Low,
+    /// The data matches a signature of the format.
+    High,
+}
+
+/// A byte string found at a fixed offset of the files of
+/// some format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Signature {
+    pub offset: usize,
+    pub bytes: &'static [u8],
+    /// The bits of each byte which are compared. Bytes past
+    /// the end of the mask are compared in full.
+    pub mask: Option<&'static [u8]>,
+}
+
+impl Signature {
+    /// A signature at the start of a file.
+    pub const fn new(bytes: &'static [u8]) -> Self {
+        Self::at(0, bytes)
+    }
+
+    /// A signature at the given offset of a file.
+    pub const fn at(offset: usize, bytes: &'static [u8]) -> Self {
+        Self {
+            offset,
+            bytes,
+            mask: None,
+        }
+    }
+
+    /// Only compares the bits of each byte set in `mask`.
+    pub const fn masked(self, mask: &'static [u8]) -> Self {
+        Self {
+            mask: Some(mask),
+            ..self
+        }
+    }
+
+    /// Compares the signature against the start of a file.
+    pub fn detect(&self, data: &[u8]) -> Confidence {
+        let data = data.get(self.offset..).unwrap_or(&[]);
+        let mask = self.mask.unwrap_or(&[]);
+        let matches = self
+            .bytes
+            .iter()
+            .zip(data)
+            .enumerate()
+            .all(|(i, (&b, &d))| {
+                let m = mask.get(i).copied().unwrap_or(0xff);
+                b & m == d & m
+            });
+        if !matches {
+            Confidence::No
+        } else if data.len() < self.bytes.len() {
+            Confidence::Incomplete
+        } else {
+            Confidence::High
+        }
+    }
 }
 
 pub trait Format {
@@ -35,21 +207,66 @@ pub trait Format {
     /// - feim:png
     /// - feim:jpeg
     /// - feim:webp
+    /// - feim:pnm
+    /// - feim:bmp
+    /// - feim:qoi
+    /// - feim:tga
+    /// - feim:gif
+    /// - feim:tiff
+    /// - feim:ico
+    /// - feim:hdr
+    /// - feim:exr
+    /// - feim:xbm
+    /// - feim:xpm
+    /// - feim:y4m
+    /// - feim:fits
+    ///
+    /// And, for formats which are only encoded, and so are not
+    /// part of `BuiltInFormat`:
+    ///
+    /// - feim:sixel
+    /// - feim:kitty
+    /// - feim:iterm
+    /// - feim:pdf
+    /// - feim:eps
     fn id(&self) -> &'static str;
 
     /// Returns the magic string situated at the start of the image file.
     fn magic(&self) -> &'static [u8];
 
+    /// Returns the signatures of the format, any of which may
+    /// be matched by its files. Formats without signatures are
+    /// matched against their magic string, where `?` stands for
+    /// any byte.
+    fn signatures(&self) -> &'static [Signature] {
+        &[]
+    }
+
+    /// Tells how likely `data` is to be the start of a file of
+    /// this format.
+    fn detect(&self, data: &[u8]) -> Confidence {
+        let signatures = self.signatures();
+        if !signatures.is_empty() {
+            return signatures
+                .iter()
+                .map(|signature| signature.detect(data))
+                .max()
+                .unwrap_or(Confidence::No);
+        }
+        let magic = self.magic();
+        let matches = magic.iter().zip(data).all(|(&m, &n)| m == n || m == b'?');
+        if !matches {
+            Confidence::No
+        } else if data.len() < magic.len() {
+            Confidence::Incomplete
+        } else {
+            Confidence::High
+        }
+    }
+
     /// Compares the format's magic string against another byte string.
     fn is_valid_magic(&self, magic: &[u8]) -> bool {
-        if magic.len() < self.magic().len() {
-            return false;
-        }
-        self.magic()
-            .iter()
-            .copied()
-            .zip(magic.iter().copied())
-            .all(|(m, n)| m == n || m == b'?')
+        self.detect(magic) >= Confidence::Low
     }
 }
 
@@ -89,6 +306,84 @@ pub trait ImageMut<Specialized = No> {
     }
 }
 
+/// The color model of the samples stored in an image file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ColorType {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+    /// Indices into a palette of colors.
+    Indexed,
+    Cmyk,
+    /// Luma and chroma samples, such as those of video frames.
+    YCbCr,
+}
+
+impl ColorType {
+    #[inline]
+    pub fn has_alpha(self) -> bool {
+        matches!(self, ColorType::GrayAlpha | ColorType::Rgba)
+    }
+}
+
+/// The properties of an image, as read from the header of
+/// its file, without decoding any pixels.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ImageInfo {
+    pub width: usize,
+    pub height: usize,
+    pub color_type: ColorType,
+    /// The number of bits of each sample, as stored in the file.
+    /// For indexed images, the number of bits of each index.
+    pub bit_depth: u8,
+    /// The number of frames of the image, or `None` if it can't
+    /// be known without reading the whole file.
+    pub frame_count: Option<usize>,
+    /// Whether the image has an alpha channel, or a palette
+    /// with transparent colors.
+    pub has_alpha: bool,
+    /// Whether the rows or pixels of the image are stored out
+    /// of order, for progressive display.
+    pub interlaced: bool,
+}
+
+impl ImageInfo {
+    /// Describes a single, non-interlaced image.
+    pub fn new(width: usize, height: usize, color_type: ColorType, bit_depth: u8) -> Self {
+        Self {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            frame_count: Some(1),
+            has_alpha: color_type.has_alpha(),
+            interlaced: false,
+        }
+    }
+}
+
+impl Dimensions for ImageInfo {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// The largest image, in pixels, decoders allocate a buffer for.
+/// Larger images are rejected, rather than trusting the size in
+/// their headers with an allocation which may exhaust memory.
+pub const MAX_PIXELS: usize = 400_000_000;
+
+/// Returns the number of pixels of an image of the given
+/// dimensions, unless it is larger than `MAX_PIXELS`.
+pub fn pixel_count(width: usize, height: usize) -> Option<usize> {
+    width.checked_mul(height).filter(|&n| n <= MAX_PIXELS)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum BuiltInFormat {
     #[cfg(feature = "fmt-farbfeld")]
@@ -97,8 +392,34 @@ pub enum BuiltInFormat {
     Jpeg,
     #[cfg(feature = "fmt-png")]
     Png,
+    #[cfg(feature = "fmt-pnm")]
+    Pnm,
+    #[cfg(feature = "fmt-bmp")]
+    Bmp,
+    #[cfg(feature = "fmt-qoi")]
+    Qoi,
+    #[cfg(feature = "fmt-gif")]
+    Gif,
+    #[cfg(feature = "fmt-tiff")]
+    Tiff,
+    #[cfg(feature = "fmt-ico")]
+    Ico,
+    #[cfg(feature = "fmt-hdr")]
+    Hdr,
+    #[cfg(feature = "fmt-exr")]
+    Exr,
     #[cfg(feature = "fmt-webp")]
     Webp,
+    #[cfg(feature = "fmt-tga")]
+    Tga,
+    #[cfg(feature = "fmt-xpm")]
+    Xbm,
+    #[cfg(feature = "fmt-xpm")]
+    Xpm,
+    #[cfg(feature = "fmt-y4m")]
+    Y4m,
+    #[cfg(feature = "fmt-fits")]
+    Fits,
 }
 
 impl BuiltInFormat {
@@ -113,10 +434,222 @@ impl BuiltInFormat {
             #[cfg(feature = "fmt-png")]
             BuiltInFormat::Png => &png::Png,
 
+            #[cfg(feature = "fmt-pnm")]
+            BuiltInFormat::Pnm => &pnm::Pnm,
+
+            #[cfg(feature = "fmt-bmp")]
+            BuiltInFormat::Bmp => &bmp::Bmp,
+
+            #[cfg(feature = "fmt-qoi")]
+            BuiltInFormat::Qoi => &qoi::Qoi,
+
+            #[cfg(feature = "fmt-gif")]
+            BuiltInFormat::Gif => &gif::Gif,
+
+            #[cfg(feature = "fmt-tiff")]
+            BuiltInFormat::Tiff => &tiff::Tiff,
+
+            #[cfg(feature = "fmt-ico")]
+            BuiltInFormat::Ico => &ico::Ico,
+
+            #[cfg(feature = "fmt-hdr")]
+            BuiltInFormat::Hdr => &hdr::Hdr,
+
+            #[cfg(feature = "fmt-exr")]
+            BuiltInFormat::Exr => &exr::Exr,
+
             #[cfg(feature = "fmt-webp")]
             BuiltInFormat::Webp => &webp::Webp,
+
+            #[cfg(feature = "fmt-tga")]
+            BuiltInFormat::Tga => &tga::Tga,
+
+            #[cfg(feature = "fmt-xpm")]
+            BuiltInFormat::Xbm => &xpm::Xbm,
+
+            #[cfg(feature = "fmt-xpm")]
+            BuiltInFormat::Xpm => &xpm::Xpm,
+
+            #[cfg(feature = "fmt-y4m")]
+            BuiltInFormat::Y4m => &y4m::Y4m,
+
+            #[cfg(feature = "fmt-fits")]
+            BuiltInFormat::Fits => &fits::Fits,
         }
     }
+
+    pub fn handler(self) -> &'static dyn FormatHandler {
+        match self {
+            #[cfg(feature = "fmt-farbfeld")]
+            BuiltInFormat::Farbfeld => &farbfeld::Farbfeld,
+
+            #[cfg(feature = "fmt-jpeg")]
+            BuiltInFormat::Jpeg => &jpeg::Jpeg,
+
+            #[cfg(feature = "fmt-png")]
+            BuiltInFormat::Png => &png::Png,
+
+            #[cfg(feature = "fmt-pnm")]
+            BuiltInFormat::Pnm => &pnm::Pnm,
+
+            #[cfg(feature = "fmt-bmp")]
+            BuiltInFormat::Bmp => &bmp::Bmp,
+
+            #[cfg(feature = "fmt-qoi")]
+            BuiltInFormat::Qoi => &qoi::Qoi,
+
+            #[cfg(feature = "fmt-gif")]
+            BuiltInFormat::Gif => &gif::Gif,
+
+            #[cfg(feature = "fmt-tiff")]
+            BuiltInFormat::Tiff => &tiff::Tiff,
+
+            #[cfg(feature = "fmt-ico")]
+            BuiltInFormat::Ico => &ico::Ico,
+
+            #[cfg(feature = "fmt-hdr")]
+            BuiltInFormat::Hdr => &hdr::Hdr,
+
+            #[cfg(feature = "fmt-exr")]
+            BuiltInFormat::Exr => &exr::Exr,
+
+            #[cfg(feature = "fmt-webp")]
+            BuiltInFormat::Webp => &webp::Webp,
+
+            #[cfg(feature = "fmt-tga")]
+            BuiltInFormat::Tga => &tga::Tga,
+
+            #[cfg(feature = "fmt-xpm")]
+            BuiltInFormat::Xbm => &xpm::Xbm,
+
+            #[cfg(feature = "fmt-xpm")]
+            BuiltInFormat::Xpm => &xpm::Xpm,
+
+            #[cfg(feature = "fmt-y4m")]
+            BuiltInFormat::Y4m => &y4m::Y4m,
+
+            #[cfg(feature = "fmt-fits")]
+            BuiltInFormat::Fits => &fits::Fits,
+        }
+    }
+
+    /// Reads the properties of an image of this format.
+    pub fn probe<R: Read>(self, mut r: R) -> io::Result<ImageInfo> {
+        self.handler().probe_dyn(&mut r)
+    }
+
+    /// Decodes an image of this format.
+    pub fn decode<R: Read>(self, mut r: R) -> io::Result<DynImage> {
+        self.handler().decode_dyn(&mut r)
+    }
+
+    /// Encodes an image in this format, with the options in `opts`
+    /// that belong to it.
+    pub fn encode<W: Write>(self, mut w: W, buf: &DynImage, opts: FormatOptions) -> io::Result<()> {
+        self.handler().encode_dyn(&mut w, buf, &opts)
+    }
+
+    /// Guess a format from a file extension. This is mostly useful
+    /// for formats which can't be reliably detected from their
+    /// contents, such as TGA.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let ext = ext.to_ascii_lowercase();
+        match ext.as_str() {
+            #[cfg(feature = "fmt-farbfeld")]
+            "ff" => Some(BuiltInFormat::Farbfeld),
+
+            #[cfg(feature = "fmt-jpeg")]
+            "jpg" | "jpeg" | "jpe" | "jfif" => Some(BuiltInFormat::Jpeg),
+
+            #[cfg(feature = "fmt-png")]
+            "png" => Some(BuiltInFormat::Png),
+
+            #[cfg(feature = "fmt-pnm")]
+            "pbm" | "pgm" | "ppm" | "pnm" | "pam" => Some(BuiltInFormat::Pnm),
+
+            #[cfg(feature = "fmt-bmp")]
+            "bmp" | "dib" => Some(BuiltInFormat::Bmp),
+
+            #[cfg(feature = "fmt-qoi")]
+            "qoi" => Some(BuiltInFormat::Qoi),
+
+            #[cfg(feature = "fmt-gif")]
+            "gif" => Some(BuiltInFormat::Gif),
+
+            #[cfg(feature = "fmt-tiff")]
+            "tif" | "tiff" => Some(BuiltInFormat::Tiff),
+
+            #[cfg(feature = "fmt-ico")]
+            "ico" | "cur" => Some(BuiltInFormat::Ico),
+
+            #[cfg(feature = "fmt-hdr")]
+            "hdr" | "pic" => Some(BuiltInFormat::Hdr),
+
+            #[cfg(feature = "fmt-exr")]
+            "exr" => Some(BuiltInFormat::Exr),
+
+            #[cfg(feature = "fmt-webp")]
+            "webp" => Some(BuiltInFormat::Webp),
+
+            #[cfg(feature = "fmt-tga")]
+            "tga" | "icb" | "vda" | "vst" => Some(BuiltInFormat::Tga),
+
+            #[cfg(feature = "fmt-xpm")]
+            "xbm" => Some(BuiltInFormat::Xbm),
+
+            #[cfg(feature = "fmt-xpm")]
+            "xpm" => Some(BuiltInFormat::Xpm),
+
+            #[cfg(feature = "fmt-y4m")]
+            "y4m" => Some(BuiltInFormat::Y4m),
+
+            #[cfg(feature = "fmt-fits")]
+            "fits" | "fit" | "fts" => Some(BuiltInFormat::Fits),
+
+            _ => None,
+        }
+    }
+}
+
+impl Format for BuiltInFormat {
+    fn id(&self) -> &'static str {
+        self.get().id()
+    }
+
+    fn magic(&self) -> &'static [u8] {
+        self.get().magic()
+    }
+
+    fn signatures(&self) -> &'static [Signature] {
+        self.get().signatures()
+    }
+
+    fn detect(&self, data: &[u8]) -> Confidence {
+        self.get().detect(data)
+    }
+
+    fn is_valid_magic(&self, magic: &[u8]) -> bool {
+        self.get().is_valid_magic(magic)
+    }
+}
+
+impl FormatHandler for BuiltInFormat {
+    fn probe_dyn(&self, r: &mut dyn Read) -> io::Result<ImageInfo> {
+        self.handler().probe_dyn(r)
+    }
+
+    fn decode_dyn(&self, r: &mut dyn Read) -> io::Result<DynImage> {
+        self.handler().decode_dyn(r)
+    }
+
+    fn encode_dyn(
+        &self,
+        w: &mut dyn Write,
+        buf: &DynImage,
+        opts: &FormatOptions,
+    ) -> io::Result<()> {
+        self.handler().encode_dyn(w, buf, opts)
+    }
 }
 
 pub fn built_in_formats() -> &'static [(BuiltInFormat, &'static dyn Format)] {
@@ -133,13 +666,71 @@ pub fn built_in_formats() -> &'static [(BuiltInFormat, &'static dyn Format)] {
         {
             (BuiltInFormat::Png, &png::Png)
         },
+        #[cfg(feature = "fmt-pnm")]
+        {
+            (BuiltInFormat::Pnm, &pnm::Pnm)
+        },
+        #[cfg(feature = "fmt-bmp")]
+        {
+            (BuiltInFormat::Bmp, &bmp::Bmp)
+        },
+        #[cfg(feature = "fmt-qoi")]
+        {
+            (BuiltInFormat::Qoi, &qoi::Qoi)
+        },
+        #[cfg(feature = "fmt-gif")]
+        {
+            (BuiltInFormat::Gif, &gif::Gif)
+        },
+        #[cfg(feature = "fmt-tiff")]
+        {
+            (BuiltInFormat::Tiff, &tiff::Tiff)
+        },
+        #[cfg(feature = "fmt-ico")]
+        {
+            (BuiltInFormat::Ico, &ico::Ico)
+        },
+        #[cfg(feature = "fmt-hdr")]
+        {
+            (BuiltInFormat::Hdr, &hdr::Hdr)
+        },
+        #[cfg(feature = "fmt-exr")]
+        {
+            (BuiltInFormat::Exr, &exr::Exr)
+        },
         #[cfg(feature = "fmt-webp")]
         {
             (BuiltInFormat::Webp, &webp::Webp)
         },
+        #[cfg(feature = "fmt-tga")]
+        {
+            (BuiltInFormat::Tga, &tga::Tga)
+        },
+        #[cfg(feature = "fmt-xpm")]
+        {
+            (BuiltInFormat::Xbm, &xpm::Xbm)
+        },
+        #[cfg(feature = "fmt-xpm")]
+        {
+            (BuiltInFormat::Xpm, &xpm::Xpm)
+        },
+        #[cfg(feature = "fmt-y4m")]
+        {
+            (BuiltInFormat::Y4m, &y4m::Y4m)
+        },
+        #[cfg(feature = "fmt-fits")]
+        {
+            (BuiltInFormat::Fits, &fits::Fits)
+        },
     ]
 }
 
 pub fn built_in_formats_iter() -> impl Iterator<Item = (BuiltInFormat, &'static dyn Format)> {
     built_in_formats().iter().copied()
 }
+
+/// Decodes an image of any of the built-in formats, detected
+/// from its magic string.
+pub fn decode_any<R: Peek>(r: R) -> io::Result<DynImage> {
+    FormatRegistry::new().decode_any(r)
+}
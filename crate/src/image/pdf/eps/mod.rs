@@ -0,0 +1,132 @@
+use std::io::{self, Write};
+
+use super::{image_points, number, JpegData, Samples};
+use crate::image::{Dimensions, Image};
+use crate::impl_format;
+use crate::serialize::{Encode, EncodeOptions};
+use crate::specialized;
+
+// the number of characters of encoded data on each line
+const LINE_LEN: usize = 76;
+
+/// Encapsulated PostScript documents, embedding an image.
+/// Only encoding is supported.
+pub struct Eps;
+
+impl_format! {
+    name: Eps,
+    id: "feim:eps",
+    magic: b"%!PS-Adobe-",
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct EpsEncodeOptions {
+    /// The resolution the image is printed at, in pixels
+    /// per inch. The bounding box of the document fits
+    /// the image.
+    pub dpi: u32,
+}
+
+impl Default for EpsEncodeOptions {
+    fn default() -> Self {
+        Self { dpi: 72 }
+    }
+}
+
+impl EncodeOptions for Eps {
+    type Options = EpsEncodeOptions;
+}
+
+// encodes binary data with printable characters, four bytes
+// to every five characters
+fn write_ascii85<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut line = Vec::with_capacity(LINE_LEN + 5);
+    for chunk in data.chunks(4) {
+        let mut group = [0; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(group);
+        if value == 0 && chunk.len() == 4 {
+            line.push(b'z');
+        } else {
+            let mut digits = [0; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = b'!' + (value % 85) as u8;
+                value /= 85;
+            }
+            // partial groups leave out the digits of their padding
+            line.extend_from_slice(&digits[..chunk.len() + 1]);
+        }
+        if line.len() >= LINE_LEN {
+            w.write_all(&line)?;
+            w.write_all(b"\n")?;
+            line.clear();
+        }
+    }
+    w.write_all(&line)?;
+    w.write_all(b"~>\n")
+}
+
+fn encode_impl<W: Write>(mut w: W, opts: EpsEncodeOptions, samples: &Samples) -> io::Result<()> {
+    let (width, height) = image_points((samples.width, samples.height), opts.dpi)?;
+    let decode = samples.decode.map_or_else(
+        || format!("[{}]", vec!["0 1"; samples.components].join(" ")),
+        str::to_string,
+    );
+    // flate decoding only came with the third language level
+    let level = if samples.filter == "/DCTDecode" { 2 } else { 3 };
+
+    writeln!(w, "%!PS-Adobe-3.0 EPSF-3.0")?;
+    writeln!(w, "%%Creator: feim")?;
+    writeln!(
+        w,
+        "%%BoundingBox: 0 0 {} {}",
+        width.ceil() as u32,
+        height.ceil() as u32
+    )?;
+    writeln!(
+        w,
+        "%%HiResBoundingBox: 0 0 {} {}",
+        number(width),
+        number(height)
+    )?;
+    writeln!(w, "%%LanguageLevel: {}", level)?;
+    writeln!(w, "%%Pages: 1")?;
+    writeln!(w, "%%EndComments")?;
+    writeln!(w, "gsave")?;
+    writeln!(w, "{} {} scale", number(width), number(height))?;
+    writeln!(w, "{} setcolorspace", samples.color_space)?;
+    writeln!(w, "<<")?;
+    writeln!(w, "  /ImageType 1")?;
+    writeln!(w, "  /Width {}", samples.width)?;
+    writeln!(w, "  /Height {}", samples.height)?;
+    writeln!(w, "  /BitsPerComponent 8")?;
+    writeln!(w, "  /Decode {}", decode)?;
+    // the first row of samples is at the top
+    writeln!(
+        w,
+        "  /ImageMatrix [{} 0 0 -{} 0 {}]",
+        samples.width, samples.height, samples.height
+    )?;
+    writeln!(
+        w,
+        "  /DataSource currentfile /ASCII85Decode filter {} filter",
+        samples.filter
+    )?;
+    writeln!(w, ">> image")?;
+    write_ascii85(&mut w, &samples.data)?;
+    writeln!(w, "grestore")?;
+    writeln!(w, "showpage")?;
+    writeln!(w, "%%EOF")
+}
+
+impl<B: AsRef<[u8]>> Encode<JpegData<B>> for Eps {
+    fn encode<W: Write>(w: W, opts: EpsEncodeOptions, buf: &JpegData<B>) -> io::Result<()> {
+        encode_impl(w, opts, &buf.samples()?)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Eps {
+    fn encode<W: Write>(w: W, opts: EpsEncodeOptions, buf: &I) -> io::Result<()> {
+        encode_impl(w, opts, &Samples::from_image(buf, false))
+    }
+}
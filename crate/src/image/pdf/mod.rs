@@ -0,0 +1,376 @@
+mod eps;
+
+pub use eps::*;
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba;
+use crate::image::{Dimensions, Image};
+use crate::impl_format;
+use crate::serialize::{Encode, EncodeOptions};
+use crate::specialized;
+
+// the resolution of document space, in points per inch
+const POINTS_PER_INCH: f32 = 72.0;
+
+// the level images are compressed with
+const DEFLATE_LEVEL: u8 = 6;
+
+/// Single page PDF documents, embedding an image. Only
+/// encoding is supported.
+pub struct Pdf;
+
+impl_format! {
+    name: Pdf,
+    id: "feim:pdf",
+    magic: b"%PDF-",
+}
+
+/// The size of the page an image is placed on.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum PageSize {
+    /// A page of the same size as the image.
+    #[default]
+    Fit,
+    /// ISO A4, 210x297 mm.
+    A4,
+    /// US Letter, 8.5x11 in.
+    Letter,
+    /// A page of any size, in points.
+    Custom { width: u32, height: u32 },
+}
+
+impl PageSize {
+    // the size of the page, in points
+    fn points(self, image: (f32, f32)) -> (f32, f32) {
+        match self {
+            PageSize::Fit => image,
+            PageSize::A4 => (595.0, 842.0),
+            PageSize::Letter => (612.0, 792.0),
+            PageSize::Custom { width, height } => (width as f32, height as f32),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PdfEncodeOptions {
+    pub page_size: PageSize,
+    /// The resolution the image is printed at, in pixels
+    /// per inch. Images larger than the page are scaled
+    /// down to fit it.
+    pub dpi: u32,
+}
+
+impl Default for PdfEncodeOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::Fit,
+            dpi: 72,
+        }
+    }
+}
+
+impl EncodeOptions for Pdf {
+    type Options = PdfEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// the size of an image printed at some resolution, in points
+fn image_points((width, height): (usize, usize), dpi: u32) -> io::Result<(f32, f32)> {
+    if dpi == 0 {
+        return Err(invalid("Invalid document resolution"));
+    }
+    let scale = POINTS_PER_INCH / dpi as f32;
+    Ok((width as f32 * scale, height as f32 * scale))
+}
+
+// formats a number without trailing zeros
+fn number(v: f32) -> String {
+    let s = format!("{:.2}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// The bytes of a JPEG image, embedded into documents as they
+/// are, with no decoding and encoding in between.
+#[derive(Debug, Clone)]
+pub struct JpegData<B> {
+    data: B,
+    width: usize,
+    height: usize,
+    components: u8,
+    // adobe applications store cmyk inverted
+    adobe: bool,
+}
+
+impl<B: AsRef<[u8]>> JpegData<B> {
+    /// Wraps the bytes of a JPEG image, reading its dimensions
+    /// from the frame header.
+    pub fn new(data: B) -> io::Result<Self> {
+        let bytes = data.as_ref();
+        if !bytes.starts_with(b"\xff\xd8") {
+            return Err(invalid("Invalid jpeg header."));
+        }
+        let mut adobe = false;
+        let mut i = 2;
+        loop {
+            // markers may be preceded by any number of fill bytes
+            while bytes.get(i) == Some(&0xff) && bytes.get(i + 1) == Some(&0xff) {
+                i += 1;
+            }
+            let (marker, len) = match bytes.get(i..i + 4) {
+                Some(&[0xff, marker, hi, lo]) => (marker, u16::from_be_bytes([hi, lo]) as usize),
+                _ => return Err(invalid("Missing jpeg frame header")),
+            };
+            let segment = bytes
+                .get(i + 4..i + 2 + len.max(2))
+                .ok_or_else(|| invalid("Truncated jpeg segment"))?;
+            match marker {
+                0xee => adobe |= segment.starts_with(b"Adobe"),
+                // every start of frame, other than huffman
+                // tables, arithmetic conditioning and jpeg-ls
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return match *segment {
+                        [8, h0, h1, w0, w1, components, ..] => Ok(Self {
+                            width: u16::from_be_bytes([w0, w1]) as usize,
+                            height: u16::from_be_bytes([h0, h1]) as usize,
+                            components,
+                            adobe,
+                            data,
+                        }),
+                        _ => Err(invalid("Unsupported jpeg frame")),
+                    };
+                }
+                _ => (),
+            }
+            i += 2 + len;
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> B {
+        self.data
+    }
+
+    fn samples(&self) -> io::Result<Samples<'_>> {
+        let (color_space, decode) = match (self.components, self.adobe) {
+            (1, _) => ("/DeviceGray", None),
+            (3, _) => ("/DeviceRGB", None),
+            (4, false) => ("/DeviceCMYK", None),
+            (4, true) => ("/DeviceCMYK", Some("[1 0 1 0 1 0 1 0]")),
+            _ => return Err(invalid("Unsupported jpeg components")),
+        };
+        Ok(Samples {
+            width: self.width,
+            height: self.height,
+            components: self.components as usize,
+            color_space,
+            decode,
+            filter: "/DCTDecode",
+            data: Cow::Borrowed(self.data.as_ref()),
+            alpha: None,
+        })
+    }
+}
+
+impl<B> Dimensions for JpegData<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+// the samples of an image, as embedded into a document
+struct Samples<'a> {
+    width: usize,
+    height: usize,
+    components: usize,
+    color_space: &'static str,
+    decode: Option<&'static str>,
+    filter: &'static str,
+    data: Cow<'a, [u8]>,
+    // compressed like the color samples
+    alpha: Option<Vec<u8>>,
+}
+
+impl Samples<'_> {
+    /// Compresses the samples of any image, stored as gray when
+    /// every pixel is gray. Transparent pixels are blended with
+    /// a white background, unless `keep_alpha` is set.
+    fn from_image<I: Image + Dimensions>(buf: &I, keep_alpha: bool) -> Self {
+        let (width, height) = buf.dimensions();
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let c: Nrgba = buf.color_get(x, y).convert_into();
+                pixels.push(c);
+            }
+        }
+        let opaque = pixels.iter().all(|c| c.a == 0xff);
+        let gray = pixels.iter().all(|c| c.r == c.g && c.g == c.b);
+
+        let mut data = Vec::with_capacity(pixels.len() * if gray { 1 } else { 3 });
+        for c in &pixels {
+            let blend = |v: u8| {
+                if keep_alpha {
+                    v
+                } else {
+                    let a = c.a as u32;
+                    ((v as u32 * a + 255 * (255 - a) + 127) / 255) as u8
+                }
+            };
+            if gray {
+                data.push(blend(c.r));
+            } else {
+                data.extend_from_slice(&[blend(c.r), blend(c.g), blend(c.b)]);
+            }
+        }
+        let alpha = (keep_alpha && !opaque).then(|| {
+            let alpha: Vec<u8> = pixels.iter().map(|c| c.a).collect();
+            miniz_oxide::deflate::compress_to_vec_zlib(&alpha, DEFLATE_LEVEL)
+        });
+
+        Self {
+            width,
+            height,
+            components: if gray { 1 } else { 3 },
+            color_space: if gray { "/DeviceGray" } else { "/DeviceRGB" },
+            decode: None,
+            filter: "/FlateDecode",
+            data: Cow::Owned(miniz_oxide::deflate::compress_to_vec_zlib(
+                &data,
+                DEFLATE_LEVEL,
+            )),
+            alpha,
+        }
+    }
+}
+
+// the objects of a document, with their byte offsets
+struct Document {
+    out: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl Document {
+    fn new() -> Self {
+        // the comment of binary bytes marks the file as binary
+        let out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        Self {
+            out,
+            offsets: Vec::new(),
+        }
+    }
+
+    fn object(&mut self, dict: &str, stream: Option<&[u8]>) {
+        self.offsets.push(self.out.len());
+        let id = self.offsets.len();
+        match stream {
+            Some(stream) => {
+                let _ = write!(
+                    self.out,
+                    "{} 0 obj\n<< {} /Length {} >>\nstream\n",
+                    id,
+                    dict,
+                    stream.len()
+                );
+                self.out.extend_from_slice(stream);
+                self.out.extend_from_slice(b"\nendstream\nendobj\n");
+            }
+            None => {
+                let _ = write!(self.out, "{} 0 obj\n<< {} >>\nendobj\n", id, dict);
+            }
+        }
+    }
+
+    fn finish<W: Write>(mut self, mut w: W) -> io::Result<()> {
+        let xref = self.out.len();
+        let _ = writeln!(self.out, "xref\n0 {}", self.offsets.len() + 1);
+        // every entry takes exactly 20 bytes
+        self.out.extend_from_slice(b"0000000000 65535 f \n");
+        for offset in &self.offsets {
+            let _ = writeln!(self.out, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            self.out,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            xref
+        );
+        w.write_all(&self.out)
+    }
+}
+
+fn encode_impl<W: Write>(w: W, opts: PdfEncodeOptions, samples: &Samples) -> io::Result<()> {
+    let (iw, ih) = image_points((samples.width, samples.height), opts.dpi)?;
+    let (pw, ph) = opts.page_size.points((iw, ih));
+
+    // center the image, scaled down if it doesn't fit
+    let scale = (pw / iw).min(ph / ih).min(1.0);
+    let (iw, ih) = if scale.is_finite() {
+        (iw * scale, ih * scale)
+    } else {
+        (iw, ih)
+    };
+    let (x, y) = ((pw - iw) / 2.0, (ph - ih) / 2.0);
+
+    let mut doc = Document::new();
+    doc.object("/Type /Catalog /Pages 2 0 R", None);
+    doc.object("/Type /Pages /Kids [3 0 R] /Count 1", None);
+    let page = format!(
+        "/Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+         /Resources << /XObject << /Im0 5 0 R >> >> /Contents 4 0 R",
+        number(pw),
+        number(ph)
+    );
+    doc.object(&page, None);
+    let contents = format!(
+        "q {} 0 0 {} {} {} cm /Im0 Do Q",
+        number(iw),
+        number(ih),
+        number(x),
+        number(y)
+    );
+    doc.object("", Some(contents.as_bytes()));
+
+    let mut image = format!(
+        "/Type /XObject /Subtype /Image /Width {} /Height {} \
+         /ColorSpace {} /BitsPerComponent 8 /Filter {}",
+        samples.width, samples.height, samples.color_space, samples.filter
+    );
+    if let Some(decode) = samples.decode {
+        image.push_str(&format!(" /Decode {}", decode));
+    }
+    if samples.alpha.is_some() {
+        image.push_str(" /SMask 6 0 R");
+    }
+    doc.object(&image, Some(&samples.data));
+    if let Some(alpha) = &samples.alpha {
+        let mask = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} \
+             /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode",
+            samples.width, samples.height
+        );
+        doc.object(&mask, Some(alpha));
+    }
+    doc.finish(w)
+}
+
+impl<B: AsRef<[u8]>> Encode<JpegData<B>> for Pdf {
+    fn encode<W: Write>(w: W, opts: PdfEncodeOptions, buf: &JpegData<B>) -> io::Result<()> {
+        encode_impl(w, opts, &buf.samples()?)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Pdf {
+    fn encode<W: Write>(w: W, opts: PdfEncodeOptions, buf: &I) -> io::Result<()> {
+        encode_impl(w, opts, &Samples::from_image(buf, true))
+    }
+}
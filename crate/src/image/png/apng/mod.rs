@@ -0,0 +1,571 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use png::{
+    BitDepth, BlendOp, ColorType, Decoder, DecodingError, DisposeOp, Encoder, EncodingError,
+    FrameControl, Reader, Transformations,
+};
+
+use super::{buf_format, new_buf, Png, PngBuf, PngEncodeOptions};
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba64Be;
+use crate::image::animation::{Animation, Disposal, Frame, Frames, LoopCount};
+use crate::image::{self, Dimensions, Image, ImageMut};
+use crate::serialize::{DecodeFrames, EncodeFrames};
+use crate::specialized;
+
+/// An iterator over the frames of a PNG image. Non animated
+/// images yield a single frame.
+///
+/// Frames hold the full canvas, composited according to the
+/// blend and dispose operations of every frame so far. The original
+/// `fcTL` chunk of each frame is available through `frame_control`.
+pub struct ApngFrames<R: Read> {
+    reader: Reader<R>,
+    fctl: Option<FrameControl>,
+    canvas: PngBuf,
+    scratch: PngBuf,
+    previous: Option<PngBuf>,
+    dispose: Option<(DisposeOp, Region)>,
+    remaining: usize,
+    loop_count: LoopCount,
+}
+
+#[derive(Copy, Clone)]
+struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+fn decoding_error(e: DecodingError) -> io::Error {
+    match e {
+        DecodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+fn encoding_error(e: EncodingError) -> io::Error {
+    match e {
+        EncodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+impl<R: Read> ApngFrames<R> {
+    fn new(r: R) -> io::Result<Self> {
+        let mut decoder = Decoder::new(r);
+        decoder.set_transformations(Transformations::EXPAND);
+
+        let mut reader = decoder.read_info().map_err(decoding_error)?;
+
+        let width = reader.info().width as usize;
+        let height = reader.info().height as usize;
+        if image::pixel_count(width, height).is_none() {
+            let k = io::ErrorKind::InvalidData;
+            return Err(io::Error::new(k, "Apng image too large"));
+        }
+        let format = reader.output_color_type();
+        let canvas = new_buf(format, width, height)?;
+        let mut scratch = canvas.clone();
+
+        let (remaining, loop_count) = match reader.info().animation_control() {
+            Some(actl) if actl.num_plays == 0 => (actl.num_frames as usize, LoopCount::Infinite),
+            Some(actl) => (actl.num_frames as usize, LoopCount::Finite(actl.num_plays)),
+            None => (1, LoopCount::default()),
+        };
+
+        // the default image is not part of the animation
+        if reader.info().is_animated() && reader.info().frame_control().is_none() {
+            reader
+                .next_frame(scratch.as_mut())
+                .map_err(decoding_error)?;
+        }
+
+        Ok(ApngFrames {
+            reader,
+            fctl: None,
+            canvas,
+            scratch,
+            previous: None,
+            dispose: None,
+            remaining,
+            loop_count,
+        })
+    }
+
+    /// The number of frames left to decode.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// The frame control chunk of the last decoded frame, holding
+    /// its region, timing, blend and dispose operations.
+    #[inline]
+    pub fn frame_control(&self) -> Option<&FrameControl> {
+        self.fctl.as_ref()
+    }
+
+    fn next_frame(&mut self) -> io::Result<Frame<PngBuf>> {
+        let pending = self.dispose.take();
+        let first = pending.is_none();
+        match pending {
+            Some((DisposeOp::Background, region)) => self.clear(region),
+            Some((DisposeOp::Previous, _)) => {
+                if let Some(previous) = self.previous.take() {
+                    self.canvas = previous;
+                }
+            }
+            _ => (),
+        }
+
+        let out = self
+            .reader
+            .next_frame(self.scratch.as_mut())
+            .map_err(decoding_error)?;
+
+        let fctl = self.reader.info().frame_control().copied();
+        let (dispose_op, blend_op) = fctl
+            .map(|fctl| (fctl.dispose_op, fctl.blend_op))
+            .unwrap_or((DisposeOp::None, BlendOp::Source));
+        let dispose_op = match dispose_op {
+            DisposeOp::Previous if first => DisposeOp::Background,
+            op => op,
+        };
+        if dispose_op == DisposeOp::Previous {
+            self.previous = Some(self.canvas.clone());
+        }
+
+        let region = Region {
+            x: fctl.map(|fctl| fctl.x_offset as usize).unwrap_or(0),
+            y: fctl.map(|fctl| fctl.y_offset as usize).unwrap_or(0),
+            width: out.width as usize,
+            height: out.height as usize,
+        };
+        if region.x + region.width > self.canvas.width()
+            || region.y + region.height > self.canvas.height()
+        {
+            return Err(invalid("Apng frame out of bounds"));
+        }
+        self.blit(region, out.line_size, blend_op);
+        self.dispose = Some((dispose_op, region));
+        self.fctl = fctl;
+
+        let delay = fctl
+            .map(|fctl| {
+                let den = if fctl.delay_den == 0 {
+                    100
+                } else {
+                    fctl.delay_den
+                };
+                Duration::from_secs_f64(fctl.delay_num as f64 / den as f64)
+            })
+            .unwrap_or_default();
+        Ok(Frame {
+            buffer: self.canvas.clone(),
+            delay,
+            left: 0,
+            top: 0,
+            disposal: Disposal::Background,
+        })
+    }
+
+    fn pixel_size(&self) -> usize {
+        let (depth, color) = buf_format(&self.canvas);
+        let depth = match depth {
+            BitDepth::Sixteen => 2,
+            _ => 1,
+        };
+        color.samples() * depth
+    }
+
+    fn clear(&mut self, region: Region) {
+        let size = self.pixel_size();
+        let stride = self.canvas.width() * size;
+        let canvas = self.canvas.as_mut();
+        for y in region.y..region.y + region.height {
+            let start = y * stride + region.x * size;
+            for b in canvas[start..start + region.width * size].iter_mut() {
+                *b = 0;
+            }
+        }
+    }
+
+    fn blit(&mut self, region: Region, line_size: usize, blend_op: BlendOp) {
+        let size = self.pixel_size();
+        let (depth, color) = buf_format(&self.canvas);
+        let blend = blend_op == BlendOp::Over && color == ColorType::Rgba;
+        let wide = depth == BitDepth::Sixteen;
+        let stride = self.canvas.width() * size;
+        let canvas = self.canvas.as_mut();
+        let scratch = self.scratch.as_ref();
+        for y in 0..region.height {
+            let start = (region.y + y) * stride + region.x * size;
+            let dst = &mut canvas[start..start + region.width * size];
+            let src = &scratch[y * line_size..y * line_size + region.width * size];
+            if blend {
+                blend_over(dst, src, wide);
+            } else {
+                dst.copy_from_slice(src);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ApngFrames<R> {
+    type Item = io::Result<Frame<PngBuf>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let frame = self.next_frame();
+        self.remaining = if frame.is_ok() { self.remaining - 1 } else { 0 };
+        Some(frame)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<R: Read> Dimensions for ApngFrames<R> {
+    fn width(&self) -> usize {
+        self.canvas.width()
+    }
+
+    fn height(&self) -> usize {
+        self.canvas.height()
+    }
+}
+
+impl<R: Read> Frames for ApngFrames<R> {
+    type Buffer = PngBuf;
+
+    #[inline]
+    fn loop_count(&self) -> LoopCount {
+        self.loop_count
+    }
+}
+
+impl DecodeFrames<PngBuf> for Png {
+    type Frames<R: Read> = ApngFrames<R>;
+
+    #[inline]
+    fn decode_frames<R: Read>(r: R, _opt: ()) -> io::Result<ApngFrames<R>> {
+        ApngFrames::new(r)
+    }
+}
+
+/// Composite non-premultiplied RGBA pixels, either 8 or 16 bits
+/// per component, with the alpha over operator.
+fn blend_over(dst: &mut [u8], src: &[u8], wide: bool) {
+    let (size, max) = if wide { (2, 0xffff) } else { (1, 0xff) };
+    let get = |b: &[u8], i: usize| -> u64 {
+        if wide {
+            u16::from_be_bytes([b[i * 2], b[i * 2 + 1]]) as u64
+        } else {
+            b[i] as u64
+        }
+    };
+    for (d, s) in dst
+        .chunks_exact_mut(4 * size)
+        .zip(src.chunks_exact(4 * size))
+    {
+        let sa = get(s, 3);
+        if sa == max {
+            d.copy_from_slice(s);
+            continue;
+        }
+        if sa == 0 {
+            continue;
+        }
+        let da = get(d, 3) * (max - sa) / max;
+        let a = sa + da;
+        let mut out = [0u64; 4];
+        for (i, c) in out.iter_mut().enumerate().take(3) {
+            *c = (get(s, i) * sa + get(d, i) * da) / a;
+        }
+        out[3] = a;
+        for (i, &c) in out.iter().enumerate() {
+            if wide {
+                d[i * 2..i * 2 + 2].copy_from_slice(&(c as u16).to_be_bytes());
+            } else {
+                d[i] = c as u8;
+            }
+        }
+    }
+}
+
+/// Approximate a frame delay as a fraction of a second.
+fn delay_fraction(delay: Duration) -> (u16, u16) {
+    let millis = delay.as_millis();
+    if millis <= u16::MAX as u128 {
+        (millis as u16, 1000)
+    } else {
+        ((millis / 10).min(u16::MAX as u128) as u16, 100)
+    }
+}
+
+fn dispose_op(disposal: Disposal) -> DisposeOp {
+    match disposal {
+        Disposal::None => DisposeOp::None,
+        Disposal::Background => DisposeOp::Background,
+        Disposal::Previous => DisposeOp::Previous,
+    }
+}
+
+pub(super) fn encode_frames_impl<W, B, F>(
+    w: W,
+    opts: PngEncodeOptions,
+    (depth, color): (BitDepth, ColorType),
+    frames: F,
+) -> io::Result<()>
+where
+    W: Write,
+    B: Dimensions + AsRef<[u8]>,
+    F: Frames<Buffer = B>,
+{
+    let width = u32::try_from(frames.width()).map_err(|_| invalid("Apng image too wide"))?;
+    let height = u32::try_from(frames.height()).map_err(|_| invalid("Apng image too tall"))?;
+    let num_plays = match frames.loop_count() {
+        LoopCount::Finite(n) => n.max(1),
+        LoopCount::Infinite => 0,
+    };
+
+    // the frame count is stored before any frame data
+    let frames = frames.collect::<io::Result<Vec<_>>>()?;
+
+    let first = frames
+        .first()
+        .ok_or_else(|| invalid("Apng images need at least one frame"))?;
+    if first.left != 0
+        || first.top != 0
+        || first.buffer.width() != width as usize
+        || first.buffer.height() != height as usize
+    {
+        return Err(invalid("The first apng frame must cover the whole canvas"));
+    }
+    let num_frames = u32::try_from(frames.len()).map_err(|_| invalid("Too many apng frames"))?;
+
+    let mut encoder = Encoder::new(w, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    encoder.set_filter(opts.filter);
+    encoder.set_compression(opts.compression);
+    encoder
+        .set_animated(num_frames, num_plays)
+        .map_err(encoding_error)?;
+
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+
+    for frame in frames.iter() {
+        let width =
+            u32::try_from(frame.buffer.width()).map_err(|_| invalid("Apng frame too wide"))?;
+        let height =
+            u32::try_from(frame.buffer.height()).map_err(|_| invalid("Apng frame too tall"))?;
+        let left = u32::try_from(frame.left).map_err(|_| invalid("Apng frame out of bounds"))?;
+        let top = u32::try_from(frame.top).map_err(|_| invalid("Apng frame out of bounds"))?;
+        let (delay_num, delay_den) = delay_fraction(frame.delay);
+
+        writer.reset_frame_position().map_err(encoding_error)?;
+        writer
+            .set_frame_dimension(width, height)
+            .map_err(encoding_error)?;
+        writer
+            .set_frame_position(left, top)
+            .map_err(encoding_error)?;
+        writer
+            .set_frame_delay(delay_num, delay_den)
+            .map_err(encoding_error)?;
+        writer
+            .set_dispose_op(dispose_op(frame.disposal))
+            .map_err(encoding_error)?;
+        writer.set_blend_op(BlendOp::Over).map_err(encoding_error)?;
+        writer
+            .write_image_data(frame.buffer.as_ref())
+            .map_err(encoding_error)?;
+    }
+
+    writer.finish().map_err(encoding_error)
+}
+
+impl EncodeFrames<PngBuf> for Png {
+    fn encode_frames<W, F>(w: W, opts: PngEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = PngBuf>,
+    {
+        let (width, height) = frames.dimensions();
+        let loop_count = frames.loop_count();
+        let frames = frames.collect::<io::Result<Vec<_>>>()?;
+        let format = frames
+            .first()
+            .map(|frame| buf_format(&frame.buffer))
+            .ok_or_else(|| invalid("Apng images need at least one frame"))?;
+        if frames
+            .iter()
+            .any(|frame| buf_format(&frame.buffer) != format)
+        {
+            return Err(invalid("Apng frames must share the same pixel format"));
+        }
+        let frames = Animation {
+            width,
+            height,
+            loop_count,
+            frames,
+        };
+        encode_frames_impl(w, opts, format, frames.into_frames())
+    }
+}
+
+impl<I: Image + Dimensions> EncodeFrames<I, specialized::No> for Png {
+    fn encode_frames<W, F>(w: W, opts: PngEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = I>,
+    {
+        let (width, height) = frames.dimensions();
+        let loop_count = frames.loop_count();
+        let frames = frames
+            .map(|frame| {
+                frame.map(|frame| {
+                    frame.map(|buf| {
+                        let (width, height) = buf.dimensions();
+                        let mut new_buf = RawPixBuf::new(width, height);
+                        for y in 0..height {
+                            for x in 0..width {
+                                let c = buf.color_get(x, y);
+                                let c: Nrgba64Be = c.convert_into();
+                                new_buf.pixel_set(x, y, c);
+                            }
+                        }
+                        new_buf
+                    })
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let frames = Animation {
+            width,
+            height,
+            loop_count,
+            frames,
+        };
+        encode_frames_impl(
+            w,
+            opts,
+            (BitDepth::Sixteen, ColorType::Rgba),
+            frames.into_frames(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Nrgba;
+    use crate::serialize::EncodeFramesSpecialized;
+
+    fn frame(w: usize, h: usize, c: Nrgba, millis: u64) -> Frame<PngBuf> {
+        let mut buf = RawPixBuf::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                buf.pixel_set(x, y, c);
+            }
+        }
+        Frame::new(PngBuf::Nrgba(buf), Duration::from_millis(millis))
+    }
+
+    fn at(frame: Frame<PngBuf>, left: usize, top: usize, disposal: Disposal) -> Frame<PngBuf> {
+        Frame {
+            left,
+            top,
+            disposal,
+            ..frame
+        }
+    }
+
+    #[test]
+    fn round_trip_blend_and_dispose() {
+        let red = Nrgba {
+            r: 0xff,
+            g: 0,
+            b: 0,
+            a: 0xff,
+        };
+        let blue = Nrgba {
+            r: 0,
+            g: 0,
+            b: 0xff,
+            a: 0x80,
+        };
+        let green = Nrgba {
+            r: 0,
+            g: 0xff,
+            b: 0,
+            a: 0xff,
+        };
+        let animation = Animation {
+            width: 4,
+            height: 4,
+            loop_count: LoopCount::Infinite,
+            frames: vec![
+                frame(4, 4, red, 100),
+                at(frame(2, 2, blue, 40), 1, 1, Disposal::Background),
+                at(frame(1, 1, green, 40), 0, 0, Disposal::Previous),
+                at(frame(1, 1, green, 250), 3, 3, Disposal::None),
+            ],
+        };
+        let mut out = Vec::new();
+        Png::encode_frames_specialized(&mut out, Default::default(), animation.into_frames())
+            .unwrap();
+
+        let frames = Png::decode_frames(&out[..], ()).unwrap();
+        assert_eq!(frames.loop_count(), LoopCount::Infinite);
+        let frames: Vec<_> = frames.collect::<io::Result<_>>().unwrap();
+        assert_eq!(frames.len(), 4);
+        let delays: Vec<_> = frames.iter().map(|f| f.delay.as_millis()).collect();
+        assert_eq!(delays, [100, 40, 40, 250]);
+
+        let pixel = |i: usize, x, y| -> Nrgba {
+            match &frames[i].buffer {
+                PngBuf::Nrgba(buf) => buf.color_get(x, y),
+                _ => panic!("unexpected pixel format"),
+            }
+        };
+        let clear = Nrgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        // blended over the red background
+        let purple = Nrgba {
+            r: 0x7f,
+            g: 0,
+            b: 0x80,
+            a: 0xff,
+        };
+        assert_eq!(pixel(0, 1, 1), red);
+        assert_eq!(pixel(1, 0, 0), red);
+        assert_eq!(pixel(1, 1, 1), purple);
+        assert_eq!(pixel(1, 2, 2), purple);
+        // the second frame's area is cleared, before drawing the third
+        assert_eq!(pixel(2, 0, 0), green);
+        assert_eq!(pixel(2, 1, 1), clear);
+        assert_eq!(pixel(2, 3, 3), red);
+        // and the third frame is undone, before drawing the fourth
+        assert_eq!(pixel(3, 0, 0), red);
+        assert_eq!(pixel(3, 2, 2), clear);
+        assert_eq!(pixel(3, 3, 3), green);
+    }
+}
@@ -1,20 +1,28 @@
+mod apng;
 mod png_buf;
 
+pub use apng::*;
 pub use png_buf::*;
 
 use std::io::{self, Read, Write};
 
-use png::{BitDepth, ColorType, Decoder, DecodingError, Encoder, EncodingError, Transformations};
+use png::{
+    BitDepth, ColorType, Decoder, DecodingError, Encoder, EncodingError, Info, Transformations,
+};
 
 // re-export this stuff
-pub use png::{Compression, FilterType};
+pub use png::{BlendOp, Compression, DisposeOp, FilterType, FrameControl};
 
 use crate::buffer::RawPixBuf;
 use crate::color::convert::ConvertInto;
+use crate::color::icc::WithIcc;
 use crate::color::{Gray, Gray16Be, Nrgba, Nrgba64Be, Rgb, Rgb48Be};
-use crate::image::{Dimensions, Image, ImageMut};
+use crate::image::animation::Frames;
+use crate::image::{self, Dimensions, Image, ImageInfo, ImageMut};
 use crate::impl_format;
-use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized};
+use crate::serialize::{
+    Decode, DecodeOptions, Encode, EncodeFrames, EncodeOptions, EncodeSpecialized, Probe,
+};
 use crate::specialized;
 
 pub struct Png;
@@ -30,41 +38,101 @@ impl DecodeOptions for Png {
 }
 
 impl Decode<PngBuf> for Png {
+    #[inline]
     fn decode<R: Read>(r: R, _opt: ()) -> io::Result<PngBuf> {
-        let mut decoder = Decoder::new(r);
-        decoder.set_transformations(Transformations::EXPAND);
+        decode_impl(r).map(|buf| buf.image)
+    }
+}
 
-        let mut reader = decoder.read_info().map_err(|e| match e {
-            DecodingError::IoError(e) => e,
-            other => io::Error::new(io::ErrorKind::Other, other),
-        })?;
+impl Decode<WithIcc<PngBuf>> for Png {
+    #[inline]
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<WithIcc<PngBuf>> {
+        decode_impl(r)
+    }
+}
 
-        let width = reader.info().width as usize;
-        let height = reader.info().height as usize;
+fn decode_impl<R: Read>(r: R) -> io::Result<WithIcc<PngBuf>> {
+    let mut decoder = Decoder::new(r);
+    decoder.set_transformations(Transformations::EXPAND);
 
-        let mut buffer = match reader.output_color_type() {
-            (ColorType::Grayscale, BitDepth::Eight) => PngBuf::Gray(RawPixBuf::new(width, height)),
-            (ColorType::Grayscale, BitDepth::Sixteen) => {
-                PngBuf::Gray16(RawPixBuf::new(width, height))
-            }
-            (ColorType::Rgba, BitDepth::Eight) => PngBuf::Nrgba(RawPixBuf::new(width, height)),
-            (ColorType::Rgba, BitDepth::Sixteen) => PngBuf::Nrgba64(RawPixBuf::new(width, height)),
-            (ColorType::Rgb, BitDepth::Eight) => PngBuf::Rgb(RawPixBuf::new(width, height)),
-            (ColorType::Rgb, BitDepth::Sixteen) => PngBuf::Rgb48(RawPixBuf::new(width, height)),
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Unsupported color type detected",
-                ))
-            }
-        };
+    let mut reader = decoder.read_info().map_err(|e| match e {
+        DecodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    })?;
+
+    let width = reader.info().width as usize;
+    let height = reader.info().height as usize;
+    let icc_profile = reader.info().icc_profile.as_deref().map(Vec::from);
 
-        reader.next_frame(buffer.as_mut()).map_err(|e| match e {
+    let mut buffer = new_buf(reader.output_color_type(), width, height)?;
+
+    reader.next_frame(buffer.as_mut()).map_err(|e| match e {
+        DecodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    })?;
+
+    Ok(WithIcc::new(buffer, icc_profile))
+}
+
+impl Probe for Png {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        // reading the info stops at the first image data chunk
+        let reader = Decoder::new(r).read_info().map_err(|e| match e {
             DecodingError::IoError(e) => e,
-            other => io::Error::new(io::ErrorKind::Other, other),
+            other => io::Error::other(other),
         })?;
+        let info = reader.info();
+
+        let color_type = match info.color_type {
+            ColorType::Grayscale => image::ColorType::Gray,
+            ColorType::GrayscaleAlpha => image::ColorType::GrayAlpha,
+            ColorType::Rgb => image::ColorType::Rgb,
+            ColorType::Rgba => image::ColorType::Rgba,
+            ColorType::Indexed => image::ColorType::Indexed,
+        };
+        let mut probed = ImageInfo::new(
+            info.width as usize,
+            info.height as usize,
+            color_type,
+            info.bit_depth as u8,
+        );
+        probed.has_alpha |= info.trns.is_some();
+        probed.interlaced = info.interlaced;
+        // like when decoding frames, the default image is
+        // only counted when it is part of the animation
+        if let Some(actl) = info.animation_control() {
+            probed.frame_count = Some(actl.num_frames as usize);
+        }
+        Ok(probed)
+    }
+}
 
-        Ok(buffer)
+fn new_buf(
+    (color, depth): (ColorType, BitDepth),
+    width: usize,
+    height: usize,
+) -> io::Result<PngBuf> {
+    match (color, depth) {
+        (ColorType::Grayscale, BitDepth::Eight) => Ok(PngBuf::Gray(RawPixBuf::new(width, height))),
+        (ColorType::Grayscale, BitDepth::Sixteen) => {
+            Ok(PngBuf::Gray16(RawPixBuf::new(width, height)))
+        }
+        (ColorType::Rgba, BitDepth::Eight) => Ok(PngBuf::Nrgba(RawPixBuf::new(width, height))),
+        (ColorType::Rgba, BitDepth::Sixteen) => Ok(PngBuf::Nrgba64(RawPixBuf::new(width, height))),
+        (ColorType::Rgb, BitDepth::Eight) => Ok(PngBuf::Rgb(RawPixBuf::new(width, height))),
+        (ColorType::Rgb, BitDepth::Sixteen) => Ok(PngBuf::Rgb48(RawPixBuf::new(width, height))),
+        _ => Err(io::Error::other("Unsupported color type detected")),
+    }
+}
+
+fn buf_format(buf: &PngBuf) -> (BitDepth, ColorType) {
+    match buf {
+        PngBuf::Gray(_) => (BitDepth::Eight, ColorType::Grayscale),
+        PngBuf::Gray16(_) => (BitDepth::Sixteen, ColorType::Grayscale),
+        PngBuf::Nrgba(_) => (BitDepth::Eight, ColorType::Rgba),
+        PngBuf::Nrgba64(_) => (BitDepth::Sixteen, ColorType::Rgba),
+        PngBuf::Rgb(_) => (BitDepth::Eight, ColorType::Rgb),
+        PngBuf::Rgb48(_) => (BitDepth::Sixteen, ColorType::Rgb),
     }
 }
 
@@ -87,28 +155,69 @@ impl EncodeOptions for Png {
     type Options = PngEncodeOptions;
 }
 
+fn encode_impl<W: Write>(
+    w: W,
+    opts: PngEncodeOptions,
+    (width, height): (usize, usize),
+    (depth, color): (BitDepth, ColorType),
+    icc_profile: Option<&[u8]>,
+    data: &[u8],
+) -> io::Result<()> {
+    let width = (width & u32::MAX as usize) as u32;
+    let height = (height & u32::MAX as usize) as u32;
+
+    let mut info = Info::with_size(width, height);
+    info.icc_profile = icc_profile.map(Into::into);
+
+    let mut encoder = Encoder::with_info(w, info).map_err(|e| match e {
+        EncodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    })?;
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    encoder.set_filter(opts.filter);
+    encoder.set_compression(opts.compression);
+
+    let mut writer = encoder.write_header().map_err(|e| match e {
+        EncodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    })?;
+
+    writer.write_image_data(data).map_err(|e| match e {
+        EncodingError::IoError(e) => e,
+        other => io::Error::other(other),
+    })
+}
+
 macro_rules! impl_encode {
     ($type:ty, $depth:expr, $color:expr) => {
         impl Encode<$type> for Png {
             fn encode<W: Write>(w: W, opts: PngEncodeOptions, buf: &$type) -> io::Result<()> {
-                let width = (buf.width() & u32::MAX as usize) as u32;
-                let height = (buf.height() & u32::MAX as usize) as u32;
-
-                let mut encoder = Encoder::new(w, width, height);
-                encoder.set_color($color);
-                encoder.set_depth($depth);
-                encoder.set_filter(opts.filter);
-                encoder.set_compression(opts.compression);
-
-                let mut writer = encoder.write_header().map_err(|e| match e {
-                    EncodingError::IoError(e) => e,
-                    other => io::Error::new(io::ErrorKind::Other, other),
-                })?;
-
-                writer.write_image_data(buf.as_ref()).map_err(|e| match e {
-                    EncodingError::IoError(e) => e,
-                    other => io::Error::new(io::ErrorKind::Other, other),
-                })
+                let dims = buf.dimensions();
+                encode_impl(w, opts, dims, ($depth, $color), None, buf.as_ref())
+            }
+        }
+
+        impl Encode<WithIcc<$type>> for Png {
+            fn encode<W: Write>(
+                w: W,
+                opts: PngEncodeOptions,
+                buf: &WithIcc<$type>,
+            ) -> io::Result<()> {
+                let dims = buf.dimensions();
+                let icc_profile = buf.icc_profile.as_deref();
+                let data = buf.image.as_ref();
+                encode_impl(w, opts, dims, ($depth, $color), icc_profile, data)
+            }
+        }
+
+        impl EncodeFrames<$type> for Png {
+            fn encode_frames<W, F>(w: W, opts: PngEncodeOptions, frames: F) -> io::Result<()>
+            where
+                W: Write,
+                F: Frames<Buffer = $type>,
+            {
+                encode_frames_impl(w, opts, ($depth, $color), frames)
             }
         }
     };
@@ -147,6 +256,16 @@ impl Encode<PngBuf> for Png {
     }
 }
 
+impl Encode<WithIcc<PngBuf>> for Png {
+    fn encode<W: Write>(w: W, opts: PngEncodeOptions, buf: &WithIcc<PngBuf>) -> io::Result<()> {
+        let (depth, color) = buf_format(&buf.image);
+        let dims = buf.dimensions();
+        let icc_profile = buf.icc_profile.as_deref();
+        let data = buf.image.as_ref();
+        encode_impl(w, opts, dims, (depth, color), icc_profile, data)
+    }
+}
+
 impl<I: Image + Dimensions> Encode<I, specialized::No> for Png {
     fn encode<W: Write>(w: W, opts: PngEncodeOptions, buf: &I) -> io::Result<()> {
         let (width, height) = buf.dimensions();
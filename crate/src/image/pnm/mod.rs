@@ -0,0 +1,596 @@
+mod pnm_buf;
+
+pub use pnm_buf::*;
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Gray, Gray16Be, Nrgba, Nrgba64Be, Rgb, Rgb48Be};
+use crate::image::{ColorType, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+pub struct Pnm;
+
+impl_format! {
+    name: Pnm,
+    id: "feim:pnm",
+    magic: b"P?",
+    signatures: &[
+        Signature::new(b"P1"),
+        Signature::new(b"P2"),
+        Signature::new(b"P3"),
+        Signature::new(b"P4"),
+        Signature::new(b"P5"),
+        Signature::new(b"P6"),
+        Signature::new(b"P7"),
+    ],
+}
+
+impl DecodeOptions for Pnm {
+    type Options = ();
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: usize,
+    maxval: u32,
+}
+
+impl Decode<PnmBuf> for Pnm {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<PnmBuf> {
+        let mut r = ByteReader::new(r);
+        let (kind, header) = read_any_header(&mut r)?;
+
+        let Header {
+            width,
+            height,
+            depth,
+            maxval,
+        } = header;
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(depth))
+            .ok_or_else(|| invalid("Netpbm image dimensions too large"))?;
+
+        let samples = match kind {
+            1 => read_plain_bits(&mut r, count)?,
+            4 => read_packed_bits(&mut r, width, height)?,
+            2 | 3 => read_plain_samples(&mut r, count, maxval)?,
+            _ => read_raw_samples(&mut r, count, maxval)?,
+        };
+
+        pack_samples(&header, samples)
+    }
+}
+
+impl Probe for Pnm {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        let (_, header) = read_any_header(&mut ByteReader::new(r))?;
+        let color_type = match header.depth {
+            1 => ColorType::Gray,
+            2 => ColorType::GrayAlpha,
+            3 => ColorType::Rgb,
+            4 => ColorType::Rgba,
+            _ => return Err(invalid("Unsupported PAM depth")),
+        };
+        let bit_depth = (32 - header.maxval.leading_zeros()) as u8;
+        Ok(ImageInfo::new(
+            header.width,
+            header.height,
+            color_type,
+            bit_depth,
+        ))
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// reads a byte at a time, so that none of the data after the
+// image is lost, when reading from a stream
+struct ByteReader<R> {
+    inner: R,
+    next: Option<u8>,
+}
+
+impl<R: Read> ByteReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, next: None }
+    }
+}
+
+impl<R: Read> Read for ByteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match (self.next.take(), buf.first_mut()) {
+            (Some(b), Some(first)) => {
+                *first = b;
+                Ok(1)
+            }
+            (next, _) => {
+                self.next = next;
+                self.inner.read(buf)
+            }
+        }
+    }
+}
+
+impl<R: Read> BufRead for ByteReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.next.is_none() {
+            let mut b = [0];
+            loop {
+                match self.inner.read(&mut b) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        self.next = Some(b[0]);
+                        break;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(self.next.as_slice())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 {
+            self.next = None;
+        }
+    }
+}
+
+fn peek_byte<R: BufRead>(r: &mut R) -> io::Result<Option<u8>> {
+    Ok(r.fill_buf()?.first().copied())
+}
+
+fn skip_whitespace<R: BufRead>(r: &mut R) -> io::Result<()> {
+    while let Some(b) = peek_byte(r)? {
+        if b == b'#' {
+            let mut comment = Vec::new();
+            r.read_until(b'\n', &mut comment)?;
+        } else if b.is_ascii_whitespace() {
+            r.consume(1);
+        } else {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_number<R: BufRead>(r: &mut R) -> io::Result<u32> {
+    skip_whitespace(r)?;
+    let mut value: u32 = 0;
+    let mut digits = 0;
+    while let Some(b) = peek_byte(r)? {
+        if !b.is_ascii_digit() {
+            break;
+        }
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add((b - b'0') as u32))
+            .ok_or_else(|| invalid("Netpbm number out of range"))?;
+        digits += 1;
+        r.consume(1);
+    }
+    if digits == 0 {
+        return Err(invalid("Expected a number in netpbm data"));
+    }
+    Ok(value)
+}
+
+// reads the magic and the header of any kind of image
+fn read_any_header<R: BufRead>(r: &mut R) -> io::Result<(u8, Header)> {
+    let mut magic = [0; 2];
+    r.read_exact(&mut magic)?;
+    if !Pnm.is_valid_magic(&magic) {
+        return Err(invalid("Invalid netpbm magic."));
+    }
+    let kind = magic[1] - b'0';
+
+    let header = if kind == 7 {
+        read_pam_header(r)?
+    } else {
+        read_header(r, kind)?
+    };
+    Ok((kind, header))
+}
+
+fn read_header<R: BufRead>(r: &mut R, kind: u8) -> io::Result<Header> {
+    let width = read_number(r)? as usize;
+    let height = read_number(r)? as usize;
+    let maxval = if kind == 1 || kind == 4 {
+        1
+    } else {
+        read_number(r)?
+    };
+    if maxval == 0 || maxval > 0xffff {
+        return Err(invalid("Invalid netpbm maxval"));
+    }
+    // a single whitespace character separates the header
+    // from the raster of binary images
+    if kind != 1 {
+        let mut sep = [0];
+        r.read_exact(&mut sep)?;
+    }
+    let depth = if kind == 3 || kind == 6 { 3 } else { 1 };
+    Ok(Header {
+        width,
+        height,
+        depth,
+        maxval,
+    })
+}
+
+fn read_pam_header<R: BufRead>(r: &mut R) -> io::Result<Header> {
+    let mut width = None;
+    let mut height = None;
+    let mut depth = None;
+    let mut maxval = None;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of PAM header"));
+        }
+        let mut fields = line.split_ascii_whitespace();
+        let key = match fields.next() {
+            Some(key) if !key.starts_with('#') => key,
+            _ => continue,
+        };
+        let mut value = || -> io::Result<u32> {
+            fields
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| invalid("Invalid PAM header value"))
+        };
+        match key {
+            "WIDTH" => width = Some(value()? as usize),
+            "HEIGHT" => height = Some(value()? as usize),
+            "DEPTH" => depth = Some(value()? as usize),
+            "MAXVAL" => maxval = Some(value()?),
+            "ENDHDR" => break,
+            // the tuple type is implied by the depth, for
+            // all of the tuple types we support
+            _ => (),
+        }
+    }
+
+    let missing = || invalid("Incomplete PAM header");
+    let maxval = maxval.ok_or_else(missing)?;
+    if maxval == 0 || maxval > 0xffff {
+        return Err(invalid("Invalid netpbm maxval"));
+    }
+    Ok(Header {
+        width: width.ok_or_else(missing)?,
+        height: height.ok_or_else(missing)?,
+        depth: depth.ok_or_else(missing)?,
+        maxval,
+    })
+}
+
+// NOTE: in pbm images, 1 is black, so we invert the
+// bits here, to get regular gray samples out
+fn read_plain_bits<R: BufRead>(r: &mut R, count: usize) -> io::Result<Vec<u16>> {
+    let mut samples = Vec::new();
+    while samples.len() < count {
+        skip_whitespace(r)?;
+        match peek_byte(r)? {
+            Some(b'0') => samples.push(1),
+            Some(b'1') => samples.push(0),
+            Some(_) => return Err(invalid("Invalid plain PBM pixel")),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+        r.consume(1);
+    }
+    Ok(samples)
+}
+
+fn read_packed_bits<R: Read>(r: &mut R, width: usize, height: usize) -> io::Result<Vec<u16>> {
+    let stride = width.div_ceil(8);
+    let len = stride
+        .checked_mul(height)
+        .ok_or_else(|| invalid("Netpbm image dimensions too large"))?;
+    let data = read_raster(r, len)?;
+    let mut samples = Vec::with_capacity(data.len() * 8);
+    for row in data.chunks_exact(stride.max(1)).take(height) {
+        for x in 0..width {
+            let bit = (row[x / 8] >> (7 - (x % 8))) & 1;
+            samples.push((bit ^ 1) as u16);
+        }
+    }
+    Ok(samples)
+}
+
+fn read_plain_samples<R: BufRead>(r: &mut R, count: usize, maxval: u32) -> io::Result<Vec<u16>> {
+    let mut samples = Vec::new();
+    for _ in 0..count {
+        let sample = read_number(r)?;
+        if sample > maxval {
+            return Err(invalid("Netpbm sample exceeds maxval"));
+        }
+        samples.push(sample as u16);
+    }
+    Ok(samples)
+}
+
+fn read_raw_samples<R: Read>(r: &mut R, count: usize, maxval: u32) -> io::Result<Vec<u16>> {
+    let samples = if maxval < 256 {
+        let raw = read_raster(r, count)?;
+        raw.into_iter().map(u16::from).collect::<Vec<_>>()
+    } else {
+        let len = count
+            .checked_mul(2)
+            .ok_or_else(|| invalid("Netpbm image dimensions too large"))?;
+        let raw = read_raster(r, len)?;
+        raw.chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect()
+    };
+    if samples.iter().any(|&s| s as u32 > maxval) {
+        return Err(invalid("Netpbm sample exceeds maxval"));
+    }
+    Ok(samples)
+}
+
+// read the raster as it comes, rather than trusting
+// the header with a large allocation
+fn read_raster<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+fn pack_samples(header: &Header, samples: Vec<u16>) -> io::Result<PnmBuf> {
+    let Header {
+        width,
+        height,
+        depth,
+        maxval,
+    } = *header;
+
+    // gray + alpha samples are expanded into rgba
+    let expand = |samples: Vec<u16>| -> Vec<u16> {
+        samples
+            .chunks_exact(2)
+            .flat_map(|s| [s[0], s[0], s[0], s[1]])
+            .collect()
+    };
+    let (samples, depth) = match depth {
+        1 | 3 | 4 => (samples, depth),
+        2 => (expand(samples), 4),
+        _ => return Err(invalid("Unsupported PAM depth")),
+    };
+
+    let maxval = maxval as u64;
+    let buf = if maxval < 256 {
+        samples
+            .into_iter()
+            .map(|s| ((s as u64 * 0xff + maxval / 2) / maxval) as u8)
+            .collect::<Vec<_>>()
+    } else {
+        samples
+            .into_iter()
+            .flat_map(|s| {
+                let s = ((s as u64 * 0xffff + maxval / 2) / maxval) as u16;
+                s.to_be_bytes()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let wrong_size = |_| invalid("Netpbm raster size mismatch");
+    Ok(match (depth, maxval < 256) {
+        (1, true) => PnmBuf::Gray(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+        (1, false) => PnmBuf::Gray16(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+        (3, true) => PnmBuf::Rgb(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+        (3, false) => PnmBuf::Rgb48(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+        (_, true) => PnmBuf::Nrgba(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+        (_, false) => PnmBuf::Nrgba64(RawPixBuf::from_vec(width, height, buf).map_err(wrong_size)?),
+    })
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PnmEncodeOptions {
+    /// Write the plain (ASCII) variant of the format. PAM images,
+    /// used for buffers with an alpha channel, are always binary.
+    pub plain: bool,
+}
+
+impl EncodeOptions for Pnm {
+    type Options = PnmEncodeOptions;
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: PnmEncodeOptions,
+    (width, height): (usize, usize),
+    depth: usize,
+    wide: bool,
+    data: &[u8],
+) -> io::Result<()> {
+    let maxval = if wide { 0xffff } else { 0xff };
+
+    if depth == 4 {
+        write!(
+            w,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL {}\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            width, height, maxval
+        )?;
+        return w.write_all(data);
+    }
+
+    let kind = match (depth, opts.plain) {
+        (1, true) => 2,
+        (1, false) => 5,
+        (_, true) => 3,
+        (_, false) => 6,
+    };
+    write!(w, "P{}\n{} {}\n{}\n", kind, width, height, maxval)?;
+
+    if !opts.plain {
+        return w.write_all(data);
+    }
+
+    // plain images should not have lines longer
+    // than 70 characters
+    let mut line_len = 0;
+    let mut write_sample = |w: &mut W, sample: u16| -> io::Result<()> {
+        let sample = sample.to_string();
+        if line_len + sample.len() + 1 > 70 {
+            w.write_all(b"\n")?;
+            line_len = 0;
+        } else if line_len > 0 {
+            w.write_all(b" ")?;
+            line_len += 1;
+        }
+        line_len += sample.len();
+        w.write_all(sample.as_bytes())
+    };
+    if wide {
+        for s in data.chunks_exact(2) {
+            write_sample(&mut w, u16::from_be_bytes([s[0], s[1]]))?;
+        }
+    } else {
+        for &s in data {
+            write_sample(&mut w, s as u16)?;
+        }
+    }
+    w.write_all(b"\n")
+}
+
+macro_rules! impl_encode {
+    ($type:ty, $depth:expr, $wide:expr) => {
+        impl Encode<$type> for Pnm {
+            fn encode<W: Write>(w: W, opts: PnmEncodeOptions, buf: &$type) -> io::Result<()> {
+                let dims = buf.dimensions();
+                encode_impl(w, opts, dims, $depth, $wide, buf.as_ref())
+            }
+        }
+    };
+}
+
+impl_encode!(RawPixBuf<Gray>, 1, false);
+impl_encode!(RawPixBuf<Gray16Be>, 1, true);
+impl_encode!(RawPixBuf<Rgb>, 3, false);
+impl_encode!(RawPixBuf<Rgb48Be>, 3, true);
+impl_encode!(RawPixBuf<Nrgba>, 4, false);
+impl_encode!(RawPixBuf<Nrgba64Be>, 4, true);
+
+impl Encode<PnmBuf> for Pnm {
+    fn encode<W: Write>(w: W, opts: PnmEncodeOptions, buf: &PnmBuf) -> io::Result<()> {
+        match buf {
+            PnmBuf::Gray(buf) => Pnm::encode_specialized(w, opts, buf),
+            PnmBuf::Gray16(buf) => Pnm::encode_specialized(w, opts, buf),
+            PnmBuf::Nrgba(buf) => Pnm::encode_specialized(w, opts, buf),
+            PnmBuf::Nrgba64(buf) => Pnm::encode_specialized(w, opts, buf),
+            PnmBuf::Rgb(buf) => Pnm::encode_specialized(w, opts, buf),
+            PnmBuf::Rgb48(buf) => Pnm::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Pnm {
+    fn encode<W: Write>(w: W, opts: PnmEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba64Be = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Pnm::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 + 11) as u8).collect()
+    }
+
+    fn round_trip(buf: &PnmBuf, plain: bool) -> PnmBuf {
+        let mut out = Vec::new();
+        Pnm::encode_specialized(&mut out, PnmEncodeOptions { plain }, buf).unwrap();
+        Pnm::decode(&out[..], ()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let (w, h) = (7, 3);
+        let bufs = [
+            PnmBuf::Gray(RawPixBuf::from_vec(w, h, pattern(w * h)).unwrap()),
+            PnmBuf::Gray16(RawPixBuf::from_vec(w, h, pattern(w * h * 2)).unwrap()),
+            PnmBuf::Rgb(RawPixBuf::from_vec(w, h, pattern(w * h * 3)).unwrap()),
+            PnmBuf::Rgb48(RawPixBuf::from_vec(w, h, pattern(w * h * 6)).unwrap()),
+            PnmBuf::Nrgba(RawPixBuf::from_vec(w, h, pattern(w * h * 4)).unwrap()),
+            PnmBuf::Nrgba64(RawPixBuf::from_vec(w, h, pattern(w * h * 8)).unwrap()),
+        ];
+        for buf in &bufs {
+            for &plain in &[false, true] {
+                let decoded = round_trip(buf, plain);
+                assert_eq!(decoded.dimensions(), (w, h));
+                let same = match (buf, &decoded) {
+                    (PnmBuf::Gray(a), PnmBuf::Gray(b)) => a.as_ref() == b.as_ref(),
+                    (PnmBuf::Gray16(a), PnmBuf::Gray16(b)) => a.as_ref() == b.as_ref(),
+                    (PnmBuf::Rgb(a), PnmBuf::Rgb(b)) => a.as_ref() == b.as_ref(),
+                    (PnmBuf::Rgb48(a), PnmBuf::Rgb48(b)) => a.as_ref() == b.as_ref(),
+                    (PnmBuf::Nrgba(a), PnmBuf::Nrgba(b)) => a.as_ref() == b.as_ref(),
+                    (PnmBuf::Nrgba64(a), PnmBuf::Nrgba64(b)) => a.as_ref() == b.as_ref(),
+                    _ => false,
+                };
+                assert!(same, "{:?} plain: {}", buf, plain);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_bitmaps() {
+        let plain = b"P1\n3 2\n1 0 1\n0 1 0\n";
+        let packed = b"P4\n3 2\n\xa0\x40";
+        for data in [&plain[..], &packed[..]] {
+            match Pnm::decode(data, ()).unwrap() {
+                PnmBuf::Gray(buf) => assert_eq!(buf.as_ref(), &[0, 255, 0, 255, 0, 255]),
+                other => panic!("unexpected buffer {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let headers: [&[u8]; 5] = [
+            b"P1 4294967295 4294967295\n",
+            b"P2 4294967295 4294967295 255\n",
+            b"P4 4294967295 4294967295\n",
+            b"P5 4294967295 4294967295 255\n",
+            b"P6 4294967295 4294967295 65535\n",
+        ];
+        for header in &headers {
+            assert!(Pnm::decode(*header, ()).is_err());
+        }
+        let pam = b"P7\nWIDTH 4294967295\nHEIGHT 4294967295\nDEPTH 4\nMAXVAL 65535\nENDHDR\n";
+        assert!(Pnm::decode(&pam[..], ()).is_err());
+    }
+
+    #[test]
+    fn trailing_data_is_left_unread() {
+        let mut data = &b"P5\n2 1\n255\n\x01\x02P5\n1 1\n255\n\x03"[..];
+        Pnm::decode(&mut data, ()).unwrap();
+        assert_eq!(data, b"P5\n1 1\n255\n\x03");
+        let info = Pnm::probe(&mut data).unwrap();
+        assert_eq!(info.dimensions(), (1, 1));
+        assert_eq!(data, b"\x03");
+    }
+}
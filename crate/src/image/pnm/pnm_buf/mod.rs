@@ -0,0 +1,132 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Gray, Gray16Be, Nrgba, Nrgba64Be, Rgb, Rgb48Be};
+use crate::image::{Dimensions, Image, ImageMut};
+
+#[derive(Clone, Debug)]
+pub enum PnmBuf {
+    Gray(RawPixBuf<Gray>),
+    Gray16(RawPixBuf<Gray16Be>),
+    Nrgba(RawPixBuf<Nrgba>),
+    Nrgba64(RawPixBuf<Nrgba64Be>),
+    Rgb(RawPixBuf<Rgb>),
+    Rgb48(RawPixBuf<Rgb48Be>),
+}
+
+impl Dimensions for PnmBuf {
+    fn width(&self) -> usize {
+        match self {
+            PnmBuf::Gray(buf) => buf.width(),
+            PnmBuf::Gray16(buf) => buf.width(),
+            PnmBuf::Nrgba(buf) => buf.width(),
+            PnmBuf::Nrgba64(buf) => buf.width(),
+            PnmBuf::Rgb(buf) => buf.width(),
+            PnmBuf::Rgb48(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            PnmBuf::Gray(buf) => buf.height(),
+            PnmBuf::Gray16(buf) => buf.height(),
+            PnmBuf::Nrgba(buf) => buf.height(),
+            PnmBuf::Nrgba64(buf) => buf.height(),
+            PnmBuf::Rgb(buf) => buf.height(),
+            PnmBuf::Rgb48(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for PnmBuf {
+    type Pixel = PnmPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            PnmBuf::Gray(buf) => PnmPix::Gray(buf.color_get(x, y)),
+            PnmBuf::Gray16(buf) => PnmPix::Gray16(buf.color_get(x, y)),
+            PnmBuf::Nrgba(buf) => PnmPix::Nrgba(buf.color_get(x, y)),
+            PnmBuf::Nrgba64(buf) => PnmPix::Nrgba64(buf.color_get(x, y)),
+            PnmBuf::Rgb(buf) => PnmPix::Rgb(buf.color_get(x, y)),
+            PnmBuf::Rgb48(buf) => PnmPix::Rgb48(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for PnmBuf {
+    type Pixel = PnmPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<PnmPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<PnmPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (PnmBuf::Gray(buf), PnmPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (PnmBuf::Gray16(buf), PnmPix::Gray16(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Gray16(buf), c) => buf.color_set_generic(x, y, c),
+
+            (PnmBuf::Nrgba(buf), PnmPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (PnmBuf::Nrgba64(buf), PnmPix::Nrgba64(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Nrgba64(buf), c) => buf.color_set_generic(x, y, c),
+
+            (PnmBuf::Rgb(buf), PnmPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+
+            (PnmBuf::Rgb48(buf), PnmPix::Rgb48(c)) => buf.pixel_set(x, y, c),
+            (PnmBuf::Rgb48(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PnmPix {
+    Gray(Gray),
+    Gray16(Gray16Be),
+    Nrgba(Nrgba),
+    Nrgba64(Nrgba64Be),
+    Rgb(Rgb),
+    Rgb48(Rgb48Be),
+}
+
+impl Color for PnmPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            PnmPix::Gray(c) => c.as_rgba(),
+            PnmPix::Gray16(c) => c.as_rgba(),
+            PnmPix::Nrgba(c) => c.as_rgba(),
+            PnmPix::Nrgba64(c) => c.as_rgba(),
+            PnmPix::Rgb(c) => c.as_rgba(),
+            PnmPix::Rgb48(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for PnmBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            PnmBuf::Gray(buf) => buf.as_ref(),
+            PnmBuf::Gray16(buf) => buf.as_ref(),
+            PnmBuf::Nrgba(buf) => buf.as_ref(),
+            PnmBuf::Nrgba64(buf) => buf.as_ref(),
+            PnmBuf::Rgb(buf) => buf.as_ref(),
+            PnmBuf::Rgb48(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for PnmBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            PnmBuf::Gray(buf) => buf.as_mut(),
+            PnmBuf::Gray16(buf) => buf.as_mut(),
+            PnmBuf::Nrgba(buf) => buf.as_mut(),
+            PnmBuf::Nrgba64(buf) => buf.as_mut(),
+            PnmBuf::Rgb(buf) => buf.as_mut(),
+            PnmBuf::Rgb48(buf) => buf.as_mut(),
+        }
+    }
+}
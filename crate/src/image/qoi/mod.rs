@@ -0,0 +1,366 @@
+mod qoi_buf;
+
+pub use qoi_buf::*;
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Nrgba, Rgb};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xc0;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+
+const QOI_MASK_2: u8 = 0xc0;
+
+const QOI_END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+pub struct Qoi;
+
+impl_format! {
+    name: Qoi,
+    id: "feim:qoi",
+    magic: b"qoif",
+}
+
+/// The colorspace flag stored in a QOI header. It is purely
+/// informative, and does not change how pixels are coded.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum QoiColorspace {
+    /// sRGB color channels, with a linear alpha channel.
+    #[default]
+    Srgb,
+    /// All channels are linear.
+    Linear,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct QoiEncodeOptions {
+    pub colorspace: QoiColorspace,
+}
+
+impl DecodeOptions for Qoi {
+    type Options = ();
+}
+
+impl EncodeOptions for Qoi {
+    type Options = QoiEncodeOptions;
+}
+
+#[inline]
+fn hash([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+impl Decode<QoiBuf> for Qoi {
+    #[inline]
+    fn decode<R: Read>(r: R, opt: ()) -> io::Result<QoiBuf> {
+        <Qoi as Decode<(QoiBuf, QoiColorspace)>>::decode(r, opt).map(|(buf, _)| buf)
+    }
+}
+
+impl Decode<(QoiBuf, QoiColorspace)> for Qoi {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<(QoiBuf, QoiColorspace)> {
+        let mut r = BufReader::new(r);
+
+        let mut header = [0; 14];
+        r.read_exact(&mut header)?;
+        if !Qoi.is_valid_magic(&header) {
+            return Err(invalid("Invalid qoi magic."));
+        }
+        let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let colorspace = match header[13] {
+            0 => QoiColorspace::Srgb,
+            1 => QoiColorspace::Linear,
+            _ => return Err(invalid("Invalid qoi colorspace")),
+        };
+        // the limit happens to match the one of the qoi
+        // specification, of 400 million pixels
+        let too_large = || invalid("Qoi image dimensions too large");
+        let pixel_count = image::pixel_count(width, height).ok_or_else(too_large)?;
+        let channels = header[12] as usize;
+        pixel_count.checked_mul(channels).ok_or_else(too_large)?;
+
+        let buf = match header[12] {
+            3 => {
+                let mut buf: RawPixBuf<Rgb> = RawPixBuf::new(width, height);
+                decode_pixels(&mut r, pixel_count, |i, [r, g, b, _]| {
+                    buf.as_typed_mut()[i] = Rgb { r, g, b };
+                })?;
+                QoiBuf::Rgb(buf)
+            }
+            4 => {
+                let mut buf: RawPixBuf<Nrgba> = RawPixBuf::new(width, height);
+                decode_pixels(&mut r, pixel_count, |i, [r, g, b, a]| {
+                    buf.as_typed_mut()[i] = Nrgba { r, g, b, a };
+                })?;
+                QoiBuf::Nrgba(buf)
+            }
+            _ => return Err(invalid("Invalid qoi channel count")),
+        };
+
+        let mut end = [0; 8];
+        r.read_exact(&mut end)?;
+        if end != QOI_END_MARKER {
+            return Err(invalid("Missing qoi end marker"));
+        }
+
+        Ok((buf, colorspace))
+    }
+}
+
+impl Probe for Qoi {
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let mut header = [0; 14];
+        r.read_exact(&mut header)?;
+        if !Qoi.is_valid_magic(&header) {
+            return Err(invalid("Invalid qoi magic."));
+        }
+        let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let color_type = match header[12] {
+            3 => ColorType::Rgb,
+            4 => ColorType::Rgba,
+            _ => return Err(invalid("Invalid qoi channel count")),
+        };
+        Ok(ImageInfo::new(width, height, color_type, 8))
+    }
+}
+
+fn decode_pixels<R, F>(r: &mut R, pixel_count: usize, mut put: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(usize, [u8; 4]),
+{
+    let mut byte = || -> io::Result<u8> {
+        let mut b = [0];
+        r.read_exact(&mut b)?;
+        Ok(b[0])
+    };
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0, 0, 0, 0xff];
+    let mut i = 0;
+
+    while i < pixel_count {
+        let b1 = byte()?;
+        let mut run = 1;
+
+        match b1 {
+            QOI_OP_RGB => {
+                px[0] = byte()?;
+                px[1] = byte()?;
+                px[2] = byte()?;
+            }
+            QOI_OP_RGBA => {
+                px[0] = byte()?;
+                px[1] = byte()?;
+                px[2] = byte()?;
+                px[3] = byte()?;
+            }
+            _ => match b1 & QOI_MASK_2 {
+                QOI_OP_INDEX => px = index[b1 as usize],
+                QOI_OP_DIFF => {
+                    px[0] = px[0].wrapping_add(((b1 >> 4) & 0x03).wrapping_sub(2));
+                    px[1] = px[1].wrapping_add(((b1 >> 2) & 0x03).wrapping_sub(2));
+                    px[2] = px[2].wrapping_add((b1 & 0x03).wrapping_sub(2));
+                }
+                QOI_OP_LUMA => {
+                    let b2 = byte()?;
+                    let vg = (b1 & 0x3f).wrapping_sub(32);
+                    px[0] = px[0].wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 >> 4));
+                    px[1] = px[1].wrapping_add(vg);
+                    px[2] = px[2].wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 & 0x0f));
+                }
+                _ => run = (b1 & 0x3f) as usize + 1,
+            },
+        }
+
+        index[hash(px)] = px;
+        for _ in 0..run.min(pixel_count - i) {
+            put(i, px);
+            i += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_impl<W, I>(
+    w: W,
+    opts: QoiEncodeOptions,
+    (width, height): (usize, usize),
+    channels: u8,
+    pixels: I,
+) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = [u8; 4]>,
+{
+    let width = u32::try_from(width).map_err(|_| invalid("Qoi image too wide"))?;
+    let height = u32::try_from(height).map_err(|_| invalid("Qoi image too tall"))?;
+
+    let mut w = io::BufWriter::new(w);
+    w.write_all(Qoi.magic())?;
+    w.write_all(&width.to_be_bytes())?;
+    w.write_all(&height.to_be_bytes())?;
+    w.write_all(&[channels, opts.colorspace as u8])?;
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0, 0, 0, 0xff];
+    let mut run = 0u8;
+
+    for px in pixels {
+        if px == prev {
+            run += 1;
+            if run == 62 {
+                w.write_all(&[QOI_OP_RUN | (run - 1)])?;
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            w.write_all(&[QOI_OP_RUN | (run - 1)])?;
+            run = 0;
+        }
+
+        let h = hash(px);
+        if index[h] == px {
+            w.write_all(&[QOI_OP_INDEX | h as u8])?;
+        } else {
+            index[h] = px;
+
+            if px[3] == prev[3] {
+                let vr = px[0].wrapping_sub(prev[0]) as i8;
+                let vg = px[1].wrapping_sub(prev[1]) as i8;
+                let vb = px[2].wrapping_sub(prev[2]) as i8;
+                let vg_r = vr.wrapping_sub(vg);
+                let vg_b = vb.wrapping_sub(vg);
+
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    let op = QOI_OP_DIFF
+                        | (((vr + 2) as u8) << 4)
+                        | (((vg + 2) as u8) << 2)
+                        | ((vb + 2) as u8);
+                    w.write_all(&[op])?;
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    let op = QOI_OP_LUMA | ((vg + 32) as u8);
+                    let diff = (((vg_r + 8) as u8) << 4) | ((vg_b + 8) as u8);
+                    w.write_all(&[op, diff])?;
+                } else {
+                    w.write_all(&[QOI_OP_RGB, px[0], px[1], px[2]])?;
+                }
+            } else {
+                w.write_all(&[QOI_OP_RGBA, px[0], px[1], px[2], px[3]])?;
+            }
+        }
+
+        prev = px;
+    }
+    if run > 0 {
+        w.write_all(&[QOI_OP_RUN | (run - 1)])?;
+    }
+
+    w.write_all(&QOI_END_MARKER)?;
+    w.flush()
+}
+
+impl Encode<RawPixBuf<Rgb>> for Qoi {
+    fn encode<W: Write>(w: W, opts: QoiEncodeOptions, buf: &RawPixBuf<Rgb>) -> io::Result<()> {
+        let pixels = buf.as_typed().iter().map(|c| [c.r, c.g, c.b, 0xff]);
+        encode_impl(w, opts, buf.dimensions(), 3, pixels)
+    }
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Qoi {
+    fn encode<W: Write>(w: W, opts: QoiEncodeOptions, buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        let pixels = buf.as_typed().iter().map(|c| [c.r, c.g, c.b, c.a]);
+        encode_impl(w, opts, buf.dimensions(), 4, pixels)
+    }
+}
+
+impl Encode<QoiBuf> for Qoi {
+    fn encode<W: Write>(w: W, opts: QoiEncodeOptions, buf: &QoiBuf) -> io::Result<()> {
+        match buf {
+            QoiBuf::Nrgba(buf) => Qoi::encode_specialized(w, opts, buf),
+            QoiBuf::Rgb(buf) => Qoi::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Qoi {
+    fn encode<W: Write>(w: W, opts: QoiEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Qoi::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        // runs of equal and of slowly changing pixels, to go
+        // through every kind of chunk
+        (0..len)
+            .map(|i| ((i / 7) * 3 + (i / 50) * 91) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let (w, h) = (33, 9);
+        let bufs = [
+            QoiBuf::Rgb(RawPixBuf::from_vec(w, h, pattern(w * h * 3)).unwrap()),
+            QoiBuf::Nrgba(RawPixBuf::from_vec(w, h, pattern(w * h * 4)).unwrap()),
+        ];
+        for buf in &bufs {
+            let opts = QoiEncodeOptions {
+                colorspace: QoiColorspace::Linear,
+            };
+            let mut out = Vec::new();
+            Qoi::encode_specialized(&mut out, opts, buf).unwrap();
+            let (decoded, colorspace): (QoiBuf, _) = Qoi::decode(&out[..], ()).unwrap();
+            assert_eq!(colorspace, QoiColorspace::Linear);
+            let same = match (buf, &decoded) {
+                (QoiBuf::Rgb(a), QoiBuf::Rgb(b)) => a.as_ref() == b.as_ref(),
+                (QoiBuf::Nrgba(a), QoiBuf::Nrgba(b)) => a.as_ref() == b.as_ref(),
+                _ => false,
+            };
+            assert!(same, "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let mut header = b"qoif\xff\xff\xff\xff\xff\xff\xff\xff\x04\x00".to_vec();
+        assert!(<Qoi as Decode<QoiBuf>>::decode(&header[..], ()).is_err());
+        header[4..12].copy_from_slice(&[0, 0, 0x80, 0, 0, 0, 0x80, 0]);
+        assert!(<Qoi as Decode<QoiBuf>>::decode(&header[..], ()).is_err());
+    }
+}
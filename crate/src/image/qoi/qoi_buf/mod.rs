@@ -0,0 +1,88 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Nrgba, Rgb};
+use crate::image::{Dimensions, Image, ImageMut};
+
+#[derive(Clone, Debug)]
+pub enum QoiBuf {
+    Nrgba(RawPixBuf<Nrgba>),
+    Rgb(RawPixBuf<Rgb>),
+}
+
+impl Dimensions for QoiBuf {
+    fn width(&self) -> usize {
+        match self {
+            QoiBuf::Nrgba(buf) => buf.width(),
+            QoiBuf::Rgb(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            QoiBuf::Nrgba(buf) => buf.height(),
+            QoiBuf::Rgb(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for QoiBuf {
+    type Pixel = QoiPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            QoiBuf::Nrgba(buf) => QoiPix::Nrgba(buf.color_get(x, y)),
+            QoiBuf::Rgb(buf) => QoiPix::Rgb(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for QoiBuf {
+    type Pixel = QoiPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<QoiPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<QoiPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (QoiBuf::Nrgba(buf), QoiPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (QoiBuf::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (QoiBuf::Rgb(buf), QoiPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (QoiBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum QoiPix {
+    Nrgba(Nrgba),
+    Rgb(Rgb),
+}
+
+impl Color for QoiPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            QoiPix::Nrgba(c) => c.as_rgba(),
+            QoiPix::Rgb(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for QoiBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            QoiBuf::Nrgba(buf) => buf.as_ref(),
+            QoiBuf::Rgb(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for QoiBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            QoiBuf::Nrgba(buf) => buf.as_mut(),
+            QoiBuf::Rgb(buf) => buf.as_mut(),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::io::{self, Read, Write};
+
+use crate::image::{built_in_formats_iter, DynImage, Format, FormatOptions, ImageInfo};
+use crate::serialize::{try_format, Peek};
+
+/// Reads, decodes and encodes the images of a format, picked
+/// at runtime, through a `DynImage`.
+///
+/// Applications may implement this trait for their own formats,
+/// and register them in a `FormatRegistry`. Encoding options of
+/// formats other than the built-in ones may be kept in the
+/// handler itself.
+pub trait FormatHandler: Format {
+    /// Reads the properties of an image from the header of its file.
+    fn probe_dyn(&self, r: &mut dyn Read) -> io::Result<ImageInfo>;
+
+    /// Decodes an image of this format.
+    fn decode_dyn(&self, r: &mut dyn Read) -> io::Result<DynImage>;
+
+    /// Encodes an image in this format.
+    fn encode_dyn(&self, w: &mut dyn Write, buf: &DynImage, opts: &FormatOptions)
+        -> io::Result<()>;
+}
+
+/// A set of formats to detect and decode images with, at runtime.
+pub struct FormatRegistry {
+    handlers: Vec<Box<dyn FormatHandler>>,
+}
+
+impl FormatRegistry {
+    /// Creates a registry with all the built-in formats.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for (format, _) in built_in_formats_iter() {
+            registry.register(format);
+        }
+        registry
+    }
+
+    /// Creates a registry without any format.
+    pub fn empty() -> Self {
+        Self {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Adds a format to the registry. If a format with the same
+    /// id is already registered, it is replaced. Otherwise, the
+    /// new format is tried after all the formats before it.
+    pub fn register<H: FormatHandler + 'static>(&mut self, handler: H) {
+        let handler = Box::new(handler);
+        match self.handlers.iter_mut().find(|h| h.id() == handler.id()) {
+            Some(h) => *h = handler,
+            None => self.handlers.push(handler),
+        }
+    }
+
+    /// Returns the format registered with the given id.
+    pub fn get(&self, id: &str) -> Option<&dyn FormatHandler> {
+        self.iter().find(|h| h.id() == id)
+    }
+
+    /// Iterates over the registered formats, in the order they
+    /// are tried.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn FormatHandler> {
+        self.handlers.iter().map(|h| h.as_ref())
+    }
+
+    /// Returns the registered format which most likely matches
+    /// the start of `r`. Ties go to the format registered first.
+    pub fn detect<R: Peek>(&self, r: R) -> io::Result<&dyn FormatHandler> {
+        let formats = self.iter().map(|h| (h, h as &dyn Format));
+        try_format(r, formats)
+    }
+
+    /// Reads the properties of an image of any of the registered
+    /// formats.
+    pub fn probe_any<R: Peek>(&self, mut r: R) -> io::Result<ImageInfo> {
+        self.detect(&mut r)?.probe_dyn(&mut r)
+    }
+
+    /// Decodes an image of any of the registered formats.
+    pub fn decode_any<R: Peek>(&self, mut r: R) -> io::Result<DynImage> {
+        self.detect(&mut r)?.decode_dyn(&mut r)
+    }
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+// Buffers with a pixel type listed for a format are encoded
+// through its specialized implementation, and all others
+// through its generic one.
+#[cfg(any(
+    feature = "fmt-farbfeld",
+    feature = "fmt-jpeg",
+    feature = "fmt-png",
+    feature = "fmt-pnm",
+    feature = "fmt-bmp",
+    feature = "fmt-qoi",
+    feature = "fmt-gif",
+    feature = "fmt-tiff",
+    feature = "fmt-ico",
+    feature = "fmt-hdr",
+    feature = "fmt-exr",
+    feature = "fmt-webp",
+    feature = "fmt-tga",
+    feature = "fmt-xpm",
+    feature = "fmt-y4m",
+    feature = "fmt-fits",
+))]
+macro_rules! impl_format_handler {
+    ($format:ty, $decoded:ty, |$opts:ident| $options:expr, []) => {
+        impl FormatHandler for $format {
+            fn probe_dyn(&self, r: &mut dyn Read) -> io::Result<ImageInfo> {
+                <$format as crate::serialize::Probe>::probe(r)
+            }
+
+            fn decode_dyn(&self, r: &mut dyn Read) -> io::Result<DynImage> {
+                let opts = Default::default();
+                <$format as crate::serialize::Decode<$decoded>>::decode(r, opts).map(DynImage::from)
+            }
+
+            fn encode_dyn(
+                &self,
+                w: &mut dyn Write,
+                buf: &DynImage,
+                $opts: &FormatOptions,
+            ) -> io::Result<()> {
+                use crate::serialize::EncodeGeneric;
+
+                <$format>::encode_generic(w, $options, buf)
+            }
+        }
+    };
+    ($format:ty, $decoded:ty, |$opts:ident| $options:expr, [$($pixel:ident),+]) => {
+        impl FormatHandler for $format {
+            fn probe_dyn(&self, r: &mut dyn Read) -> io::Result<ImageInfo> {
+                <$format as crate::serialize::Probe>::probe(r)
+            }
+
+            fn decode_dyn(&self, r: &mut dyn Read) -> io::Result<DynImage> {
+                let opts = Default::default();
+                <$format as crate::serialize::Decode<$decoded>>::decode(r, opts).map(DynImage::from)
+            }
+
+            fn encode_dyn(
+                &self,
+                w: &mut dyn Write,
+                buf: &DynImage,
+                $opts: &FormatOptions,
+            ) -> io::Result<()> {
+                use crate::serialize::{EncodeGeneric, EncodeSpecialized};
+
+                match buf {
+                    $(DynImage::$pixel(buf) => <$format>::encode_specialized(w, $options, buf),)*
+                    buf => <$format>::encode_generic(w, $options, buf),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "fmt-farbfeld")]
+impl_format_handler!(
+    crate::image::farbfeld::Farbfeld,
+    crate::buffer::RawPixBuf<crate::color::Nrgba64Be>,
+    |_opts| (),
+    [Nrgba64Be]
+);
+
+#[cfg(feature = "fmt-jpeg")]
+impl_format_handler!(
+    crate::image::jpeg::Jpeg,
+    crate::image::jpeg::JpegBuf,
+    |opts| opts.jpeg,
+    [Gray, Rgb, Nrgba, Cmyk]
+);
+
+#[cfg(feature = "fmt-png")]
+impl_format_handler!(
+    crate::image::png::Png,
+    crate::image::png::PngBuf,
+    |opts| opts.png,
+    [Gray, Gray16Be, Nrgba, Nrgba64Be, Rgb, Rgb48Be]
+);
+
+#[cfg(feature = "fmt-pnm")]
+impl_format_handler!(
+    crate::image::pnm::Pnm,
+    crate::image::pnm::PnmBuf,
+    |opts| opts.pnm,
+    [Gray, Gray16Be, Nrgba, Nrgba64Be, Rgb, Rgb48Be]
+);
+
+#[cfg(feature = "fmt-bmp")]
+impl_format_handler!(
+    crate::image::bmp::Bmp,
+    crate::image::bmp::BmpBuf,
+    |_opts| (),
+    [Gray, Rgb, Nrgba]
+);
+
+#[cfg(feature = "fmt-qoi")]
+impl_format_handler!(
+    crate::image::qoi::Qoi,
+    crate::image::qoi::QoiBuf,
+    |opts| opts.qoi,
+    [Rgb, Nrgba]
+);
+
+#[cfg(feature = "fmt-tga")]
+impl_format_handler!(
+    crate::image::tga::Tga,
+    crate::image::tga::TgaBuf,
+    |opts| opts.tga,
+    [Gray, Rgb, Nrgba]
+);
+
+#[cfg(feature = "fmt-gif")]
+impl_format_handler!(
+    crate::image::gif::Gif,
+    crate::buffer::RawPixBuf<crate::color::Nrgba>,
+    |opts| opts.gif,
+    [Nrgba]
+);
+
+#[cfg(feature = "fmt-tiff")]
+impl_format_handler!(
+    crate::image::tiff::Tiff,
+    crate::image::tiff::TiffBuf,
+    |opts| opts.tiff,
+    [Gray, Gray16Be, Gray16Le, Rgb, Rgb48Be, Rgb48Le, Nrgba, Nrgba64Be, Nrgba64Le]
+);
+
+#[cfg(feature = "fmt-ico")]
+impl_format_handler!(
+    crate::image::ico::Ico,
+    crate::image::ico::IcoBuf,
+    |opts| opts.ico,
+    []
+);
+
+#[cfg(feature = "fmt-hdr")]
+impl_format_handler!(
+    crate::image::hdr::Hdr,
+    crate::buffer::RawPixBuf<crate::color::RgbF32>,
+    |opts| opts.hdr,
+    [RgbF32]
+);
+
+#[cfg(feature = "fmt-exr")]
+impl_format_handler!(
+    crate::image::exr::Exr,
+    crate::image::exr::ExrBuf,
+    |opts| opts.exr,
+    [GrayF32, RgbF32, RgbaF32]
+);
+
+#[cfg(feature = "fmt-webp")]
+impl_format_handler!(
+    crate::image::webp::Webp,
+    either::Either<crate::image::webp::RgbWebpBuf, crate::image::webp::NrgbaWebpBuf>,
+    |opts| opts.webp,
+    [Rgb, Nrgba]
+);
+
+#[cfg(feature = "fmt-xpm")]
+impl_format_handler!(
+    crate::image::xpm::Xbm,
+    crate::buffer::RawPixBuf<crate::color::Gray>,
+    |opts| opts.xbm,
+    [Gray]
+);
+
+#[cfg(feature = "fmt-xpm")]
+impl_format_handler!(
+    crate::image::xpm::Xpm,
+    crate::buffer::RawPixBuf<crate::color::Nrgba>,
+    |opts| opts.xpm,
+    []
+);
+
+#[cfg(feature = "fmt-y4m")]
+impl_format_handler!(
+    crate::image::y4m::Y4m,
+    crate::buffer::RawPixBuf<crate::color::Rgb>,
+    |opts| opts.y4m,
+    [Rgb]
+);
+
+#[cfg(feature = "fmt-fits")]
+impl_format_handler!(
+    crate::image::fits::Fits,
+    crate::image::fits::FitsBuf,
+    |opts| opts.fits,
+    [Gray, Gray16Be, GrayF32, Rgb, Rgb48Be, RgbF32]
+);
+
+#[cfg(all(test, feature = "fmt-png", feature = "fmt-qoi"))]
+mod tests {
+    use super::*;
+    use crate::buffer::RawPixBuf;
+    use crate::image::{BuiltInFormat, Dimensions};
+
+    fn image() -> DynImage {
+        let data = (0..5 * 3 * 4).map(|i| (i * 37 + 5) as u8).collect();
+        DynImage::Nrgba(RawPixBuf::from_vec(5, 3, data).unwrap())
+    }
+
+    fn encode(registry: &FormatRegistry, id: &str, image: &DynImage) -> Vec<u8> {
+        let mut out = Vec::new();
+        let handler = registry.get(id).unwrap();
+        handler
+            .encode_dyn(&mut out, image, &FormatOptions::default())
+            .unwrap();
+        out
+    }
+
+    // a format of this test alone, encoding nothing but its magic
+    struct Blank;
+
+    impl Format for Blank {
+        fn id(&self) -> &'static str {
+            "test:blank"
+        }
+
+        fn magic(&self) -> &'static [u8] {
+            b"BLANK"
+        }
+    }
+
+    impl FormatHandler for Blank {
+        fn probe_dyn(&self, _r: &mut dyn Read) -> io::Result<ImageInfo> {
+            Err(io::Error::other("Blank images have no header"))
+        }
+
+        fn decode_dyn(&self, _r: &mut dyn Read) -> io::Result<DynImage> {
+            Ok(DynImage::Gray(RawPixBuf::new(0, 0)))
+        }
+
+        fn encode_dyn(
+            &self,
+            w: &mut dyn Write,
+            _buf: &DynImage,
+            _opts: &FormatOptions,
+        ) -> io::Result<()> {
+            w.write_all(self.magic())
+        }
+    }
+
+    #[test]
+    fn lookup_by_extension_and_magic() {
+        let registry = FormatRegistry::new();
+        for &(ext, id) in &[("png", "feim:png"), ("QOI", "feim:qoi")] {
+            let format = BuiltInFormat::from_extension(ext).unwrap();
+            let handler = registry.get(format.id()).unwrap();
+            assert_eq!(handler.id(), id);
+
+            let data = encode(&registry, id, &image());
+            assert_eq!(registry.detect(&data[..]).unwrap().id(), id);
+        }
+        assert!(registry.get("test:blank").is_none());
+        assert!(registry.detect(&b"BLANK"[..]).is_err());
+    }
+
+    #[test]
+    fn registered_formats_are_detected() {
+        let mut registry = FormatRegistry::new();
+        registry.register(Blank);
+        let data = encode(&registry, "test:blank", &image());
+        assert_eq!(registry.detect(&data[..]).unwrap().id(), "test:blank");
+        let decoded = registry.decode_any(&data[..]).unwrap();
+        assert_eq!(decoded.dimensions(), (0, 0));
+
+        // registering a format again replaces it, in place
+        let len = registry.iter().count();
+        registry.register(Blank);
+        assert_eq!(registry.iter().count(), len);
+        assert_eq!(registry.iter().last().unwrap().id(), "test:blank");
+    }
+
+    #[test]
+    fn round_trip_through_dyn_image() {
+        let registry = FormatRegistry::new();
+        let image = image();
+        for &id in &["feim:png", "feim:qoi"] {
+            let data = encode(&registry, id, &image);
+            let decoded = registry.decode_any(&data[..]).unwrap();
+            assert!(matches!(decoded, DynImage::Nrgba(_)), "{}", id);
+            assert_eq!(decoded.dimensions(), image.dimensions());
+            assert_eq!(decoded.as_ref(), image.as_ref(), "{}", id);
+
+            let info = registry.probe_any(&data[..]).unwrap();
+            assert_eq!((info.width, info.height), (5, 3));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{self, Write};
+
+use crate::buffer::PalettedBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Nrgba};
+use crate::image::{Confidence, Dimensions, Image};
+use crate::impl_format;
+use crate::serialize::{Encode, EncodeOptions};
+use crate::specialized;
+
+// each band of sixels covers this many rows
+const BAND_HEIGHT: usize = 6;
+
+// repeating a sixel at least this many times is
+// shorter with a repeat introducer
+const MIN_REPEAT: usize = 4;
+
+// colors are quantized from a histogram of 5 bits per channel
+const HISTOGRAM_BITS: u32 = 5;
+const HISTOGRAM_SIZE: usize = 1 << (HISTOGRAM_BITS * 3);
+
+/// Sixel graphics, as understood by terminals such as
+/// xterm, mlterm or foot. Only encoding is supported.
+pub struct Sixel;
+
+impl_format! {
+    name: Sixel,
+    id: "feim:sixel",
+    magic: b"\x1bP",
+    detect: detect,
+}
+
+// a device control string, with optional numeric
+// parameters, followed by the sixel introducer
+fn detect(data: &[u8]) -> Confidence {
+    let params = match data.strip_prefix(b"\x1bP") {
+        Some(params) => params,
+        None if b"\x1bP".starts_with(data) => return Confidence::Incomplete,
+        None => return Confidence::No,
+    };
+    match params.iter().find(|&&b| !(b.is_ascii_digit() || b == b';')) {
+        Some(b'q') => Confidence::High,
+        Some(_) => Confidence::No,
+        None => Confidence::Incomplete,
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SixelEncodeOptions {
+    /// The maximum number of colors in the palette, from 2 to 256.
+    pub palette_size: usize,
+    /// Leave mostly transparent pixels unpainted, rather
+    /// than blending them with a black background.
+    pub transparency: bool,
+}
+
+impl Default for SixelEncodeOptions {
+    fn default() -> Self {
+        Self {
+            palette_size: 256,
+            transparency: false,
+        }
+    }
+}
+
+impl EncodeOptions for Sixel {
+    type Options = SixelEncodeOptions;
+}
+
+#[inline]
+fn histogram_index(c: [u8; 3]) -> usize {
+    let shift = 8 - HISTOGRAM_BITS;
+    let [r, g, b] = c.map(|v| (v >> shift) as usize);
+    (r << (HISTOGRAM_BITS * 2)) | (g << HISTOGRAM_BITS) | b
+}
+
+#[derive(Copy, Clone)]
+struct Bucket {
+    count: u64,
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    #[inline]
+    fn mean(&self) -> [u8; 3] {
+        self.sum.map(|s| ((s + self.count / 2) / self.count) as u8)
+    }
+}
+
+// a box of the median cut, over a range of buckets
+struct ColorBox {
+    start: usize,
+    end: usize,
+}
+
+impl ColorBox {
+    // the widest channel of the box, and its extent
+    fn widest_channel(&self, buckets: &[(usize, Bucket)]) -> (usize, u8) {
+        let mut min = [u8::MAX; 3];
+        let mut max = [u8::MIN; 3];
+        for (_, bucket) in &buckets[self.start..self.end] {
+            let mean = bucket.mean();
+            for c in 0..3 {
+                min[c] = min[c].min(mean[c]);
+                max[c] = max[c].max(mean[c]);
+            }
+        }
+        (0..3)
+            .map(|c| (c, max[c] - min[c]))
+            .max_by_key(|&(_, extent)| extent)
+            .unwrap()
+    }
+
+    fn mean(&self, buckets: &[(usize, Bucket)]) -> [u8; 3] {
+        let mut total = Bucket {
+            count: 0,
+            sum: [0; 3],
+        };
+        for (_, bucket) in &buckets[self.start..self.end] {
+            total.count += bucket.count;
+            for c in 0..3 {
+                total.sum[c] += bucket.sum[c];
+            }
+        }
+        total.mean()
+    }
+}
+
+/// Reduces the colors of an image to at most `size` colors, with
+/// median cut. Returns the palette, and the palette index of each
+/// histogram bucket.
+fn quantize(colors: impl Iterator<Item = [u8; 3]>, size: usize) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut histogram = vec![
+        Bucket {
+            count: 0,
+            sum: [0; 3],
+        };
+        HISTOGRAM_SIZE
+    ];
+    for c in colors {
+        let bucket = &mut histogram[histogram_index(c)];
+        bucket.count += 1;
+        for (sum, &v) in bucket.sum.iter_mut().zip(&c) {
+            *sum += v as u64;
+        }
+    }
+    let mut buckets: Vec<(usize, Bucket)> = histogram
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, bucket)| bucket.count != 0)
+        .collect();
+    if buckets.is_empty() {
+        return (Vec::new(), vec![0; HISTOGRAM_SIZE]);
+    }
+
+    // keep splitting the widest box at its median,
+    // until there are enough boxes
+    let mut boxes = vec![ColorBox {
+        start: 0,
+        end: buckets.len(),
+    }];
+    while boxes.len() < size {
+        let split = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.end - b.start > 1)
+            .map(|(i, b)| (i, b.widest_channel(&buckets).1))
+            .max_by_key(|&(_, extent)| extent);
+        let i = match split {
+            Some((i, extent)) if extent > 0 => i,
+            _ => break,
+        };
+        let (start, end) = (boxes[i].start, boxes[i].end);
+        let (channel, _) = boxes[i].widest_channel(&buckets);
+        buckets[start..end].sort_unstable_by_key(|(_, bucket)| bucket.mean()[channel]);
+
+        let total: u64 = buckets[start..end].iter().map(|(_, b)| b.count).sum();
+        let mut seen = 0;
+        let mut median = start + 1;
+        for (j, (_, bucket)) in buckets[start..end - 1].iter().enumerate() {
+            seen += bucket.count;
+            median = start + j + 1;
+            if seen * 2 >= total {
+                break;
+            }
+        }
+        boxes[i].end = median;
+        boxes.push(ColorBox { start: median, end });
+    }
+
+    let palette: Vec<[u8; 3]> = boxes.iter().map(|b| b.mean(&buckets)).collect();
+    let mut lookup = vec![0; HISTOGRAM_SIZE];
+    for (i, b) in boxes.iter().enumerate() {
+        for &(index, _) in &buckets[b.start..b.end] {
+            lookup[index] = i as u8;
+        }
+    }
+    (palette, lookup)
+}
+
+// the color of a pixel, or none if it's left transparent
+#[inline]
+fn pixel_color<C: Color>(c: C, transparency: bool) -> Option<[u8; 3]> {
+    let c: Nrgba = c.convert_into();
+    if transparency {
+        return (c.a >= 0x80).then_some([c.r, c.g, c.b]);
+    }
+    // blend with a black background
+    let blend = |v: u8| ((v as u32 * c.a as u32 + 127) / 255) as u8;
+    Some([blend(c.r), blend(c.g), blend(c.b)])
+}
+
+fn write_rle<W: Write>(w: &mut W, sixels: &[u8]) -> io::Result<()> {
+    let mut i = 0;
+    while i < sixels.len() {
+        let run = sixels[i..].iter().take_while(|&&s| s == sixels[i]).count();
+        let ch = b'?' + sixels[i];
+        if run >= MIN_REPEAT {
+            write!(w, "!{}", run)?;
+            w.write_all(&[ch])?;
+        } else {
+            w.write_all(&[ch; MIN_REPEAT][..run])?;
+        }
+        i += run;
+    }
+    Ok(())
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    transparency: bool,
+    (width, height): (usize, usize),
+    palette: &[[u8; 3]],
+    indices: &[Option<u8>],
+) -> io::Result<()> {
+    // the second parameter keeps unpainted pixels transparent
+    let background = if transparency { 1 } else { 0 };
+    write!(w, "\x1bP0;{};0q\"1;1;{};{}", background, width, height)?;
+    for (i, [r, g, b]) in palette.iter().enumerate() {
+        let [r, g, b] = [r, g, b].map(|&v| (v as u32 * 100 + 127) / 255);
+        write!(w, "#{};2;{};{};{}", i, r, g, b)?;
+    }
+
+    let mut slots = vec![usize::MAX; palette.len()];
+    let mut used = Vec::with_capacity(palette.len());
+    let mut sixels = Vec::new();
+    for top in (0..height).step_by(BAND_HEIGHT) {
+        let rows = BAND_HEIGHT.min(height - top);
+
+        // gather the sixels of every color in the band,
+        // in the order colors first appear
+        used.clear();
+        sixels.clear();
+        for x in 0..width {
+            for dy in 0..rows {
+                let index = match indices[(top + dy) * width + x] {
+                    Some(index) => index as usize,
+                    None => continue,
+                };
+                if slots[index] == usize::MAX {
+                    slots[index] = used.len();
+                    used.push(index);
+                    sixels.resize(sixels.len() + width, 0);
+                }
+                sixels[slots[index] * width + x] |= 1 << dy;
+            }
+        }
+
+        for (slot, &index) in used.iter().enumerate() {
+            if slot > 0 {
+                // return to the start of the band
+                w.write_all(b"$")?;
+            }
+            write!(w, "#{}", index)?;
+            let row = &sixels[slot * width..(slot + 1) * width];
+            let len = row.iter().rposition(|&s| s != 0).map_or(0, |end| end + 1);
+            write_rle(&mut w, &row[..len])?;
+            slots[index] = usize::MAX;
+        }
+        if top + rows < height {
+            w.write_all(b"-")?;
+        }
+    }
+
+    w.write_all(b"\x1b\\")
+}
+
+impl Encode<PalettedBuf<Nrgba>> for Sixel {
+    fn encode<W: Write>(
+        w: W,
+        opts: SixelEncodeOptions,
+        buf: &PalettedBuf<Nrgba>,
+    ) -> io::Result<()> {
+        let palette: Vec<[u8; 3]> = buf
+            .palette()
+            .iter()
+            .take(256)
+            .map(|&c| pixel_color(c, opts.transparency).unwrap_or([0; 3]))
+            .collect();
+        let indices: Vec<Option<u8>> = buf
+            .indices()
+            .iter()
+            .map(|&i| {
+                let c = buf.palette().get(i as usize)?;
+                (!opts.transparency || c.a >= 0x80).then_some(i)
+            })
+            .collect();
+        encode_impl(w, opts.transparency, buf.dimensions(), &palette, &indices)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Sixel {
+    fn encode<W: Write>(w: W, opts: SixelEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let colors: Vec<Option<[u8; 3]>> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel_color(buf.color_get(x, y), opts.transparency))
+            .collect();
+
+        let size = opts.palette_size.clamp(2, 256);
+        let (palette, lookup) = quantize(colors.iter().flatten().copied(), size);
+        let indices: Vec<Option<u8>> = colors
+            .iter()
+            .map(|c| c.map(|c| lookup[histogram_index(c)]))
+            .collect();
+        encode_impl(w, opts.transparency, (width, height), &palette, &indices)
+    }
+}
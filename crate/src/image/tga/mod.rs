@@ -0,0 +1,462 @@
+mod tga_buf;
+
+pub use tga_buf::*;
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::{Gray, Nrgba, Rgb};
+use crate::image::{self, ColorType, Confidence, Dimensions, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const HEADER_SIZE: usize = 18;
+
+const TYPE_COLOR_MAPPED: u8 = 1;
+const TYPE_TRUE_COLOR: u8 = 2;
+const TYPE_GRAY: u8 = 3;
+const TYPE_RLE_FLAG: u8 = 8;
+
+const DESC_RIGHT_TO_LEFT: u8 = 0x10;
+const DESC_TOP_TO_BOTTOM: u8 = 0x20;
+
+const FOOTER_SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+
+pub struct Tga;
+
+// NOTE: tga images have no magic string, so the best
+// we can do is to check if the header looks sane. for
+// this reason, tga is only ever detected with a low
+// confidence, below that of any matching signature.
+impl_format! {
+    name: Tga,
+    id: "feim:tga",
+    magic: b"",
+    detect: detect,
+}
+
+fn detect(header: &[u8]) -> Confidence {
+    if header.len() < HEADER_SIZE {
+        Confidence::Incomplete
+    } else if is_valid_header(header) {
+        Confidence::Low
+    } else {
+        Confidence::No
+    }
+}
+
+fn is_valid_header(header: &[u8]) -> bool {
+    if header.len() < HEADER_SIZE {
+        return false;
+    }
+    let color_map_type = header[1];
+    let image_type = header[2];
+    let cmap_entry_size = header[7];
+    let width = u16::from_le_bytes([header[12], header[13]]);
+    let height = u16::from_le_bytes([header[14], header[15]]);
+    let depth = header[16];
+    let descriptor = header[17];
+
+    let valid_depth = match image_type & !TYPE_RLE_FLAG {
+        TYPE_COLOR_MAPPED => {
+            color_map_type == 1
+                && matches!(depth, 8 | 16)
+                && matches!(cmap_entry_size, 15 | 16 | 24 | 32)
+        }
+        TYPE_TRUE_COLOR => matches!(depth, 15 | 16 | 24 | 32),
+        TYPE_GRAY => matches!(depth, 8 | 16),
+        _ => false,
+    };
+
+    valid_depth
+        && color_map_type <= 1
+        && image_type & !(TYPE_RLE_FLAG | 3) == 0
+        && width != 0
+        && height != 0
+        && descriptor & 0xc0 == 0
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TgaEncodeOptions {
+    /// Compress the image with run-length encoding.
+    pub rle: bool,
+}
+
+impl DecodeOptions for Tga {
+    type Options = ();
+}
+
+impl EncodeOptions for Tga {
+    type Options = TgaEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// converts a single pixel, in any of the supported
+// pixel depths, into rgba
+#[inline]
+fn to_rgba(raw: &[u8], gray: bool, alpha: bool) -> [u8; 4] {
+    match (raw, gray) {
+        ([y], true) => [*y, *y, *y, 0xff],
+        ([y, a], true) => [*y, *y, *y, if alpha { *a } else { 0xff }],
+        ([lo, hi], false) => {
+            let p = u16::from_le_bytes([*lo, *hi]);
+            let expand = |c: u16| ((c & 0x1f) * 0xff / 0x1f) as u8;
+            let a = if !alpha || p & 0x8000 != 0 { 0xff } else { 0 };
+            [expand(p >> 10), expand(p >> 5), expand(p), a]
+        }
+        ([b, g, r], false) => [*r, *g, *b, 0xff],
+        ([b, g, r, a], false) => [*r, *g, *b, if alpha { *a } else { 0xff }],
+        _ => [0, 0, 0, 0xff],
+    }
+}
+
+fn read_pixels<R: Read>(r: &mut R, count: usize, bytes: usize, rle: bool) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; count * bytes];
+    if !rle {
+        r.read_exact(&mut data)?;
+        return Ok(data);
+    }
+    let mut i = 0;
+    let mut packet = [0];
+    while i < data.len() {
+        r.read_exact(&mut packet)?;
+        let run = (packet[0] & 0x7f) as usize + 1;
+        let len = (run * bytes).min(data.len() - i);
+        if packet[0] & 0x80 != 0 {
+            let mut pixel = [0; 4];
+            r.read_exact(&mut pixel[..bytes])?;
+            for chunk in data[i..i + len].chunks_mut(bytes) {
+                chunk.copy_from_slice(&pixel[..chunk.len()]);
+            }
+        } else {
+            r.read_exact(&mut data[i..i + len])?;
+        }
+        i += len;
+    }
+    Ok(data)
+}
+
+impl Probe for Tga {
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let mut header = [0; HEADER_SIZE];
+        r.read_exact(&mut header)?;
+        if !is_valid_header(&header) {
+            return Err(invalid("Invalid tga header."));
+        }
+
+        let image_type = header[2];
+        let cmap_entry_size = header[7];
+        let width = u16::from_le_bytes([header[12], header[13]]) as usize;
+        let height = u16::from_le_bytes([header[14], header[15]]) as usize;
+        let depth = header[16];
+        let alpha = header[17] & 0x0f > 0;
+
+        let info = match (image_type & !TYPE_RLE_FLAG, depth) {
+            (TYPE_COLOR_MAPPED, _) => {
+                let mut info = ImageInfo::new(width, height, ColorType::Indexed, depth);
+                info.has_alpha = cmap_entry_size == 32 || (cmap_entry_size == 16 && alpha);
+                info
+            }
+            (TYPE_GRAY, 16) => ImageInfo::new(width, height, ColorType::GrayAlpha, 8),
+            (TYPE_GRAY, _) => ImageInfo::new(width, height, ColorType::Gray, 8),
+            (_, 15 | 16) if alpha => ImageInfo::new(width, height, ColorType::Rgba, 5),
+            (_, 15 | 16) => ImageInfo::new(width, height, ColorType::Rgb, 5),
+            (_, _) if alpha => ImageInfo::new(width, height, ColorType::Rgba, 8),
+            (_, _) => ImageInfo::new(width, height, ColorType::Rgb, 8),
+        };
+        Ok(info)
+    }
+}
+
+impl Decode<TgaBuf> for Tga {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<TgaBuf> {
+        let mut header = [0; HEADER_SIZE];
+        r.read_exact(&mut header)?;
+        if !is_valid_header(&header) {
+            return Err(invalid("Invalid tga header."));
+        }
+
+        let id_length = header[0] as u64;
+        let color_map_type = header[1];
+        let image_type = header[2];
+        let cmap_first = u16::from_le_bytes([header[3], header[4]]) as usize;
+        let cmap_len = u16::from_le_bytes([header[5], header[6]]) as usize;
+        let cmap_entry_size = header[7];
+        let width = u16::from_le_bytes([header[12], header[13]]) as usize;
+        let height = u16::from_le_bytes([header[14], header[15]]) as usize;
+        let depth = header[16];
+        let descriptor = header[17];
+        let alpha_bits = descriptor & 0x0f;
+
+        io::copy(&mut (&mut r).take(id_length), &mut io::sink())?;
+
+        let mut palette = Vec::new();
+        if color_map_type == 1 {
+            let entry_bytes = (cmap_entry_size as usize).div_ceil(8);
+            let mut raw = vec![0; cmap_len * entry_bytes];
+            r.read_exact(&mut raw)?;
+            let alpha = cmap_entry_size == 32 || (cmap_entry_size == 16 && alpha_bits > 0);
+            palette = raw
+                .chunks_exact(entry_bytes)
+                .map(|c| to_rgba(c, false, alpha))
+                .collect();
+        }
+
+        let rle = image_type & TYPE_RLE_FLAG != 0;
+        let kind = image_type & !TYPE_RLE_FLAG;
+        let bytes = (depth as usize).div_ceil(8);
+        let count = image::pixel_count(width, height)
+            .ok_or_else(|| invalid("Tga image dimensions too large"))?;
+        let data = read_pixels(&mut r, count, bytes, rle)?;
+
+        let pixels: Vec<[u8; 4]> = match kind {
+            TYPE_COLOR_MAPPED => data
+                .chunks_exact(bytes)
+                .map(|i| {
+                    let i = match i {
+                        [i] => *i as usize,
+                        _ => u16::from_le_bytes([i[0], i[1]]) as usize,
+                    };
+                    i.checked_sub(cmap_first)
+                        .and_then(|i| palette.get(i).copied())
+                        .unwrap_or([0, 0, 0, 0xff])
+                })
+                .collect(),
+            TYPE_GRAY => data
+                .chunks_exact(bytes)
+                .map(|c| to_rgba(c, true, alpha_bits > 0 || bytes == 2))
+                .collect(),
+            _ => data
+                .chunks_exact(bytes)
+                .map(|c| to_rgba(c, false, alpha_bits > 0))
+                .collect(),
+        };
+
+        let has_alpha = pixels.iter().any(|p| p[3] != 0xff);
+        let gray = kind == TYPE_GRAY;
+
+        // remap the pixels onto a top-left origin
+        let right_to_left = descriptor & DESC_RIGHT_TO_LEFT != 0;
+        let top_to_bottom = descriptor & DESC_TOP_TO_BOTTOM != 0;
+        let source = |x: usize, y: usize| {
+            let x = if right_to_left { width - 1 - x } else { x };
+            let y = if top_to_bottom { y } else { height - 1 - y };
+            pixels[y * width + x]
+        };
+
+        macro_rules! collect {
+            ($variant:ident, $pix:ty, |$p:ident| $conv:expr) => {{
+                let mut buf: RawPixBuf<$pix> = RawPixBuf::new(width, height);
+                let dest = buf.as_typed_mut();
+                for y in 0..height {
+                    for x in 0..width {
+                        let $p = source(x, y);
+                        dest[y * width + x] = $conv;
+                    }
+                }
+                TgaBuf::$variant(buf)
+            }};
+        }
+
+        Ok(match (gray, has_alpha) {
+            (_, true) => collect!(Nrgba, Nrgba, |p| Nrgba {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+                a: p[3],
+            }),
+            (true, false) => collect!(Gray, Gray, |p| Gray { y: p[0] }),
+            (false, false) => collect!(Rgb, Rgb, |p| Rgb {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+            }),
+        })
+    }
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: TgaEncodeOptions,
+    (width, height): (usize, usize),
+    (kind, bytes, alpha_bits): (u8, usize, u8),
+    data: &[u8],
+) -> io::Result<()> {
+    let width = u16::try_from(width).map_err(|_| invalid("Tga image too wide"))?;
+    let height = u16::try_from(height).map_err(|_| invalid("Tga image too tall"))?;
+
+    let image_type = if opts.rle { kind | TYPE_RLE_FLAG } else { kind };
+    let mut header = [0; HEADER_SIZE];
+    header[2] = image_type;
+    header[12..14].copy_from_slice(&width.to_le_bytes());
+    header[14..16].copy_from_slice(&height.to_le_bytes());
+    header[16] = (bytes * 8) as u8;
+    header[17] = DESC_TOP_TO_BOTTOM | alpha_bits;
+    w.write_all(&header)?;
+
+    if !opts.rle {
+        w.write_all(data)?;
+    } else {
+        // packets never cross scanlines
+        let row_len = width as usize * bytes;
+        for row in data.chunks_exact(row_len.max(1)) {
+            write_rle_row(&mut w, row, bytes)?;
+        }
+    }
+
+    // extension area offset, developer area offset, signature
+    w.write_all(&[0; 8])?;
+    w.write_all(FOOTER_SIGNATURE)
+}
+
+fn write_rle_row<W: Write>(w: &mut W, row: &[u8], bytes: usize) -> io::Result<()> {
+    let pixels: Vec<&[u8]> = row.chunks_exact(bytes).collect();
+    let mut i = 0;
+    while i < pixels.len() {
+        let mut run = 1;
+        while i + run < pixels.len() && run < 128 && pixels[i + run] == pixels[i] {
+            run += 1;
+        }
+        if run > 1 {
+            w.write_all(&[0x80 | (run - 1) as u8])?;
+            w.write_all(pixels[i])?;
+            i += run;
+            continue;
+        }
+        let mut raw = 1;
+        while i + raw < pixels.len()
+            && raw < 128
+            && (i + raw + 1 >= pixels.len() || pixels[i + raw] != pixels[i + raw + 1])
+        {
+            raw += 1;
+        }
+        w.write_all(&[(raw - 1) as u8])?;
+        for p in &pixels[i..i + raw] {
+            w.write_all(p)?;
+        }
+        i += raw;
+    }
+    Ok(())
+}
+
+impl Encode<RawPixBuf<Gray>> for Tga {
+    fn encode<W: Write>(w: W, opts: TgaEncodeOptions, buf: &RawPixBuf<Gray>) -> io::Result<()> {
+        let dims = buf.dimensions();
+        encode_impl(w, opts, dims, (TYPE_GRAY, 1, 0), buf.as_ref())
+    }
+}
+
+impl Encode<RawPixBuf<Rgb>> for Tga {
+    fn encode<W: Write>(w: W, opts: TgaEncodeOptions, buf: &RawPixBuf<Rgb>) -> io::Result<()> {
+        let data: Vec<u8> = buf
+            .as_typed()
+            .iter()
+            .flat_map(|c| [c.b, c.g, c.r])
+            .collect();
+        let dims = buf.dimensions();
+        encode_impl(w, opts, dims, (TYPE_TRUE_COLOR, 3, 0), &data)
+    }
+}
+
+impl Encode<RawPixBuf<Nrgba>> for Tga {
+    fn encode<W: Write>(w: W, opts: TgaEncodeOptions, buf: &RawPixBuf<Nrgba>) -> io::Result<()> {
+        let data: Vec<u8> = buf
+            .as_typed()
+            .iter()
+            .flat_map(|c| [c.b, c.g, c.r, c.a])
+            .collect();
+        let dims = buf.dimensions();
+        encode_impl(w, opts, dims, (TYPE_TRUE_COLOR, 4, 8), &data)
+    }
+}
+
+impl Encode<TgaBuf> for Tga {
+    fn encode<W: Write>(w: W, opts: TgaEncodeOptions, buf: &TgaBuf) -> io::Result<()> {
+        match buf {
+            TgaBuf::Gray(buf) => Tga::encode_specialized(w, opts, buf),
+            TgaBuf::Nrgba(buf) => Tga::encode_specialized(w, opts, buf),
+            TgaBuf::Rgb(buf) => Tga::encode_specialized(w, opts, buf),
+        }
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Tga {
+    fn encode<W: Write>(w: W, opts: TgaEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Tga::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        // runs of equal bytes, for the rle packets
+        (0..len).map(|i| ((i / 9) * 37 + 11) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let (w, h) = (300, 3);
+        let mut nrgba = pattern(w * h * 4);
+        nrgba[3] = 0;
+        let bufs = [
+            TgaBuf::Gray(RawPixBuf::from_vec(w, h, pattern(w * h)).unwrap()),
+            TgaBuf::Rgb(RawPixBuf::from_vec(w, h, pattern(w * h * 3)).unwrap()),
+            TgaBuf::Nrgba(RawPixBuf::from_vec(w, h, nrgba).unwrap()),
+        ];
+        for buf in &bufs {
+            for &rle in &[false, true] {
+                let mut out = Vec::new();
+                Tga::encode_specialized(&mut out, TgaEncodeOptions { rle }, buf).unwrap();
+                let decoded = Tga::decode(&out[..], ()).unwrap();
+                let same = match (buf, &decoded) {
+                    (TgaBuf::Gray(a), TgaBuf::Gray(b)) => a.as_ref() == b.as_ref(),
+                    (TgaBuf::Rgb(a), TgaBuf::Rgb(b)) => a.as_ref() == b.as_ref(),
+                    (TgaBuf::Nrgba(a), TgaBuf::Nrgba(b)) => a.as_ref() == b.as_ref(),
+                    _ => false,
+                };
+                assert!(same, "{:?} rle: {}", buf, rle);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_images_are_encoded() {
+        for &(w, h) in &[(0, 3), (3, 0)] {
+            let buf: RawPixBuf<Rgb> = RawPixBuf::new(w, h);
+            for &rle in &[false, true] {
+                let mut out = Vec::new();
+                Tga::encode_specialized(&mut out, TgaEncodeOptions { rle }, &buf).unwrap();
+                assert_eq!(out.len(), HEADER_SIZE + 8 + FOOTER_SIGNATURE.len());
+            }
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let mut header = [0; HEADER_SIZE];
+        header[2] = TYPE_TRUE_COLOR | TYPE_RLE_FLAG;
+        header[12..16].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        header[16] = 32;
+        assert!(Tga::decode(&header[..], ()).is_err());
+        header[2] = TYPE_TRUE_COLOR;
+        header[12..16].copy_from_slice(&[0xff, 0x7f, 0xff, 0x7f]);
+        assert!(Tga::decode(&header[..], ()).is_err());
+    }
+}
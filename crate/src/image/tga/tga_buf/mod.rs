@@ -0,0 +1,99 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{Color, Gray, Nrgba, Rgb};
+use crate::image::{Dimensions, Image, ImageMut};
+
+#[derive(Clone, Debug)]
+pub enum TgaBuf {
+    Gray(RawPixBuf<Gray>),
+    Nrgba(RawPixBuf<Nrgba>),
+    Rgb(RawPixBuf<Rgb>),
+}
+
+impl Dimensions for TgaBuf {
+    fn width(&self) -> usize {
+        match self {
+            TgaBuf::Gray(buf) => buf.width(),
+            TgaBuf::Nrgba(buf) => buf.width(),
+            TgaBuf::Rgb(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            TgaBuf::Gray(buf) => buf.height(),
+            TgaBuf::Nrgba(buf) => buf.height(),
+            TgaBuf::Rgb(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for TgaBuf {
+    type Pixel = TgaPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            TgaBuf::Gray(buf) => TgaPix::Gray(buf.color_get(x, y)),
+            TgaBuf::Nrgba(buf) => TgaPix::Nrgba(buf.color_get(x, y)),
+            TgaBuf::Rgb(buf) => TgaPix::Rgb(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for TgaBuf {
+    type Pixel = TgaPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<TgaPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<TgaPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (TgaBuf::Gray(buf), TgaPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (TgaBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TgaBuf::Nrgba(buf), TgaPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (TgaBuf::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TgaBuf::Rgb(buf), TgaPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (TgaBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum TgaPix {
+    Gray(Gray),
+    Nrgba(Nrgba),
+    Rgb(Rgb),
+}
+
+impl Color for TgaPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            TgaPix::Gray(c) => c.as_rgba(),
+            TgaPix::Nrgba(c) => c.as_rgba(),
+            TgaPix::Rgb(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for TgaBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            TgaBuf::Gray(buf) => buf.as_ref(),
+            TgaBuf::Nrgba(buf) => buf.as_ref(),
+            TgaBuf::Rgb(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for TgaBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            TgaBuf::Gray(buf) => buf.as_mut(),
+            TgaBuf::Nrgba(buf) => buf.as_mut(),
+            TgaBuf::Rgb(buf) => buf.as_mut(),
+        }
+    }
+}
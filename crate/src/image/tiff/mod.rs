@@ -0,0 +1,1324 @@
+mod tiff_buf;
+
+pub use tiff_buf::*;
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{
+    Gray, Gray16Be, Gray16Le, Nrgba, Nrgba64Be, Nrgba64Le, Rgb, Rgb48Be, Rgb48Le, Zero,
+};
+use crate::image::{self, ColorType, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, EncodeSpecialized, Probe};
+use crate::specialized;
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_PLANAR_CONFIG: u16 = 284;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_COLOR_MAP: u16 = 320;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_EXTRA_SAMPLES: u16 = 338;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_LZW: u16 = 5;
+const COMPRESSION_DEFLATE: u16 = 8;
+const COMPRESSION_DEFLATE_OLD: u16 = 32946;
+const COMPRESSION_PACKBITS: u16 = 32773;
+
+const PHOTOMETRIC_WHITE_IS_ZERO: u16 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+const PHOTOMETRIC_PALETTE: u16 = 3;
+
+const EXTRA_ASSOCIATED_ALPHA: u16 = 1;
+const EXTRA_UNASSOCIATED_ALPHA: u16 = 2;
+
+const PREDICTOR_HORIZONTAL: u16 = 2;
+
+// rgba, along with a few extra samples, at most
+const MAX_SAMPLES_PER_PIXEL: usize = 8;
+
+pub struct Tiff;
+
+impl_format! {
+    name: Tiff,
+    id: "feim:tiff",
+    magic: b"II*\0",
+    signatures: &[Signature::new(b"II*\0"), Signature::new(b"MM\0*")],
+}
+
+/// The compression scheme applied to each strip or tile.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+/// How the samples of a page are split up in the file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TiffLayout {
+    /// Store the image in strips of the given number of rows.
+    Strips { rows: u32 },
+    /// Store the image in tiles of the given dimensions,
+    /// which must be multiples of 16.
+    Tiles { width: u32, height: u32 },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TiffEncodeOptions {
+    pub compression: TiffCompression,
+    pub layout: TiffLayout,
+    /// Apply horizontal differencing before compressing
+    /// the image, which usually helps with lzw and deflate.
+    pub predictor: bool,
+}
+
+impl Default for TiffEncodeOptions {
+    fn default() -> Self {
+        Self {
+            compression: TiffCompression::None,
+            layout: TiffLayout::Strips { rows: 64 },
+            predictor: false,
+        }
+    }
+}
+
+impl DecodeOptions for Tiff {
+    type Options = ();
+}
+
+impl EncodeOptions for Tiff {
+    type Options = TiffEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, b: &[u8]) -> u16 {
+        match self {
+            ByteOrder::Little => u16::from_le_bytes([b[0], b[1]]),
+            ByteOrder::Big => u16::from_be_bytes([b[0], b[1]]),
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        match self {
+            ByteOrder::Little => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            ByteOrder::Big => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+
+    fn put_u16(self, b: &mut [u8], x: u16) {
+        match self {
+            ByteOrder::Little => b[..2].copy_from_slice(&x.to_le_bytes()),
+            ByteOrder::Big => b[..2].copy_from_slice(&x.to_be_bytes()),
+        }
+    }
+
+    fn put_u32(self, b: &mut [u8], x: u32) {
+        match self {
+            ByteOrder::Little => b[..4].copy_from_slice(&x.to_le_bytes()),
+            ByteOrder::Big => b[..4].copy_from_slice(&x.to_be_bytes()),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+/// The pages of a tiff file, as returned by `Tiff::decode_pages`.
+pub struct TiffPages {
+    data: Vec<u8>,
+    order: ByteOrder,
+    next: u32,
+    visited: Vec<u32>,
+}
+
+impl Tiff {
+    /// Read a tiff file, to decode each of its pages in turn.
+    pub fn decode_pages<R: Read>(mut r: R) -> io::Result<TiffPages> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        if !Tiff.is_valid_magic(&data) || data.len() < 8 {
+            return Err(invalid("Invalid tiff header."));
+        }
+        let order = match data[0] {
+            b'I' => ByteOrder::Little,
+            _ => ByteOrder::Big,
+        };
+        let next = order.u32(&data[4..]);
+        Ok(TiffPages {
+            data,
+            order,
+            next,
+            visited: Vec::new(),
+        })
+    }
+
+    /// Encode multiple pages into a single tiff file.
+    pub fn encode_pages<'a, W, I>(w: W, opts: TiffEncodeOptions, pages: I) -> io::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = &'a TiffBuf>,
+    {
+        let pages: Vec<_> = pages.into_iter().map(page_of).collect();
+        encode_impl(w, opts, &pages)
+    }
+}
+
+impl Iterator for TiffPages {
+    type Item = io::Result<TiffBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 {
+            return None;
+        }
+        // guard against ifd chains looping back on themselves
+        if self.visited.contains(&self.next) {
+            self.next = 0;
+            return Some(Err(invalid("Tiff ifd chain contains a cycle")));
+        }
+        self.visited.push(self.next);
+
+        let result =
+            read_ifd(&self.data, self.order, self.next as usize).and_then(|(ifd, next)| {
+                self.next = next;
+                decode_page(&self.data, self.order, &ifd)
+            });
+        if result.is_err() {
+            self.next = 0;
+        }
+        Some(result)
+    }
+}
+
+impl Decode<TiffBuf> for Tiff {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<TiffBuf> {
+        Tiff::decode_pages(r)?
+            .next()
+            .unwrap_or_else(|| Err(invalid("Tiff file contains no images")))
+    }
+}
+
+// a tiff file, read only as far as it is needed
+struct PartialFile<R> {
+    r: R,
+    data: Vec<u8>,
+}
+
+impl<R: Read> PartialFile<R> {
+    fn get(&mut self, at: usize, len: usize) -> io::Result<&[u8]> {
+        let truncated = || invalid("Truncated tiff file");
+        let end = at.checked_add(len).ok_or_else(truncated)?;
+        if end > self.data.len() {
+            let missing = (end - self.data.len()) as u64;
+            (&mut self.r).take(missing).read_to_end(&mut self.data)?;
+        }
+        self.data.get(at..end).ok_or_else(truncated)
+    }
+
+    // the first value of an ifd entry, which is stored apart
+    // from the entry when the values don't fit in it
+    fn first_value(&mut self, order: ByteOrder, table: &[u8], tag: u16) -> io::Result<Option<u32>> {
+        let entry = match table.chunks_exact(12).find(|e| order.u16(e) == tag) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let kind = order.u16(&entry[2..]);
+        let count = order.u32(&entry[4..]) as usize;
+        let size = match kind {
+            TYPE_BYTE => 1,
+            TYPE_SHORT => 2,
+            TYPE_LONG => 4,
+            _ => return Ok(None),
+        };
+        let value = match count.checked_mul(size) {
+            Some(0) => return Ok(None),
+            Some(len) if len <= 4 => &entry[8..8 + size],
+            _ => self.get(order.u32(&entry[8..]) as usize, size)?,
+        };
+        Ok(Some(match kind {
+            TYPE_BYTE => value[0] as u32,
+            TYPE_SHORT => order.u16(value) as u32,
+            _ => order.u32(value),
+        }))
+    }
+}
+
+impl Probe for Tiff {
+    /// Reads the first ifd, and follows the chain of ifds to
+    /// count the pages of the file, skipping over image data.
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        let mut file = PartialFile {
+            r,
+            data: Vec::new(),
+        };
+        let header = file.get(0, 8)?;
+        if !Tiff.is_valid_magic(header) {
+            return Err(invalid("Invalid tiff header."));
+        }
+        let order = match header[0] {
+            b'I' => ByteOrder::Little,
+            _ => ByteOrder::Big,
+        };
+
+        let mut next = order.u32(&header[4..]) as usize;
+        let mut first = None;
+        let mut visited = Vec::new();
+        while next != 0 {
+            if visited.contains(&next) {
+                return Err(invalid("Tiff ifd chain contains a cycle"));
+            }
+            visited.push(next);
+            let count = order.u16(file.get(next, 2)?) as usize;
+            let table = file.get(next + 2, count * 12 + 4)?;
+            let (entries, link) = table.split_at(count * 12);
+            first.get_or_insert_with(|| entries.to_vec());
+            next = order.u32(link) as usize;
+        }
+        let table = first.ok_or_else(|| invalid("Tiff file contains no images"))?;
+
+        let mut value = |tag| file.first_value(order, &table, tag);
+        let width = value(TAG_IMAGE_WIDTH)?.ok_or_else(|| invalid("Missing tiff image width"))?;
+        let height =
+            value(TAG_IMAGE_LENGTH)?.ok_or_else(|| invalid("Missing tiff image length"))?;
+        let spp = value(TAG_SAMPLES_PER_PIXEL)?.unwrap_or(1);
+        let bits = value(TAG_BITS_PER_SAMPLE)?.unwrap_or(1);
+        let photometric = value(TAG_PHOTOMETRIC)?
+            .ok_or_else(|| invalid("Missing tiff photometric interpretation"))?;
+        let alpha = matches!(
+            value(TAG_EXTRA_SAMPLES)?.map(|e| e as u16),
+            Some(EXTRA_ASSOCIATED_ALPHA) | Some(EXTRA_UNASSOCIATED_ALPHA)
+        );
+
+        let color_type = match photometric as u16 {
+            PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO if alpha && spp >= 2 => {
+                ColorType::GrayAlpha
+            }
+            PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO => ColorType::Gray,
+            PHOTOMETRIC_RGB if alpha && spp >= 4 => ColorType::Rgba,
+            PHOTOMETRIC_RGB if spp >= 3 => ColorType::Rgb,
+            PHOTOMETRIC_PALETTE if spp == 1 => ColorType::Indexed,
+            _ => return Err(invalid("Unsupported tiff photometric interpretation")),
+        };
+        let mut info = ImageInfo::new(width as usize, height as usize, color_type, bits as u8);
+        info.frame_count = Some(visited.len());
+        Ok(info)
+    }
+}
+
+struct Ifd {
+    entries: Vec<(u16, Vec<u32>)>,
+}
+
+impl Ifd {
+    fn get(&self, tag: u16) -> Option<&[u32]> {
+        self.entries
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, values)| &values[..])
+    }
+
+    fn first(&self, tag: u16) -> Option<u32> {
+        self.get(tag).and_then(|values| values.first().copied())
+    }
+
+    fn require(&self, tag: u16, msg: &'static str) -> io::Result<u32> {
+        self.first(tag).ok_or_else(|| invalid(msg))
+    }
+}
+
+fn read_ifd(data: &[u8], order: ByteOrder, offset: usize) -> io::Result<(Ifd, u32)> {
+    let truncated = || invalid("Truncated tiff ifd");
+    let count = data
+        .get(offset..offset + 2)
+        .map(|b| order.u16(b) as usize)
+        .ok_or_else(truncated)?;
+    let table = data
+        .get(offset + 2..offset + 2 + count * 12 + 4)
+        .ok_or_else(truncated)?;
+
+    let mut entries = Vec::with_capacity(count);
+    for entry in table.chunks_exact(12) {
+        let tag = order.u16(&entry[0..]);
+        let kind = order.u16(&entry[2..]);
+        let count = order.u32(&entry[4..]) as usize;
+        let size = match kind {
+            TYPE_BYTE => 1,
+            TYPE_SHORT => 2,
+            TYPE_LONG => 4,
+            // we don't need any of the other field types
+            _ => continue,
+        };
+        let values = match count.checked_mul(size) {
+            Some(len) if len <= 4 => &entry[8..8 + len],
+            Some(len) => {
+                let at = order.u32(&entry[8..]) as usize;
+                data.get(at..at.saturating_add(len)).ok_or_else(truncated)?
+            }
+            None => return Err(truncated()),
+        };
+        let values = values
+            .chunks_exact(size)
+            .map(|v| match kind {
+                TYPE_BYTE => v[0] as u32,
+                TYPE_SHORT => order.u16(v) as u32,
+                _ => order.u32(v),
+            })
+            .collect();
+        entries.push((tag, values));
+    }
+
+    let next = order.u32(&table[count * 12..]);
+    Ok((Ifd { entries }, next))
+}
+
+fn decompress(compression: u16, raw: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut data = match compression {
+        COMPRESSION_NONE => raw.to_vec(),
+        COMPRESSION_LZW => {
+            let mut data = Vec::new();
+            // some encoders leave out the end of information
+            // code, so don't insist on finding it
+            weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+                .into_vec(&mut data)
+                .decode(raw)
+                .status
+                .map_err(|_| invalid("Invalid tiff lzw data"))?;
+            data
+        }
+        COMPRESSION_DEFLATE | COMPRESSION_DEFLATE_OLD => {
+            miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(raw, len)
+                .map_err(|_| invalid("Invalid tiff deflate data"))?
+        }
+        COMPRESSION_PACKBITS => unpack_bits(raw, len),
+        _ => return Err(invalid("Unsupported tiff compression")),
+    };
+    data.resize(len, 0);
+    Ok(data)
+}
+
+fn unpack_bits(mut raw: &[u8], len: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    while data.len() < len {
+        match raw {
+            [n @ 0..=127, rest @ ..] => {
+                let n = (*n as usize + 1).min(rest.len());
+                data.extend_from_slice(&rest[..n]);
+                raw = &rest[n..];
+            }
+            // a no-op
+            [128, rest @ ..] => raw = rest,
+            [n, b, rest @ ..] => {
+                let n = 257 - *n as usize;
+                data.extend(std::iter::repeat_n(*b, n));
+                raw = rest;
+            }
+            _ => break,
+        }
+    }
+    data
+}
+
+fn pack_bits(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        if run > 1 {
+            out.push((257 - run) as u8);
+            out.push(row[i]);
+            i += run;
+            continue;
+        }
+        let mut raw = 1;
+        while i + raw < row.len()
+            && raw < 128
+            && (i + raw + 1 >= row.len() || row[i + raw] != row[i + raw + 1])
+        {
+            raw += 1;
+        }
+        out.push((raw - 1) as u8);
+        out.extend_from_slice(&row[i..i + raw]);
+        i += raw;
+    }
+}
+
+// the samples of a row are stored as differences to the
+// same sample of the previous pixel
+fn undo_predictor(row: &mut [u8], samples: usize, bits: usize, order: ByteOrder) {
+    if bits == 16 {
+        for i in (samples * 2..row.len() - row.len() % 2).step_by(2) {
+            let prev = order.u16(&row[i - samples * 2..]);
+            let x = order.u16(&row[i..]).wrapping_add(prev);
+            order.put_u16(&mut row[i..], x);
+        }
+    } else {
+        for i in samples..row.len() {
+            row[i] = row[i].wrapping_add(row[i - samples]);
+        }
+    }
+}
+
+fn apply_predictor(row: &mut [u8], samples: usize, bits: usize, order: ByteOrder) {
+    if bits == 16 {
+        for i in (samples * 2..row.len()).step_by(2).rev() {
+            let prev = order.u16(&row[i - samples * 2..]);
+            let x = order.u16(&row[i..]).wrapping_sub(prev);
+            order.put_u16(&mut row[i..], x);
+        }
+    } else {
+        for i in (samples..row.len()).rev() {
+            row[i] = row[i].wrapping_sub(row[i - samples]);
+        }
+    }
+}
+
+// every strip or tile needs an offset and a byte count, which
+// are checked before allocating anything for the chunks
+fn check_chunks(offsets: Option<&[u32]>, counts: Option<&[u32]>, len: usize) -> io::Result<()> {
+    let offsets = offsets.ok_or_else(|| invalid("Missing tiff data offsets"))?;
+    let counts = counts.ok_or_else(|| invalid("Missing tiff data byte counts"))?;
+    if offsets.len() < len || counts.len() < len {
+        return Err(invalid("Missing tiff strips or tiles"));
+    }
+    Ok(())
+}
+
+// a rectangular region of the image, stored
+// in a single strip or tile
+struct Chunk {
+    plane: usize,
+    left: usize,
+    top: usize,
+    stored_width: usize,
+    stored_height: usize,
+    width: usize,
+    height: usize,
+}
+
+fn decode_page(data: &[u8], order: ByteOrder, ifd: &Ifd) -> io::Result<TiffBuf> {
+    let width = ifd.require(TAG_IMAGE_WIDTH, "Missing tiff image width")? as usize;
+    let height = ifd.require(TAG_IMAGE_LENGTH, "Missing tiff image length")? as usize;
+    let spp = ifd.first(TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+    let bits = ifd.first(TAG_BITS_PER_SAMPLE).unwrap_or(1) as usize;
+    let compression = ifd.first(TAG_COMPRESSION).unwrap_or(1) as u16;
+    let photometric =
+        ifd.require(TAG_PHOTOMETRIC, "Missing tiff photometric interpretation")? as u16;
+    let planar = ifd.first(TAG_PLANAR_CONFIG).unwrap_or(1) == 2;
+    let predictor = ifd.first(TAG_PREDICTOR).unwrap_or(1) as u16;
+    let extra = ifd.first(TAG_EXTRA_SAMPLES).map(|e| e as u16);
+
+    if width == 0 || height == 0 || spp == 0 {
+        return Err(invalid("Invalid tiff image dimensions"));
+    }
+    if image::pixel_count(width, height).is_none() {
+        return Err(invalid("Tiff image dimensions too large"));
+    }
+    if spp > MAX_SAMPLES_PER_PIXEL {
+        return Err(invalid("Unsupported tiff samples per pixel"));
+    }
+    let same_bits = ifd
+        .get(TAG_BITS_PER_SAMPLE)
+        .is_none_or(|b| b.iter().all(|&b| b as usize == bits));
+    if !same_bits || !matches!(bits, 1 | 2 | 4 | 8 | 16) || (bits < 8 && spp != 1) {
+        return Err(invalid("Unsupported tiff bit depth"));
+    }
+    let unsigned = ifd
+        .get(TAG_SAMPLE_FORMAT)
+        .is_none_or(|f| f.iter().all(|&f| f == 1));
+    if !unsigned {
+        return Err(invalid("Unsupported tiff sample format"));
+    }
+    let predictor = match predictor {
+        1 => false,
+        PREDICTOR_HORIZONTAL if bits >= 8 => true,
+        _ => return Err(invalid("Unsupported tiff predictor")),
+    };
+
+    let planes = if planar { spp } else { 1 };
+    let chunk_spp = spp / planes;
+
+    let (offsets, counts, chunks) = match ifd.first(TAG_TILE_WIDTH) {
+        Some(tile_width) => {
+            let tile_width = tile_width as usize;
+            let tile_height = ifd.require(TAG_TILE_LENGTH, "Missing tiff tile length")? as usize;
+            if tile_width == 0 || tile_height == 0 {
+                return Err(invalid("Invalid tiff tile dimensions"));
+            }
+            if image::pixel_count(tile_width, tile_height).is_none() {
+                return Err(invalid("Tiff tile dimensions too large"));
+            }
+            let across = width.div_ceil(tile_width);
+            let down = height.div_ceil(tile_height);
+            let offsets = ifd.get(TAG_TILE_OFFSETS);
+            let counts = ifd.get(TAG_TILE_BYTE_COUNTS);
+            check_chunks(offsets, counts, planes * across * down)?;
+            let chunks: Vec<_> = (0..planes * across * down)
+                .map(|i| {
+                    let j = i % (across * down);
+                    let left = (j % across) * tile_width;
+                    let top = (j / across) * tile_height;
+                    Chunk {
+                        plane: i / (across * down),
+                        left,
+                        top,
+                        stored_width: tile_width,
+                        stored_height: tile_height,
+                        width: tile_width.min(width - left),
+                        height: tile_height.min(height - top),
+                    }
+                })
+                .collect();
+            (offsets, counts, chunks)
+        }
+        None => {
+            let rows = ifd.first(TAG_ROWS_PER_STRIP).unwrap_or(u32::MAX) as usize;
+            let rows = rows.clamp(1, height);
+            let down = height.div_ceil(rows);
+            let offsets = ifd.get(TAG_STRIP_OFFSETS);
+            let counts = ifd.get(TAG_STRIP_BYTE_COUNTS);
+            check_chunks(offsets, counts, planes * down)?;
+            let chunks: Vec<_> = (0..planes * down)
+                .map(|i| {
+                    let top = (i % down) * rows;
+                    let rows = rows.min(height - top);
+                    Chunk {
+                        plane: i / down,
+                        left: 0,
+                        top,
+                        stored_width: width,
+                        stored_height: rows,
+                        width,
+                        height: rows,
+                    }
+                })
+                .collect();
+            (offsets, counts, chunks)
+        }
+    };
+    let offsets = offsets.unwrap_or_default();
+    let counts = counts.unwrap_or_default();
+
+    // samples are unpacked to one byte each, if they are
+    // narrower than that, while 16 bit samples are kept
+    // in the byte order of the file
+    let bps = if bits == 16 { 2 } else { 1 };
+    let pixel_size = spp * bps;
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(pixel_size))
+        .ok_or_else(|| invalid("Tiff image dimensions too large"))?;
+    let mut samples = vec![0; size];
+
+    for ((chunk, &offset), &count) in chunks.iter().zip(offsets).zip(counts) {
+        let (offset, count) = (offset as usize, count as usize);
+        let raw = data
+            .get(offset..offset.saturating_add(count))
+            .ok_or_else(|| invalid("Truncated tiff image data"))?;
+        let row_bytes = (chunk.stored_width * chunk_spp * bits).div_ceil(8);
+        let len = row_bytes
+            .checked_mul(chunk.stored_height)
+            .ok_or_else(|| invalid("Tiff tile dimensions too large"))?;
+        let mut buf = decompress(compression, raw, len)?;
+
+        for (y, row) in buf
+            .chunks_exact_mut(row_bytes)
+            .take(chunk.height)
+            .enumerate()
+        {
+            if predictor {
+                undo_predictor(row, chunk_spp, bits, order);
+            }
+            let row: Cow<'_, [u8]> = if bits < 8 {
+                let mask = (1 << bits) - 1;
+                let unpacked = (0..chunk.width)
+                    .map(|x| {
+                        let bit = x * bits;
+                        (row[bit / 8] >> (8 - bits - bit % 8)) & mask
+                    })
+                    .collect();
+                Cow::Owned(unpacked)
+            } else {
+                Cow::Borrowed(row)
+            };
+            let dest_row = (chunk.top + y) * width + chunk.left;
+            for x in 0..chunk.width {
+                for s in 0..chunk_spp {
+                    let src = (x * chunk_spp + s) * bps;
+                    let dest = (dest_row + x) * pixel_size + (chunk.plane + s) * bps;
+                    samples[dest..dest + bps].copy_from_slice(&row[src..src + bps]);
+                }
+            }
+        }
+    }
+
+    let alpha = matches!(
+        extra,
+        Some(EXTRA_ASSOCIATED_ALPHA) | Some(EXTRA_UNASSOCIATED_ALPHA)
+    );
+    let channels: &[usize] = match photometric {
+        PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO => {
+            if photometric == PHOTOMETRIC_WHITE_IS_ZERO {
+                let max = (1u32 << bits) - 1;
+                for s in samples.chunks_exact_mut(pixel_size) {
+                    if bps == 2 {
+                        let y = !order.u16(s);
+                        order.put_u16(s, y);
+                    } else {
+                        s[0] = (max - s[0] as u32) as u8;
+                    }
+                }
+            }
+            if bits < 8 {
+                let max = (1u32 << bits) - 1;
+                for s in samples.iter_mut() {
+                    *s = (*s as u32 * 0xff / max) as u8;
+                }
+            }
+            if alpha && spp >= 2 {
+                &[0, 0, 0, 1]
+            } else {
+                &[0]
+            }
+        }
+        PHOTOMETRIC_RGB if spp >= 3 => {
+            if alpha && spp >= 4 {
+                &[0, 1, 2, 3]
+            } else {
+                &[0, 1, 2]
+            }
+        }
+        PHOTOMETRIC_PALETTE if spp == 1 => {
+            let map = ifd
+                .get(TAG_COLOR_MAP)
+                .ok_or_else(|| invalid("Missing tiff color map"))?;
+            let colors = 1 << bits;
+            if map.len() < colors * 3 {
+                return Err(invalid("Invalid tiff color map"));
+            }
+            let pixels = samples
+                .chunks_exact(bps)
+                .flat_map(|s| {
+                    let i = if bps == 2 {
+                        order.u16(s) as usize
+                    } else {
+                        s[0] as usize
+                    };
+                    [map[i], map[colors + i], map[2 * colors + i]].map(|c| (c >> 8) as u8)
+                })
+                .collect();
+            return from_samples(TiffBuf::Rgb, width, height, pixels);
+        }
+        _ => return Err(invalid("Unsupported tiff photometric interpretation")),
+    };
+
+    let mut pixels: Vec<u8> = samples
+        .chunks_exact(pixel_size)
+        .flat_map(|s| {
+            channels
+                .iter()
+                .flat_map(move |c| &s[c * bps..(c + 1) * bps])
+        })
+        .copied()
+        .collect();
+
+    if channels.len() == 4 && extra == Some(EXTRA_ASSOCIATED_ALPHA) {
+        unpremultiply(&mut pixels, bps, order);
+    }
+
+    match (channels.len(), bps, order) {
+        (1, 1, _) => from_samples(TiffBuf::Gray, width, height, pixels),
+        (1, 2, ByteOrder::Big) => from_samples(TiffBuf::Gray16Be, width, height, pixels),
+        (1, 2, ByteOrder::Little) => from_samples(TiffBuf::Gray16Le, width, height, pixels),
+        (3, 1, _) => from_samples(TiffBuf::Rgb, width, height, pixels),
+        (3, 2, ByteOrder::Big) => from_samples(TiffBuf::Rgb48Be, width, height, pixels),
+        (3, 2, ByteOrder::Little) => from_samples(TiffBuf::Rgb48Le, width, height, pixels),
+        (4, 1, _) => from_samples(TiffBuf::Nrgba, width, height, pixels),
+        (_, _, ByteOrder::Big) => from_samples(TiffBuf::Nrgba64Be, width, height, pixels),
+        (_, _, ByteOrder::Little) => from_samples(TiffBuf::Nrgba64Le, width, height, pixels),
+    }
+}
+
+fn from_samples<T: Zero, F>(
+    variant: F,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+) -> io::Result<TiffBuf>
+where
+    F: FnOnce(RawPixBuf<T>) -> TiffBuf,
+{
+    RawPixBuf::from_vec(width, height, data)
+        .map(variant)
+        .map_err(|_| invalid("Invalid tiff image data"))
+}
+
+fn unpremultiply(pixels: &mut [u8], bps: usize, order: ByteOrder) {
+    if bps == 2 {
+        for p in pixels.chunks_exact_mut(8) {
+            let a = order.u16(&p[6..]) as u32;
+            if a == 0 {
+                continue;
+            }
+            for c in p[..6].chunks_exact_mut(2) {
+                let x = (order.u16(c) as u32 * 0xffff / a).min(0xffff);
+                order.put_u16(c, x as u16);
+            }
+        }
+    } else {
+        for p in pixels.chunks_exact_mut(4) {
+            let a = p[3] as u32;
+            if a == 0 {
+                continue;
+            }
+            for c in p[..3].iter_mut() {
+                *c = (*c as u32 * 0xff / a).min(0xff) as u8;
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+// the layout of a page's samples, to be encoded
+struct Page<'a> {
+    width: usize,
+    height: usize,
+    bits: u16,
+    samples: u16,
+    alpha: bool,
+    // the byte order of 16 bit samples
+    order: Option<ByteOrder>,
+    data: &'a [u8],
+}
+
+type PageFormat = (u16, u16, bool, Option<ByteOrder>);
+
+const GRAY: PageFormat = (8, 1, false, None);
+const GRAY16_BE: PageFormat = (16, 1, false, Some(ByteOrder::Big));
+const GRAY16_LE: PageFormat = (16, 1, false, Some(ByteOrder::Little));
+const RGB: PageFormat = (8, 3, false, None);
+const RGB48_BE: PageFormat = (16, 3, false, Some(ByteOrder::Big));
+const RGB48_LE: PageFormat = (16, 3, false, Some(ByteOrder::Little));
+const NRGBA: PageFormat = (8, 4, true, None);
+const NRGBA64_BE: PageFormat = (16, 4, true, Some(ByteOrder::Big));
+const NRGBA64_LE: PageFormat = (16, 4, true, Some(ByteOrder::Little));
+
+fn page<B>(buf: &B, (bits, samples, alpha, order): PageFormat) -> Page<'_>
+where
+    B: Dimensions + AsRef<[u8]>,
+{
+    Page {
+        width: buf.width(),
+        height: buf.height(),
+        bits,
+        samples,
+        alpha,
+        order,
+        data: buf.as_ref(),
+    }
+}
+
+fn page_of(buf: &TiffBuf) -> Page<'_> {
+    match buf {
+        TiffBuf::Gray(buf) => page(buf, GRAY),
+        TiffBuf::Gray16Be(buf) => page(buf, GRAY16_BE),
+        TiffBuf::Gray16Le(buf) => page(buf, GRAY16_LE),
+        TiffBuf::Rgb(buf) => page(buf, RGB),
+        TiffBuf::Rgb48Be(buf) => page(buf, RGB48_BE),
+        TiffBuf::Rgb48Le(buf) => page(buf, RGB48_LE),
+        TiffBuf::Nrgba(buf) => page(buf, NRGBA),
+        TiffBuf::Nrgba64Be(buf) => page(buf, NRGBA64_BE),
+        TiffBuf::Nrgba64Le(buf) => page(buf, NRGBA64_LE),
+    }
+}
+
+// an ifd entry, with its values already serialized
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    values: Vec<u8>,
+}
+
+impl Entry {
+    fn shorts(order: ByteOrder, tag: u16, values: &[u16]) -> Self {
+        let mut bytes = vec![0; values.len() * 2];
+        for (b, &v) in bytes.chunks_exact_mut(2).zip(values) {
+            order.put_u16(b, v);
+        }
+        Entry {
+            tag,
+            kind: TYPE_SHORT,
+            count: values.len() as u32,
+            values: bytes,
+        }
+    }
+
+    fn longs(order: ByteOrder, tag: u16, values: &[u32]) -> Self {
+        let mut bytes = vec![0; values.len() * 4];
+        for (b, &v) in bytes.chunks_exact_mut(4).zip(values) {
+            order.put_u32(b, v);
+        }
+        Entry {
+            tag,
+            kind: TYPE_LONG,
+            count: values.len() as u32,
+            values: bytes,
+        }
+    }
+
+    fn rational(order: ByteOrder, tag: u16, num: u32, den: u32) -> Self {
+        let mut entry = Entry::longs(order, tag, &[num, den]);
+        entry.kind = TYPE_RATIONAL;
+        entry.count = 1;
+        entry
+    }
+}
+
+fn offset_of(out: &[u8]) -> io::Result<u32> {
+    u32::try_from(out.len()).map_err(|_| invalid("Tiff file too large"))
+}
+
+fn compress(compression: TiffCompression, data: Vec<u8>, row_bytes: usize) -> io::Result<Vec<u8>> {
+    match compression {
+        TiffCompression::None => Ok(data),
+        TiffCompression::Lzw => {
+            weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+                .encode(&data)
+                .map_err(|_| invalid("Tiff lzw encoding failed"))
+        }
+        TiffCompression::Deflate => Ok(miniz_oxide::deflate::compress_to_vec_zlib(&data, 6)),
+        TiffCompression::PackBits => {
+            // runs may not cross rows
+            let mut out = Vec::with_capacity(data.len());
+            for row in data.chunks(row_bytes) {
+                pack_bits(row, &mut out);
+            }
+            Ok(out)
+        }
+    }
+}
+
+fn encode_impl<W: Write>(mut w: W, opts: TiffEncodeOptions, pages: &[Page<'_>]) -> io::Result<()> {
+    if pages.is_empty() {
+        return Err(invalid("Tiff files need at least one page"));
+    }
+    let order = pages
+        .iter()
+        .find_map(|p| p.order)
+        .unwrap_or(ByteOrder::Little);
+    let compression = match opts.compression {
+        TiffCompression::None => COMPRESSION_NONE,
+        TiffCompression::Lzw => COMPRESSION_LZW,
+        TiffCompression::Deflate => COMPRESSION_DEFLATE,
+        TiffCompression::PackBits => COMPRESSION_PACKBITS,
+    };
+
+    let mut out = Vec::new();
+    out.extend_from_slice(match order {
+        ByteOrder::Little => b"II*\0",
+        ByteOrder::Big => b"MM\0*",
+    });
+    out.extend_from_slice(&[0; 4]);
+    let mut next_ifd_at = 4;
+
+    for page in pages {
+        let width = u32::try_from(page.width).map_err(|_| invalid("Tiff image too wide"))?;
+        let height = u32::try_from(page.height).map_err(|_| invalid("Tiff image too tall"))?;
+        if width == 0 || height == 0 {
+            return Err(invalid("Invalid tiff image dimensions"));
+        }
+
+        // 16 bit samples of other pages may need to be swapped
+        // around, to match the byte order of the file
+        let data: Cow<'_, [u8]> = match page.order {
+            Some(o) if o != order => Cow::Owned(
+                page.data
+                    .chunks_exact(2)
+                    .flat_map(|c| [c[1], c[0]])
+                    .collect(),
+            ),
+            _ => Cow::Borrowed(page.data),
+        };
+
+        let spp = page.samples as usize;
+        let bits = page.bits as usize;
+        let pixel_size = spp * bits / 8;
+        let stride = page.width * pixel_size;
+
+        let (chunk_width, chunk_height, tiled) = match opts.layout {
+            TiffLayout::Strips { rows } if rows > 0 => (width, rows.min(height), false),
+            TiffLayout::Tiles { width, height }
+                if width > 0 && height > 0 && width % 16 == 0 && height % 16 == 0 =>
+            {
+                (width, height, true)
+            }
+            _ => return Err(invalid("Invalid tiff strip or tile size")),
+        };
+        let (chunk_width, chunk_height) = (chunk_width as usize, chunk_height as usize);
+        let across = page.width.div_ceil(chunk_width);
+        let down = page.height.div_ceil(chunk_height);
+        let row_bytes = chunk_width * pixel_size;
+
+        let mut offsets = Vec::with_capacity(across * down);
+        let mut counts = Vec::with_capacity(across * down);
+        for ty in 0..down {
+            for tx in 0..across {
+                let left = tx * chunk_width;
+                let top = ty * chunk_height;
+                let visible = chunk_width.min(page.width - left) * pixel_size;
+                // the last strip only holds the remaining rows,
+                // while tiles are always padded to their full size
+                let rows = if tiled {
+                    chunk_height
+                } else {
+                    chunk_height.min(page.height - top)
+                };
+
+                let mut chunk = vec![0; row_bytes * rows];
+                for (y, row) in chunk.chunks_exact_mut(row_bytes).enumerate() {
+                    if top + y < page.height {
+                        let at = (top + y) * stride + left * pixel_size;
+                        row[..visible].copy_from_slice(&data[at..at + visible]);
+                    }
+                    if opts.predictor {
+                        apply_predictor(row, spp, bits, order);
+                    }
+                }
+
+                let chunk = compress(opts.compression, chunk, row_bytes)?;
+                offsets.push(offset_of(&out)?);
+                counts
+                    .push(u32::try_from(chunk.len()).map_err(|_| invalid("Tiff file too large"))?);
+                out.extend_from_slice(&chunk);
+            }
+        }
+
+        let mut entries = vec![
+            Entry::longs(order, TAG_IMAGE_WIDTH, &[width]),
+            Entry::longs(order, TAG_IMAGE_LENGTH, &[height]),
+            Entry::shorts(order, TAG_BITS_PER_SAMPLE, &vec![page.bits; spp]),
+            Entry::shorts(order, TAG_COMPRESSION, &[compression]),
+            Entry::shorts(
+                order,
+                TAG_PHOTOMETRIC,
+                &[if spp >= 3 {
+                    PHOTOMETRIC_RGB
+                } else {
+                    PHOTOMETRIC_BLACK_IS_ZERO
+                }],
+            ),
+            Entry::shorts(order, TAG_SAMPLES_PER_PIXEL, &[page.samples]),
+            Entry::rational(order, TAG_X_RESOLUTION, 72, 1),
+            Entry::rational(order, TAG_Y_RESOLUTION, 72, 1),
+            Entry::shorts(order, TAG_PLANAR_CONFIG, &[1]),
+            // inches
+            Entry::shorts(order, TAG_RESOLUTION_UNIT, &[2]),
+        ];
+        if tiled {
+            entries.push(Entry::longs(order, TAG_TILE_WIDTH, &[chunk_width as u32]));
+            entries.push(Entry::longs(order, TAG_TILE_LENGTH, &[chunk_height as u32]));
+            entries.push(Entry::longs(order, TAG_TILE_OFFSETS, &offsets));
+            entries.push(Entry::longs(order, TAG_TILE_BYTE_COUNTS, &counts));
+        } else {
+            entries.push(Entry::longs(order, TAG_STRIP_OFFSETS, &offsets));
+            entries.push(Entry::longs(
+                order,
+                TAG_ROWS_PER_STRIP,
+                &[chunk_height as u32],
+            ));
+            entries.push(Entry::longs(order, TAG_STRIP_BYTE_COUNTS, &counts));
+        }
+        if opts.predictor {
+            entries.push(Entry::shorts(order, TAG_PREDICTOR, &[PREDICTOR_HORIZONTAL]));
+        }
+        if page.alpha {
+            entries.push(Entry::shorts(
+                order,
+                TAG_EXTRA_SAMPLES,
+                &[EXTRA_UNASSOCIATED_ALPHA],
+            ));
+        }
+        entries.sort_by_key(|e| e.tag);
+
+        // ifds start on a word boundary, and are followed
+        // by the values that don't fit in their entries
+        if out.len() % 2 != 0 {
+            out.push(0);
+        }
+        let ifd_at = offset_of(&out)?;
+        order.put_u32(&mut out[next_ifd_at..], ifd_at);
+
+        let mut values_at = out.len() + 2 + entries.len() * 12 + 4;
+        let mut ifd = vec![0; 2];
+        let mut values = Vec::new();
+        order.put_u16(&mut ifd, entries.len() as u16);
+        for entry in entries.iter() {
+            let mut raw = [0; 12];
+            order.put_u16(&mut raw[0..], entry.tag);
+            order.put_u16(&mut raw[2..], entry.kind);
+            order.put_u32(&mut raw[4..], entry.count);
+            if entry.values.len() <= 4 {
+                raw[8..8 + entry.values.len()].copy_from_slice(&entry.values);
+            } else {
+                let at = u32::try_from(values_at).map_err(|_| invalid("Tiff file too large"))?;
+                order.put_u32(&mut raw[8..], at);
+                values.extend_from_slice(&entry.values);
+                values_at += entry.values.len();
+            }
+            ifd.extend_from_slice(&raw);
+        }
+        next_ifd_at = out.len() + ifd.len();
+        ifd.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&ifd);
+        out.extend_from_slice(&values);
+    }
+
+    w.write_all(&out)
+}
+
+macro_rules! impl_encode {
+    ($type:ty, $format:expr) => {
+        impl Encode<$type> for Tiff {
+            fn encode<W: Write>(w: W, opts: TiffEncodeOptions, buf: &$type) -> io::Result<()> {
+                encode_impl(w, opts, &[page(buf, $format)])
+            }
+        }
+    };
+}
+
+impl_encode!(RawPixBuf<Gray>, GRAY);
+impl_encode!(RawPixBuf<Gray16Be>, GRAY16_BE);
+impl_encode!(RawPixBuf<Gray16Le>, GRAY16_LE);
+impl_encode!(RawPixBuf<Rgb>, RGB);
+impl_encode!(RawPixBuf<Rgb48Be>, RGB48_BE);
+impl_encode!(RawPixBuf<Rgb48Le>, RGB48_LE);
+impl_encode!(RawPixBuf<Nrgba>, NRGBA);
+impl_encode!(RawPixBuf<Nrgba64Be>, NRGBA64_BE);
+impl_encode!(RawPixBuf<Nrgba64Le>, NRGBA64_LE);
+
+impl Encode<TiffBuf> for Tiff {
+    fn encode<W: Write>(w: W, opts: TiffEncodeOptions, buf: &TiffBuf) -> io::Result<()> {
+        encode_impl(w, opts, &[page_of(buf)])
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Tiff {
+    fn encode<W: Write>(w: W, opts: TiffEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut new_buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = buf.color_get(x, y);
+                let c: Nrgba64Be = c.convert_into();
+                new_buf.pixel_set(x, y, c);
+            }
+        }
+        Tiff::encode_specialized(w, opts, &new_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i / 5) * 37 + 11) as u8).collect()
+    }
+
+    fn same(a: &TiffBuf, b: &TiffBuf) -> bool {
+        match (a, b) {
+            (TiffBuf::Gray(a), TiffBuf::Gray(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Gray16Be(a), TiffBuf::Gray16Be(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Gray16Le(a), TiffBuf::Gray16Le(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Rgb(a), TiffBuf::Rgb(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Rgb48Be(a), TiffBuf::Rgb48Be(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Rgb48Le(a), TiffBuf::Rgb48Le(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Nrgba(a), TiffBuf::Nrgba(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Nrgba64Be(a), TiffBuf::Nrgba64Be(b)) => a.as_ref() == b.as_ref(),
+            (TiffBuf::Nrgba64Le(a), TiffBuf::Nrgba64Le(b)) => a.as_ref() == b.as_ref(),
+            _ => false,
+        }
+    }
+
+    fn bufs(w: usize, h: usize) -> Vec<TiffBuf> {
+        vec![
+            TiffBuf::Gray(RawPixBuf::from_vec(w, h, pattern(w * h)).unwrap()),
+            TiffBuf::Gray16Be(RawPixBuf::from_vec(w, h, pattern(w * h * 2)).unwrap()),
+            TiffBuf::Gray16Le(RawPixBuf::from_vec(w, h, pattern(w * h * 2)).unwrap()),
+            TiffBuf::Rgb(RawPixBuf::from_vec(w, h, pattern(w * h * 3)).unwrap()),
+            TiffBuf::Rgb48Be(RawPixBuf::from_vec(w, h, pattern(w * h * 6)).unwrap()),
+            TiffBuf::Rgb48Le(RawPixBuf::from_vec(w, h, pattern(w * h * 6)).unwrap()),
+            TiffBuf::Nrgba(RawPixBuf::from_vec(w, h, pattern(w * h * 4)).unwrap()),
+            TiffBuf::Nrgba64Be(RawPixBuf::from_vec(w, h, pattern(w * h * 8)).unwrap()),
+            TiffBuf::Nrgba64Le(RawPixBuf::from_vec(w, h, pattern(w * h * 8)).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn round_trip_every_buffer() {
+        let compressions = [
+            TiffCompression::None,
+            TiffCompression::Lzw,
+            TiffCompression::Deflate,
+            TiffCompression::PackBits,
+        ];
+        let layouts = [
+            TiffLayout::Strips { rows: 4 },
+            TiffLayout::Tiles {
+                width: 16,
+                height: 16,
+            },
+        ];
+        for buf in &bufs(21, 18) {
+            for &compression in &compressions {
+                for &layout in &layouts {
+                    for &predictor in &[false, true] {
+                        let opts = TiffEncodeOptions {
+                            compression,
+                            layout,
+                            predictor,
+                        };
+                        let mut out = Vec::new();
+                        Tiff::encode_specialized(&mut out, opts, buf).unwrap();
+                        let decoded = Tiff::decode(&out[..], ()).unwrap();
+                        assert!(same(buf, &decoded), "{:?} {:?}", buf, opts);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_pages() {
+        let pages = bufs(3, 2);
+        let mut out = Vec::new();
+        Tiff::encode_pages(&mut out, Default::default(), &pages).unwrap();
+        let decoded = Tiff::decode_pages(&out[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(decoded.len(), pages.len());
+        // pages share the byte order of the file
+        for (a, b) in pages.iter().zip(&decoded) {
+            assert_eq!(a.dimensions(), b.dimensions());
+            for y in 0..a.height() {
+                for x in 0..a.width() {
+                    let (p, q) = (a.color_get(x, y), b.color_get(x, y));
+                    assert_eq!(p.as_rgba(), q.as_rgba(), "{:?}", a);
+                }
+            }
+        }
+    }
+
+    // a little endian file, with a single ifd of long values
+    fn file(entries: &[(u16, u32)]) -> Vec<u8> {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, value) in entries {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&TYPE_LONG.to_le_bytes());
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let image = |width, height| {
+            file(&[
+                (TAG_IMAGE_WIDTH, width),
+                (TAG_IMAGE_LENGTH, height),
+                (TAG_BITS_PER_SAMPLE, 8),
+                (TAG_PHOTOMETRIC, PHOTOMETRIC_BLACK_IS_ZERO as u32),
+                (TAG_STRIP_OFFSETS, 8),
+                (TAG_ROWS_PER_STRIP, 1),
+                (TAG_STRIP_BYTE_COUNTS, 1),
+            ])
+        };
+        assert!(Tiff::decode(&image(u32::MAX, u32::MAX)[..], ()).is_err());
+        assert!(Tiff::decode(&image(20_000, 30_000)[..], ()).is_err());
+        // a single strip for many rows
+        assert!(Tiff::decode(&image(1, 10_000_000)[..], ()).is_err());
+
+        let tiled = file(&[
+            (TAG_IMAGE_WIDTH, 1),
+            (TAG_IMAGE_LENGTH, 1),
+            (TAG_BITS_PER_SAMPLE, 8),
+            (TAG_COMPRESSION, COMPRESSION_PACKBITS as u32),
+            (TAG_PHOTOMETRIC, PHOTOMETRIC_BLACK_IS_ZERO as u32),
+            (TAG_TILE_WIDTH, u32::MAX),
+            (TAG_TILE_LENGTH, u32::MAX),
+            (TAG_TILE_OFFSETS, 8),
+            (TAG_TILE_BYTE_COUNTS, 1),
+        ]);
+        assert!(Tiff::decode(&tiled[..], ()).is_err());
+
+        let samples = file(&[
+            (TAG_IMAGE_WIDTH, 1),
+            (TAG_IMAGE_LENGTH, 1),
+            (TAG_BITS_PER_SAMPLE, 8),
+            (TAG_SAMPLES_PER_PIXEL, u32::MAX),
+            (TAG_PHOTOMETRIC, PHOTOMETRIC_RGB as u32),
+            (TAG_STRIP_OFFSETS, 8),
+            (TAG_STRIP_BYTE_COUNTS, 1),
+        ]);
+        assert!(Tiff::decode(&samples[..], ()).is_err());
+    }
+
+    #[test]
+    fn short_strips_are_padded() {
+        let data = file(&[
+            (TAG_IMAGE_WIDTH, 4),
+            (TAG_IMAGE_LENGTH, 2),
+            (TAG_BITS_PER_SAMPLE, 8),
+            (TAG_PHOTOMETRIC, PHOTOMETRIC_BLACK_IS_ZERO as u32),
+            (TAG_STRIP_OFFSETS, 0),
+            (TAG_STRIP_BYTE_COUNTS, 4),
+        ]);
+        match Tiff::decode(&data[..], ()).unwrap() {
+            TiffBuf::Gray(buf) => assert_eq!(buf.as_ref(), b"II*\0\0\0\0\0"),
+            other => panic!("unexpected buffer {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
+use crate::color::{
+    Color, Gray, Gray16Be, Gray16Le, Nrgba, Nrgba64Be, Nrgba64Le, Rgb, Rgb48Be, Rgb48Le,
+};
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// A decoded tiff page. 16 bit samples keep the byte
+/// order of the file they were read from.
+#[derive(Clone, Debug)]
+pub enum TiffBuf {
+    Gray(RawPixBuf<Gray>),
+    Gray16Be(RawPixBuf<Gray16Be>),
+    Gray16Le(RawPixBuf<Gray16Le>),
+    Rgb(RawPixBuf<Rgb>),
+    Rgb48Be(RawPixBuf<Rgb48Be>),
+    Rgb48Le(RawPixBuf<Rgb48Le>),
+    Nrgba(RawPixBuf<Nrgba>),
+    Nrgba64Be(RawPixBuf<Nrgba64Be>),
+    Nrgba64Le(RawPixBuf<Nrgba64Le>),
+}
+
+impl Dimensions for TiffBuf {
+    fn width(&self) -> usize {
+        match self {
+            TiffBuf::Gray(buf) => buf.width(),
+            TiffBuf::Gray16Be(buf) => buf.width(),
+            TiffBuf::Gray16Le(buf) => buf.width(),
+            TiffBuf::Rgb(buf) => buf.width(),
+            TiffBuf::Rgb48Be(buf) => buf.width(),
+            TiffBuf::Rgb48Le(buf) => buf.width(),
+            TiffBuf::Nrgba(buf) => buf.width(),
+            TiffBuf::Nrgba64Be(buf) => buf.width(),
+            TiffBuf::Nrgba64Le(buf) => buf.width(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            TiffBuf::Gray(buf) => buf.height(),
+            TiffBuf::Gray16Be(buf) => buf.height(),
+            TiffBuf::Gray16Le(buf) => buf.height(),
+            TiffBuf::Rgb(buf) => buf.height(),
+            TiffBuf::Rgb48Be(buf) => buf.height(),
+            TiffBuf::Rgb48Le(buf) => buf.height(),
+            TiffBuf::Nrgba(buf) => buf.height(),
+            TiffBuf::Nrgba64Be(buf) => buf.height(),
+            TiffBuf::Nrgba64Le(buf) => buf.height(),
+        }
+    }
+}
+
+impl Image for TiffBuf {
+    type Pixel = TiffPix;
+
+    fn color_get(&self, x: usize, y: usize) -> Self::Pixel {
+        match self {
+            TiffBuf::Gray(buf) => TiffPix::Gray(buf.color_get(x, y)),
+            TiffBuf::Gray16Be(buf) => TiffPix::Gray16Be(buf.color_get(x, y)),
+            TiffBuf::Gray16Le(buf) => TiffPix::Gray16Le(buf.color_get(x, y)),
+            TiffBuf::Rgb(buf) => TiffPix::Rgb(buf.color_get(x, y)),
+            TiffBuf::Rgb48Be(buf) => TiffPix::Rgb48Be(buf.color_get(x, y)),
+            TiffBuf::Rgb48Le(buf) => TiffPix::Rgb48Le(buf.color_get(x, y)),
+            TiffBuf::Nrgba(buf) => TiffPix::Nrgba(buf.color_get(x, y)),
+            TiffBuf::Nrgba64Be(buf) => TiffPix::Nrgba64Be(buf.color_get(x, y)),
+            TiffBuf::Nrgba64Le(buf) => TiffPix::Nrgba64Le(buf.color_get(x, y)),
+        }
+    }
+}
+
+impl ImageMut for TiffBuf {
+    type Pixel = TiffPix;
+
+    fn color_set<C, ColorSpecialized>(&mut self, x: usize, y: usize, color: C)
+    where
+        C: ConvertInto<TiffPix, ColorSpecialized> + Color,
+    {
+        let color = <_ as ConvertInto<TiffPix, ColorSpecialized>>::convert_into(color);
+        match (self, color) {
+            (TiffBuf::Gray(buf), TiffPix::Gray(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Gray(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Gray16Be(buf), TiffPix::Gray16Be(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Gray16Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Gray16Le(buf), TiffPix::Gray16Le(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Gray16Le(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Rgb(buf), TiffPix::Rgb(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Rgb(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Rgb48Be(buf), TiffPix::Rgb48Be(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Rgb48Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Rgb48Le(buf), TiffPix::Rgb48Le(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Rgb48Le(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Nrgba(buf), TiffPix::Nrgba(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Nrgba(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Nrgba64Be(buf), TiffPix::Nrgba64Be(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Nrgba64Be(buf), c) => buf.color_set_generic(x, y, c),
+
+            (TiffBuf::Nrgba64Le(buf), TiffPix::Nrgba64Le(c)) => buf.pixel_set(x, y, c),
+            (TiffBuf::Nrgba64Le(buf), c) => buf.color_set_generic(x, y, c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum TiffPix {
+    Gray(Gray),
+    Gray16Be(Gray16Be),
+    Gray16Le(Gray16Le),
+    Rgb(Rgb),
+    Rgb48Be(Rgb48Be),
+    Rgb48Le(Rgb48Le),
+    Nrgba(Nrgba),
+    Nrgba64Be(Nrgba64Be),
+    Nrgba64Le(Nrgba64Le),
+}
+
+impl Color for TiffPix {
+    fn as_rgba(&self) -> (u32, u32, u32, u32) {
+        match self {
+            TiffPix::Gray(c) => c.as_rgba(),
+            TiffPix::Gray16Be(c) => c.as_rgba(),
+            TiffPix::Gray16Le(c) => c.as_rgba(),
+            TiffPix::Rgb(c) => c.as_rgba(),
+            TiffPix::Rgb48Be(c) => c.as_rgba(),
+            TiffPix::Rgb48Le(c) => c.as_rgba(),
+            TiffPix::Nrgba(c) => c.as_rgba(),
+            TiffPix::Nrgba64Be(c) => c.as_rgba(),
+            TiffPix::Nrgba64Le(c) => c.as_rgba(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for TiffBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            TiffBuf::Gray(buf) => buf.as_ref(),
+            TiffBuf::Gray16Be(buf) => buf.as_ref(),
+            TiffBuf::Gray16Le(buf) => buf.as_ref(),
+            TiffBuf::Rgb(buf) => buf.as_ref(),
+            TiffBuf::Rgb48Be(buf) => buf.as_ref(),
+            TiffBuf::Rgb48Le(buf) => buf.as_ref(),
+            TiffBuf::Nrgba(buf) => buf.as_ref(),
+            TiffBuf::Nrgba64Be(buf) => buf.as_ref(),
+            TiffBuf::Nrgba64Le(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl AsMut<[u8]> for TiffBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self {
+            TiffBuf::Gray(buf) => buf.as_mut(),
+            TiffBuf::Gray16Be(buf) => buf.as_mut(),
+            TiffBuf::Gray16Le(buf) => buf.as_mut(),
+            TiffBuf::Rgb(buf) => buf.as_mut(),
+            TiffBuf::Rgb48Be(buf) => buf.as_mut(),
+            TiffBuf::Rgb48Le(buf) => buf.as_mut(),
+            TiffBuf::Nrgba(buf) => buf.as_mut(),
+            TiffBuf::Nrgba64Be(buf) => buf.as_mut(),
+            TiffBuf::Nrgba64Le(buf) => buf.as_mut(),
+        }
+    }
+}
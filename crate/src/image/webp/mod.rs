@@ -1,16 +1,24 @@
 mod webp_buf;
 
+use std::convert::TryFrom;
 use std::io::{self, Read, Write};
+use std::time::Duration;
 
 use either::Either;
 pub use webp::WebPConfig as WebpEncodeOptions;
 
 pub use self::webp_buf::*;
 use crate::buffer::RawPixBuf;
+use crate::color::convert::ConvertInto;
 use crate::color::{Nrgba, Rgb};
-use crate::image::Dimensions;
+use crate::image::animation::{Animation, AnimationFrames, Disposal, Frame, Frames, LoopCount};
+use crate::image::{ColorType, Dimensions, Format, Image, ImageInfo, ImageMut, Signature};
 use crate::impl_format;
-use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions};
+use crate::serialize::{
+    Decode, DecodeFrames, DecodeOptions, Encode, EncodeFrames, EncodeOptions, EncodeSpecialized,
+    Probe,
+};
+use crate::specialized;
 
 pub struct Webp;
 
@@ -18,6 +26,10 @@ impl_format! {
     name: Webp,
     id: "feim:webp",
     magic: b"RIFF????WEBPVP8",
+    signatures: &[
+        // the file size is skipped
+        Signature::new(b"RIFF\0\0\0\0WEBPVP8").masked(b"\xff\xff\xff\xff\0\0\0\0"),
+    ],
 }
 
 impl DecodeOptions for Webp {
@@ -31,7 +43,250 @@ impl Decode<Either<RgbWebpBuf, NrgbaWebpBuf>> for Webp {
         webp::Decoder::new(&buf[..])
             .decode()
             .map(WebpFeimExt::into_feim)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Webp decode failed"))
+            .ok_or_else(|| io::Error::other("Webp decode failed"))
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// skips the rest of a chunk, along with its padding byte
+fn skip_chunk<R: Read>(r: &mut R, size: u32, read: u32) -> io::Result<u64> {
+    let padded = size as u64 + (size & 1) as u64;
+    let left = padded.saturating_sub(read as u64);
+    io::copy(&mut r.take(left), &mut io::sink())?;
+    Ok(padded)
+}
+
+impl Probe for Webp {
+    /// Reads the first chunk of the image. The chunks of
+    /// animations are walked over to count their frames,
+    /// without decoding them.
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let mut header = [0; 20];
+        r.read_exact(&mut header)?;
+        if !Webp.is_valid_magic(&header) {
+            return Err(invalid("Invalid webp header."));
+        }
+        let le_u32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let le_u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]);
+        let riff_size = le_u32(&header[4..]) as u64;
+        let chunk_size = le_u32(&header[16..]);
+
+        match &header[12..16] {
+            b"VP8 " => {
+                // the frame tag, followed by the start code
+                let mut frame = [0; 10];
+                r.read_exact(&mut frame)?;
+                if frame[3..6] != [0x9d, 0x01, 0x2a] {
+                    return Err(invalid("Invalid webp frame header"));
+                }
+                let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3fff;
+                let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3fff;
+                Ok(ImageInfo::new(
+                    width as usize,
+                    height as usize,
+                    ColorType::Rgb,
+                    8,
+                ))
+            }
+            b"VP8L" => {
+                let mut frame = [0; 5];
+                r.read_exact(&mut frame)?;
+                if frame[0] != 0x2f {
+                    return Err(invalid("Invalid webp lossless signature"));
+                }
+                let bits = le_u32(&frame[1..]);
+                let width = (bits & 0x3fff) + 1;
+                let height = ((bits >> 14) & 0x3fff) + 1;
+                let color_type = if bits & (1 << 28) != 0 {
+                    ColorType::Rgba
+                } else {
+                    ColorType::Rgb
+                };
+                Ok(ImageInfo::new(
+                    width as usize,
+                    height as usize,
+                    color_type,
+                    8,
+                ))
+            }
+            b"VP8X" => {
+                let mut ext = [0; 10];
+                r.read_exact(&mut ext)?;
+                let width = le_u24(&ext[4..]) + 1;
+                let height = le_u24(&ext[7..]) + 1;
+                let mut info = ImageInfo::new(width as usize, height as usize, ColorType::Rgb, 8);
+                if ext[0] & 0x10 != 0 {
+                    info.color_type = ColorType::Rgba;
+                    info.has_alpha = true;
+                }
+                if ext[0] & 0x02 == 0 {
+                    return Ok(info);
+                }
+
+                // the riff size counts the bytes after itself
+                let mut pos = 12 + 8 + skip_chunk(&mut r, chunk_size, 10)?;
+                let mut frames = 0;
+                while pos < riff_size + 8 {
+                    let mut chunk = [0; 8];
+                    r.read_exact(&mut chunk)?;
+                    if &chunk[..4] == b"ANMF" {
+                        frames += 1;
+                    }
+                    pos += 8 + skip_chunk(&mut r, le_u32(&chunk[4..]), 0)?;
+                }
+                info.frame_count = Some(frames);
+                Ok(info)
+            }
+            _ => Err(invalid("Unsupported webp chunk")),
+        }
+    }
+}
+
+impl DecodeFrames<RawPixBuf<Nrgba>> for Webp {
+    type Frames<R: Read> = AnimationFrames<RawPixBuf<Nrgba>>;
+
+    fn decode_frames<R: Read>(mut r: R, _opt: ()) -> io::Result<AnimationFrames<RawPixBuf<Nrgba>>> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        let anim = webp::AnimDecoder::new(&buf[..])
+            .decode()
+            .map_err(|e| io::Error::other(format!("Webp decoding error: {e}")))?;
+
+        let mut frames = Vec::with_capacity(anim.len());
+        let mut width = 0;
+        let mut height = 0;
+        let mut start = 0;
+
+        // frames are full canvases, and their timestamps
+        // mark the end of their display time
+        for frame in anim.into_iter() {
+            width = frame.width() as usize;
+            height = frame.height() as usize;
+            let end = frame.get_time_ms();
+            let buffer = RawPixBuf::from_vec(width, height, frame.get_image().to_vec())
+                .map_err(|_| invalid("Invalid webp frame data"))?;
+            frames.push(Frame {
+                buffer,
+                delay: Duration::from_millis(end.saturating_sub(start).max(0) as u64),
+                left: 0,
+                top: 0,
+                disposal: Disposal::Background,
+            });
+            start = end;
+        }
+
+        let loop_count = match anim.loop_count {
+            0 => LoopCount::Infinite,
+            n => LoopCount::Finite(n),
+        };
+        let anim = Animation {
+            width,
+            height,
+            loop_count,
+            frames,
+        };
+        Ok(anim.into_frames())
+    }
+}
+
+fn encode_frames_impl<W, F>(mut w: W, opts: WebpEncodeOptions, frames: F) -> io::Result<()>
+where
+    W: Write,
+    F: Frames,
+    F::Buffer: Image + Dimensions,
+{
+    let width = u32::try_from(frames.width()).map_err(|_| invalid("Webp image too wide"))?;
+    let height = u32::try_from(frames.height()).map_err(|_| invalid("Webp image too tall"))?;
+    let loop_count = match frames.loop_count() {
+        LoopCount::Finite(n) => i32::try_from(n.max(1)).unwrap_or(i32::MAX),
+        LoopCount::Infinite => 0,
+    };
+
+    let frames = frames.composite()?.collect::<io::Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        return Err(invalid("Webp animations need at least one frame"));
+    }
+
+    let mut encoder = webp::AnimEncoder::new(width, height, &opts);
+    encoder.set_loop_count(loop_count);
+
+    let mut timestamp = 0i32;
+    for frame in frames.iter() {
+        encoder.add_frame(webp::AnimFrame::from_rgba(
+            frame.buffer.as_ref(),
+            width,
+            height,
+            timestamp,
+        ));
+        let delay = i32::try_from(frame.delay.as_millis()).unwrap_or(i32::MAX);
+        timestamp = timestamp.saturating_add(delay);
+    }
+
+    let mut encoded = encoder
+        .try_encode()
+        .map_err(|e| io::Error::other(format!("Webp encoding error: {e:?}")))?
+        .to_vec();
+    set_end_time(&mut encoded, timestamp as u32);
+    w.write_all(&encoded)
+}
+
+/// The encoder guesses the duration of the last frame from
+/// the previous ones, so fix it up in the encoded stream,
+/// such that the animation ends at `end` milliseconds.
+fn set_end_time(data: &mut [u8], end: u32) {
+    let mut offset = 12;
+    let mut elapsed = 0u32;
+    let mut last = None;
+
+    while offset + 8 <= data.len() {
+        let size = u32::from_le_bytes([
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ]) as usize;
+        let payload = offset + 8;
+        if &data[offset..offset + 4] == b"ANMF" && size >= 16 && payload + 16 <= data.len() {
+            if let Some(duration) = last.map(|at: usize| read_u24(&data[at..])) {
+                elapsed = elapsed.saturating_add(duration);
+            }
+            last = Some(payload + 12);
+        }
+        offset = payload.saturating_add(size + (size & 1));
+    }
+
+    if let Some(at) = last {
+        let duration = end.saturating_sub(elapsed).min(0xff_ffff);
+        data[at..at + 3].copy_from_slice(&duration.to_le_bytes()[..3]);
+    }
+}
+
+fn read_u24(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], 0])
+}
+
+impl EncodeFrames<RawPixBuf<Nrgba>> for Webp {
+    #[inline]
+    fn encode_frames<W, F>(w: W, opts: WebpEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = RawPixBuf<Nrgba>>,
+    {
+        encode_frames_impl(w, opts, frames)
+    }
+}
+
+impl<I: Image + Dimensions> EncodeFrames<I, specialized::No> for Webp {
+    #[inline]
+    fn encode_frames<W, F>(w: W, opts: WebpEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = I>,
+    {
+        encode_frames_impl(w, opts, frames)
     }
 }
 
@@ -64,9 +319,7 @@ macro_rules! impl_encode {
                 let (width, height) = image.dimensions();
                 let encoded = webp::Encoder::new(buf, $pixel_layout, width as u32, height as u32)
                     .encode_advanced(&opts)
-                    .map_err(|e| {
-                        io::Error::new(io::ErrorKind::Other, format!("Webp encoding error: {e:?}"))
-                    })?;
+                    .map_err(|e| io::Error::other(format!("Webp encoding error: {e:?}")))?;
                 w.write_all(&encoded)
             }
         }
@@ -77,3 +330,71 @@ impl_encode!(RawPixBuf<Rgb>, webp::PixelLayout::Rgb);
 impl_encode!(RgbWebpBuf, webp::PixelLayout::Rgb);
 impl_encode!(RawPixBuf<Nrgba>, webp::PixelLayout::Rgba);
 impl_encode!(NrgbaWebpBuf, webp::PixelLayout::Rgba);
+
+fn to_nrgba<I: Image + Dimensions>(buf: &I) -> RawPixBuf<Nrgba> {
+    let (width, height) = buf.dimensions();
+    let mut new_buf = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let c = buf.color_get(x, y);
+            let c: Nrgba = c.convert_into();
+            new_buf.pixel_set(x, y, c);
+        }
+    }
+    new_buf
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Webp {
+    fn encode<W: Write>(w: W, opts: WebpEncodeOptions, buf: &I) -> io::Result<()> {
+        Webp::encode_specialized(w, opts, &to_nrgba(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::EncodeFramesSpecialized;
+
+    #[test]
+    fn animation_round_trip() {
+        let (w, h) = (4, 3);
+        let delays = [100, 250, 70];
+        let frames: Vec<_> = delays
+            .iter()
+            .enumerate()
+            .map(|(i, &delay)| {
+                let data = (0..w * h * 4)
+                    .map(|j| {
+                        if j % 4 == 3 {
+                            0xff
+                        } else {
+                            (j * 29 + i * 71) as u8
+                        }
+                    })
+                    .collect();
+                let buffer = RawPixBuf::from_vec(w, h, data).unwrap();
+                Frame::new(buffer, Duration::from_millis(delay))
+            })
+            .collect();
+        let animation = Animation {
+            width: w,
+            height: h,
+            loop_count: LoopCount::Finite(3),
+            frames: frames.clone(),
+        };
+
+        let mut out = Vec::new();
+        Webp::encode_frames_specialized(&mut out, default_config(), animation.into_frames())
+            .unwrap();
+        let decoded: AnimationFrames<RawPixBuf<Nrgba>> = Webp::decode_frames(&out[..], ()).unwrap();
+        assert_eq!(decoded.dimensions(), (w, h));
+        assert_eq!(decoded.loop_count(), LoopCount::Finite(3));
+        let decoded: Vec<_> = decoded.collect::<io::Result<_>>().unwrap();
+        assert_eq!(decoded.len(), frames.len());
+        for (a, b) in frames.iter().zip(decoded.iter()) {
+            // the last delay is only kept by patching the stream
+            assert_eq!(a.delay, b.delay);
+            assert_eq!(a.buffer.as_ref(), b.buffer.as_ref());
+        }
+    }
+}
@@ -0,0 +1,413 @@
+mod x11_colors;
+mod xbm;
+
+pub use xbm::*;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::buffer::{AsTypedMut, PalettedBuf, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::Nrgba;
+use crate::image::{ColorType, Dimensions, Format, Image, ImageInfo};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, Probe};
+use crate::specialized;
+
+// the characters pixels are encoded with, in the
+// same order as libXpm, leaving out quotes, escapes
+// and question marks, which could form trigraphs
+const PRINTABLE: &[u8; 92] =
+    b" .XoO+@#$%&*=-;:>,<1234567890qwertyuipasdfghjklzxcvbnmMNBVCZASDFGHJKLPIUYTREWQ!~^/()_`'][{}|";
+
+// the keys of each color context, from the most preferred
+const COLOR_KEYS: [&str; 4] = ["c", "g", "g4", "m"];
+const SYMBOLIC_KEY: &str = "s";
+
+const TRANSPARENT: Nrgba = Nrgba {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0,
+};
+
+/// X PixMap images, as C source code. Only version 3 of
+/// the format is supported.
+pub struct Xpm;
+
+impl_format! {
+    name: Xpm,
+    id: "feim:xpm",
+    magic: b"/* XPM */",
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct XpmEncodeOptions {
+    /// The name of the C variable holding the image.
+    pub name: &'static str,
+}
+
+impl Default for XpmEncodeOptions {
+    fn default() -> Self {
+        Self { name: "image" }
+    }
+}
+
+impl DecodeOptions for Xpm {
+    type Options = ();
+}
+
+impl EncodeOptions for Xpm {
+    type Options = XpmEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// images are declared as C variables
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = chars.next();
+    first.is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// strips the comments from C source code, replacing
+// each of them with a space
+fn strip_comments(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let rest = &data[i..];
+        if rest.starts_with(b"/*") {
+            i += rest[2..]
+                .windows(2)
+                .position(|w| w == b"*/")
+                .map_or(rest.len(), |end| end + 4);
+            out.push(b' ');
+        } else if rest.starts_with(b"//") {
+            i += rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            out.push(b' ');
+        } else if rest[0] == b'"' {
+            // comment markers may show up inside strings
+            let end = rest[1..]
+                .iter()
+                .position(|&b| b == b'"')
+                .map_or(rest.len(), |end| end + 2);
+            out.extend_from_slice(&rest[..end]);
+            i += end;
+        } else {
+            out.push(rest[0]);
+            i += 1;
+        }
+    }
+    out
+}
+
+// the string literals of C source code, without comments
+fn strings(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let mut strings = Vec::new();
+    let mut rest = data;
+    while let Some(start) = rest.iter().position(|&b| b == b'"') {
+        rest = &rest[start + 1..];
+        let end = rest
+            .iter()
+            .position(|&b| b == b'"')
+            .ok_or_else(|| invalid("Unterminated xpm string"))?;
+        strings.push(&rest[..end]);
+        rest = &rest[end + 1..];
+    }
+    Ok(strings)
+}
+
+fn parse_values(line: &[u8]) -> io::Result<Vec<usize>> {
+    std::str::from_utf8(line)
+        .map_err(|_| invalid("Invalid xpm values"))?
+        .split_whitespace()
+        .take(4)
+        .map(|v| v.parse().map_err(|_| invalid("Invalid xpm values")))
+        .collect()
+}
+
+// parses a color such as "#ff8000", "red" or "None"
+fn parse_color(value: &str) -> io::Result<Nrgba> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(TRANSPARENT);
+    }
+    let [r, g, b] = match value.strip_prefix('#') {
+        Some(hex) => {
+            let digits = hex.len() / 3;
+            let valid = hex.len() % 3 == 0 && (1..=4).contains(&digits);
+            if !valid || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid("Invalid xpm color"));
+            }
+            // channels of any depth are scaled to 8 bits
+            let max = (1u32 << (digits * 4)) - 1;
+            let channel = |i: usize| {
+                let v = u32::from_str_radix(&hex[i * digits..(i + 1) * digits], 16).unwrap();
+                ((v * 255 + max / 2) / max) as u8
+            };
+            [channel(0), channel(1), channel(2)]
+        }
+        None => x11_colors::lookup(value).ok_or_else(|| invalid("Unknown xpm color name"))?,
+    };
+    Ok(Nrgba { r, g, b, a: 0xff })
+}
+
+// parses the colors of a palette entry, such as "c red m black",
+// preferring the colors of the richest visual
+fn parse_entry(entry: &[u8]) -> io::Result<Nrgba> {
+    let entry = std::str::from_utf8(entry).map_err(|_| invalid("Invalid xpm color"))?;
+    let mut contexts: Vec<(&str, Vec<&str>)> = Vec::new();
+    for word in entry.split_whitespace() {
+        if COLOR_KEYS.contains(&word) || word == SYMBOLIC_KEY {
+            contexts.push((word, Vec::new()));
+        } else if let Some((_, value)) = contexts.last_mut() {
+            // color names may contain spaces
+            value.push(word);
+        } else {
+            return Err(invalid("Invalid xpm color"));
+        }
+    }
+    let value = COLOR_KEYS
+        .iter()
+        .find_map(|key| contexts.iter().find(|(k, _)| k == key))
+        .ok_or_else(|| invalid("Xpm color has no visual"))?;
+    parse_color(&value.1.join(" "))
+}
+
+// an image, with one palette index per pixel
+struct Parsed {
+    width: usize,
+    height: usize,
+    palette: Vec<Nrgba>,
+    indices: Vec<usize>,
+}
+
+// the values and colors at the start of an image
+struct Header<'a> {
+    width: usize,
+    height: usize,
+    cpp: usize,
+    keys: HashMap<&'a [u8], usize>,
+    palette: Vec<Nrgba>,
+}
+
+fn parse_header<'a, I>(strings: &mut I) -> io::Result<Header<'a>>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let values = parse_values(strings.next().unwrap_or_default())?;
+    let (width, height, colors, cpp) = match values[..] {
+        [width, height, colors, cpp, ..] => (width, height, colors, cpp),
+        _ => return Err(invalid("Invalid xpm values")),
+    };
+    if cpp == 0 || colors == 0 {
+        return Err(invalid("Invalid xpm values"));
+    }
+
+    let mut keys = HashMap::new();
+    let mut palette = Vec::new();
+    for _ in 0..colors {
+        let line = strings
+            .next()
+            .ok_or_else(|| invalid("Truncated xpm colors"))?;
+        if line.len() < cpp {
+            return Err(invalid("Invalid xpm color"));
+        }
+        let (key, entry) = line.split_at(cpp);
+        keys.insert(key, palette.len());
+        palette.push(parse_entry(entry)?);
+    }
+
+    Ok(Header {
+        width,
+        height,
+        cpp,
+        keys,
+        palette,
+    })
+}
+
+fn parse<R: Read>(mut r: R) -> io::Result<Parsed> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if !Xpm.is_valid_magic(&data) {
+        return Err(invalid("Invalid xpm header."));
+    }
+    let data = strip_comments(&data);
+    let strings = strings(&data)?;
+    let mut strings = strings.iter().copied();
+    let Header {
+        width,
+        height,
+        cpp,
+        keys,
+        palette,
+    } = parse_header(&mut strings)?;
+
+    let row_len = width
+        .checked_mul(cpp)
+        .ok_or_else(|| invalid("Xpm image too large"))?;
+    let mut indices = Vec::new();
+    for _ in 0..height {
+        let row = strings
+            .next()
+            .ok_or_else(|| invalid("Truncated xpm pixels"))?;
+        let row = row
+            .get(..row_len)
+            .ok_or_else(|| invalid("Truncated xpm row"))?;
+        for key in row.chunks_exact(cpp) {
+            let index = keys
+                .get(key)
+                .ok_or_else(|| invalid("Undefined xpm color"))?;
+            indices.push(*index);
+        }
+    }
+
+    Ok(Parsed {
+        width,
+        height,
+        palette,
+        indices,
+    })
+}
+
+impl Decode<RawPixBuf<Nrgba>> for Xpm {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<RawPixBuf<Nrgba>> {
+        let image = parse(r)?;
+        let mut buf = RawPixBuf::new(image.width, image.height);
+        for (pixel, &index) in buf.as_typed_mut().iter_mut().zip(&image.indices) {
+            *pixel = image.palette[index];
+        }
+        Ok(buf)
+    }
+}
+
+impl Decode<PalettedBuf<Nrgba>> for Xpm {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<PalettedBuf<Nrgba>> {
+        let image = parse(r)?;
+        if image.palette.len() > 256 {
+            return Err(invalid("Too many xpm colors for a palette"));
+        }
+        let indices = image.indices.iter().map(|&i| i as u8).collect();
+        PalettedBuf::from_indices(image.width, image.height, image.palette, indices)
+            .map_err(|_| invalid("Invalid xpm pixels"))
+    }
+}
+
+impl Probe for Xpm {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        // strings can't span lines, so read whole lines until the
+        // colors are all there, reading twice as much on each try
+        let mut r = BufReader::new(r);
+        let mut data = Vec::new();
+        let mut want = 4096;
+        loop {
+            let mut eof = false;
+            while data.len() < want && !eof {
+                eof = r.read_until(b'\n', &mut data)? == 0;
+            }
+            if !Xpm.is_valid_magic(&data) {
+                return Err(invalid("Invalid xpm header."));
+            }
+            let stripped = strip_comments(&data);
+            let header = strings(&stripped).and_then(|strings| {
+                let header = parse_header(&mut strings.iter().copied())?;
+                Ok((header.width, header.height, header.palette))
+            });
+            match header {
+                Ok((width, height, palette)) => {
+                    let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(1);
+                    let mut info = ImageInfo::new(width, height, ColorType::Indexed, bits as u8);
+                    info.has_alpha = palette.iter().any(|c| c.a != 0xff);
+                    return Ok(info);
+                }
+                Err(e) if eof => return Err(e),
+                Err(_) => want *= 2,
+            }
+        }
+    }
+}
+
+// the characters of a palette entry
+fn key(mut index: usize, cpp: usize) -> Vec<u8> {
+    (0..cpp)
+        .map(|_| {
+            let c = PRINTABLE[index % PRINTABLE.len()];
+            index /= PRINTABLE.len();
+            c
+        })
+        .collect()
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: XpmEncodeOptions,
+    (width, height): (usize, usize),
+    palette: &[Nrgba],
+    indices: &[usize],
+) -> io::Result<()> {
+    if !is_identifier(opts.name) {
+        return Err(invalid("Invalid xpm variable name"));
+    }
+    let mut cpp = 1;
+    while PRINTABLE.len().pow(cpp as u32) < palette.len() {
+        cpp += 1;
+    }
+    let keys: Vec<Vec<u8>> = (0..palette.len()).map(|i| key(i, cpp)).collect();
+
+    writeln!(w, "/* XPM */")?;
+    writeln!(w, "static char *{}[] = {{", opts.name)?;
+    writeln!(w, "/* columns rows colors chars-per-pixel */")?;
+    write!(w, "\"{} {} {} {}\"", width, height, palette.len(), cpp)?;
+    for (key, c) in keys.iter().zip(palette) {
+        w.write_all(b",\n\"")?;
+        w.write_all(key)?;
+        // pixels are either opaque or fully transparent
+        if c.a < 0x80 {
+            write!(w, " c None\"")?;
+        } else {
+            write!(w, " c #{:02X}{:02X}{:02X}\"", c.r, c.g, c.b)?;
+        }
+    }
+    writeln!(w, ",\n/* pixels */")?;
+    for y in 0..height {
+        w.write_all(b"\"")?;
+        for &index in &indices[y * width..(y + 1) * width] {
+            w.write_all(&keys[index])?;
+        }
+        w.write_all(if y + 1 < height { b"\",\n" } else { b"\"\n" })?;
+    }
+    writeln!(w, "}};")
+}
+
+impl Encode<PalettedBuf<Nrgba>> for Xpm {
+    fn encode<W: Write>(w: W, opts: XpmEncodeOptions, buf: &PalettedBuf<Nrgba>) -> io::Result<()> {
+        let indices: Vec<usize> = buf.indices().iter().map(|&i| i as usize).collect();
+        encode_impl(w, opts, buf.dimensions(), buf.palette(), &indices)
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Xpm {
+    fn encode<W: Write>(w: W, opts: XpmEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut palette = Vec::new();
+        let mut seen = HashMap::new();
+        let mut indices = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let c: Nrgba = buf.color_get(x, y).convert_into();
+                // every transparent pixel shares the same entry
+                let c = if c.a < 0x80 { TRANSPARENT } else { c };
+                let index = *seen.entry((c.r, c.g, c.b, c.a)).or_insert_with(|| {
+                    palette.push(c);
+                    palette.len() - 1
+                });
+                indices.push(index);
+            }
+        }
+        encode_impl(w, opts, (width, height), &palette, &indices)
+    }
+}
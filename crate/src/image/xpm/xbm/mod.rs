@@ -0,0 +1,247 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use super::{invalid, is_identifier, strip_comments};
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::Gray;
+use crate::image::{ColorType, Confidence, Dimensions, Format, Image, ImageInfo};
+use crate::impl_format;
+use crate::serialize::{Decode, DecodeOptions, Encode, EncodeOptions, Probe};
+use crate::specialized;
+
+// the number of values written on each line
+const VALUES_PER_LINE: usize = 12;
+
+const BLACK: Gray = Gray { y: 0x00 };
+const WHITE: Gray = Gray { y: 0xff };
+
+/// X BitMap images, as C source code. Set bits are
+/// black, and clear bits are white.
+pub struct Xbm;
+
+impl_format! {
+    name: Xbm,
+    id: "feim:xbm",
+    magic: b"#define ",
+    detect: detect,
+}
+
+// the first definition is the width of the image
+fn detect(data: &[u8]) -> Confidence {
+    let prefix = Xbm.magic();
+    let rest = match data.strip_prefix(prefix) {
+        Some(rest) => rest,
+        None if prefix.starts_with(data) => return Confidence::Incomplete,
+        None => return Confidence::No,
+    };
+    let start = match rest.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(start) => start,
+        None => return Confidence::Incomplete,
+    };
+    let rest = &rest[start..];
+    let end = match rest
+        .iter()
+        .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+    {
+        Some(end) => end,
+        None => return Confidence::Incomplete,
+    };
+    if rest[..end].ends_with(b"_width") {
+        Confidence::High
+    } else {
+        Confidence::No
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct XbmEncodeOptions {
+    /// The prefix of the C definitions of the image.
+    pub name: &'static str,
+    /// The hotspot of a cursor, relative to the top
+    /// left corner of the image.
+    pub hotspot: Option<(usize, usize)>,
+}
+
+impl Default for XbmEncodeOptions {
+    fn default() -> Self {
+        Self {
+            name: "image",
+            hotspot: None,
+        }
+    }
+}
+
+impl DecodeOptions for Xbm {
+    type Options = ();
+}
+
+impl EncodeOptions for Xbm {
+    type Options = XbmEncodeOptions;
+}
+
+fn parse_value(value: &str) -> io::Result<usize> {
+    let value = value.trim();
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| invalid("Invalid xbm value"))
+}
+
+// parses the definitions before the bits of an image, returning
+// its dimensions, and whether the bits are stored in words
+fn parse_header(header: &str) -> io::Result<(usize, usize, bool)> {
+    let (mut width, mut height) = (None, None);
+    let mut words = false;
+    for line in header.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("#define") => {
+                let name = fields.next().unwrap_or_default();
+                let value = fields.next().unwrap_or_default();
+                if name.ends_with("_width") {
+                    width = Some(parse_value(value)?);
+                } else if name.ends_with("_height") {
+                    height = Some(parse_value(value)?);
+                }
+            }
+            // the older X10 format stores bits in 16 bit words
+            Some(first) => words |= first == "short" || fields.any(|f| f == "short"),
+            None => (),
+        }
+    }
+    match (width, height) {
+        (Some(width), Some(height)) => Ok((width, height, words)),
+        _ => Err(invalid("Missing xbm dimensions")),
+    }
+}
+
+impl Probe for Xbm {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo> {
+        // read up to the start of the bits, which may take a few
+        // tries when braces show up inside of comments
+        let mut r = BufReader::new(r);
+        let mut data = Vec::new();
+        loop {
+            let eof = r.read_until(b'{', &mut data)? == 0;
+            if !Xbm.is_valid_magic(&data) {
+                return Err(invalid("Invalid xbm header."));
+            }
+            let stripped = strip_comments(&data);
+            let text = std::str::from_utf8(&stripped).map_err(|_| invalid("Invalid xbm data"))?;
+            if let Some(start) = text.find('{') {
+                let (width, height, _) = parse_header(&text[..start])?;
+                return Ok(ImageInfo::new(width, height, ColorType::Gray, 1));
+            }
+            if eof {
+                return Err(invalid("Missing xbm bits"));
+            }
+        }
+    }
+}
+
+impl Decode<RawPixBuf<Gray>> for Xbm {
+    fn decode<R: Read>(mut r: R, _opt: ()) -> io::Result<RawPixBuf<Gray>> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        if !Xbm.is_valid_magic(&data) {
+            return Err(invalid("Invalid xbm header."));
+        }
+        let data = strip_comments(&data);
+        let text = std::str::from_utf8(&data).map_err(|_| invalid("Invalid xbm data"))?;
+
+        let start = text.find('{').ok_or_else(|| invalid("Missing xbm bits"))?;
+        let end = text[start..]
+            .find('}')
+            .ok_or_else(|| invalid("Unterminated xbm bits"))?;
+        let (header, bits) = (&text[..start], &text[start + 1..start + end]);
+        let (width, height, words) = parse_header(header)?;
+
+        let unit_bits = if words { 16 } else { 8 };
+        let row_units = width.div_ceil(unit_bits);
+        let values = bits
+            .split(',')
+            .filter(|v| !v.trim().is_empty())
+            .map(parse_value)
+            .collect::<io::Result<Vec<usize>>>()?;
+        let total = row_units
+            .checked_mul(height)
+            .ok_or_else(|| invalid("Xbm image too large"))?;
+        if values.len() < total {
+            return Err(invalid("Truncated xbm bits"));
+        }
+
+        let mut buf = RawPixBuf::new(width, height);
+        let pixels = buf.as_typed_mut();
+        for (y, row) in values.chunks(row_units.max(1)).take(height).enumerate() {
+            for x in 0..width {
+                // bits are stored from the least significant one
+                let set = (row[x / unit_bits] >> (x % unit_bits)) & 1 == 1;
+                pixels[y * width + x] = if set { BLACK } else { WHITE };
+            }
+        }
+        Ok(buf)
+    }
+}
+
+fn encode_impl<W: Write>(
+    mut w: W,
+    opts: XbmEncodeOptions,
+    (width, height): (usize, usize),
+    pixels: &[Gray],
+) -> io::Result<()> {
+    if !is_identifier(opts.name) {
+        return Err(invalid("Invalid xbm variable name"));
+    }
+    writeln!(w, "#define {}_width {}", opts.name, width)?;
+    writeln!(w, "#define {}_height {}", opts.name, height)?;
+    if let Some((x, y)) = opts.hotspot {
+        writeln!(w, "#define {}_x_hot {}", opts.name, x)?;
+        writeln!(w, "#define {}_y_hot {}", opts.name, y)?;
+    }
+    write!(w, "static unsigned char {}_bits[] = {{", opts.name)?;
+
+    let mut bytes = Vec::with_capacity(width.div_ceil(8) * height);
+    for row in pixels.chunks_exact(width.max(1)).take(height) {
+        for chunk in row.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.y < 0x80)
+                .fold(0u8, |byte, (bit, _)| byte | (1 << bit));
+            bytes.push(byte);
+        }
+    }
+    for (i, byte) in bytes.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        if i % VALUES_PER_LINE == 0 {
+            write!(w, "{}\n   0x{:02x}", separator, byte)?;
+        } else {
+            write!(w, "{} 0x{:02x}", separator, byte)?;
+        }
+    }
+    writeln!(w, "}};")
+}
+
+impl Encode<RawPixBuf<Gray>> for Xbm {
+    fn encode<W: Write>(w: W, opts: XbmEncodeOptions, buf: &RawPixBuf<Gray>) -> io::Result<()> {
+        encode_impl(w, opts, buf.dimensions(), buf.as_typed())
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Xbm {
+    fn encode<W: Write>(w: W, opts: XbmEncodeOptions, buf: &I) -> io::Result<()> {
+        let (width, height) = buf.dimensions();
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let c: Gray = buf.color_get(x, y).convert_into();
+                pixels.push(c);
+            }
+        }
+        encode_impl(w, opts, (width, height), &pixels)
+    }
+}
@@ -0,0 +1,536 @@
+use std::borrow::Borrow;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::time::Duration;
+
+use crate::buffer::{AsTyped, AsTypedMut, RawPixBuf};
+use crate::color::convert::ConvertInto;
+use crate::color::Rgb;
+use crate::image::animation::{Frame, Frames, LoopCount};
+use crate::image::{ColorType, Dimensions, Image, ImageInfo, ImageMut};
+use crate::impl_format;
+use crate::raw::{sample, YuvMatrix, YuvOptions};
+use crate::serialize::{
+    Decode, DecodeFrames, DecodeOptions, Encode, EncodeFrames, EncodeOptions, Probe,
+};
+use crate::specialized;
+
+const MAGIC: &[u8] = b"YUV4MPEG2 ";
+const FRAME_MAGIC: &[u8] = b"FRAME";
+
+// longer header lines are most likely not y4m streams
+const MAX_LINE: usize = 4096;
+
+// frames are shown at this rate, when no other is known
+const DEFAULT_FRAME_RATE: (u32, u32) = (25, 1);
+
+/// YUV4MPEG2 streams of uncompressed video frames, as read
+/// and written by most video encoders. Only 8 bit samples
+/// are supported, and pixels are converted with BT.601.
+pub struct Y4m;
+
+impl_format! {
+    name: Y4m,
+    id: "feim:y4m",
+    magic: MAGIC,
+}
+
+/// The resolution of the chroma planes of a stream.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Y4mChroma {
+    /// Half the resolution, horizontally and vertically.
+    #[default]
+    C420,
+    /// Half the horizontal resolution.
+    C422,
+    /// The full resolution.
+    C444,
+    /// No chroma planes, only luma.
+    Mono,
+}
+
+impl Y4mChroma {
+    fn tag(self) -> &'static str {
+        match self {
+            Y4mChroma::C420 => "420jpeg",
+            Y4mChroma::C422 => "422",
+            Y4mChroma::C444 => "444",
+            Y4mChroma::Mono => "mono",
+        }
+    }
+
+    fn from_tag(tag: &str) -> io::Result<Self> {
+        match tag {
+            "420" | "420jpeg" | "420mpeg2" | "420paldv" => Ok(Y4mChroma::C420),
+            "422" => Ok(Y4mChroma::C422),
+            "444" => Ok(Y4mChroma::C444),
+            "mono" => Ok(Y4mChroma::Mono),
+            _ => Err(invalid("Unsupported y4m chroma")),
+        }
+    }
+
+    // the dimensions of each chroma plane
+    fn plane_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Y4mChroma::C420 => (width.div_ceil(2), height.div_ceil(2)),
+            Y4mChroma::C422 => (width.div_ceil(2), height),
+            Y4mChroma::C444 => (width, height),
+            Y4mChroma::Mono => (0, 0),
+        }
+    }
+
+    // the horizontal and vertical subsampling shifts
+    fn shifts(self) -> (usize, usize) {
+        match self {
+            Y4mChroma::C420 => (1, 1),
+            Y4mChroma::C422 => (1, 0),
+            Y4mChroma::C444 | Y4mChroma::Mono => (0, 0),
+        }
+    }
+}
+
+/// The parameters of a stream, shared by all of its frames.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Y4mHeader {
+    pub width: usize,
+    pub height: usize,
+    pub chroma: Y4mChroma,
+    /// Frames per second, as a fraction. Zero if unknown.
+    pub frame_rate: (u32, u32),
+    /// The aspect ratio of each pixel, as a fraction.
+    /// Zero if unknown.
+    pub aspect: (u32, u32),
+    /// Whether samples use their full range, rather than
+    /// the range of studio video.
+    pub full_range: bool,
+}
+
+impl Y4mHeader {
+    // the display time of each frame
+    fn delay(&self) -> Duration {
+        match self.frame_rate {
+            (0, _) | (_, 0) => Duration::ZERO,
+            (num, den) => Duration::from_nanos(den as u64 * 1_000_000_000 / num as u64),
+        }
+    }
+
+    fn frame_len(&self) -> io::Result<usize> {
+        let (cw, ch) = self.chroma.plane_size(self.width, self.height);
+        self.width
+            .checked_mul(self.height)
+            .and_then(|luma| cw.checked_mul(ch)?.checked_mul(2)?.checked_add(luma))
+            .ok_or_else(|| invalid("Y4m frame too large"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Y4mEncodeOptions {
+    pub chroma: Y4mChroma,
+    /// Frames per second, as a fraction. If none is given, the
+    /// rate follows the delay of the first frame.
+    pub frame_rate: Option<(u32, u32)>,
+    /// The aspect ratio of each pixel, as a fraction.
+    pub aspect: (u32, u32),
+    /// Use the full range of samples, rather than the range
+    /// of studio video.
+    pub full_range: bool,
+}
+
+impl Default for Y4mEncodeOptions {
+    fn default() -> Self {
+        Self {
+            chroma: Y4mChroma::C420,
+            frame_rate: None,
+            aspect: (1, 1),
+            full_range: false,
+        }
+    }
+}
+
+impl DecodeOptions for Y4m {
+    type Options = ();
+}
+
+impl EncodeOptions for Y4m {
+    type Options = Y4mEncodeOptions;
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+// pixels are converted as standard definition video
+#[inline]
+fn yuv_options(full_range: bool) -> YuvOptions {
+    YuvOptions {
+        matrix: YuvMatrix::Bt601,
+        full_range,
+    }
+}
+
+// reads a line of the stream, or none at its end
+fn read_line<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        if r.read(&mut byte)? == 0 {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid("Truncated y4m header"));
+        }
+        if byte[0] == b'\n' {
+            return Ok(Some(line));
+        }
+        if line.len() >= MAX_LINE {
+            return Err(invalid("Y4m header too long"));
+        }
+        line.push(byte[0]);
+    }
+}
+
+fn parse_ratio(value: &str) -> io::Result<(u32, u32)> {
+    let (num, den) = value
+        .split_once(':')
+        .ok_or_else(|| invalid("Invalid y4m ratio"))?;
+    let num = num.parse().map_err(|_| invalid("Invalid y4m ratio"))?;
+    let den = den.parse().map_err(|_| invalid("Invalid y4m ratio"))?;
+    Ok((num, den))
+}
+
+fn parse_header(line: &[u8]) -> io::Result<Y4mHeader> {
+    let params = line
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| invalid("Invalid y4m header."))?;
+    let params = std::str::from_utf8(params).map_err(|_| invalid("Invalid y4m header."))?;
+
+    let (mut width, mut height) = (None, None);
+    let mut header = Y4mHeader {
+        width: 0,
+        height: 0,
+        chroma: Y4mChroma::C420,
+        frame_rate: (0, 0),
+        aspect: (0, 0),
+        full_range: false,
+    };
+    for param in params.split(' ').filter(|p| !p.is_empty()) {
+        let mut chars = param.chars();
+        let tag = chars.next();
+        let value = chars.as_str();
+        match tag {
+            Some('W') => width = Some(value.parse().map_err(|_| invalid("Invalid y4m width"))?),
+            Some('H') => height = Some(value.parse().map_err(|_| invalid("Invalid y4m height"))?),
+            Some('C') => header.chroma = Y4mChroma::from_tag(value)?,
+            Some('F') => header.frame_rate = parse_ratio(value)?,
+            Some('A') => header.aspect = parse_ratio(value)?,
+            Some('X') => match value {
+                "COLORRANGE=FULL" => header.full_range = true,
+                "COLORRANGE=LIMITED" => header.full_range = false,
+                _ => (),
+            },
+            // interlacing is left to the consumer of the frames
+            _ => (),
+        }
+    }
+    match (width, height) {
+        (Some(width), Some(height)) => {
+            header.width = width;
+            header.height = height;
+            Ok(header)
+        }
+        _ => Err(invalid("Missing y4m dimensions")),
+    }
+}
+
+/// The frames of a YUV4MPEG2 stream, decoded as they are read.
+pub struct Y4mFrames<R> {
+    r: R,
+    header: Y4mHeader,
+    done: bool,
+}
+
+impl<R: Read> Y4mFrames<R> {
+    fn new(mut r: R) -> io::Result<Self> {
+        let line = read_line(&mut r)?.ok_or_else(|| invalid("Invalid y4m header."))?;
+        let header = parse_header(&line)?;
+        header.frame_len()?;
+        Ok(Self {
+            r,
+            header,
+            done: false,
+        })
+    }
+
+    /// The parameters of the stream.
+    #[inline]
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<Frame<RawPixBuf<Rgb>>>> {
+        let line = match read_line(&mut self.r)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if !line.starts_with(FRAME_MAGIC) {
+            return Err(invalid("Invalid y4m frame header"));
+        }
+
+        // read the planes as they come, rather than trusting
+        // the header with a large allocation
+        let len = self.header.frame_len()?;
+        let mut data = Vec::new();
+        (&mut self.r).take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(invalid("Truncated y4m frame"));
+        }
+
+        let Y4mHeader {
+            width,
+            height,
+            chroma,
+            full_range,
+            ..
+        } = self.header;
+        let (cw, ch) = chroma.plane_size(width, height);
+        let (sx, sy) = chroma.shifts();
+        let (luma, rest) = data.split_at(width * height);
+        let (cb, cr) = rest.split_at(cw * ch);
+
+        let opts = yuv_options(full_range);
+        let mut buf = RawPixBuf::new(width, height);
+        let pixels = buf.as_typed_mut();
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = match chroma {
+                    Y4mChroma::Mono => (128, 128),
+                    _ => {
+                        let i = (y >> sy) * cw + (x >> sx);
+                        (cb[i], cr[i])
+                    }
+                };
+                pixels[y * width + x] = opts.to_rgb([luma[y * width + x], u, v]);
+            }
+        }
+        Ok(Some(Frame::new(buf, self.header.delay())))
+    }
+}
+
+impl<R: Read> Iterator for Y4mFrames<R> {
+    type Item = io::Result<Frame<RawPixBuf<Rgb>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.done = !matches!(frame, Some(Ok(_)));
+        frame
+    }
+}
+
+impl<R: Read> Dimensions for Y4mFrames<R> {
+    fn width(&self) -> usize {
+        self.header.width
+    }
+
+    fn height(&self) -> usize {
+        self.header.height
+    }
+}
+
+impl<R: Read> Frames for Y4mFrames<R> {
+    type Buffer = RawPixBuf<Rgb>;
+
+    fn loop_count(&self) -> LoopCount {
+        LoopCount::default()
+    }
+}
+
+impl DecodeFrames<RawPixBuf<Rgb>> for Y4m {
+    type Frames<R: Read> = Y4mFrames<R>;
+
+    #[inline]
+    fn decode_frames<R: Read>(r: R, _opt: ()) -> io::Result<Y4mFrames<R>> {
+        Y4mFrames::new(r)
+    }
+}
+
+impl Decode<RawPixBuf<Rgb>> for Y4m {
+    fn decode<R: Read>(r: R, _opt: ()) -> io::Result<RawPixBuf<Rgb>> {
+        Y4mFrames::new(r)?
+            .next()
+            .unwrap_or_else(|| Err(invalid("Y4m stream has no frames")))
+            .map(|frame| frame.buffer)
+    }
+}
+
+impl Probe for Y4m {
+    /// The number of frames of a stream is never known,
+    /// since it has no end marker.
+    fn probe<R: Read>(mut r: R) -> io::Result<ImageInfo> {
+        let line = read_line(&mut r)?.ok_or_else(|| invalid("Invalid y4m header."))?;
+        let header = parse_header(&line)?;
+        let color_type = match header.chroma {
+            Y4mChroma::Mono => ColorType::Gray,
+            _ => ColorType::YCbCr,
+        };
+        let mut info = ImageInfo::new(header.width, header.height, color_type, 8);
+        info.frame_count = None;
+        // top or bottom field first, or mixed per frame
+        info.interlaced = line
+            .split(|&b| b == b' ')
+            .any(|p| matches!(p, b"It" | b"Ib" | b"Im"));
+        Ok(info)
+    }
+}
+
+#[inline]
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// the frame rate matching a frame's delay, in microseconds
+fn frame_rate(delay: Duration) -> (u32, u32) {
+    let micros = delay.as_micros().min(u32::MAX as u128) as u64;
+    if micros == 0 {
+        return DEFAULT_FRAME_RATE;
+    }
+    let d = gcd(1_000_000, micros);
+    ((1_000_000 / d) as u32, (micros / d) as u32)
+}
+
+fn write_frame<W: Write>(
+    w: &mut W,
+    opts: &Y4mEncodeOptions,
+    (width, height): (usize, usize),
+    pixels: &[Rgb],
+) -> io::Result<()> {
+    let (cw, ch) = opts.chroma.plane_size(width, height);
+    let (sx, sy) = opts.chroma.shifts();
+    let yuv = yuv_options(opts.full_range);
+    let mut luma = Vec::with_capacity(width * height);
+
+    // chroma is averaged over the pixels of each sample
+    let mut sums = vec![[0f32; 3]; cw * ch];
+    for y in 0..height {
+        for x in 0..width {
+            let [l, cb, cr] = yuv.to_yuv(pixels[y * width + x]);
+            luma.push(sample(l));
+            if let Some(sum) = sums.get_mut((y >> sy) * cw + (x >> sx)) {
+                sum[0] += cb;
+                sum[1] += cr;
+                sum[2] += 1.0;
+            }
+        }
+    }
+
+    w.write_all(FRAME_MAGIC)?;
+    w.write_all(b"\n")?;
+    w.write_all(&luma)?;
+    for c in 0..2 {
+        let plane: Vec<u8> = sums.iter().map(|s| sample(s[c] / s[2])).collect();
+        w.write_all(&plane)?;
+    }
+    Ok(())
+}
+
+fn encode_impl<W, B, I>(
+    mut w: W,
+    opts: Y4mEncodeOptions,
+    (width, height): (usize, usize),
+    frames: I,
+) -> io::Result<()>
+where
+    W: Write,
+    B: Borrow<RawPixBuf<Rgb>>,
+    I: Iterator<Item = io::Result<Frame<B>>>,
+{
+    let mut frames = frames.peekable();
+    let frame_rate = match (opts.frame_rate, frames.peek()) {
+        (Some(frame_rate), _) => frame_rate,
+        (None, Some(Ok(frame))) => frame_rate(frame.delay),
+        (None, _) => DEFAULT_FRAME_RATE,
+    };
+
+    write!(
+        w,
+        "YUV4MPEG2 W{} H{} F{}:{} Ip A{}:{} C{}",
+        width,
+        height,
+        frame_rate.0,
+        frame_rate.1,
+        opts.aspect.0,
+        opts.aspect.1,
+        opts.chroma.tag(),
+    )?;
+    if opts.full_range {
+        w.write_all(b" XCOLORRANGE=FULL")?;
+    }
+    w.write_all(b"\n")?;
+
+    for frame in frames {
+        let frame = frame?;
+        let buf = frame.buffer.borrow();
+        // every frame covers the whole picture
+        if buf.dimensions() != (width, height) || (frame.left, frame.top) != (0, 0) {
+            return Err(invalid("Y4m frames must cover the whole picture"));
+        }
+        write_frame(&mut w, &opts, (width, height), buf.as_typed())?;
+    }
+    Ok(())
+}
+
+fn to_rgb<I: Image + Dimensions>(buf: &I) -> RawPixBuf<Rgb> {
+    let (width, height) = buf.dimensions();
+    let mut new_buf = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let c = buf.color_get(x, y);
+            let c: Rgb = c.convert_into();
+            new_buf.pixel_set(x, y, c);
+        }
+    }
+    new_buf
+}
+
+impl EncodeFrames<RawPixBuf<Rgb>> for Y4m {
+    fn encode_frames<W, F>(w: W, opts: Y4mEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = RawPixBuf<Rgb>>,
+    {
+        let dims = frames.dimensions();
+        encode_impl(w, opts, dims, frames)
+    }
+}
+
+impl<I: Image + Dimensions> EncodeFrames<I, specialized::No> for Y4m {
+    fn encode_frames<W, F>(w: W, opts: Y4mEncodeOptions, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = I>,
+    {
+        let dims = frames.dimensions();
+        let frames = frames.map(|frame| frame.map(|frame| frame.map(|buf| to_rgb(&buf))));
+        encode_impl(w, opts, dims, frames)
+    }
+}
+
+impl Encode<RawPixBuf<Rgb>> for Y4m {
+    fn encode<W: Write>(w: W, opts: Y4mEncodeOptions, buf: &RawPixBuf<Rgb>) -> io::Result<()> {
+        let frame = Frame::new(buf, Duration::ZERO);
+        encode_impl(w, opts, buf.dimensions(), iter::once(Ok(frame)))
+    }
+}
+
+impl<I: Image + Dimensions> Encode<I, specialized::No> for Y4m {
+    fn encode<W: Write>(w: W, opts: Y4mEncodeOptions, buf: &I) -> io::Result<()> {
+        let frame = Frame::new(to_rgb(buf), Duration::ZERO);
+        encode_impl(w, opts, buf.dimensions(), iter::once(Ok(frame)))
+    }
+}
@@ -41,7 +41,7 @@
 //! fn main() -> io::Result<()> {
 //!     let stdin = io::stdin();
 //!     let stdin_lock = stdin.lock();
-//!     let mut stdin_reader = BufReader::new(stdin_lock);
+//!     let mut stdin_reader = PeekReader::new(stdin_lock);
 //!
 //!     let stdout = io::stdout();
 //!     let stdout_lock = stdout.lock();
@@ -80,5 +80,7 @@
 pub mod buffer;
 pub mod color;
 pub mod image;
+pub mod raw;
 pub mod serialize;
 pub mod specialized;
+pub mod text_render;
@@ -0,0 +1,536 @@
+//! Raw YUV images with no container around them, in the memory
+//! layouts handed over by cameras and video decoders.
+
+use std::io;
+
+use crate::buffer::{AsTyped, RawPixBuf};
+use crate::color::Rgb;
+use crate::image::{Dimensions, Image, ImageMut};
+
+/// The coefficients relating luma and chroma to RGB.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum YuvMatrix {
+    /// ITU-R BT.601, used by standard definition video and JPEG.
+    #[default]
+    Bt601,
+    /// ITU-R BT.709, used by high definition video.
+    Bt709,
+}
+
+impl YuvMatrix {
+    // the weights of red and blue in luma
+    #[inline]
+    fn weights(self) -> (f32, f32) {
+        match self {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct YuvOptions {
+    pub matrix: YuvMatrix,
+    /// Samples use their full range, rather than the range
+    /// of studio video.
+    pub full_range: bool,
+}
+
+impl YuvOptions {
+    /// Converts a color to luma and chroma samples, before rounding.
+    pub(crate) fn to_yuv(self, c: Rgb) -> [f32; 3] {
+        let (kr, kb) = self.matrix.weights();
+        let [r, g, b] = [c.r, c.g, c.b].map(|v| v as f32 / 255.0);
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+        if self.full_range {
+            [y * 255.0, u * 255.0 + 128.0, v * 255.0 + 128.0]
+        } else {
+            [y * 219.0 + 16.0, u * 224.0 + 128.0, v * 224.0 + 128.0]
+        }
+    }
+
+    /// Converts luma and chroma samples to a color.
+    pub(crate) fn to_rgb(self, [y, u, v]: [u8; 3]) -> Rgb {
+        let (kr, kb) = self.matrix.weights();
+        let (y, u, v) = if self.full_range {
+            (
+                y as f32 / 255.0,
+                (u as f32 - 128.0) / 255.0,
+                (v as f32 - 128.0) / 255.0,
+            )
+        } else {
+            (
+                (y as f32 - 16.0) / 219.0,
+                (u as f32 - 128.0) / 224.0,
+                (v as f32 - 128.0) / 224.0,
+            )
+        };
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+        let [r, g, b] = [r, g, b].map(|c| sample(c * 255.0));
+        Rgb { r, g, b }
+    }
+}
+
+#[inline]
+pub(crate) fn sample(v: f32) -> u8 {
+    v.round().clamp(0.0, 255.0) as u8
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+/// The rows of samples of an image, each starting `stride`
+/// bytes after the previous one.
+#[derive(Debug, Clone)]
+pub struct Plane<B> {
+    pub data: B,
+    pub stride: usize,
+}
+
+impl<B: AsRef<[u8]>> Plane<B> {
+    // checks the plane holds enough rows of the given length
+    fn check(&self, row_len: usize, rows: usize) -> io::Result<()> {
+        if self.stride < row_len {
+            return Err(invalid("Raw plane stride too short"));
+        }
+        let len = match rows {
+            0 => 0,
+            rows => self
+                .stride
+                .checked_mul(rows - 1)
+                .and_then(|len| len.checked_add(row_len))
+                .ok_or_else(|| invalid("Raw plane too large"))?,
+        };
+        if self.data.as_ref().len() < len {
+            return Err(invalid("Raw plane too short"));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.data.as_ref()[y * self.stride + x]
+    }
+}
+
+impl Plane<Vec<u8>> {
+    fn packed(data: Vec<u8>, stride: usize) -> Self {
+        Self { data, stride }
+    }
+}
+
+// the luma of every pixel, and the chroma averaged over
+// blocks of 2x2 pixels, or 2x1 pixels if not `vertical`
+fn subsample(
+    buf: &RawPixBuf<Rgb>,
+    opts: YuvOptions,
+    vertical: bool,
+) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let (width, height) = buf.dimensions();
+    let sy = if vertical { 1 } else { 0 };
+    let (cw, ch) = (width.div_ceil(2), (height + sy) >> sy);
+    let mut luma = Vec::with_capacity(width * height);
+    let mut sums = vec![[0f32; 3]; cw * ch];
+    for (y, row) in buf.as_typed().chunks_exact(width.max(1)).enumerate() {
+        for (x, &c) in row.iter().enumerate() {
+            let [l, u, v] = opts.to_yuv(c);
+            luma.push(sample(l));
+            let sum = &mut sums[(y >> sy) * cw + (x >> 1)];
+            sum[0] += u;
+            sum[1] += v;
+            sum[2] += 1.0;
+        }
+    }
+    let chroma = |c: usize| sums.iter().map(|s| sample(s[c] / s[2])).collect();
+    (luma, chroma(0), chroma(1))
+}
+
+fn to_rgb<I: Image<Pixel = Rgb> + Dimensions>(image: &I) -> RawPixBuf<Rgb> {
+    let (width, height) = image.dimensions();
+    let mut buf = RawPixBuf::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            buf.pixel_set(x, y, image.color_get(x, y));
+        }
+    }
+    buf
+}
+
+/// Planar 4:2:0 images, with a plane of luma followed by
+/// planes of blue and red chroma, at half the resolution
+/// horizontally and vertically.
+#[derive(Debug, Clone)]
+pub struct I420<B> {
+    width: usize,
+    height: usize,
+    y: Plane<B>,
+    u: Plane<B>,
+    v: Plane<B>,
+    opts: YuvOptions,
+}
+
+impl<B: AsRef<[u8]>> I420<B> {
+    /// Wraps the planes of an image, failing if any of them is
+    /// too short for its dimensions.
+    pub fn new(
+        width: usize,
+        height: usize,
+        y: Plane<B>,
+        u: Plane<B>,
+        v: Plane<B>,
+        opts: YuvOptions,
+    ) -> io::Result<Self> {
+        y.check(width, height)?;
+        u.check(width.div_ceil(2), height.div_ceil(2))?;
+        v.check(width.div_ceil(2), height.div_ceil(2))?;
+        Ok(Self {
+            width,
+            height,
+            y,
+            u,
+            v,
+            opts,
+        })
+    }
+
+    #[inline]
+    pub fn into_planes(self) -> (Plane<B>, Plane<B>, Plane<B>) {
+        (self.y, self.u, self.v)
+    }
+
+    #[inline]
+    pub fn to_rgb(&self) -> RawPixBuf<Rgb> {
+        to_rgb(self)
+    }
+}
+
+impl I420<Vec<u8>> {
+    /// Converts an image, with tightly packed planes.
+    pub fn from_rgb(buf: &RawPixBuf<Rgb>, opts: YuvOptions) -> Self {
+        let (luma, u, v) = subsample(buf, opts, true);
+        let cw = buf.width().div_ceil(2);
+        Self {
+            width: buf.width(),
+            height: buf.height(),
+            y: Plane::packed(luma, buf.width()),
+            u: Plane::packed(u, cw),
+            v: Plane::packed(v, cw),
+            opts,
+        }
+    }
+}
+
+impl<B> Dimensions for I420<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl<B: AsRef<[u8]>> Image for I420<B> {
+    type Pixel = Rgb;
+
+    fn color_get(&self, x: usize, y: usize) -> Rgb {
+        let luma = self.y.get(x, y);
+        let (u, v) = (self.u.get(x / 2, y / 2), self.v.get(x / 2, y / 2));
+        self.opts.to_rgb([luma, u, v])
+    }
+}
+
+/// Semi-planar 4:2:0 images, with a plane of luma followed
+/// by a plane of interleaved blue and red chroma, at half the
+/// resolution horizontally and vertically.
+#[derive(Debug, Clone)]
+pub struct Nv12<B> {
+    width: usize,
+    height: usize,
+    y: Plane<B>,
+    uv: Plane<B>,
+    opts: YuvOptions,
+}
+
+impl<B: AsRef<[u8]>> Nv12<B> {
+    /// Wraps the planes of an image, failing if any of them is
+    /// too short for its dimensions.
+    pub fn new(
+        width: usize,
+        height: usize,
+        y: Plane<B>,
+        uv: Plane<B>,
+        opts: YuvOptions,
+    ) -> io::Result<Self> {
+        y.check(width, height)?;
+        uv.check(width.div_ceil(2) * 2, height.div_ceil(2))?;
+        Ok(Self {
+            width,
+            height,
+            y,
+            uv,
+            opts,
+        })
+    }
+
+    #[inline]
+    pub fn into_planes(self) -> (Plane<B>, Plane<B>) {
+        (self.y, self.uv)
+    }
+
+    #[inline]
+    pub fn to_rgb(&self) -> RawPixBuf<Rgb> {
+        to_rgb(self)
+    }
+}
+
+impl Nv12<Vec<u8>> {
+    /// Converts an image, with tightly packed planes.
+    pub fn from_rgb(buf: &RawPixBuf<Rgb>, opts: YuvOptions) -> Self {
+        let (luma, u, v) = subsample(buf, opts, true);
+        let uv = u.iter().zip(&v).flat_map(|(&u, &v)| [u, v]).collect();
+        Self {
+            width: buf.width(),
+            height: buf.height(),
+            y: Plane::packed(luma, buf.width()),
+            uv: Plane::packed(uv, buf.width().div_ceil(2) * 2),
+            opts,
+        }
+    }
+}
+
+impl<B> Dimensions for Nv12<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl<B: AsRef<[u8]>> Image for Nv12<B> {
+    type Pixel = Rgb;
+
+    fn color_get(&self, x: usize, y: usize) -> Rgb {
+        let luma = self.y.get(x, y);
+        let (cx, cy) = (x / 2 * 2, y / 2);
+        let (u, v) = (self.uv.get(cx, cy), self.uv.get(cx + 1, cy));
+        self.opts.to_rgb([luma, u, v])
+    }
+}
+
+/// Packed 4:2:2 images, with every pair of pixels stored as
+/// their luma interleaved with their shared blue and red
+/// chroma, in the order Y0 U Y1 V.
+#[derive(Debug, Clone)]
+pub struct Yuyv<B> {
+    width: usize,
+    height: usize,
+    data: Plane<B>,
+    opts: YuvOptions,
+}
+
+impl<B: AsRef<[u8]>> Yuyv<B> {
+    /// Wraps the samples of an image, failing if they are
+    /// too short for its dimensions. Rows of an odd width
+    /// are padded with a last pixel.
+    pub fn new(width: usize, height: usize, data: Plane<B>, opts: YuvOptions) -> io::Result<Self> {
+        data.check(width.div_ceil(2) * 4, height)?;
+        Ok(Self {
+            width,
+            height,
+            data,
+            opts,
+        })
+    }
+
+    #[inline]
+    pub fn into_plane(self) -> Plane<B> {
+        self.data
+    }
+
+    #[inline]
+    pub fn to_rgb(&self) -> RawPixBuf<Rgb> {
+        to_rgb(self)
+    }
+}
+
+impl Yuyv<Vec<u8>> {
+    /// Converts an image, with tightly packed rows.
+    pub fn from_rgb(buf: &RawPixBuf<Rgb>, opts: YuvOptions) -> Self {
+        let (width, height) = buf.dimensions();
+        let (luma, u, v) = subsample(buf, opts, false);
+        let pairs = width.div_ceil(2);
+        let mut data = Vec::with_capacity(pairs * 4 * height);
+        for y in 0..height {
+            let row = &luma[y * width..(y + 1) * width];
+            for i in 0..pairs {
+                let y0 = row[i * 2];
+                // the padding repeats the last pixel
+                let y1 = row.get(i * 2 + 1).copied().unwrap_or(y0);
+                data.extend_from_slice(&[y0, u[y * pairs + i], y1, v[y * pairs + i]]);
+            }
+        }
+        Self {
+            width,
+            height,
+            data: Plane::packed(data, pairs * 4),
+            opts,
+        }
+    }
+}
+
+impl<B> Dimensions for Yuyv<B> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl<B: AsRef<[u8]>> Image for Yuyv<B> {
+    type Pixel = Rgb;
+
+    fn color_get(&self, x: usize, y: usize) -> Rgb {
+        let pair = x / 2 * 4;
+        let luma = self.data.get(pair + (x % 2) * 2, y);
+        let (u, v) = (self.data.get(pair + 1, y), self.data.get(pair + 3, y));
+        self.opts.to_rgb([luma, u, v])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+    // red, in limited range bt.601
+    const RED_YUV: [u8; 3] = [81, 90, 240];
+
+    fn close(a: Rgb, b: Rgb) -> bool {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 2;
+        d(a.r, b.r) && d(a.g, b.g) && d(a.b, b.b)
+    }
+
+    fn plane(data: Vec<u8>, stride: usize) -> Plane<Vec<u8>> {
+        Plane { data, stride }
+    }
+
+    // blocks of 2x2 pixels of the same color, which
+    // survive chroma subsampling
+    fn blocks(width: usize, height: usize) -> RawPixBuf<Rgb> {
+        let mut buf = RawPixBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = (x / 2 + y / 2 * 3) as u8;
+                let c = Rgb {
+                    r: i.wrapping_mul(47),
+                    g: 200 - i * 13,
+                    b: i.wrapping_mul(91),
+                };
+                buf.pixel_set(x, y, c);
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn known_values() {
+        let [y, u, v] = RED_YUV;
+        let opts = YuvOptions::default();
+        // the planes have a byte of padding at the end of each row
+        let i420 = I420::new(
+            2,
+            2,
+            plane(vec![y, y, 0, y, y, 0], 3),
+            plane(vec![u], 1),
+            plane(vec![v], 1),
+            opts,
+        )
+        .unwrap();
+        let nv12 = Nv12::new(2, 2, plane(vec![y, y, y, y], 2), plane(vec![u, v], 2), opts).unwrap();
+        let yuyv = Yuyv::new(2, 1, plane(vec![y, u, y, v], 4), opts).unwrap();
+        for buf in [i420.to_rgb(), nv12.to_rgb(), yuyv.to_rgb()].iter() {
+            assert!(buf.as_typed().iter().all(|&c| close(c, RED)));
+        }
+
+        let white = YuvOptions {
+            matrix: YuvMatrix::Bt709,
+            full_range: true,
+        };
+        assert_eq!(
+            white.to_rgb([255, 128, 128]),
+            Rgb {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+        assert_eq!(opts.to_rgb([16, 128, 128]), Rgb { r: 0, g: 0, b: 0 });
+    }
+
+    #[test]
+    fn conversions_from_rgb() {
+        let mut buf = RawPixBuf::new(2, 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                buf.pixel_set(x, y, RED);
+            }
+        }
+        let opts = YuvOptions::default();
+        let [y, u, v] = RED_YUV;
+
+        let (py, pu, pv) = I420::from_rgb(&buf, opts).into_planes();
+        assert_eq!((py.data, pu.data, pv.data), (vec![y; 4], vec![u], vec![v]));
+        let (py, puv) = Nv12::from_rgb(&buf, opts).into_planes();
+        assert_eq!((py.data, puv.data), (vec![y; 4], vec![u, v]));
+        let data = Yuyv::from_rgb(&buf, opts).into_plane().data;
+        assert_eq!(data, [y, u, y, v, y, u, y, v]);
+    }
+
+    #[test]
+    fn round_trips() {
+        for &(width, height) in &[(6, 4), (5, 3)] {
+            let buf = blocks(width, height);
+            for &full_range in &[false, true] {
+                let opts = YuvOptions {
+                    matrix: YuvMatrix::Bt709,
+                    full_range,
+                };
+                let i420 = I420::from_rgb(&buf, opts).to_rgb();
+                let nv12 = Nv12::from_rgb(&buf, opts).to_rgb();
+                let yuyv = Yuyv::from_rgb(&buf, opts).to_rgb();
+                for out in [i420, nv12, yuyv].iter() {
+                    assert_eq!(out.dimensions(), (width, height));
+                    let same = buf.as_typed().iter().zip(out.as_typed());
+                    assert!(same.into_iter().all(|(&a, &b)| close(a, b)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_planes_are_rejected() {
+        let opts = YuvOptions::default();
+        assert!(Yuyv::new(3, 2, plane(vec![0; 15], 8), opts).is_err());
+        assert!(Yuyv::new(3, 2, plane(vec![0; 16], 6), opts).is_err());
+        assert!(Nv12::new(3, 3, plane(vec![0; 9], 3), plane(vec![0; 7], 4), opts).is_err());
+        assert!(I420::new(
+            usize::MAX,
+            2,
+            plane(vec![], usize::MAX),
+            plane(vec![], 0),
+            plane(vec![], 0),
+            opts
+        )
+        .is_err());
+    }
+}
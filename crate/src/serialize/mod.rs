@@ -1,6 +1,8 @@
+use std::convert::TryFrom;
 use std::io::{self, BufRead, Read, Write};
 
-use crate::image::Format;
+use crate::image::animation::Frames;
+use crate::image::{Confidence, Format, ImageInfo};
 use crate::specialized::{Aye, No};
 
 // -------------------------------------------------------------------------- //
@@ -79,18 +81,344 @@ impl<B, F: Decode<B, No>> DecodeGeneric<B> for F {
 
 // -------------------------------------------------------------------------- //
 
+pub trait EncodeFrames<B, Specialized = Aye>: EncodeOptions {
+    fn encode_frames<W, F>(w: W, opts: Self::Options, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = B>;
+}
+
+// -------------------------------------------------------------------------- //
+
+pub trait EncodeFramesSpecialized<B>: EncodeOptions {
+    fn encode_frames_specialized<W, F>(w: W, opts: Self::Options, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = B>;
+}
+
+impl<B, E: EncodeFrames<B, Aye>> EncodeFramesSpecialized<B> for E {
+    #[inline]
+    fn encode_frames_specialized<W, F>(w: W, opts: Self::Options, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = B>,
+    {
+        Self::encode_frames(w, opts, frames)
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+pub trait EncodeFramesGeneric<B>: EncodeOptions {
+    fn encode_frames_generic<W, F>(w: W, opts: Self::Options, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = B>;
+}
+
+impl<B, E: EncodeFrames<B, No>> EncodeFramesGeneric<B> for E {
+    #[inline]
+    fn encode_frames_generic<W, F>(w: W, opts: Self::Options, frames: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Frames<Buffer = B>,
+    {
+        Self::encode_frames(w, opts, frames)
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+pub trait DecodeFrames<B>: DecodeOptions {
+    type Frames<R: Read>: Frames<Buffer = B>;
+
+    fn decode_frames<R: Read>(r: R, opt: Self::Options) -> io::Result<Self::Frames<R>>;
+}
+
+// -------------------------------------------------------------------------- //
+
+/// Reads the properties of an image from the header of its
+/// file, without decoding its pixels.
+pub trait Probe {
+    fn probe<R: Read>(r: R) -> io::Result<ImageInfo>;
+}
+
+// -------------------------------------------------------------------------- //
+
+/// A reader which can look ahead at the data it has yet to
+/// return, without consuming it.
+pub trait Peek: BufRead {
+    /// Returns at least `n` bytes of the data yet to be read,
+    /// or fewer if the end of the stream is reached first.
+    fn peek(&mut self, n: usize) -> io::Result<&[u8]>;
+}
+
+impl Peek for &[u8] {
+    fn peek(&mut self, _n: usize) -> io::Result<&[u8]> {
+        Ok(self)
+    }
+}
+
+impl<T: AsRef<[u8]>> Peek for io::Cursor<T> {
+    fn peek(&mut self, _n: usize) -> io::Result<&[u8]> {
+        let data = self.get_ref().as_ref();
+        let pos = usize::try_from(self.position()).map_or(data.len(), |pos| pos.min(data.len()));
+        Ok(&data[pos..])
+    }
+}
+
+impl<P: Peek + ?Sized> Peek for &mut P {
+    #[inline]
+    fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        (**self).peek(n)
+    }
+}
+
+const PEEK_READER_CAPACITY: usize = 8192;
+
+/// A buffered reader, which can peek as far ahead into its
+/// stream as needed to detect the format of an image.
+pub struct PeekReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> PeekReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwraps the inner reader. Any data buffered, but not
+    /// read yet, is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // buffers at least `n` bytes, unless the stream ends first
+    fn fill(&mut self, n: usize) -> io::Result<()> {
+        while self.buf.len() - self.pos < n {
+            if self.pos > 0 {
+                self.buf.drain(..self.pos);
+                self.pos = 0;
+            }
+            let len = self.buf.len();
+            self.buf
+                .resize(len + (n - len).max(PEEK_READER_CAPACITY), 0);
+            let result = self.inner.read(&mut self.buf[len..]);
+            self.buf.truncate(len + *result.as_ref().unwrap_or(&0));
+            match result {
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // skip the buffer on large reads, if it's empty
+        if self.pos == self.buf.len() && out.len() >= PEEK_READER_CAPACITY {
+            return self.inner.read(out);
+        }
+        let data = self.fill_buf()?;
+        let n = data.len().min(out.len());
+        out[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for PeekReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.fill(1)?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+impl<R: Read> Peek for PeekReader<R> {
+    fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        self.fill(n)?;
+        Ok(&self.buf[self.pos..])
+    }
+}
+
+// -------------------------------------------------------------------------- //
+
+// how much of a stream is peeked at, at first and at most,
+// to detect its format
+const MAGIC_MIN_LEN: usize = 64;
+const MAGIC_MAX_LEN: usize = 4096;
+
+/// Detects the format of the data at the start of `r`, among
+/// `formats`, without consuming it. More data is peeked at
+/// while some format needs it to tell whether it matches. Then
+/// the first format with the highest confidence is returned.
+///
+/// Unlike in earlier releases, any `BufRead` won't do: it only
+/// shows what it happens to have buffered, which may be too
+/// short to tell formats apart. Readers such as a `BufReader`
+/// or stdin should be wrapped in a `PeekReader` instead.
 pub fn try_format<'f, I, F, R>(mut r: R, formats: F) -> io::Result<I>
 where
     F: IntoIterator<Item = (I, &'f dyn Format)>,
-    R: BufRead,
+    R: Peek,
 {
-    let buf = r.fill_buf()?;
-    for (i, fmt) in formats {
-        if fmt.is_valid_magic(buf) {
-            return Ok(i);
+    let mut formats: Vec<_> = formats.into_iter().collect();
+    let mut len = MAGIC_MIN_LEN;
+    loop {
+        let data = r.peek(len)?;
+        let confidence: Vec<_> = formats.iter().map(|(_, fmt)| fmt.detect(data)).collect();
+        let more = data.len() >= len && len < MAGIC_MAX_LEN;
+        if more && confidence.contains(&Confidence::Incomplete) {
+            len *= 2;
+            continue;
+        }
+        let best = confidence
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c >= Confidence::Low)
+            .fold(
+                None,
+                |best: Option<(usize, Confidence)>, (i, &c)| match best {
+                    Some((_, b)) if b >= c => best,
+                    _ => Some((i, c)),
+                },
+            );
+        return match best {
+            Some((i, _)) => Ok(formats.swap_remove(i).0),
+            None => Err(io::Error::other("No matching magic found")),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Signature;
+
+    // returns a single byte per read
+    struct Slow<'a>(&'a [u8]);
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(out.len()).min(1);
+            out[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    struct Signed(&'static str, &'static [Signature]);
+
+    impl Format for Signed {
+        fn id(&self) -> &'static str {
+            self.0
+        }
+
+        fn magic(&self) -> &'static [u8] {
+            b""
+        }
+
+        fn signatures(&self) -> &'static [Signature] {
+            self.1
+        }
+    }
+
+    // matches anything, with a low confidence
+    struct Anything;
+
+    impl Format for Anything {
+        fn id(&self) -> &'static str {
+            "test:anything"
+        }
+
+        fn magic(&self) -> &'static [u8] {
+            b""
         }
+
+        fn detect(&self, _data: &[u8]) -> Confidence {
+            Confidence::Low
+        }
+    }
+
+    const START: Signed = Signed("test:start", &[Signature::new(b"START")]);
+    const MASKED: Signed = Signed(
+        "test:masked",
+        &[Signature::new(b"M\x10").masked(b"\xff\xf0")],
+    );
+    const TWIN: Signed = Signed("test:twin", &[Signature::new(b"START")]);
+    // past the data peeked at first
+    const FAR: Signed = Signed("test:far", &[Signature::at(1000, b"FAR")]);
+
+    fn detect(data: &[u8], formats: &[&dyn Format]) -> io::Result<&'static str> {
+        let formats = formats.iter().map(|&fmt| (fmt.id(), fmt));
+        let mut r = PeekReader::new(Slow(data));
+        let id = try_format(&mut r, formats)?;
+        // nothing is consumed
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        assert_eq!(rest, data);
+        Ok(id)
+    }
+
+    #[test]
+    fn signatures_at_an_offset() {
+        let mut data = vec![0; 1003];
+        data[1000..].copy_from_slice(b"FAR");
+        assert_eq!(detect(&data, &[&START, &FAR]).unwrap(), "test:far");
+        data[1002] = b'X';
+        assert!(detect(&data, &[&START, &FAR]).is_err());
+    }
+
+    #[test]
+    fn masked_signatures() {
+        assert_eq!(detect(b"M\x1f..", &[&MASKED]).unwrap(), "test:masked");
+        assert_eq!(detect(b"M\x10..", &[&MASKED]).unwrap(), "test:masked");
+        assert!(detect(b"M\x2f..", &[&MASKED]).is_err());
+        assert!(detect(b"m\x1f..", &[&MASKED]).is_err());
+    }
+
+    #[test]
+    fn incomplete_data_is_not_matched() {
+        // the stream ends before the signature does
+        assert!(detect(b"STA", &[&START]).is_err());
+        let mut data = vec![0; 1001];
+        data[1000] = b'F';
+        assert!(detect(&data, &[&FAR]).is_err());
+        assert_eq!(START.detect(b"STA"), Confidence::Incomplete);
+        assert_eq!(FAR.detect(&data), Confidence::Incomplete);
+    }
+
+    #[test]
+    fn highest_confidence_wins() {
+        let data = b"START of the data";
+        assert_eq!(detect(data, &[&Anything, &START]).unwrap(), "test:start");
+        assert_eq!(detect(data, &[&START, &Anything]).unwrap(), "test:start");
+        assert_eq!(
+            detect(b"other", &[&START, &Anything]).unwrap(),
+            "test:anything"
+        );
+        // ties go to the first format
+        assert_eq!(detect(data, &[&TWIN, &START]).unwrap(), "test:twin");
+        assert_eq!(detect(data, &[&START, &TWIN]).unwrap(), "test:start");
     }
-    let k = std::io::ErrorKind::Other;
-    let e = std::io::Error::new(k, "No matching magic found");
-    Err(e)
 }
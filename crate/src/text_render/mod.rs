@@ -0,0 +1,539 @@
+//! Render images as Unicode block or braille characters,
+//! colored with ANSI escape sequences, to be displayed in
+//! any terminal.
+
+use std::io::{self, Write};
+
+use crate::color::convert::ConvertInto;
+use crate::color::{Gray, Nrgba, Rgb};
+use crate::image::{Dimensions, Image};
+
+// the base colors of xterm, which most terminals follow
+const ANSI16_PALETTE: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+// the channel levels of the 6x6x6 color cube of the 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+// the sextants which already had their own character
+// before the block of legacy computing symbols
+const SEXTANT_LEFT: u32 = 0b010101;
+const SEXTANT_RIGHT: u32 = 0b101010;
+
+/// The characters each cell of the terminal is drawn with.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum CellStyle {
+    /// Half blocks, with two pixels per cell, stacked vertically.
+    #[default]
+    HalfBlock,
+    /// Quadrants, with 2x2 pixels per cell.
+    Quadrant,
+    /// Sextants, with 2x3 pixels per cell. Not every font
+    /// has these characters.
+    Sextant,
+    /// Braille patterns, with 2x4 pixels per cell. Each
+    /// dot is drawn or not depending on the luminance of
+    /// its pixel, in a single color per cell.
+    Braille,
+}
+
+impl CellStyle {
+    /// The number of pixels covered by each cell, horizontally
+    /// and vertically.
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            CellStyle::HalfBlock => (1, 2),
+            CellStyle::Quadrant => (2, 2),
+            CellStyle::Sextant => (2, 3),
+            CellStyle::Braille => (2, 4),
+        }
+    }
+
+    // the bit of a pixel within a cell, in the mask of drawn pixels
+    #[inline]
+    fn pixel_bit(self, dx: usize, dy: usize) -> u32 {
+        match self {
+            // the dots of the bottom row were added last to braille
+            CellStyle::Braille if dy == 3 => 6 + dx as u32,
+            CellStyle::Braille => (dx * 3 + dy) as u32,
+            _ => {
+                let (width, _) = self.cell_size();
+                (dy * width + dx) as u32
+            }
+        }
+    }
+
+    fn glyph(self, mask: u32) -> char {
+        match self {
+            CellStyle::HalfBlock => [' ', '▀', '▄', '█'][mask as usize],
+            CellStyle::Quadrant => QUADRANTS[mask as usize],
+            CellStyle::Sextant => match mask {
+                0 => ' ',
+                0b111111 => '█',
+                SEXTANT_LEFT => '▌',
+                SEXTANT_RIGHT => '▐',
+                _ => {
+                    // the block skips the two halves, in order
+                    let skipped = (mask > SEXTANT_LEFT) as u32 + (mask > SEXTANT_RIGHT) as u32;
+                    char::from_u32(0x1fb00 + mask - 1 - skipped).unwrap()
+                }
+            },
+            CellStyle::Braille => char::from_u32(0x2800 + mask).unwrap(),
+        }
+    }
+}
+
+/// The colors available to the terminal.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum ColorMode {
+    /// 24 bit colors.
+    #[default]
+    TrueColor,
+    /// The color cube and grayscale ramp of the 256 color palette.
+    Ansi256,
+    /// The 16 base colors, whose exact values depend on
+    /// the terminal.
+    Ansi16,
+    /// No colors, only the shapes of the characters, which
+    /// are drawn depending on the luminance of each pixel.
+    Monochrome,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct RenderOptions {
+    pub cell_style: CellStyle,
+    pub color_mode: ColorMode,
+    /// Diffuse the error of reduced palettes, and of pixels
+    /// drawn by their luminance, with Floyd-Steinberg dithering.
+    pub dither: bool,
+    /// The luminance from which pixels are drawn, in braille
+    /// cells and in monochrome.
+    pub threshold: u8,
+    /// Draw the pixels below the threshold, rather than
+    /// those above it.
+    pub invert: bool,
+    /// Draw every pixel as if it were opaque, rather than
+    /// leaving mostly transparent ones to the background.
+    pub ignore_alpha: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            cell_style: CellStyle::HalfBlock,
+            color_mode: ColorMode::TrueColor,
+            dither: false,
+            threshold: 128,
+            invert: false,
+            ignore_alpha: false,
+        }
+    }
+}
+
+// the color of an escape sequence
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Ink {
+    Rgb([u8; 3]),
+    Ansi256(u8),
+    Ansi16(u8),
+}
+
+impl Ink {
+    fn write_escape<W: Write>(ink: Option<Ink>, background: bool, w: &mut W) -> io::Result<()> {
+        let base = if background { 40 } else { 30 };
+        match ink {
+            None => write!(w, "{}", base + 9),
+            Some(Ink::Rgb([r, g, b])) => write!(w, "{};2;{};{};{}", base + 8, r, g, b),
+            Some(Ink::Ansi256(i)) => write!(w, "{};5;{}", base + 8, i),
+            Some(Ink::Ansi16(i)) if i < 8 => write!(w, "{}", base + i as u32),
+            Some(Ink::Ansi16(i)) => write!(w, "{}", base + 60 + i as u32 - 8),
+        }
+    }
+}
+
+#[inline]
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(&b)
+        .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+fn nearest_ansi256(c: [u8; 3]) -> (u8, [u8; 3]) {
+    let level = |v: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs())
+            .unwrap()
+    };
+    let [r, g, b] = c.map(level);
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // the grayscale ramp goes from 8 to 238, in steps of 10
+    let mean = c.iter().map(|&v| v as u32).sum::<u32>() / 3;
+    let step = (mean.saturating_sub(3) / 10).min(23);
+    let gray = [8 + step as u8 * 10; 3];
+    let gray_index = 232 + step as usize;
+
+    if distance(c, gray) < distance(c, cube) {
+        (gray_index as u8, gray)
+    } else {
+        (cube_index as u8, cube)
+    }
+}
+
+fn nearest_ansi16(c: [u8; 3]) -> (u8, [u8; 3]) {
+    let i = (0..ANSI16_PALETTE.len())
+        .min_by_key(|&i| distance(c, ANSI16_PALETTE[i]))
+        .unwrap();
+    (i as u8, ANSI16_PALETTE[i])
+}
+
+impl ColorMode {
+    // the nearest color of the palette, and its ink
+    fn nearest(self, c: [u8; 3]) -> (Ink, [u8; 3]) {
+        match self {
+            ColorMode::TrueColor | ColorMode::Monochrome => (Ink::Rgb(c), c),
+            ColorMode::Ansi256 => {
+                let (i, c) = nearest_ansi256(c);
+                (Ink::Ansi256(i), c)
+            }
+            ColorMode::Ansi16 => {
+                let (i, c) = nearest_ansi16(c);
+                (Ink::Ansi16(i), c)
+            }
+        }
+    }
+}
+
+// the pixels of an image, where mostly transparent
+// pixels are left to the background of the terminal
+struct Pixels {
+    width: usize,
+    height: usize,
+    colors: Vec<Option<[u8; 3]>>,
+}
+
+impl Pixels {
+    fn new<I: Image + Dimensions>(image: &I, ignore_alpha: bool) -> Self {
+        let (width, height) = image.dimensions();
+        let colors = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let c: Nrgba = image.color_get(x, y).convert_into();
+                (ignore_alpha || c.a >= 0x80).then_some([c.r, c.g, c.b])
+            })
+            .collect();
+        Pixels {
+            width,
+            height,
+            colors,
+        }
+    }
+
+    // pixels past the edges of the image are empty
+    #[inline]
+    fn get(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        if x < self.width && y < self.height {
+            self.colors[y * self.width + x]
+        } else {
+            None
+        }
+    }
+
+    // spreads the error of quantizing each value to its
+    // neighbours, with the weights of Floyd-Steinberg
+    fn diffuse<const N: usize>(
+        &self,
+        values: &mut [[f32; N]],
+        mut quantize: impl FnMut(usize, [f32; N]) -> [f32; N],
+    ) {
+        let (width, height) = (self.width, self.height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                if self.colors[i].is_none() {
+                    continue;
+                }
+                let old = values[i];
+                let new = quantize(i, old);
+                let mut spread = |dx: isize, dy: usize, weight: f32| {
+                    let nx = x as isize + dx;
+                    if nx < 0 || nx as usize >= width || y + dy >= height {
+                        return;
+                    }
+                    let j = (y + dy) * width + nx as usize;
+                    for c in 0..N {
+                        values[j][c] += (old[c] - new[c]) * weight;
+                    }
+                };
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+        }
+    }
+
+    // whether each pixel is drawn, depending on its luminance
+    fn drawn(&self, opts: &RenderOptions) -> Vec<bool> {
+        let luma = |c: [u8; 3]| {
+            let [r, g, b] = c;
+            let y: Gray = Rgb { r, g, b }.convert_into();
+            y.y
+        };
+        if !opts.dither {
+            return self
+                .colors
+                .iter()
+                .map(|c| c.is_some_and(|c| (luma(c) >= opts.threshold) != opts.invert))
+                .collect();
+        }
+
+        let threshold = opts.threshold as f32;
+        let mut drawn = vec![false; self.colors.len()];
+        let mut values: Vec<[f32; 1]> = self
+            .colors
+            .iter()
+            .map(|c| [c.map_or(0.0, |c| luma(c) as f32)])
+            .collect();
+        self.diffuse(&mut values, |i, [v]| {
+            let on = v >= threshold;
+            drawn[i] = on != opts.invert;
+            [if on { 255.0 } else { 0.0 }]
+        });
+        drawn
+    }
+
+    // reduces the colors of every pixel to the palette
+    fn dither_palette(&mut self, mode: ColorMode) {
+        let mut values: Vec<[f32; 3]> = self
+            .colors
+            .iter()
+            .map(|c| c.unwrap_or_default().map(|v| v as f32))
+            .collect();
+        let mut colors = self.colors.clone();
+        self.diffuse(&mut values, |i, old| {
+            let c = old.map(|v| v.round().clamp(0.0, 255.0) as u8);
+            let (_, c) = mode.nearest(c);
+            colors[i] = Some(c);
+            c.map(|v| v as f32)
+        });
+        self.colors = colors;
+    }
+}
+
+// the mean of the colors in a cell
+fn mean<'a>(colors: impl Iterator<Item = &'a [u8; 3]>) -> Option<[u8; 3]> {
+    let mut count = 0;
+    let mut sum = [0u32; 3];
+    for c in colors {
+        count += 1;
+        for (s, &v) in sum.iter_mut().zip(c) {
+            *s += v as u32;
+        }
+    }
+    (count > 0).then(|| sum.map(|s| ((s + count / 2) / count) as u8))
+}
+
+// splits the pixels of a cell between a foreground and
+// a background color, with the least squared error,
+// returning the mask of foreground pixels
+fn fit(colors: &[[u8; 3]]) -> (u32, Option<[u8; 3]>, Option<[u8; 3]>) {
+    let n = colors.len();
+    let in_mask = |mask: u32, set: bool| {
+        colors
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| (mask >> i & 1 == 1) == set)
+            .map(|(_, c)| c)
+    };
+    let error = |mask: u32| -> u32 {
+        [true, false]
+            .iter()
+            .filter_map(|&set| {
+                let m = mean(in_mask(mask, set))?;
+                Some(in_mask(mask, set).map(|&c| distance(c, m)).sum::<u32>())
+            })
+            .sum()
+    };
+    // the last pixel is always in the background,
+    // since swapping both colors is the same split
+    let mask = (0..1 << (n - 1)).min_by_key(|&mask| error(mask)).unwrap();
+    (mask, mean(in_mask(mask, true)), mean(in_mask(mask, false)))
+}
+
+/// Render an image as text, one line per row of cells. Pixels
+/// past the edges of the image, or mostly transparent unless
+/// alpha is ignored, are left to the background of the terminal.
+pub fn render<W, I>(mut w: W, opts: RenderOptions, image: &I) -> io::Result<()>
+where
+    W: Write,
+    I: Image + Dimensions,
+{
+    let mut pixels = Pixels::new(image, opts.ignore_alpha);
+    let style = opts.cell_style;
+    let mode = opts.color_mode;
+    let by_luminance = style == CellStyle::Braille || mode == ColorMode::Monochrome;
+    let drawn = if by_luminance {
+        pixels.drawn(&opts)
+    } else {
+        Vec::new()
+    };
+    if opts.dither && !by_luminance && mode != ColorMode::TrueColor {
+        pixels.dither_palette(mode);
+    }
+
+    let (cell_width, cell_height) = style.cell_size();
+    let columns = pixels.width.div_ceil(cell_width);
+    let rows = pixels.height.div_ceil(cell_height);
+    let mut cell = Vec::with_capacity(cell_width * cell_height);
+    let mut glyph = [0; 4];
+    for row in 0..rows {
+        let (mut fg, mut bg) = (None, None);
+        for column in 0..columns {
+            cell.clear();
+            let mut mask = 0;
+            let mut padded = false;
+            for dy in 0..cell_height {
+                for dx in 0..cell_width {
+                    let (x, y) = (column * cell_width + dx, row * cell_height + dy);
+                    let c = match pixels.get(x, y) {
+                        Some(c) => c,
+                        None => {
+                            padded = true;
+                            continue;
+                        }
+                    };
+                    let bit = style.pixel_bit(dx, dy);
+                    if !by_luminance || drawn[y * pixels.width + x] {
+                        mask |= 1 << bit;
+                        cell.push(c);
+                    }
+                }
+            }
+
+            // cells which aren't entirely filled keep the
+            // background of the terminal, and draw the rest
+            // in a single color
+            let (mask, cell_fg, cell_bg) = if by_luminance || padded {
+                (mask, mean(cell.iter()), None)
+            } else {
+                let (fit_mask, cell_fg, cell_bg) = fit(&cell);
+                let mask = (0..cell.len())
+                    .filter(|&i| fit_mask >> i & 1 == 1)
+                    .map(|i| 1 << style.pixel_bit(i % cell_width, i / cell_width))
+                    .sum();
+                (mask, cell_fg, cell_bg)
+            };
+
+            if mode != ColorMode::Monochrome {
+                let cell_bg = cell_bg.map(|c| mode.nearest(c).0);
+                let mut params = Vec::with_capacity(2);
+                // the foreground of an empty cell doesn't matter
+                if let (Some(c), true) = (cell_fg, mask != 0) {
+                    let cell_fg = Some(mode.nearest(c).0);
+                    if cell_fg != fg {
+                        params.push((cell_fg, false));
+                        fg = cell_fg;
+                    }
+                }
+                if cell_bg != bg {
+                    params.push((cell_bg, true));
+                    bg = cell_bg;
+                }
+                for (i, &(ink, background)) in params.iter().enumerate() {
+                    w.write_all(if i == 0 { b"\x1b[" } else { b";" })?;
+                    Ink::write_escape(ink, background, &mut w)?;
+                }
+                if !params.is_empty() {
+                    w.write_all(b"m")?;
+                }
+            }
+            w.write_all(style.glyph(mask).encode_utf8(&mut glyph).as_bytes())?;
+        }
+        if fg.is_some() || bg.is_some() {
+            w.write_all(b"\x1b[0m")?;
+        }
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::RawPixBuf;
+
+    fn image(w: usize, h: usize, pixel: impl Fn(usize, usize) -> [u8; 4]) -> RawPixBuf<Nrgba> {
+        let data = (0..w * h).flat_map(|i| pixel(i % w, i / w)).collect();
+        RawPixBuf::from_vec(w, h, data).unwrap()
+    }
+
+    fn render_to_string<I: Image + Dimensions>(opts: RenderOptions, image: &I) -> String {
+        let mut out = Vec::new();
+        render(&mut out, opts, image).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn braille_dots_follow_the_threshold() {
+        // a single cell, white on the left and black on the right
+        let image = image(2, 4, |x, _| if x == 0 { [255; 4] } else { [0, 0, 0, 255] });
+        let mut opts = RenderOptions {
+            cell_style: CellStyle::Braille,
+            color_mode: ColorMode::Monochrome,
+            ..Default::default()
+        };
+        assert_eq!(render_to_string(opts, &image), "⡇\n");
+        opts.invert = true;
+        assert_eq!(render_to_string(opts, &image), "⢸\n");
+    }
+
+    #[test]
+    fn transparent_pixels_are_left_out() {
+        let image = image(2, 4, |_, _| [0; 4]);
+        let mut opts = RenderOptions {
+            cell_style: CellStyle::Braille,
+            color_mode: ColorMode::Monochrome,
+            invert: true,
+            ..Default::default()
+        };
+        assert_eq!(render_to_string(opts, &image), "⠀\n");
+        opts.ignore_alpha = true;
+        assert_eq!(render_to_string(opts, &image), "⣿\n");
+    }
+
+    #[test]
+    fn half_blocks_split_the_cell_colors() {
+        let image = image(1, 2, |_, y| {
+            if y == 0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            }
+        });
+        let out = render_to_string(Default::default(), &image);
+        assert_eq!(out, "\x1b[38;2;255;0;0;48;2;0;0;255m▀\x1b[0m\n");
+    }
+}
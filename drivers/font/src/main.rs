@@ -23,8 +23,7 @@ fn main() -> io::Result<()> {
 
         let font_data = std::fs::read(&args[1])?;
 
-        Font::from_bytes(font_data, Default::default())
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+        Font::from_bytes(font_data, Default::default()).map_err(io::Error::other)?
     };
 
     let stdout = io::stdout();
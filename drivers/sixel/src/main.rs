@@ -0,0 +1,42 @@
+use std::io::{self, BufReader, BufWriter};
+
+use clap::Parser;
+use feim::buffer::RawPixBuf;
+use feim::color::Nrgba64Be;
+use feim::image::farbfeld::{Farbfeld, FarbfeldDecodeOptions};
+use feim::image::sixel::{Sixel, SixelEncodeOptions};
+use feim::serialize::{Decode, EncodeGeneric};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CmdLineArgs {
+    /// The maximum number of colors to use, from 2 to 256
+    #[arg(short, long, default_value_t = 256)]
+    colors: usize,
+    /// Whether transparent pixels shall be left unpainted
+    #[arg(short, long, default_value_t = false)]
+    transparency: bool,
+}
+
+fn main() -> io::Result<()> {
+    let args = CmdLineArgs::parse();
+
+    let stdin = io::stdin();
+    let stdin_lock = stdin.lock();
+    let stdin_reader = BufReader::new(stdin_lock);
+
+    let stdout = io::stdout();
+    let stdout_lock = stdout.lock();
+    let stdout_writer = BufWriter::new(stdout_lock);
+
+    let opts = FarbfeldDecodeOptions {
+        check_header: false,
+    };
+    let image: RawPixBuf<Nrgba64Be> = Farbfeld::decode(stdin_reader, opts)?;
+
+    let opts = SixelEncodeOptions {
+        palette_size: args.colors,
+        transparency: args.transparency,
+    };
+    Sixel::encode_generic(stdout_writer, opts, &image)
+}
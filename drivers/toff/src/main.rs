@@ -1,59 +1,34 @@
-use std::io::{self, BufReader, BufWriter};
+use std::io::{self, BufWriter};
 
 use feim::image::{
     self,
-    farbfeld::Farbfeld,
-    jpeg::{Jpeg, JpegBuf},
-    png::{Png, PngBuf},
-    webp::Webp,
-    BuiltInFormat,
+    fits::{Fits, FitsBuf, FitsDecodeOptions},
+    BuiltInFormat, DynImage,
 };
-use feim::serialize::{try_format, Decode, Encode, EncodeSpecialized};
+use feim::serialize::{try_format, Decode, PeekReader};
 
 fn main() -> io::Result<()> {
     let stdin = io::stdin();
     let stdin_lock = stdin.lock();
-    let mut stdin_reader = BufReader::new(stdin_lock);
+    let mut stdin_reader = PeekReader::new(stdin_lock);
 
     let stdout = io::stdout();
     let stdout_lock = stdout.lock();
     let mut stdout_writer = BufWriter::new(stdout_lock);
 
-    match try_format(&mut stdin_reader, image::built_in_formats_iter()) {
-        Ok(BuiltInFormat::Farbfeld) => {
+    let image = match try_format(&mut stdin_reader, image::built_in_formats_iter())? {
+        BuiltInFormat::Farbfeld => {
             io::copy(&mut stdin_reader, &mut stdout_writer)?;
-            Ok(())
+            return Ok(());
         }
-        Ok(BuiltInFormat::Jpeg) => {
-            let image = Jpeg::decode(stdin_reader, ())?;
-
-            match &image {
-                JpegBuf::Gray(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                JpegBuf::Gray16(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                JpegBuf::Rgb(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                JpegBuf::Cmyk(buf) => Farbfeld::encode(stdout_writer, (), buf),
-            }
-        }
-        Ok(BuiltInFormat::Png) => {
-            let image = Png::decode(stdin_reader, ())?;
-
-            match &image {
-                PngBuf::Gray(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                PngBuf::Gray16(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                PngBuf::Nrgba(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                PngBuf::Nrgba64(buf) => Farbfeld::encode_specialized(stdout_writer, (), buf),
-                PngBuf::Rgb(buf) => Farbfeld::encode(stdout_writer, (), buf),
-                PngBuf::Rgb48(buf) => Farbfeld::encode(stdout_writer, (), buf),
-            }
+        BuiltInFormat::Fits => {
+            // float samples hold physical values, of any range
+            let opts = FitsDecodeOptions { normalize: true };
+            let image: FitsBuf = Fits::decode(stdin_reader, opts)?;
+            DynImage::from(image)
         }
-        Ok(BuiltInFormat::Webp) => {
-            let image = Webp::decode(stdin_reader, ())?;
+        format => format.decode(stdin_reader)?,
+    };
 
-            match &image {
-                either::Left(rgb_buf) => Farbfeld::encode(stdout_writer, (), rgb_buf),
-                either::Right(nrgba_buf) => Farbfeld::encode(stdout_writer, (), nrgba_buf),
-            }
-        }
-        Err(e) => Err(e),
-    }
+    BuiltInFormat::Farbfeld.encode(stdout_writer, &image, Default::default())
 }